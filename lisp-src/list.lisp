@@ -12,11 +12,12 @@
 (define (reverse ls)
   (fold-left (flip2 cons) () ls))
 
-(let ((append-two
-        (lambda (la lb)
-          (if (nil? la) 
-            lb
-            (cons (car la)
-                  (append-two (cdr la) lb))))))
-  (define (append . rest)
-    (fold-right append-two () rest)))
+(define append
+  (let ((append-two
+          (lambda (la lb)
+            (if (nil? la) 
+              lb
+              (cons (car la)
+                    (append-two (cdr la) lb))))))
+    (lambda rest
+      (fold-right append-two () rest))))
@@ -0,0 +1,345 @@
+//! Converting between Rust values and lisp values, for writing native functions.
+//!
+//! `ToLisp` turns a Rust value into a lisp value, and `FromLisp` checks a lisp value's
+//! type and turns it back, throwing a 'type-error if it is the wrong kind. The mapping is:
+//!
+//! | Rust                         | lisp                                          |
+//! |------------------------------|-----------------------------------------------|
+//! | integers                     | integer                                       |
+//! | `f32`, `f64`                 | float; `FromLisp` accepts integers too        |
+//! | `bool`                       | the symbols `true` and `false`                |
+//! | `char`                       | character                                     |
+//! | `String`, `&str`             | string                                        |
+//! | `Vec<T>`                     | list; `FromLisp` accepts vectors too          |
+//! | `HashMap<K, V>`              | association list of `(key . value)`           |
+//! | `Option<T>`                  | nil for `None`                                |
+//! | `Result<T, RuntimeError>`    | `Err` is thrown; `FromLisp` gives an error value as `Err` |
+//! | `()`                         | nil                                           |
+//! | `LispObj`, `LispObjRef`      | itself                                        |
+//!
+//! Integers which don't fit the type they are converted to throw an 'argument-error.
+//!
+//! `native_fn!` uses these to wrap an ordinary Rust function as a native function:
+//!
+//! ```
+//! # #[macro_use] extern crate rustylisp;
+//! use rustylisp::core::EvalResult;
+//! use rustylisp::parser::Parser;
+//! use rustylisp::run::Evaluator;
+//!
+//! fn repeat(s: String, times: usize) -> EvalResult<String> {
+//!     Ok(s.repeat(times))
+//! }
+//!
+//! # fn main() {
+//! let mut runner = Evaluator::new();
+//! runner.register_module("", &[("repeat", native_fn!(repeat(String, usize)), None)]);
+//! let res = runner.eval_all_from_parser(Parser::from_string("(repeat \"ab\" 2)", "<example>")).unwrap();
+//! assert_eq!(*res, string!("abab"));
+//! # }
+//! ```
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::hash::Hash;
+
+use super::{LispObj, LispObjRef, AsLispObjRef, RuntimeError, EvalResult};
+use super::shared::Shared;
+
+/// A Rust value which can be turned into a lisp value.
+pub trait ToLisp {
+    fn to_lisp(self) -> EvalResult;
+}
+
+/// A Rust value which can be made from a lisp value.
+pub trait FromLisp: Sized {
+    fn from_lisp(obj: &LispObjRef) -> EvalResult<Self>;
+}
+
+/// A 'type-error for converting `obj` to a type it isn't.
+fn expected<T>(obj: &LispObjRef, expected: &str) -> EvalResult<T> {
+    let msg = format!("expected {}, got {}", expected, obj);
+    Err(RuntimeError::new(::evaluator::err_msgs::TYPE_ERROR, Some(string!(msg)), None, None)
+            .with_details(vec![obj.clone()], Some(expected)))
+}
+
+/// An 'argument-error for an integer which doesn't fit the type it is converted to.
+fn out_of_range<T, N: Display>(n: N, type_name: &str) -> EvalResult<T> {
+    let msg = format!("{} is out of range for {}", n, type_name);
+    Err(RuntimeError::new(::evaluator::err_msgs::ARGUMENT_ERROR, Some(string!(msg)), None, None))
+}
+
+macro_rules! signed_conversions {
+    ( $( $ty:ident ),* ) => {
+        $(
+            impl ToLisp for $ty {
+                fn to_lisp(self) -> EvalResult {
+                    Ok(int!(self).to_obj_ref())
+                }
+            }
+
+            impl FromLisp for $ty {
+                fn from_lisp(obj: &LispObjRef) -> EvalResult<Self> {
+                    match **obj {
+                        LispObj::LInteger(n) if (n as $ty) as i64 == n => Ok(n as $ty),
+                        LispObj::LInteger(n) => out_of_range(n, stringify!($ty)),
+                        _ => expected(obj, "int"),
+                    }
+                }
+            }
+        )*
+    }
+}
+
+macro_rules! unsigned_conversions {
+    ( $( $ty:ident ),* ) => {
+        $(
+            impl ToLisp for $ty {
+                fn to_lisp(self) -> EvalResult {
+                    if (self as i64) < 0 {
+                        return out_of_range(self, "int")
+                    }
+                    Ok(int!(self).to_obj_ref())
+                }
+            }
+
+            impl FromLisp for $ty {
+                fn from_lisp(obj: &LispObjRef) -> EvalResult<Self> {
+                    match **obj {
+                        LispObj::LInteger(n) if n >= 0 && (n as $ty) as i64 == n => Ok(n as $ty),
+                        LispObj::LInteger(n) => out_of_range(n, stringify!($ty)),
+                        _ => expected(obj, "int"),
+                    }
+                }
+            }
+        )*
+    }
+}
+
+signed_conversions!(i8, i16, i32, i64, isize);
+unsigned_conversions!(u8, u16, u32, u64, usize);
+
+impl ToLisp for f64 {
+    fn to_lisp(self) -> EvalResult {
+        Ok(float!(self).to_obj_ref())
+    }
+}
+
+impl FromLisp for f64 {
+    fn from_lisp(obj: &LispObjRef) -> EvalResult<Self> {
+        match **obj {
+            LispObj::LFloat(f) => Ok(f),
+            LispObj::LInteger(n) => Ok(n as f64),
+            _ => expected(obj, "float"),
+        }
+    }
+}
+
+impl ToLisp for f32 {
+    fn to_lisp(self) -> EvalResult {
+        (self as f64).to_lisp()
+    }
+}
+
+impl FromLisp for f32 {
+    fn from_lisp(obj: &LispObjRef) -> EvalResult<Self> {
+        f64::from_lisp(obj).map(|f| f as f32)
+    }
+}
+
+impl ToLisp for bool {
+    fn to_lisp(self) -> EvalResult {
+        Ok(lisp_bool_ref!(self))
+    }
+}
+
+impl FromLisp for bool {
+    fn from_lisp(obj: &LispObjRef) -> EvalResult<Self> {
+        match **obj {
+            LispObj::LSymbol(ref s) if s == "true" => Ok(true),
+            LispObj::LSymbol(ref s) if s == "false" => Ok(false),
+            _ => expected(obj, "boolean"),
+        }
+    }
+}
+
+impl ToLisp for char {
+    fn to_lisp(self) -> EvalResult {
+        Ok(char!(self).to_obj_ref())
+    }
+}
+
+impl FromLisp for char {
+    fn from_lisp(obj: &LispObjRef) -> EvalResult<Self> {
+        match **obj {
+            LispObj::LChar(c) => Ok(c),
+            _ => expected(obj, "char"),
+        }
+    }
+}
+
+impl ToLisp for String {
+    fn to_lisp(self) -> EvalResult {
+        Ok(LispObj::LString(Shared::new(self)).to_obj_ref())
+    }
+}
+
+impl<'a> ToLisp for &'a str {
+    fn to_lisp(self) -> EvalResult {
+        String::from(self).to_lisp()
+    }
+}
+
+impl FromLisp for String {
+    fn from_lisp(obj: &LispObjRef) -> EvalResult<Self> {
+        match **obj {
+            LispObj::LString(ref s) => Ok((**s).clone()),
+            _ => expected(obj, "string"),
+        }
+    }
+}
+
+impl<T: ToLisp> ToLisp for Vec<T> {
+    fn to_lisp(self) -> EvalResult {
+        let mut items = Vec::with_capacity(self.len());
+        for item in self {
+            items.push(try!(item.to_lisp()));
+        }
+        Ok(LispObj::from_vec(items).to_obj_ref())
+    }
+}
+
+impl<T: FromLisp> FromLisp for Vec<T> {
+    fn from_lisp(obj: &LispObjRef) -> EvalResult<Self> {
+        let mut out = vec![];
+        match **obj {
+            LispObj::LVector(ref v) => for item in v.iter() {
+                out.push(try!(T::from_lisp(item)));
+            },
+            LispObj::LCons(..) | LispObj::LNil => for item in obj.list_iter() {
+                match item {
+                    Ok(item) => out.push(try!(T::from_lisp(&item))),
+                    Err(_) => return expected(obj, "list"),
+                }
+            },
+            _ => return expected(obj, "list"),
+        }
+        Ok(out)
+    }
+}
+
+impl<K: ToLisp + Eq + Hash, V: ToLisp> ToLisp for HashMap<K, V> {
+    fn to_lisp(self) -> EvalResult {
+        let mut pairs = Vec::with_capacity(self.len());
+        for (key, val) in self {
+            pairs.push(cons!(try!(key.to_lisp()), try!(val.to_lisp())).to_obj_ref());
+        }
+        Ok(LispObj::from_vec(pairs).to_obj_ref())
+    }
+}
+
+impl<K: FromLisp + Eq + Hash, V: FromLisp> FromLisp for HashMap<K, V> {
+    fn from_lisp(obj: &LispObjRef) -> EvalResult<Self> {
+        let mut out = HashMap::new();
+        for item in obj.list_iter() {
+            match item.ok().and_then(|pair| pair.cons_split()) {
+                Some((key, val)) => { out.insert(try!(K::from_lisp(&key)), try!(V::from_lisp(&val))); },
+                None => return expected(obj, "association list"),
+            }
+        }
+        Ok(out)
+    }
+}
+
+impl<T: ToLisp> ToLisp for Option<T> {
+    fn to_lisp(self) -> EvalResult {
+        match self {
+            Some(val) => val.to_lisp(),
+            None => Ok(nil!().to_obj_ref()),
+        }
+    }
+}
+
+impl<T: FromLisp> FromLisp for Option<T> {
+    fn from_lisp(obj: &LispObjRef) -> EvalResult<Self> {
+        if obj.is_nil() {
+            Ok(None)
+        } else {
+            T::from_lisp(obj).map(Some)
+        }
+    }
+}
+
+impl<T: ToLisp> ToLisp for Result<T, RuntimeError> {
+    fn to_lisp(self) -> EvalResult {
+        self.and_then(ToLisp::to_lisp)
+    }
+}
+
+impl<T: FromLisp> FromLisp for Result<T, RuntimeError> {
+    fn from_lisp(obj: &LispObjRef) -> EvalResult<Self> {
+        match **obj {
+            LispObj::LError(ref err) => Ok(Err((**err).clone())),
+            _ => T::from_lisp(obj).map(Ok),
+        }
+    }
+}
+
+impl ToLisp for () {
+    fn to_lisp(self) -> EvalResult {
+        Ok(nil!().to_obj_ref())
+    }
+}
+
+impl ToLisp for LispObj {
+    fn to_lisp(self) -> EvalResult {
+        Ok(self.to_obj_ref())
+    }
+}
+
+impl ToLisp for LispObjRef {
+    fn to_lisp(self) -> EvalResult {
+        Ok(self)
+    }
+}
+
+impl FromLisp for LispObjRef {
+    fn from_lisp(obj: &LispObjRef) -> EvalResult<Self> {
+        Ok(obj.clone())
+    }
+}
+
+impl FromLisp for LispObj {
+    fn from_lisp(obj: &LispObjRef) -> EvalResult<Self> {
+        Ok((**obj).clone())
+    }
+}
+
+/// An 'arity-error unless there are `expected` arguments. Used by `native_fn!`.
+pub fn check_arity(args: &[LispObjRef], expected: usize) -> EvalResult<()> {
+    if args.len() != expected {
+        let msg = format!("expected {} args, not {}", expected, args.len());
+        return Err(RuntimeError::new(::evaluator::err_msgs::ARITY_ERROR, Some(string!(msg)), None, None)
+                       .with_details(args.to_vec(), None))
+    }
+    Ok(())
+}
+
+#[macro_export]
+/// Wraps a Rust function as a `NativeFuncSignature`, converting its arguments with
+/// `FromLisp` and its result with `ToLisp`. The function's argument types are listed
+/// after its name, and a call with the wrong number of arguments throws an
+/// 'arity-error. See the `convert` module for an example.
+macro_rules! native_fn {
+    ( $( $func:ident )::+ ( $( $ty:ty ),* ) ) => {{
+        fn native_fn_wrapper(args: &[$crate::core::LispObjRef], _: $crate::core::EnvironmentRef)
+                -> $crate::core::EvalResult {
+            let arg_types: &[&str] = &[$( stringify!($ty) ),*];
+            try!($crate::core::convert::check_arity(args, arg_types.len()));
+            #[allow(unused_mut, unused_variables)]
+            let mut args = args.iter();
+            let res = $( $func )::+($( try!(<$ty as $crate::core::convert::FromLisp>::from_lisp(args.next().unwrap())) ),*);
+            $crate::core::convert::ToLisp::to_lisp(res)
+        }
+        native_fn_wrapper as $crate::core::NativeFuncSignature
+    }};
+}
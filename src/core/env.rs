@@ -1,8 +1,10 @@
-use std::cell::RefCell; 
 use std::collections::HashMap;
-use std::rc::Rc;
 
-use super::LispObjRef;
+use super::{EvalResult, LispObjRef, RuntimeError};
+use super::shared::{Shared, Lock};
+
+/// The metadata key under which `(define name "docstring" value)` stores documentation
+pub static DOC_META_KEY: &'static str = "doc";
 
 pub fn get_top_level(env: EnvironmentRef) -> EnvironmentRef {
     match env.borrow().parent {
@@ -12,7 +14,7 @@ pub fn get_top_level(env: EnvironmentRef) -> EnvironmentRef {
     env
 }
 
-pub type EnvironmentRef = Rc<RefCell<Environment>>;
+pub type EnvironmentRef = Shared<Lock<Environment>>;
 
 #[derive(Debug)]
 pub struct Environment {
@@ -23,6 +25,9 @@ pub struct Environment {
     // they are really needed
     macros: Option<HashMap<String, LispObjRef>>,
     special_chars: Option<HashMap<char, LispObjRef>>,
+    // Metadata about names, e.g. their documentation, as (key, value) pairs in
+    // the order they were set
+    metadata: Option<HashMap<String, Vec<(String, LispObjRef)>>>,
 }
 
 impl Default for Environment {
@@ -39,6 +44,7 @@ impl Environment {
             max_procedure_id:   0,
             macros:             None,
             special_chars:      None,
+            metadata:           None,
         }
     }
 
@@ -84,17 +90,70 @@ impl Environment {
     }
 
     pub fn to_env_ref(self) -> EnvironmentRef {
-        Rc::new(RefCell::new(self))
+        let env = Shared::new(Lock::new(self));
+        super::gc::register(&env);
+        env
     }
 
     pub fn is_top_level(&self) -> bool {
         self.parent.is_none()
     }
 
+    pub fn parent(&self) -> Option<EnvironmentRef> {
+        self.parent.clone()
+    }
+
+    /// The bindings made directly in this frame, sorted by name.
+    pub fn local_bindings(&self) -> Vec<(String, LispObjRef)> {
+        let mut out: Vec<_> = self.bindings.iter()
+                                  .map(|(name, val)| (name.clone(), val.clone()))
+                                  .collect();
+        out.sort_by(|a, b| a.0.cmp(&b.0));
+        out
+    }
+
+    /// The macros defined directly in this frame, sorted by name.
+    pub fn local_macros(&self) -> Vec<(String, LispObjRef)> {
+        let mut out: Vec<_> = match self.macros {
+            Some(ref macros) => macros.iter().map(|(name, val)| (name.clone(), val.clone())).collect(),
+            None => vec![],
+        };
+        out.sort_by(|a, b| a.0.cmp(&b.0));
+        out
+    }
+
+    /// The special character handlers set directly in this frame, sorted by character.
+    pub fn local_char_handlers(&self) -> Vec<(char, LispObjRef)> {
+        let mut out: Vec<_> = match self.special_chars {
+            Some(ref chars) => chars.iter().map(|(c, val)| (*c, val.clone())).collect(),
+            None => vec![],
+        };
+        out.sort_by(|a, b| a.0.cmp(&b.0));
+        out
+    }
+
+    /// The metadata set directly in this frame, sorted by name.
+    pub fn local_metadata(&self) -> Vec<(String, Vec<(String, LispObjRef)>)> {
+        let mut out: Vec<_> = match self.metadata {
+            Some(ref meta) => meta.iter().map(|(name, pairs)| (name.clone(), pairs.clone())).collect(),
+            None => vec![],
+        };
+        out.sort_by(|a, b| a.0.cmp(&b.0));
+        out
+    }
+
     pub fn clear_bindings(&mut self) {
         self.bindings.clear();
         self.macros = None;
         self.special_chars = None;
+        self.metadata = None;
+    }
+
+    /// Drops everything the frame refers to, including its parent, so that a frame
+    /// in a reference cycle can be freed.
+    pub fn release(&mut self) {
+        self.clear_bindings();
+        self.parent = None;
     }
 
     pub fn next_procedure_id(&mut self) -> u32 {
@@ -144,9 +203,19 @@ impl Environment {
 
     // TODO Only sets macro in this environment - should it be set in parent
     // environment?
-    pub fn let_macro(&mut self, name: String, value: LispObjRef) -> Option<LispObjRef> {
+    /// Binds a macro, returning the one it replaced. A macro must be a procedure, a
+    /// native function or a `(syntax-rules ...)` form, or this throws a
+    /// 'macro-expansion-error.
+    pub fn let_macro(&mut self, name: String, value: LispObjRef) -> EvalResult<Option<LispObjRef>> {
+        let is_syntax_rules = value.cons_split().map_or(false, |(hd, _)| hd.symbol_equal("syntax-rules"));
+        if !(value.is_proc() || value.is_native() || is_syntax_rules) {
+            let msg = format!("macro {} must be a procedure or syntax-rules, not {}", name, value);
+            return Err(RuntimeError::new(::evaluator::err_msgs::MACRO_ERROR, Some(string!(msg)), None, None)
+                           .with_details(vec![value], Some("procedure")))
+        }
+
         let map = match self.macros {
-            Some(ref mut macros) => return macros.insert(name, value),
+            Some(ref mut macros) => return Ok(macros.insert(name, value)),
             None => {
                 let mut macros = HashMap::new();
                 let inserted   = macros.insert(name, value);
@@ -155,7 +224,7 @@ impl Environment {
             },
         };
         self.macros = Some(map);
-        None
+        Ok(None)
     }
 
     pub fn lookup_macro(&self, name: &str) -> Option<LispObjRef> {
@@ -174,6 +243,36 @@ impl Environment {
         }
     }
 
+    /// Sets a metadata key for a name in this environment, returning the previous value.
+    pub fn set_meta(&mut self, name: String, key: String, value: LispObjRef) -> Option<LispObjRef> {
+        let pairs = self.metadata.get_or_insert_with(HashMap::new)
+                                 .entry(name).or_insert_with(Vec::new);
+        for pair in pairs.iter_mut() {
+            if pair.0 == key {
+                return Some(::std::mem::replace(&mut pair.1, value))
+            }
+        }
+        pairs.push((key, value));
+        None
+    }
+
+    /// All metadata for a name, from the innermost environment which has any.
+    pub fn lookup_meta(&self, name: &str) -> Option<Vec<(String, LispObjRef)>> {
+        let lookup = match &self.metadata {
+            &Some(ref m) => m.get(name),
+            &None => None,
+        };
+
+        if lookup.is_some() {
+            lookup.cloned()
+        } else {
+            match &self.parent {
+                &Some(ref par) => par.borrow().lookup_meta(name),
+                &None => None,
+            }
+        }
+    }
+
     // Returns the previous value, if there was one.
     // If name was not previously registered, no change occurs.
     pub fn swap_values(&mut self, name: &str, new_val: LispObjRef) -> Option<LispObjRef> {
@@ -189,6 +288,11 @@ impl Environment {
         }
     }
 
+    /// Whether `name` is bound in this frame, rather than a parent.
+    pub fn binds_locally(&self, name: &str) -> bool {
+        self.bindings.contains_key(name)
+    }
+
     pub fn let_new(&mut self, name: String, value: LispObjRef) -> Option<LispObjRef> {
         self.bindings.insert(name, value)
     }
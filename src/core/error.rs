@@ -1,13 +1,26 @@
 use std::convert::{Into, From};
 use std::error::Error;
 use std::fmt;
-use std::io;
+use std::io::{self, Write};
 use std::mem;
 
 use super::{LispObj, LispObjRef, AsLispObjRef};
 
+/// The result of evaluating lisp code. Evaluation, procedures and special forms all
+/// return references, so that values can be passed on without copying them.
 pub type EvalResult<Res=LispObjRef> = Result<Res, RuntimeError>;
 
+/// The name of the error raised by `(exit)`, which unwinds the interpreter
+/// so that the host can shut down.
+pub static EXIT_REQUEST: &'static str = "exit-request";
+
+/// The name of the error raised when evaluation is interrupted, e.g. by Ctrl-C.
+pub static INTERRUPT_ERROR: &'static str = "interrupt-error";
+
+/// The name of the error raised by calling an escape continuation, which unwinds
+/// to the continuation's `call-with-escape-continuation`.
+pub static ESCAPE_REQUEST: &'static str = "escape-request";
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct RuntimeError {
     pub errname: String,
@@ -31,6 +44,71 @@ impl RuntimeError {
         Self::new::<S, LispObj>(errtype, None, None, None)
     }
 
+    /// An error requesting the interpreter exits with the given status code
+    pub fn exit_request(code: i32) -> Self {
+        Self::new(EXIT_REQUEST, Some(int!(code)), None, None)
+    }
+
+    /// An error reporting that evaluation was interrupted
+    pub fn interrupt() -> Self {
+        Self::new(INTERRUPT_ERROR, Some(string!("interrupted")), None, None)
+    }
+
+    /// An error unwinding to the escape continuation `id`, to return `value` from it
+    pub fn escape(id: i64, value: LispObjRef) -> Self {
+        Self::new(ESCAPE_REQUEST, Some(cons!(int!(id), value).to_obj_ref()), None, None)
+    }
+
+    /// Finds the first error named `errname` in this error's chain of causes,
+    /// starting with this error.
+    pub fn find_in_chain(&self, errname: &str) -> Option<&RuntimeError> {
+        let mut err = self;
+        loop {
+            if err.errname == errname {
+                return Some(err)
+            }
+
+            err = match err.cause {
+                Some(ref cause) => cause,
+                None => return None,
+            };
+        }
+    }
+
+    /// If this error, or any error in its chain of causes, is an exit request,
+    /// returns the requested status code.
+    pub fn exit_code(&self) -> Option<i32> {
+        self.find_in_chain(EXIT_REQUEST).map(|err| {
+            match err.value.as_ref().map(|v| &**v) {
+                Some(&LispObj::LInteger(code)) => code as i32,
+                _ => 0,
+            }
+        })
+    }
+
+    /// True if this error was caused by an interrupt.
+    pub fn is_interrupt(&self) -> bool {
+        self.find_in_chain(INTERRUPT_ERROR).is_some()
+    }
+
+    /// If this error is unwinding to the escape continuation `id`, returns the value
+    /// to return from it.
+    pub fn escape_value(&self, id: i64) -> Option<LispObjRef> {
+        self.find_in_chain(ESCAPE_REQUEST)
+            .and_then(|err| err.value.as_ref())
+            .and_then(|val| val.cons_split())
+            .and_then(|(target, value)| match *target {
+                LispObj::LInteger(target) if target == id => Some(value),
+                _ => None,
+            })
+    }
+
+    /// Exit requests and interrupts must unwind all the way to the host, and
+    /// escapes to their continuation, so they may not be caught by lisp code.
+    pub fn is_catchable(&self) -> bool {
+        self.exit_code().is_none() && !self.is_interrupt() && self.find_in_chain(ESCAPE_REQUEST).is_none()
+    }
+
     pub fn new_from(cause: RuntimeError, source: LispObjRef) -> Self {
         RuntimeError::new(cause.errname.clone(), cause.value.clone(), Some(cause), Some(source))
     }
@@ -42,6 +120,39 @@ impl RuntimeError {
         }
     }
 
+    /// Adds structured details to an error: the arguments it was caused by, and the type
+    /// they were expected to be, if any. The error's value becomes an association list
+    /// of its message, as `message`, the arguments, as `args`, and `expected`.
+    pub fn with_details(self, args: Vec<LispObjRef>, expected: Option<&str>) -> Self {
+        let mut details = vec![];
+        if let Some(ref message) = self.value {
+            details.push(cons!(symbol!("message"), message.clone()));
+        }
+        details.push(cons!(symbol!("args"), LispObj::to_lisp_list(args.into_iter())));
+        if let Some(expected) = expected {
+            details.push(cons!(symbol!("expected"), symbol!(expected)));
+        }
+        self.with_value(LispObj::to_lisp_list(details.into_iter()))
+    }
+
+    /// Looks up one of the details added by `with_details`.
+    pub fn detail(&self, key: &str) -> Option<LispObjRef> {
+        let mut rest = match self.value {
+            Some(ref value) => value.clone(),
+            None => return None,
+        };
+        while let Some((pair, tl)) = rest.cons_split() {
+            match pair.cons_split() {
+                Some((name, val)) => if name.symbol_equal(key) {
+                    return Some(val)
+                },
+                None => return None,
+            }
+            rest = tl;
+        }
+        None
+    }
+
     pub fn with_source(self, source: LispObjRef) -> Self {
         RuntimeError {
             source: Some(source), ..self
@@ -76,17 +187,32 @@ impl RuntimeError {
         out
     }
 
-    pub fn dump_traceback(self) {
-        let trace = self.into_traceback();
-
-        for err in trace {
-            let val    = err.value .clone().map_or(String::new(), |val| format!("{}", val));
-            println!("{}: {}", err.errname, val);
-            match err.source {
-                Some(ref source) => println!("\tfrom {}", source),
-                None => {},
+    /// Writes the error and each of its causes, one per line, with where they came from.
+    pub fn write_traceback(&self, out: &mut Write) -> io::Result<()> {
+        let mut next = Some(self);
+        while let Some(err) = next {
+            // Errors with details show just their message
+            let val    = err.detail("message").or_else(|| err.value.clone())
+                            .map_or(String::new(), |val| format!("{}", val));
+            try!(writeln!(out, "{}: {}", err.errname, val));
+            if let Some(ref source) = err.source {
+                try!(writeln!(out, "\tfrom {}", source));
             }
+            next = err.cause.as_ref().map(|cause| &**cause);
         }
+        Ok(())
+    }
+
+    /// The traceback as a string. See `write_traceback`.
+    pub fn traceback_string(&self) -> String {
+        let mut out = vec![];
+        let _ = self.write_traceback(&mut out);
+        String::from_utf8_lossy(&out).into_owned()
+    }
+
+    /// Prints the traceback to stdout. See `write_traceback`.
+    pub fn dump_traceback(&self) {
+        let _ = self.write_traceback(&mut io::stdout());
     }
 
     pub fn into_lisp_obj(self) -> LispObj {
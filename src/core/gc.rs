@@ -0,0 +1,167 @@
+//! Collection of environments leaked in reference cycles.
+//!
+//! Environments and procedures are reference counted, and a procedure holds the
+//! environment it closes over. A procedure bound in its own closure, as made by `rec`
+//! or a `let` binding a lambda, is a cycle which is never freed. Every environment is
+//! registered here when it is made, so that `collect` can find such cycles and break
+//! them.
+//!
+//! Collection counts the references to each environment, and to each procedure bound
+//! directly in one, from the other environments and procedures. Anything with more
+//! references than that is held from outside, by Rust code or another kind of object,
+//! and is live, as is everything it refers to. The rest is garbage, and is freed by
+//! releasing its environments. References through other objects, such as a procedure
+//! in a list, are not followed, so cycles through them are not found, but nothing live
+//! is ever freed.
+//!
+//! The registry is per thread, so environments made by other threads count as held
+//! from outside. Collecting while other threads are running may free environments
+//! they are still using.
+use std::cell::RefCell;
+use std::cmp;
+use std::collections::HashMap;
+
+use super::{Environment, EnvironmentRef, LispObj, LispObjRef};
+use super::shared::{Shared, Lock, WeakShared};
+
+/// The registry is pruned of freed environments once it has grown to this many
+/// entries, or twice its size after the last pruning if that is more.
+const MIN_PRUNE_LEN: usize = 1024;
+
+struct Registry {
+    envs: Vec<WeakShared<Lock<Environment>>>,
+    pruned_len: usize,
+}
+
+impl Registry {
+    fn prune(&mut self) {
+        self.envs.retain(|env| env.upgrade().is_some());
+        self.pruned_len = self.envs.len();
+    }
+}
+
+thread_local! {
+    static REGISTRY: RefCell<Registry> = RefCell::new(Registry { envs: vec![], pruned_len: 0 });
+}
+
+/// Registers a new environment, so that collection can find it.
+pub fn register(env: &EnvironmentRef) {
+    REGISTRY.with(|reg| {
+        let mut reg = reg.borrow_mut();
+        reg.envs.push(Shared::downgrade(env));
+        if reg.envs.len() >= cmp::max(MIN_PRUNE_LEN, 2 * reg.pruned_len) {
+            reg.prune();
+        }
+    })
+}
+
+/// What a collection found.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Stats {
+    /// The environments alive before collection, including garbage
+    pub environments: usize,
+    /// The environments only reachable through reference cycles
+    pub garbage: usize,
+}
+
+#[derive(Clone, Copy)]
+enum Node {
+    Env(usize),
+    Proc(usize),
+}
+
+/// Finds environments which are only reachable through reference cycles, and frees them
+/// if `free` is set.
+pub fn collect(free: bool) -> Stats {
+    let envs: Vec<EnvironmentRef> = REGISTRY.with(|reg| {
+        let mut reg = reg.borrow_mut();
+        reg.prune();
+        reg.envs.iter().filter_map(|env| env.upgrade()).collect()
+    });
+    let env_index: HashMap<*const Lock<Environment>, usize> =
+        envs.iter().enumerate().map(|(i, env)| (&**env as *const _, i)).collect();
+
+    // The procedures bound directly in environments, and the edges out of each node
+    let mut procs: Vec<LispObjRef> = vec![];
+    let mut proc_index: HashMap<*const LispObj, usize> = HashMap::new();
+    let mut env_edges: Vec<Vec<Node>> = Vec::with_capacity(envs.len());
+    for env in envs.iter() {
+        let mut edges = vec![];
+        let env = env.borrow();
+        if let Some(parent) = env.parent() {
+            if let Some(&i) = env_index.get(&(&*parent as *const _)) {
+                edges.push(Node::Env(i));
+            }
+        }
+        for (_, val) in env.local_bindings() {
+            if !val.is_proc() {
+                continue
+            }
+            let ptr = &*val as *const LispObj;
+            let i = match proc_index.get(&ptr) {
+                Some(&i) => i,
+                None => {
+                    procs.push(val.clone());
+                    procs.len() - 1
+                },
+            };
+            let _ = proc_index.insert(ptr, i);
+            edges.push(Node::Proc(i));
+        }
+        env_edges.push(edges);
+    }
+    let proc_edges: Vec<Option<Node>> = procs.iter().map(|procd| {
+        env_index.get(&(&*procd.unwrap_proc().env as *const _)).map(|&i| Node::Env(i))
+    }).collect();
+
+    let mut env_internal = vec![0; envs.len()];
+    let mut proc_internal = vec![0; procs.len()];
+    for node in env_edges.iter().flat_map(|edges| edges.iter()).chain(proc_edges.iter().filter_map(|e| e.as_ref())) {
+        match *node {
+            Node::Env(i) => env_internal[i] += 1,
+            Node::Proc(i) => proc_internal[i] += 1,
+        }
+    }
+
+    // Our own copies in envs and procs account for one reference each. Anything whose
+    // count doesn't match is held from outside.
+    let mut live_envs = vec![false; envs.len()];
+    let mut live_procs = vec![false; procs.len()];
+    let mut stack = vec![];
+    for (i, env) in envs.iter().enumerate() {
+        if Shared::strong_count(env) - 1 != env_internal[i] {
+            stack.push(Node::Env(i));
+        }
+    }
+    for (i, procd) in procs.iter().enumerate() {
+        if Shared::strong_count(procd) - 1 != proc_internal[i] {
+            stack.push(Node::Proc(i));
+        }
+    }
+
+    while let Some(node) = stack.pop() {
+        match node {
+            Node::Env(i) if !live_envs[i] => {
+                live_envs[i] = true;
+                stack.extend(env_edges[i].iter().cloned());
+            },
+            Node::Proc(i) if !live_procs[i] => {
+                live_procs[i] = true;
+                stack.extend(proc_edges[i]);
+            },
+            _ => {},
+        }
+    }
+
+    let garbage: Vec<&EnvironmentRef> = envs.iter().zip(live_envs.iter())
+                                            .filter(|&(_, live)| !live)
+                                            .map(|(env, _)| env)
+                                            .collect();
+    let stats = Stats { environments: envs.len(), garbage: garbage.len() };
+    if free {
+        for env in garbage {
+            env.borrow_mut().release();
+        }
+    }
+    stats
+}
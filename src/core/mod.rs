@@ -2,6 +2,9 @@
 //!
 //! Contains the definition of Lisp objects (`LispObj`), as well as the definition of an
 //! environment (`Environment`), as well as their respective reference types.
+//!
+//! The reference types are `Rc`-based by default, or `Arc`-based when built with the
+//! `sync` feature; see the `shared` module.
 
 #[macro_export]
 /// Creates a symbol from a string
@@ -130,13 +133,47 @@ macro_rules! lisp_bool {
     }
 }
 
+/// The shared reference to the canonical true value, without allocating.
+#[macro_export]
+macro_rules! lisp_true_ref {
+    () => ( $crate::core::LispObj::bool_ref(true) )
+}
+
+/// The shared reference to the canonical false value, without allocating.
+#[macro_export]
+macro_rules! lisp_false_ref {
+    () => ( $crate::core::LispObj::bool_ref(false) )
+}
+
+/// Converts a rust bool into a shared reference to a lisp boolean, without allocating.
+///
+/// # Example
+/// ```
+/// # #[macro_use] extern crate rustylisp;
+/// # use rustylisp::core::AsLispObjRef;
+/// # fn main() {
+/// assert_eq!(lisp_bool_ref!(1 < 2), lisp_true!().to_obj_ref());
+/// # }
+/// ```
+#[macro_export]
+macro_rules! lisp_bool_ref {
+    ( $val:expr ) => ( $crate::core::LispObj::bool_ref($val) )
+}
+
+pub mod shared;
+
 pub mod obj;
-pub use self::obj::{LispObj, LispObjRef, AsLispObjRef, NativeFunc};
+pub use self::obj::{LispObj, LispObjRef, AsLispObjRef, NativeFunc, NativeFuncSignature};
 
 pub mod env;
 pub use self::env::{Environment, EnvironmentRef};
 
 pub mod procedure;
 
+pub mod gc;
+
 pub mod error;
 pub use self::error::{RuntimeError, EvalResult};
+
+#[macro_use]
+pub mod convert;
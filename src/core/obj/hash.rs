@@ -0,0 +1,91 @@
+//! Hashing of lisp values, for building tables and sets.
+//!
+//! Only values compared by their contents can be hashed: numbers, strings, symbols,
+//! chars, nil, byte vectors, float vectors, and lists, vectors and records made of
+//! them. Hashing agrees with `PartialEq`, so equal values always have equal hashes.
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use super::LispObj;
+use super::LispObj::*;
+
+impl LispObj {
+    /// Feeds this value to a hasher. On failure, returns the first part of the
+    /// value which cannot be hashed.
+    pub fn hash_into<H: Hasher>(&self, state: &mut H) -> Result<(), LispObj> {
+        let mut obj = self;
+        // Lists are walked in a loop, so long lists do not overflow the stack
+        loop {
+            match obj {
+                &LCons(ref car, ref cdr) => {
+                    state.write_u8(0);
+                    try!(car.hash_into(state));
+                    obj = cdr;
+                    continue
+                },
+                &LNil => state.write_u8(1),
+                &LInteger(n) => {
+                    state.write_u8(2);
+                    n.hash(state);
+                },
+                &LFloat(n) => {
+                    state.write_u8(3);
+                    hash_float(n, state);
+                },
+                &LString(ref s) => {
+                    state.write_u8(4);
+                    s.hash(state);
+                },
+                &LSymbol(ref s) => {
+                    state.write_u8(5);
+                    s.hash(state);
+                },
+                &LChar(c) => {
+                    state.write_u8(6);
+                    c.hash(state);
+                },
+                &LVector(ref vec) => {
+                    state.write_u8(7);
+                    vec.len().hash(state);
+                    for item in vec.iter() {
+                        try!(item.hash_into(state));
+                    }
+                },
+                &LRecord(ref rec) => {
+                    state.write_u8(8);
+                    rec.type_name.hash(state);
+                    rec.fields.hash(state);
+                    for value in rec.values.iter() {
+                        try!(value.hash_into(state));
+                    }
+                },
+                &LBytes(ref bytes) => {
+                    state.write_u8(9);
+                    bytes.hash(state);
+                },
+                &LF64Vector(ref floats) => {
+                    state.write_u8(10);
+                    floats.len().hash(state);
+                    for f in floats.iter() {
+                        hash_float(*f, state);
+                    }
+                },
+                other => return Err(other.clone()),
+            }
+            return Ok(())
+        }
+    }
+
+    /// Hashes this value, or returns the first part of it which cannot be hashed.
+    pub fn hash_value(&self) -> Result<u64, LispObj> {
+        let mut state = DefaultHasher::new();
+        try!(self.hash_into(&mut state));
+        Ok(state.finish())
+    }
+}
+
+fn hash_float<H: Hasher>(n: f64, state: &mut H) {
+    // 0.0 and -0.0 are equal, as are all NaNs, so must hash the same
+    let n = if n == 0.0 { 0.0f64 } else if n.is_nan() { ::std::f64::NAN } else { n };
+    n.to_bits().hash(state);
+}
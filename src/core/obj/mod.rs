@@ -1,19 +1,26 @@
+pub mod hash;
+pub mod port;
+pub mod record;
 pub mod vec;
+pub use self::port::Port;
+pub use self::record::Record;
 pub use self::vec::PersistentVec;
 
+use std::cell::{Cell, RefCell};
+use std::collections::HashSet;
 use std::fmt::{self, Display};
 use std::iter::FromIterator;
-use std::rc::Rc;
 
 pub use super::procedure::Procedure;
 use super::{error, EnvironmentRef, EvalResult, RuntimeError};
+use super::shared::Shared;
 use self::LispObj::*;
 
 pub type NativeFuncSignature        = fn(&[LispObjRef], EnvironmentRef) -> EvalResult;
-pub type LispObjRef<Obj=LispObj>    = Rc<Obj>;
+pub type LispObjRef<Obj=LispObj>    = Shared<Obj>;
 
 #[derive(Clone)]
-pub struct NativeFunc(Rc<NativeFuncSignature>);
+pub struct NativeFunc(Shared<NativeFuncSignature>);
 
 pub struct ListIter {
     list: LispObjRef
@@ -29,9 +36,9 @@ pub enum LispObj {
     LFloat(f64),
 
     /// A string
-    // Rc is to prevent the overhead of copying the string's contents
+    // Shared is to prevent the overhead of copying the string's contents
     // on calls to clone
-    LString(Rc<String>),
+    LString(Shared<String>),
 
     /// Representation of a symbol
     LSymbol(String),
@@ -54,7 +61,7 @@ pub enum LispObj {
 
     /// A function implemented in Rust
     /// LNativeFunc(name, documentation, func)
-    LNativeFunc(String, Option<Rc<String>>, NativeFunc),
+    LNativeFunc(String, Option<Shared<String>>, NativeFunc),
 
     /// A function
     LProcedure(Box<Procedure>),
@@ -62,6 +69,21 @@ pub enum LispObj {
     /// A caught error
     LError(Box<error::RuntimeError>),
 
+    /// A stream, such as a socket
+    LPort(Port),
+
+    /// An instance of a record type
+    LRecord(Box<Record>),
+
+    /// Binary data. Byte vectors are immutable; updating one makes a copy.
+    LBytes(Shared<Vec<u8>>),
+
+    /// Unboxed floats, for numeric work. Like byte vectors, they are immutable.
+    LF64Vector(Shared<Vec<f64>>),
+
+    /// An environment, as a first-class value
+    LEnvironment(EnvironmentRef),
+
     /*
     /// Various parser types
     LParserFileStream(Rc<RefCell<parser::Parser<io::Chars<fs::File>, io::CharsError>>>),
@@ -87,24 +109,66 @@ impl Iterator for ListIter {
 }
 
 impl PartialEq for LispObj {
-    // TODO Equality for named procedures, lambdas
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
             (&LInteger(ref me), &LInteger(ref you))         => me == you,
-            (&LFloat(ref me), &LFloat(ref you))             => me.eq(you),
+            // Unlike for f64, NaN is equal to itself, so any value is equal to itself
+            (&LFloat(ref me), &LFloat(ref you))             => me.eq(you) || (me.is_nan() && you.is_nan()),
             (&LString(ref me), &LString(ref you))           => me == you,
             (&LSymbol(ref me), &LSymbol(ref you))           => me == you,
             (&LChar(ref me), &LChar(ref you))               => me == you,
-            (&LCons(ref hme, ref tme), &LCons(ref hyou, ref tyou))                 
-                                                            => hme == hyou && tme == tyou,
+            (&LCons(..), &LCons(..))                        => nested_equal(self, other),
             (&LNil, &LNil) => true,
-            (&LVector(ref me), &LVector(ref you))           => me.eq(you),
-            (&LNativeFunc(ref me,_,_), &LNativeFunc(ref you,_,_)) => me == you,
+            (&LVector(..), &LVector(..))                    => nested_equal(self, other),
+            (&LLazyCons(ref hme, ref tme), &LLazyCons(ref hyou, ref tyou))
+                                                            => hme == hyou && tme == tyou,
+            (&LNativeFunc(_,_,ref me), &LNativeFunc(_,_,ref you)) => me == you,
+            (&LProcedure(ref me), &LProcedure(ref you))     => me == you,
+            (&LError(ref me), &LError(ref you))             => me == you,
+            (&LPort(ref me), &LPort(ref you))               => me.same_port(you),
+            (&LRecord(ref me), &LRecord(ref you))           => me == you,
+            (&LBytes(ref me), &LBytes(ref you))             => me == you,
+            (&LF64Vector(ref me), &LF64Vector(ref you))     => {
+                me.len() == you.len() && me.iter().zip(you.iter()).all(|(a, b)| a == b || (a.is_nan() && b.is_nan()))
+            },
+            (&LEnvironment(ref me), &LEnvironment(ref you)) => Shared::ptr_eq(me, you),
             (_, _) => false,
         }
     }
 }
 
+/// Compares lists and vectors with an explicit stack of the pairs still to compare,
+/// rather than recursing, so that long or deeply nested ones can be compared.
+fn nested_equal(me: &LispObj, you: &LispObj) -> bool {
+    let mut pairs = vec![(me, you)];
+    while let Some((me, you)) = pairs.pop() {
+        match (me, you) {
+            (&LCons(ref hme, ref tme), &LCons(ref hyou, ref tyou)) => {
+                pairs.push((tme, tyou));
+                pairs.push((hme, hyou));
+            },
+            (&LVector(ref vme), &LVector(ref vyou)) => {
+                if vme.len() != vyou.len() {
+                    return false
+                }
+                pairs.extend(vme.iter().zip(vyou.iter()).map(|(a, b)| (&**a, &**b)));
+            },
+            (&LCons(..), _) | (&LVector(_), _) => return false,
+            _ => if me != you {
+                return false
+            },
+        }
+    }
+    true
+}
+
+/// Native functions are equal if they call the same function, whatever they are named
+impl PartialEq for NativeFunc {
+    fn eq(&self, other: &Self) -> bool {
+        *self.0 as usize == *other.0 as usize
+    }
+}
+
 impl fmt::Debug for NativeFunc {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
         write!(fmt, "NativeFunc(_)")
@@ -113,77 +177,304 @@ impl fmt::Debug for NativeFunc {
 
 impl Eq for LispObj { }
 
-impl Display for LispObj {
-    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
-        match self {
-            &LInteger(ref me)   => write!(fmt, "{}", me),
-            &LFloat(ref me)     => write!(fmt, "{}", me),
-            &LString(ref me)    => write!(fmt, "\"{}\"", me),
-            &LSymbol(ref me)    => write!(fmt, "{}", me),
-            &LChar(ref me)      => {
-                match *me {
-                    ' '  => write!(fmt, "\\space"),
-                    '\t' => write!(fmt, "\\tab"),
-                    '\n' => write!(fmt, "\\newline"),
-                    _    => write!(fmt, "\\{}", me),
+/// Characters which print by name, as `\name`. The reader accepts the same names.
+pub static CHAR_NAMES: &'static [(&'static str, char)] = &[
+    ("space", ' '), ("tab", '\t'), ("newline", '\n'), ("return", '\r'), ("nul", '\0'),
+    ("alarm", '\x07'), ("backspace", '\x08'), ("escape", '\x1b'), ("delete", '\x7f'),
+];
+
+/// Reads a character name, as printed after a backslash: a single character, a name
+/// from `CHAR_NAMES`, or `x` followed by the character's code in hex.
+pub fn char_from_name(name: &str) -> Option<char> {
+    let mut chars = name.chars();
+    if let (Some(ch), None) = (chars.next(), chars.next()) {
+        return Some(ch)
+    }
+    if let Some(&(_, ch)) = CHAR_NAMES.iter().find(|&&(n, _)| n == name) {
+        return Some(ch)
+    }
+    if name.starts_with('x') {
+        return u32::from_str_radix(&name[1..], 16).ok().and_then(::std::char::from_u32)
+    }
+    None
+}
+
+/// Whether a character can't be read back from `\c`, as the reader would stop before it
+/// or treat it specially.
+fn needs_char_escape(ch: char) -> bool {
+    ch.is_control() || ch.is_whitespace() || "()[];\"@'`,#\\".contains(ch)
+}
+
+/// Values nested deeper than this through records, errors and the like print as `...`,
+/// so that printing them can't overflow the stack. Lists and vectors are printed
+/// without recursion, so may be nested to any depth.
+const MAX_PRINT_DEPTH: usize = 512;
+
+thread_local! {
+    /// The lists and vectors currently being printed
+    static PRINTING: RefCell<HashSet<*const LispObj>> = RefCell::new(HashSet::new());
+    /// How many values are being printed, one inside another
+    static PRINT_DEPTH: Cell<usize> = Cell::new(0);
+}
+
+/// Counts a value as being printed, until it is dropped.
+struct PrintGuard;
+
+impl PrintGuard {
+    /// Starts printing a value, or returns None if values are nested too deep.
+    fn enter() -> Option<PrintGuard> {
+        PRINT_DEPTH.with(|depth| {
+            if depth.get() >= MAX_PRINT_DEPTH {
+                None
+            } else {
+                depth.set(depth.get() + 1);
+                Some(PrintGuard)
+            }
+        })
+    }
+}
+
+impl Drop for PrintGuard {
+    fn drop(&mut self) {
+        PRINT_DEPTH.with(|depth| depth.set(depth.get() - 1));
+    }
+}
+
+/// What is left to print of a list or vector
+enum PrintStep {
+    Value(LispObjRef),
+    /// A list's elements from `rest` on. `slow` follows the list at half speed; if
+    /// `rest` catches up with it, the list's tail loops back on itself.
+    ListTail { rest: LispObjRef, slow: LispObjRef, count: usize },
+    VectorTail(::std::vec::IntoIter<LispObjRef>),
+    Text(&'static str),
+    /// Closes a list or vector, which is no longer being printed
+    Close(&'static str, *const LispObj),
+}
+
+/// Writes `obj`, or if it is a list or vector, writes its opening bracket and pushes
+/// the steps which print the rest of it.
+fn print_open(obj: &LispObj, steps: &mut Vec<PrintStep>, fmt: &mut fmt::Formatter) -> fmt::Result {
+    let ptr = obj as *const LispObj;
+    match obj {
+        &LCons(..) | &LVector(_) => {
+            if !PRINTING.with(|printing| printing.borrow_mut().insert(ptr)) {
+                return write!(fmt, "#cycle")
+            }
+        },
+        _ if fmt.alternate() => return write!(fmt, "{:#}", obj),
+        _ => return write!(fmt, "{}", obj),
+    }
+
+    match obj {
+        &LCons(ref head, ref tail) => {
+            steps.push(PrintStep::Close(")", ptr));
+            steps.push(PrintStep::ListTail { rest: tail.clone(), slow: tail.clone(), count: 0 });
+            steps.push(PrintStep::Value(head.clone()));
+            write!(fmt, "(")
+        },
+        &LVector(ref vec) => {
+            let mut items = vec.iter().cloned().collect::<Vec<_>>().into_iter();
+            steps.push(PrintStep::Close("]", ptr));
+            if let Some(first) = items.next() {
+                steps.push(PrintStep::VectorTail(items));
+                steps.push(PrintStep::Value(first));
+            }
+            write!(fmt, "[")
+        },
+        _ => unreachable!(),
+    }
+}
+
+fn print_steps(steps: &mut Vec<PrintStep>, fmt: &mut fmt::Formatter) -> fmt::Result {
+    while let Some(step) = steps.pop() {
+        match step {
+            PrintStep::Value(obj) => try!(print_open(&obj, steps, fmt)),
+            PrintStep::ListTail { rest, slow, count } => {
+                if rest.is_nil() {
+                    continue
+                }
+                match rest.cons_split() {
+                    Some((hd, tl)) => {
+                        try!(write!(fmt, " "));
+                        let count = count + 1;
+                        let slow = if count % 2 == 0 {
+                            slow.cons_split().map_or(slow.clone(), |(_, tl)| tl)
+                        } else {
+                            slow
+                        };
+                        if !tl.is_nil() && Shared::ptr_eq(&tl, &slow) {
+                            steps.push(PrintStep::Text(" . #cycle"));
+                        } else {
+                            steps.push(PrintStep::ListTail { rest: tl, slow: slow, count: count });
+                        }
+                        steps.push(PrintStep::Value(hd));
+                    },
+                    None => {
+                        try!(write!(fmt, " . "));
+                        steps.push(PrintStep::Value(rest));
+                    },
                 }
             },
-            // TODO implement
-            &LCons(ref head, ref tail)
-                                => {
-                try!(write!(fmt, "("));
-                // Deref from &LispObjRef to LispObj
-                try!((**head).fmt(fmt));
-
-                if !tail.is_nil() {
+            PrintStep::VectorTail(mut items) => {
+                if let Some(item) = items.next() {
                     try!(write!(fmt, " "));
+                    steps.push(PrintStep::VectorTail(items));
+                    steps.push(PrintStep::Value(item));
                 }
+            },
+            PrintStep::Text(text) => try!(write!(fmt, "{}", text)),
+            PrintStep::Close(bracket, ptr) => {
+                PRINTING.with(|printing| printing.borrow_mut().remove(&ptr));
+                try!(write!(fmt, "{}", bracket));
+            },
+        }
+    }
+    Ok(())
+}
 
-                let mut rest = tail.clone();
-                while !rest.is_nil() {
-                    if let Some((hd, tl)) = rest.cons_split() {
-                        try!(write!(fmt, "{}", *hd));
+fn write_float(f: f64, fmt: &mut fmt::Formatter) -> fmt::Result {
+    // Debug formatting keeps the decimal point, so floats read back as floats.
+    // Infinities and NaN are written with a sign, which the reader needs
+    if f.is_nan() {
+        write!(fmt, "+nan")
+    } else if f.is_infinite() {
+        write!(fmt, "{}inf", if f > 0.0 { "+" } else { "-" })
+    } else {
+        write!(fmt, "{:?}", f)
+    }
+}
 
-                        if tl.is_nil() {
-                            break;
-                        }
+/// Prints a list or vector with an explicit stack, so that deeply nested ones can't
+/// overflow the real one.
+fn print_nested(obj: &LispObj, fmt: &mut fmt::Formatter) -> fmt::Result {
+    let mut steps = vec![];
+    let res = print_open(obj, &mut steps, fmt).and_then(|_| print_steps(&mut steps, fmt));
 
-                        try!(write!(fmt, " "));
-                        rest = tl;
-                    } else {
-                        try!(write!(fmt, ". {}", *rest));
-                        break;
+    // If writing failed part way, forget the lists which were being printed
+    for step in steps {
+        if let PrintStep::Close(_, ptr) = step {
+            PRINTING.with(|printing| printing.borrow_mut().remove(&ptr));
+        }
+    }
+    res
+}
+
+/// Takes the parts of a list cell or vector which nothing else refers to, leaving nil
+/// or an empty vector in their place.
+fn take_unique_parts(obj: &mut LispObj, out: &mut Vec<LispObj>) {
+    match *obj {
+        LCons(ref mut head, ref mut tail) => {
+            for part in [head, tail].iter_mut() {
+                if !is_unique_nested(part) {
+                    continue
+                }
+                let placeholder = match cached_ref(&LNil) {
+                    Some(nil) => nil,
+                    // The thread is exiting, so just free it recursively
+                    None => return,
+                };
+                if let Ok(cell) = Shared::try_unwrap(::std::mem::replace(*part, placeholder)) {
+                    out.push(cell);
+                }
+            }
+        },
+        LVector(ref mut vec) => {
+            let mut items = vec![];
+            ::std::mem::replace(vec, vec::PersistentVec::new()).drain_unique(&mut items);
+            for item in items {
+                if is_unique_nested(&item) {
+                    if let Ok(inner) = Shared::try_unwrap(item) {
+                        out.push(inner);
                     }
                 }
+            }
+        },
+        _ => (),
+    }
+}
+
+fn is_unique_nested(obj: &LispObjRef) -> bool {
+    (obj.is_cons() || obj.is_vector()) && Shared::strong_count(obj) == 1
+}
+
+/// Frees lists a cell at a time, and vectors an element at a time, so that dropping a
+/// long or deeply nested one can't overflow the stack.
+impl Drop for LispObj {
+    fn drop(&mut self) {
+        let mut cells = vec![];
+        take_unique_parts(self, &mut cells);
+        while let Some(mut cell) = cells.pop() {
+            take_unique_parts(&mut cell, &mut cells);
+        }
+    }
+}
 
-                write!(fmt, ")")
+impl Display for LispObj {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        let _guard = match PrintGuard::enter() {
+            Some(guard) => guard,
+            None => return write!(fmt, "..."),
+        };
+
+        match self {
+            &LInteger(ref me)   => write!(fmt, "{}", me),
+            &LFloat(me)         => write_float(me, fmt),
+            &LString(ref me)    => write!(fmt, "\"{}\"", me),
+            &LSymbol(ref me)    => write!(fmt, "{}", me),
+            &LChar(ref me)      => {
+                match CHAR_NAMES.iter().find(|&&(_, ch)| ch == *me) {
+                    Some(&(name, _)) => write!(fmt, "\\{}", name),
+                    None if needs_char_escape(*me) => write!(fmt, "\\x{:x}", *me as u32),
+                    None => write!(fmt, "\\{}", me),
+                }
             },
+            &LCons(..) | &LVector(_)
+                                => print_nested(self, fmt),
             &LLazyCons(_, _)    => write!(fmt, "#<lazy-cons>"),
             &LNil               => write!(fmt, "()"),
-            &LVector(ref me)    => {
-                try!(write!(fmt, "["));
-                let mut iter = me.iter();
-                if let Some(obj) = iter.next() {
-                    try!(obj.fmt(fmt));
-                }
-                for obj in iter {
-                    try!(write!(fmt, " "));
-                    try!(obj.fmt(fmt));
-                }
-                write!(fmt, "]")
-            },
             &LNativeFunc(ref name,_,_)
                                 => write!(fmt, "#<native-procedure:{}>", name),
             &LProcedure(ref procd)
                                 => {
-                match procd.name {
+                try!(match procd.name {
                     Some(ref name) => {
-                        write!(fmt, "#<named-procedure:{}>", name)
+                        write!(fmt, "#<named-procedure:{}", name)
                     },
-                    None => write!(fmt, "#<anonymous-procedure:{}>", procd.id)
+                    None => write!(fmt, "#<anonymous-procedure:{}", procd.id)
+                });
+                // The alternate form, `{:#}`, adds the argument list of each clause
+                if fmt.alternate() {
+                    for &(ref arity, _) in procd.body.iter() {
+                        try!(write!(fmt, " {}", arity.arglist()));
+                    }
                 }
+                write!(fmt, ">")
             },
             &LError(ref err)    => write!(fmt, "{}", err),
+            &LPort(ref port)    => write!(fmt, "{}", port),
+            &LRecord(ref rec)   => write!(fmt, "{}", rec),
+            &LBytes(ref bytes)  => {
+                try!(write!(fmt, "#<bytes:"));
+                for (i, b) in bytes.iter().enumerate() {
+                    if i > 0 {
+                        try!(write!(fmt, " "));
+                    }
+                    try!(write!(fmt, "{}", b));
+                }
+                write!(fmt, ">")
+            },
+            &LF64Vector(ref floats) => {
+                try!(write!(fmt, "#<f64vector:"));
+                for (i, f) in floats.iter().enumerate() {
+                    if i > 0 {
+                        try!(write!(fmt, " "));
+                    }
+                    try!(write_float(*f, fmt));
+                }
+                write!(fmt, ">")
+            },
+            &LEnvironment(_)    => write!(fmt, "#<environment>"),
                                 /*
             &LParserFileStream(ref stream) => write!(fmt, "<parser-stream:{}>", stream.borrow().source_name()),
             &LParserFromString(ref stream) => write!(fmt, "<parser-stream:{}>", stream.borrow().source_name()),
@@ -192,10 +483,21 @@ impl Display for LispObj {
     }
 }
 
+/// The position an index refers to in a sequence of `len` elements, or None if it is
+/// out of range. Negative indices count back from the end, so -1 is the last element.
+pub fn resolve_index(ind: i64, len: usize) -> Option<usize> {
+    let resolved = if ind < 0 { ind + len as i64 } else { ind };
+    if resolved < 0 || resolved as u64 >= len as u64 {
+        None
+    } else {
+        Some(resolved as usize)
+    }
+}
+
 impl<A: AsLispObjRef> FromIterator<A> for LispObj {
     fn from_iter<I>(iter: I) -> Self
         where I: IntoIterator<Item=A> {
-        Self::to_lisp_list(iter.into_iter())
+        Self::from_vec(iter.into_iter().map(|item| item.to_obj_ref()).collect())
     }
 }
 
@@ -218,19 +520,73 @@ impl<'a> AsLispObjRef for &'a LispObjRef {
     }
 }
 
+/// The smallest and largest integers which are cached
+const SMALL_INT_MIN: i64 = -128;
+const SMALL_INT_MAX: i64 = 256;
+
+/// Shared objects for the most common values
+struct Cached {
+    nil: LispObjRef,
+    true_val: LispObjRef,
+    false_val: LispObjRef,
+    ints: Vec<LispObjRef>,
+}
+
+thread_local! {
+    static CACHED: Cached = Cached {
+        nil: Shared::new(LNil),
+        true_val: Shared::new(lisp_true!()),
+        false_val: Shared::new(lisp_false!()),
+        ints: (SMALL_INT_MIN..SMALL_INT_MAX + 1).map(|n| Shared::new(LInteger(n))).collect(),
+    };
+}
+
+/// The shared reference to `obj`, if it is nil, true, false or a small integer.
+fn cached_ref(obj: &LispObj) -> Option<LispObjRef> {
+    let is_cached = match *obj {
+        LNil => true,
+        LInteger(n) => SMALL_INT_MIN <= n && n <= SMALL_INT_MAX,
+        LSymbol(ref s) => s == "true" || s == "false",
+        _ => false,
+    };
+    if !is_cached {
+        return None
+    }
+
+    CACHED.try_with(|cached| match *obj {
+        LInteger(n) => cached.ints[(n - SMALL_INT_MIN) as usize].clone(),
+        LSymbol(ref s) if s == "true" => cached.true_val.clone(),
+        LSymbol(_) => cached.false_val.clone(),
+        _ => cached.nil.clone(),
+    }).ok()
+}
+
+/// Nil, true, false and small integers are shared rather than allocated each time
+/// they are converted.
 impl AsLispObjRef for LispObj {
     fn to_obj_ref(self) -> LispObjRef {
-        Rc::new(self)
+        cached_ref(&self).unwrap_or_else(|| Shared::new(self))
     }
 }
 
 impl<'a> AsLispObjRef for &'a LispObj {
     fn to_obj_ref(self) -> LispObjRef {
-        Rc::new(self.clone())
+        Shared::new(self.clone())
     }
 }
 
 impl LispObj {
+    /// The shared reference to `true` or `false`, like `lisp_bool!(val).to_obj_ref()`
+    /// but without building the symbol first.
+    pub fn bool_ref(val: bool) -> LispObjRef {
+        let cached = CACHED.try_with(|cached| if val {
+            cached.true_val.clone()
+        } else {
+            cached.false_val.clone()
+        });
+        cached.unwrap_or_else(|_| Shared::new(lisp_bool!(val)))
+    }
+
     /// Returns true if self is a 'falsey' value,
     ///
     /// Falsey values include the empty list, (), a 0-length
@@ -257,16 +613,47 @@ impl LispObj {
         }
     }
 
-    /// Converts an iterator of LispObjs into a properly-formed lisp list
-    pub fn to_lisp_list<O: AsLispObjRef, I: Iterator<Item=O>>(it: I) -> Self {
+    /// Returns true if self is false under strict booleans: only `false` and
+    /// nil are false.
+    ///
+    /// ```
+    /// # #[macro_use] extern crate rustylisp;
+    /// # fn main() {
+    /// assert!(nil!().strict_falsey());
+    /// assert!(symbol!("false").strict_falsey());
+    /// assert!(!int!(0).strict_falsey());
+    /// assert!(!string!("").strict_falsey());
+    /// # }
+    /// ```
+    pub fn strict_falsey(&self) -> bool {
+        match self {
+            &LNil => true,
+            &LSymbol(ref s)     => s == "false",
+            _ => false,
+        }
+    }
+
+    /// True if self is one of the symbols `true` or `false`
+    pub fn is_boolean(&self) -> bool {
+        self.symbol_equal("true") || self.symbol_equal("false")
+    }
+
+    /// Converts an iterator of LispObjs into a properly-formed lisp list. The list is
+    /// built from the end, so the iterator must be double-ended; other iterators can
+    /// be collected into a list instead.
+    pub fn to_lisp_list<O: AsLispObjRef, I: DoubleEndedIterator<Item=O>>(it: I) -> Self {
         let mut out = LispObj::LNil;
-        let objs: Vec<_> = it.collect();
-        for item in objs.into_iter().rev() {
+        for item in it.rev() {
             out = cons!(item.to_obj_ref(), out.to_obj_ref());
         }
         out
     }
 
+    /// Converts a Vec into a lisp list, consuming it.
+    pub fn from_vec(items: Vec<LispObjRef>) -> Self {
+        Self::to_lisp_list(items.into_iter())
+    }
+
     pub fn list_to_vec(&self) -> Option<Vec<LispObjRef>> {
         let (head, mut tmp) = match self {
             &LCons(ref a, ref b) => (a.to_obj_ref(), b.clone()),
@@ -303,7 +690,7 @@ impl LispObj {
     }
 
     pub fn make_string<S: Into<String>>(contents: S) -> Self {
-        LString(Rc::new(contents.into()))
+        LString(Shared::new(contents.into()))
     }
 
     /// Converts an iterator into a Lisp vector
@@ -350,8 +737,8 @@ impl LispObj {
     }
 
     pub fn make_native<S: Into<String>>(name: S, val: NativeFuncSignature, doc: Option<S>) -> Self {
-        LNativeFunc(name.into(), doc.map(|s| Rc::new(s.into())),
-                    NativeFunc(Rc::new(val)))
+        LNativeFunc(name.into(), doc.map(|s| Shared::new(s.into())),
+                    NativeFunc(Shared::new(val)))
     }
 
     pub fn make_proc(p: Procedure) -> Self {
@@ -362,6 +749,23 @@ impl LispObj {
         LError(Box::new(err))
     }
 
+    pub fn make_record(rec: Record) -> Self {
+        LRecord(Box::new(rec))
+    }
+
+    pub fn make_bytes(bytes: Vec<u8>) -> Self {
+        LBytes(Shared::new(bytes))
+    }
+
+    pub fn make_f64vector(floats: Vec<f64>) -> Self {
+        LF64Vector(Shared::new(floats))
+    }
+
+    /// Takes the object out of `obj`, only copying it if something else refers to it.
+    pub fn from_obj_ref(obj: LispObjRef) -> Self {
+        Shared::try_unwrap(obj).unwrap_or_else(|obj| (*obj).clone())
+    }
+
     /// Forms a cons-cell of two objects.
     ///
     /// Also see the `cons!(car, cdr)` macro
@@ -383,7 +787,7 @@ impl LispObj {
         }
     }
 
-    pub fn string_ref(&self) -> Option<Rc<String>> {
+    pub fn string_ref(&self) -> Option<Shared<String>> {
         match self {
             &LString(ref s) => Some(s.clone()),
             _ => None,
@@ -397,6 +801,41 @@ impl LispObj {
         }
     }
 
+    pub fn port_ref(&self) -> Option<&Port> {
+        match self {
+            &LPort(ref p) => Some(p),
+            _ => None
+        }
+    }
+
+    pub fn record_ref(&self) -> Option<&Record> {
+        match self {
+            &LRecord(ref r) => Some(r),
+            _ => None
+        }
+    }
+
+    pub fn bytes_ref(&self) -> Option<&Shared<Vec<u8>>> {
+        match self {
+            &LBytes(ref b) => Some(b),
+            _ => None
+        }
+    }
+
+    pub fn f64vector_ref(&self) -> Option<&Shared<Vec<f64>>> {
+        match self {
+            &LF64Vector(ref v) => Some(v),
+            _ => None
+        }
+    }
+
+    pub fn env_ref(&self) -> Option<&EnvironmentRef> {
+        match self {
+            &LEnvironment(ref env) => Some(env),
+            _ => None
+        }
+    }
+
     pub fn procedure_id(&self) -> Option<u32> {
         match self {
             &LProcedure(ref p) => Some(p.id),
@@ -414,9 +853,40 @@ impl LispObj {
     }
     */
 
-    pub fn unwrap_symbol(self) -> String {
+    /// A 'type-error for unwrapping the wrong kind of value.
+    fn unwrap_error<T>(&self, expected: &str) -> EvalResult<T> {
+        let msg = format!("expected {}, got {}", expected, self);
+        Err(RuntimeError::new(::evaluator::err_msgs::TYPE_ERROR, Some(LString(Shared::new(msg))), None, None)
+                .with_details(vec![self.clone().to_obj_ref()], Some(expected)))
+    }
+
+    /// Like unwrap_symbol, but throws a 'type-error on non-symbols.
+    pub fn try_unwrap_symbol(&self) -> EvalResult<String> {
         match self {
-            LSymbol(s) => s,
+            &LSymbol(ref s) => Ok(s.clone()),
+            val => val.unwrap_error("symbol"),
+        }
+    }
+
+    /// Like unwrap_native, but throws a 'type-error on non-native-funcs.
+    pub fn try_unwrap_native(&self) -> EvalResult<Shared<NativeFuncSignature>> {
+        match self {
+            &LNativeFunc(_,_,NativeFunc(ref f)) => Ok(f.clone()),
+            val => val.unwrap_error("native-procedure"),
+        }
+    }
+
+    /// Like unwrap_proc, but throws a 'type-error on non-procedures.
+    pub fn try_unwrap_proc(&self) -> EvalResult<&Procedure> {
+        match self {
+            &LProcedure(ref procd) => Ok(procd),
+            val => val.unwrap_error("procedure"),
+        }
+    }
+
+    pub fn unwrap_symbol(&self) -> String {
+        match self {
+            &LSymbol(ref s) => s.clone(),
             val => panic!("unwrap_symbol performed on non-symbol: {}", val),
         }
     }
@@ -428,7 +898,7 @@ impl LispObj {
         }
     }
 
-    pub fn unwrap_native(&self) -> Rc<NativeFuncSignature> {
+    pub fn unwrap_native(&self) -> Shared<NativeFuncSignature> {
         match self {
             &LNativeFunc(_,_,NativeFunc(ref f)) => f.clone(),
             val => panic!("unwrap_native performed on non-native-func {}", val),
@@ -471,7 +941,17 @@ impl LispObj {
         }
     }
 
-    pub fn list_index(&self, mut ind: u32) -> Option<LispObjRef> {
+    /// The element of a list at an index, counting back from the end if it is negative.
+    /// Returns None if the index is out of range of the list.
+    pub fn list_index(&self, ind: i64) -> Option<LispObjRef> {
+        let mut ind = if ind < 0 {
+            match self.list_length().and_then(|len| resolve_index(ind, len)) {
+                Some(ind) => ind as u64,
+                None => return None,
+            }
+        } else {
+            ind as u64
+        };
         let mut current = self.to_obj_ref();
 
         loop {
@@ -585,6 +1065,41 @@ impl LispObj {
         }
     }
 
+    pub fn is_port(&self) -> bool {
+        match self {
+            &LPort(_) => true,
+            _ => false
+        }
+    }
+
+    pub fn is_record(&self) -> bool {
+        match self {
+            &LRecord(_) => true,
+            _ => false
+        }
+    }
+
+    pub fn is_bytes(&self) -> bool {
+        match self {
+            &LBytes(_) => true,
+            _ => false
+        }
+    }
+
+    pub fn is_f64vector(&self) -> bool {
+        match self {
+            &LF64Vector(_) => true,
+            _ => false
+        }
+    }
+
+    pub fn is_env(&self) -> bool {
+        match self {
+            &LEnvironment(_) => true,
+            _ => false
+        }
+    }
+
     /*
     pub fn is_parser(&self) -> bool {
         match self {
@@ -0,0 +1,333 @@
+//! Ports: lisp handles on streams owned by the runtime, such as sockets, files,
+//! string builders, channels, threads and generators.
+//!
+//! A port is shared between all copies of the lisp object which refers to it,
+//! and closing it closes it for all of them.
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::mem;
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::str;
+use std::sync::{Condvar, Mutex};
+#[cfg(feature = "sync")]
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use super::LispObjRef;
+use super::super::EvalResult;
+use super::super::shared::{Shared, Lock};
+
+/// A queue of lisp values, which can be sent to and received from any thread.
+pub struct Channel {
+    queue: Mutex<VecDeque<LispObjRef>>,
+    ready: Condvar,
+}
+
+/// A computation which is suspended between the values it produces, such as a
+/// generator's producer. Dropping it stops the computation.
+pub trait Resumable: Send + Sync {
+    /// Runs until the next value, returning None once the computation has finished.
+    fn resume(&mut self) -> EvalResult<Option<LispObjRef>>;
+}
+
+/// The stream behind a port.
+pub enum PortKind {
+    /// A connected TCP socket, buffered for reading
+    TcpStream(BufReader<TcpStream>),
+
+    /// A socket listening for TCP connections
+    TcpListener(TcpListener),
+
+    /// A file opened for appending
+    File(File),
+
+    /// An in-memory buffer which strings are appended to
+    StringBuilder(String),
+
+    /// A channel, shared with any receivers waiting on it
+    Channel(Shared<Channel>),
+
+    /// A running thread, which can be joined once
+    #[cfg(feature = "sync")]
+    Thread(JoinHandle<EvalResult>),
+
+    /// A generator's producer, which is taken out of the port while it runs
+    Generator(Option<Box<Resumable>>),
+
+    /// A port which has been closed
+    Closed,
+}
+
+#[derive(Clone)]
+pub struct Port {
+    name: Shared<String>,
+    kind: Shared<Lock<PortKind>>,
+}
+
+// Must not borrow the port, as callers already hold its lock
+fn wrong_kind(port: &Port, kind: &PortKind, expected: &str) -> io::Error {
+    let msg = match *kind {
+        PortKind::Closed => format!("port {} is closed", port.name),
+        _ => format!("port {} is not {}", port.name, expected),
+    };
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+impl Port {
+    pub fn new<S: Into<String>>(name: S, kind: PortKind) -> Self {
+        Port { name: Shared::new(name.into()), kind: Shared::new(Lock::new(kind)) }
+    }
+
+    pub fn tcp_stream(stream: TcpStream) -> Self {
+        let name = match stream.peer_addr() {
+            Ok(addr) => format!("tcp-stream:{}", addr),
+            Err(_) => String::from("tcp-stream"),
+        };
+        Self::new(name, PortKind::TcpStream(BufReader::new(stream)))
+    }
+
+    pub fn tcp_listener(listener: TcpListener) -> Self {
+        let name = match listener.local_addr() {
+            Ok(addr) => format!("tcp-listener:{}", addr),
+            Err(_) => String::from("tcp-listener"),
+        };
+        Self::new(name, PortKind::TcpListener(listener))
+    }
+
+    /// Opens a file for appending, creating it if it doesn't exist.
+    pub fn append_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = try!(OpenOptions::new().append(true).create(true).open(path.as_ref()));
+        Ok(Self::new(format!("file:{}", path.as_ref().display()), PortKind::File(file)))
+    }
+
+    pub fn string_builder() -> Self {
+        Self::new("string-builder", PortKind::StringBuilder(String::new()))
+    }
+
+    pub fn channel() -> Self {
+        let chan = Channel { queue: Mutex::new(VecDeque::new()), ready: Condvar::new() };
+        Self::new("channel", PortKind::Channel(Shared::new(chan)))
+    }
+
+    #[cfg(feature = "sync")]
+    pub fn thread(handle: JoinHandle<EvalResult>) -> Self {
+        Self::new("thread", PortKind::Thread(handle))
+    }
+
+    pub fn generator(producer: Box<Resumable>) -> Self {
+        Self::new("generator", PortKind::Generator(Some(producer)))
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn is_closed(&self) -> bool {
+        match *self.kind.borrow() {
+            PortKind::Closed => true,
+            _ => false,
+        }
+    }
+
+    /// Closes the port, releasing its stream. Closing a closed port does nothing.
+    pub fn close(&self) {
+        // Released once the port is unlocked, as stopping a generator runs lisp code
+        let _released = mem::replace(&mut *self.kind.borrow_mut(), PortKind::Closed);
+    }
+
+    /// Reads a line, without its line terminator. Returns None at the end of the stream.
+    pub fn read_line(&self) -> io::Result<Option<String>> {
+        match *self.kind.borrow_mut() {
+            PortKind::TcpStream(ref mut reader) => {
+                let mut line = String::new();
+                if try!(reader.read_line(&mut line)) == 0 {
+                    return Ok(None)
+                }
+
+                if line.ends_with('\n') {
+                    line.pop();
+                    if line.ends_with('\r') {
+                        line.pop();
+                    }
+                }
+                Ok(Some(line))
+            },
+            ref kind => Err(wrong_kind(self, kind, "readable")),
+        }
+    }
+
+    /// Reads a single UTF-8 character. Returns None at the end of the stream.
+    pub fn read_char(&self) -> io::Result<Option<char>> {
+        match *self.kind.borrow_mut() {
+            PortKind::TcpStream(ref mut reader) => {
+                let mut buf = [0u8; 4];
+                if try!(reader.read(&mut buf[..1])) == 0 {
+                    return Ok(None)
+                }
+
+                let width = match buf[0] {
+                    b if b < 0x80 => 1,
+                    b if b >> 5 == 0x6 => 2,
+                    b if b >> 4 == 0xe => 3,
+                    b if b >> 3 == 0x1e => 4,
+                    _ => 0,
+                };
+                if width > 1 {
+                    try!(reader.read_exact(&mut buf[1..width]));
+                }
+                match str::from_utf8(&buf[..width]).ok().and_then(|s| s.chars().next()) {
+                    Some(c) => Ok(Some(c)),
+                    None => Err(io::Error::new(io::ErrorKind::InvalidData,
+                                               format!("port {} sent invalid UTF-8", self.name))),
+                }
+            },
+            ref kind => Err(wrong_kind(self, kind, "readable")),
+        }
+    }
+
+    /// Writes the whole of a string, and flushes it.
+    pub fn write_str(&self, s: &str) -> io::Result<()> {
+        match *self.kind.borrow_mut() {
+            PortKind::TcpStream(ref mut reader) => {
+                let stream = reader.get_mut();
+                try!(stream.write_all(s.as_bytes()));
+                stream.flush()
+            },
+            PortKind::File(ref mut file) => {
+                try!(file.write_all(s.as_bytes()));
+                file.flush()
+            },
+            PortKind::StringBuilder(ref mut buf) => {
+                buf.push_str(s);
+                Ok(())
+            },
+            ref kind => Err(wrong_kind(self, kind, "writable")),
+        }
+    }
+
+    /// Everything written to a string builder so far.
+    pub fn contents(&self) -> io::Result<String> {
+        match *self.kind.borrow() {
+            PortKind::StringBuilder(ref buf) => Ok(buf.clone()),
+            ref kind => Err(wrong_kind(self, kind, "a string builder")),
+        }
+    }
+
+    /// Adds a value to a channel, waking a receiver if one is waiting.
+    pub fn send(&self, value: LispObjRef) -> io::Result<()> {
+        match *self.kind.borrow() {
+            PortKind::Channel(ref chan) => {
+                chan.queue.lock().expect("channel poisoned").push_back(value);
+                chan.ready.notify_one();
+                Ok(())
+            },
+            ref kind => Err(wrong_kind(self, kind, "a channel")),
+        }
+    }
+
+    /// Takes the oldest value from a channel, waiting up to `timeout` for one to be sent.
+    /// Returns None if none was. The port is not locked while waiting, so it can be sent
+    /// to or closed meanwhile.
+    pub fn recv_timeout(&self, timeout: Duration) -> io::Result<Option<LispObjRef>> {
+        let chan = match *self.kind.borrow() {
+            PortKind::Channel(ref chan) => chan.clone(),
+            ref kind => return Err(wrong_kind(self, kind, "a channel")),
+        };
+
+        let mut queue = chan.queue.lock().expect("channel poisoned");
+        if queue.is_empty() {
+            queue = chan.ready.wait_timeout(queue, timeout).expect("channel poisoned").0;
+        }
+        Ok(queue.pop_front())
+    }
+
+    /// Waits for a thread to finish, returning its result. The port is closed afterwards.
+    #[cfg(feature = "sync")]
+    pub fn join(&self) -> io::Result<EvalResult> {
+        let handle = {
+            let mut kind = self.kind.borrow_mut();
+            match mem::replace(&mut *kind, PortKind::Closed) {
+                PortKind::Thread(handle) => handle,
+                other => {
+                    let err = wrong_kind(self, &other, "a thread");
+                    *kind = other;
+                    return Err(err)
+                },
+            }
+        };
+        handle.join().map_err(|_| io::Error::new(io::ErrorKind::Other, "thread panicked"))
+    }
+
+    /// Resumes a generator for its next value. Once it has finished, returns None or its
+    /// error, and closes the port. The port is not locked while the generator runs, so
+    /// resuming it again from inside it is an error.
+    pub fn resume(&self) -> io::Result<EvalResult<Option<LispObjRef>>> {
+        let mut producer = match *self.kind.borrow_mut() {
+            PortKind::Generator(ref mut producer) => match producer.take() {
+                Some(producer) => producer,
+                None => return Err(io::Error::new(io::ErrorKind::Other, format!("port {} is already running", self.name))),
+            },
+            ref kind => return Err(wrong_kind(self, kind, "a generator")),
+        };
+        let res = producer.resume();
+
+        let mut kind = self.kind.borrow_mut();
+        let running = match (&*kind, &res) {
+            (&PortKind::Generator(_), &Ok(Some(_))) => true,
+            _ => false,
+        };
+        if running {
+            *kind = PortKind::Generator(Some(producer));
+        } else {
+            *kind = PortKind::Closed;
+            drop(kind);
+            drop(producer);
+        }
+        Ok(res)
+    }
+
+    /// Waits for a connection on a listening port.
+    pub fn accept(&self) -> io::Result<Port> {
+        match *self.kind.borrow() {
+            PortKind::TcpListener(ref listener) => {
+                let (stream, _) = try!(listener.accept());
+                Ok(Port::tcp_stream(stream))
+            },
+            ref kind => Err(wrong_kind(self, kind, "listening")),
+        }
+    }
+
+    /// The local address of a socket port, as "ip:port".
+    pub fn local_addr(&self) -> io::Result<String> {
+        let addr = match *self.kind.borrow() {
+            PortKind::TcpStream(ref reader) => try!(reader.get_ref().local_addr()),
+            PortKind::TcpListener(ref listener) => try!(listener.local_addr()),
+            ref kind => return Err(wrong_kind(self, kind, "a socket")),
+        };
+        Ok(format!("{}", addr))
+    }
+
+    /// True if both ports refer to the same stream.
+    pub fn same_port(&self, other: &Port) -> bool {
+        Shared::ptr_eq(&self.kind, &other.kind)
+    }
+}
+
+impl fmt::Display for Port {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        if self.is_closed() {
+            write!(fmt, "#<closed-port:{}>", self.name)
+        } else {
+            write!(fmt, "#<port:{}>", self.name)
+        }
+    }
+}
+
+impl fmt::Debug for Port {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(fmt, "Port({:?})", self.name)
+    }
+}
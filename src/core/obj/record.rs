@@ -0,0 +1,50 @@
+//! Records: instances of user-defined types with named fields.
+use std::fmt;
+
+use super::LispObjRef;
+use super::super::shared::Shared;
+
+/// An instance of a record type. Records are immutable; updating a field
+/// makes a new record.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Record {
+    pub type_name: String,
+    pub fields: Shared<Vec<String>>,
+    pub values: Vec<LispObjRef>,
+}
+
+impl Record {
+    /// Creates a record. Panics if there is not one value per field.
+    pub fn new(type_name: String, fields: Shared<Vec<String>>, values: Vec<LispObjRef>) -> Self {
+        assert_eq!(fields.len(), values.len());
+        Record { type_name: type_name, fields: fields, values: values }
+    }
+
+    pub fn field_index(&self, field: &str) -> Option<usize> {
+        self.fields.iter().position(|f| f == field)
+    }
+
+    pub fn get(&self, field: &str) -> Option<LispObjRef> {
+        self.field_index(field).map(|i| self.values[i].clone())
+    }
+
+    /// Returns a copy of this record with one field changed, or None if it has
+    /// no such field.
+    pub fn with(&self, field: &str, value: LispObjRef) -> Option<Self> {
+        self.field_index(field).map(|i| {
+            let mut out = self.clone();
+            out.values[i] = value;
+            out
+        })
+    }
+}
+
+impl fmt::Display for Record {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        try!(write!(fmt, "#<{}", self.type_name));
+        for (field, value) in self.fields.iter().zip(self.values.iter()) {
+            try!(write!(fmt, " {}:{}", field, value));
+        }
+        write!(fmt, ">")
+    }
+}
@@ -1,5 +1,6 @@
 use std::iter::{IntoIterator, FromIterator};
-use std::rc::Rc;
+
+use super::super::shared::Shared;
 
 #[derive(Clone, Debug)]
 pub struct PersistentVec<T> {
@@ -8,7 +9,7 @@ pub struct PersistentVec<T> {
     root: TrieNodeRef<T>,
 }
 
-type TrieNodeRef<T> = Rc<PersistentTrieNode<T>>;
+type TrieNodeRef<T> = Shared<PersistentTrieNode<T>>;
 
 #[derive(Clone, Debug)]
 enum PersistentTrieNode<T> {
@@ -21,7 +22,7 @@ pub struct IntoIter<T> {
     size: usize,
     cur:  usize,
     cap:  usize,
-    root: Rc<PersistentTrieNode<T>>,
+    root: Shared<PersistentTrieNode<T>>,
 }
 
 pub struct Iter<'a, T: 'a> {
@@ -138,6 +139,24 @@ impl<T> PersistentVec<T> {
         self.size
     }
 
+    /// Drops the vector, moving the items which no other vector shares into `out`.
+    pub fn drain_unique(self, out: &mut Vec<T>) {
+        let mut nodes = vec![self.root];
+        while let Some(node) = nodes.pop() {
+            match Shared::try_unwrap(node) {
+                Ok(PersistentTrieNode::Node(l, r)) => {
+                    nodes.push(r);
+                    nodes.push(l);
+                },
+                Ok(PersistentTrieNode::Leaf(l, r)) => {
+                    out.extend(l);
+                    out.extend(r);
+                },
+                Err(_) => (),
+            }
+        }
+    }
+
     pub fn is_empty(&self) -> bool {
         self.size == 0
     }
@@ -246,7 +265,7 @@ impl<T> PersistentTrieNode<T> {
     }
 
     fn to_ref(self) -> TrieNodeRef<T> {
-        Rc::new(self)
+        Shared::new(self)
     }
 
     fn lookup(&self, index: usize, cap: usize) -> Option<&T> {
@@ -1,10 +1,35 @@
 use std::fmt;
 pub use super::{LispObjRef, EnvironmentRef};
+use super::{AsLispObjRef, LispObj};
+use super::shared::Shared;
 
+/// Symbols starting with this prefix are keywords, e.g. `#:size`, which
+/// evaluate to themselves.
+pub static KEYWORD_PREFIX: &'static str = "#:";
+
+/// Marks the start of the keyword parameters in an argument list.
+pub static KEY_MARKER: &'static str = "#:key";
+
+/// Introduces a guard, in case-lambda and match clauses.
+pub static GUARD_MARKER: &'static str = "#:when";
+
+/// Describes the arguments a procedure clause accepts:
+///
+/// `(a b (c 1) #:key (d 2) e . rest)` has required parameters `a` and `b`, an
+/// optional parameter `c` which defaults to 1, keyword parameters `d` and `e`
+/// (passed as `#:d 5`), and a rest parameter.
 #[derive(Clone, Debug)]
 pub struct ArityObj { 
     pub argnames: Vec<String>,
     pub rest: Option<String>,
+    /// Optional positional parameters with their default expressions
+    pub optional: Vec<(String, LispObjRef)>,
+    /// Keyword parameters with their default expressions
+    pub keys: Vec<(String, LispObjRef)>,
+    /// Required parameters given as destructuring patterns, by their placeholder names in `argnames`
+    pub patterns: Vec<(String, LispObjRef)>,
+    /// A case-lambda clause's guard, evaluated with the arguments bound
+    pub guard: Option<LispObjRef>,
 }
 
 #[derive(Clone)]
@@ -22,22 +47,80 @@ impl fmt::Display for ArityObj {
         try!(write!(fmt, "("));
 
         for argname in self.argnames.iter() {
-            try!(write!(fmt, "{} ", argname));
+            match self.patterns.iter().find(|&&(ref name, _)| name == argname) {
+                Some(&(_, ref pattern)) => try!(write!(fmt, "{} ", pattern)),
+                None => try!(write!(fmt, "{} ", argname)),
+            }
+        }
+
+        for &(ref name, ref default) in self.optional.iter() {
+            try!(write!(fmt, "({} {}) ", name, default));
+        }
+
+        if !self.keys.is_empty() {
+            try!(write!(fmt, "{} ", KEY_MARKER));
+            for &(ref name, ref default) in self.keys.iter() {
+                try!(write!(fmt, "({} {}) ", name, default));
+            }
         }
 
         match &self.rest {
             &Some(ref name) => try!(write!(fmt, ". {}", name)),
             &None => {},
         }
+        try!(write!(fmt, ")"));
 
-        write!(fmt, ")>")
+        match &self.guard {
+            &Some(ref guard) => write!(fmt, " {} {}>", GUARD_MARKER, guard),
+            &None => write!(fmt, ">"),
+        }
     }
 }
 
 impl ArityObj {
     pub fn new(names: Vec<String>, rest: Option<String>) -> Self {
         ArityObj {
-            argnames: names, rest: rest
+            argnames: names, rest: rest,
+            optional: vec![], keys: vec![], patterns: vec![], guard: None,
+        }
+    }
+
+    pub fn with_defaults(self, optional: Vec<(String, LispObjRef)>, keys: Vec<(String, LispObjRef)>) -> Self {
+        ArityObj { optional: optional, keys: keys, ..self }
+    }
+
+    pub fn with_patterns(self, patterns: Vec<(String, LispObjRef)>) -> Self {
+        ArityObj { patterns: patterns, ..self }
+    }
+
+    pub fn with_guard(self, guard: LispObjRef) -> Self {
+        ArityObj { guard: Some(guard), ..self }
+    }
+
+    /// The argument list this arity was parsed from, e.g. `(a b (c 1) #:key (d 2) . rest)`.
+    /// Parameters without a default are given nil as theirs.
+    pub fn arglist(&self) -> LispObjRef {
+        let mut params: Vec<LispObjRef> = self.argnames.iter().map(|argname| {
+            match self.patterns.iter().find(|&&(ref name, _)| name == argname) {
+                Some(&(_, ref pattern)) => pattern.clone(),
+                None => symbol!(argname.clone()).to_obj_ref(),
+            }
+        }).collect();
+
+        let with_default = |&(ref name, ref default): &(String, LispObjRef)| {
+            lisp_list!(symbol!(name.clone()), default.clone()).to_obj_ref()
+        };
+        params.extend(self.optional.iter().map(&with_default));
+        if !self.keys.is_empty() {
+            params.push(symbol!(KEY_MARKER).to_obj_ref());
+            params.extend(self.keys.iter().map(&with_default));
+        }
+
+        match self.rest {
+            Some(ref name) => params.into_iter().rev().fold(symbol!(name.clone()).to_obj_ref(), |tail, param| {
+                cons!(param, tail).to_obj_ref()
+            }),
+            None => LispObj::from_vec(params).to_obj_ref(),
         }
     }
 }
@@ -55,6 +138,15 @@ impl fmt::Debug for Procedure {
     }
 }
 
+/// Procedures are equal if they are copies of the same procedure, e.g. the same
+/// lambda given a name by define. Ids are only unique within one top level, so
+/// the closures must match too.
+impl PartialEq for Procedure {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id && Shared::ptr_eq(&self.env, &other.env)
+    }
+}
+
 impl Procedure {
     /// Creates a new procedure object
     pub fn new(env: EnvironmentRef, name: Option<String>,
@@ -76,10 +168,49 @@ impl Procedure {
         Self::new(env, None, None, body)
     }
 
+    /// The procedure's source, as `(arglist . body)`. A procedure with several clauses,
+    /// or a guard, gives a list of its clauses as they are written in case-lambda.
+    pub fn source(&self) -> LispObjRef {
+        let clauses: Vec<LispObjRef> = self.body.iter().map(|&(ref arity, ref body)| {
+            let mut out = LispObj::from_vec(body.clone()).to_obj_ref();
+            if let Some(ref guard) = arity.guard {
+                out = cons!(symbol!(GUARD_MARKER), cons!(guard.clone(), out)).to_obj_ref();
+            }
+            cons!(arity.arglist(), out).to_obj_ref()
+        }).collect();
+
+        if clauses.len() == 1 && self.body[0].0.guard.is_none() {
+            clauses[0].clone()
+        } else {
+            LispObj::from_vec(clauses).to_obj_ref()
+        }
+    }
+
+    /// How the procedure is called, with a line for each clause, e.g. `(add a (b 1))`.
+    pub fn signature(&self) -> String {
+        let name = symbol!(self.display_name());
+        let clauses: Vec<String> = self.body.iter().map(|&(ref arity, _)| {
+            let call = cons!(name.clone(), arity.arglist());
+            match arity.guard {
+                Some(ref guard) => format!("{} {} {}", call, GUARD_MARKER, guard),
+                None => format!("{}", call),
+            }
+        }).collect();
+        clauses.join("\n")
+    }
+
     pub fn set_doc(&mut self, doc: String) {
         self.documentation = Some(doc);
     }
 
+    /// The procedure's name, or a placeholder using its id if it is anonymous.
+    pub fn display_name(&self) -> String {
+        match self.name {
+            Some(ref name) => name.clone(),
+            None => format!("#<anonymous-procedure:{}>", self.id),
+        }
+    }
+
     pub fn set_name(&mut self, name: String) {
         self.name = Some(name);
     }
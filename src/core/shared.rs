@@ -0,0 +1,65 @@
+//! Shared-ownership primitives used by the object model.
+//!
+//! By default lisp objects and environments are reference-counted with `Rc` and
+//! mutated through a `RefCell`, which is cheap but ties every value to the thread
+//! that created it.
+//!
+//! Building with the `sync` cargo feature swaps these for `Arc` and an `RwLock`,
+//! making `LispObj`, `LispObjRef` and `EnvironmentRef` `Send + Sync` so that they
+//! can be handed to other threads by embedders. Both configurations expose the same
+//! interface (`Shared::new`, `Lock::new`, `borrow`, `borrow_mut`, `into_inner`), so
+//! code elsewhere in the crate never needs to know which one is active.
+
+#[cfg(not(feature = "sync"))]
+mod imp {
+    use std::cell::RefCell;
+    use std::rc::{Rc, Weak};
+
+    /// Reference-counted pointer: `Rc` without the `sync` feature
+    pub type Shared<T> = Rc<T>;
+
+    /// Non-owning pointer to a `Shared` value
+    pub type WeakShared<T> = Weak<T>;
+
+    /// Interior mutability wrapper: `RefCell` without the `sync` feature
+    pub type Lock<T> = RefCell<T>;
+}
+
+#[cfg(feature = "sync")]
+mod imp {
+    use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard, Weak};
+
+    /// Reference-counted pointer: `Arc` with the `sync` feature
+    pub type Shared<T> = Arc<T>;
+
+    /// Non-owning pointer to a `Shared` value
+    pub type WeakShared<T> = Weak<T>;
+
+    /// Interior mutability wrapper: an `RwLock` with a `RefCell`-like interface
+    /// with the `sync` feature.
+    ///
+    /// A thread which panics while holding the lock, such as in a native function,
+    /// poisons it. Its value is still used afterwards, as a `RefCell`'s would be.
+    #[derive(Debug)]
+    pub struct Lock<T>(RwLock<T>);
+
+    impl<T> Lock<T> {
+        pub fn new(val: T) -> Self {
+            Lock(RwLock::new(val))
+        }
+
+        pub fn borrow(&self) -> RwLockReadGuard<T> {
+            self.0.read().unwrap_or_else(|e| e.into_inner())
+        }
+
+        pub fn borrow_mut(&self) -> RwLockWriteGuard<T> {
+            self.0.write().unwrap_or_else(|e| e.into_inner())
+        }
+
+        pub fn into_inner(self) -> T {
+            self.0.into_inner().unwrap_or_else(|e| e.into_inner())
+        }
+    }
+}
+
+pub use self::imp::{Shared, Lock, WeakShared};
@@ -0,0 +1,143 @@
+//! Builtins for byte vectors, for handling binary data.
+//!
+//! Bytes are integers from 0 to 255; anything else throws an 'argument-error.
+//! Byte vectors are immutable, so `bytes-set` returns an updated copy.
+use ::core::{LispObj, LispObjRef, AsLispObjRef, EnvironmentRef, EvalResult};
+use super::check_index;
+
+fn check_byte(fname: &str, n: i64) -> EvalResult<u8> {
+    if n < 0 || n > 255 {
+        argument_error!("{}: {} is not a byte", fname, n)
+    }
+    Ok(n as u8)
+}
+
+pub const MAKE_BYTES_DOCSTR: &'static str = "Creates a byte vector of a given length, filled with an optional byte.
+
+The fill byte defaults to 0.
+
+Examples:
+
+(make-bytes 3 255)
+=> #<bytes:255 255 255>";
+pub fn make_bytes(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => len: LInteger, opt fill: LInteger);
+    if len < 0 {
+        argument_error!("make-bytes: negative length {}", len)
+    }
+    let fill = try!(check_byte("make-bytes", fill.unwrap_or(0)));
+    Ok(LispObj::make_bytes(vec![fill; len as usize]).to_obj_ref())
+}
+
+pub const IS_BYTES_DOCSTR: &'static str = "Tests if an object is a byte vector.
+
+Examples:
+
+(bytes? (make-bytes 1))
+=> true";
+pub fn is_bytes(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => arg: Any);
+    Ok(lisp_bool_ref!(arg.is_bytes()))
+}
+
+pub const BYTES_LENGTH_DOCSTR: &'static str = "Returns the number of bytes in a byte vector.
+
+Examples:
+
+(bytes-length (make-bytes 3))
+=> 3";
+pub fn bytes_length(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => bytes: LBytes);
+    Ok(int!(bytes.len() as i64).to_obj_ref())
+}
+
+pub const BYTES_REF_DOCSTR: &'static str = "Returns the byte at an index of a byte vector.
+
+Negative indices count back from the end. Throws a 'bounds-error if the index is
+out of range.
+
+Examples:
+
+(bytes-ref (string->bytes \"AB\") 0)
+=> 65
+(bytes-ref (string->bytes \"AB\") -1)
+=> 66";
+pub fn bytes_ref(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => bytes: LBytes, ind: LInteger);
+    let ind = try!(check_index("bytes-ref", ind, bytes.len()));
+    Ok(int!(bytes[ind] as i64).to_obj_ref())
+}
+
+pub const BYTES_SET_DOCSTR: &'static str = "Returns a copy of a byte vector with the byte at an index changed.
+
+The original byte vector is unchanged.
+
+Examples:
+
+(bytes-set (make-bytes 2) 1 7)
+=> #<bytes:0 7>";
+pub fn bytes_set(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => bytes: LBytes, ind: LInteger, val: LInteger);
+    let ind = try!(check_index("bytes-set", ind, bytes.len()));
+    let val = try!(check_byte("bytes-set", val));
+
+    let mut out = (*bytes).clone();
+    out[ind] = val;
+    Ok(LispObj::make_bytes(out).to_obj_ref())
+}
+
+pub const BYTES_TO_LIST_DOCSTR: &'static str = "Converts a byte vector to a list of integers.
+
+Examples:
+
+(bytes->list (string->bytes \"hi\"))
+=> (104 105)";
+pub fn bytes_to_list(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => bytes: LBytes);
+    Ok(LispObj::to_lisp_list(bytes.iter().map(|b| int!(*b as i64))).to_obj_ref())
+}
+
+pub const LIST_TO_BYTES_DOCSTR: &'static str = "Converts a list of integers from 0 to 255 to a byte vector.
+
+Examples:
+
+(list->bytes '(104 105))
+=> #<bytes:104 105>";
+pub fn list_to_bytes(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => list: Any);
+    let items = flatten_list!(list, "list->bytes: expected a list");
+
+    let mut out = Vec::with_capacity(items.len());
+    for item in items {
+        let n = check_type!(item, LInteger);
+        out.push(try!(check_byte("list->bytes", n)));
+    }
+    Ok(LispObj::make_bytes(out).to_obj_ref())
+}
+
+pub const BYTES_TO_STRING_DOCSTR: &'static str = "Decodes a byte vector as a UTF-8 string.
+
+Throws an 'argument-error if the bytes are not valid UTF-8.
+
+Examples:
+
+(bytes->string (list->bytes '(104 105)))
+=> \"hi\"";
+pub fn bytes_to_string(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => bytes: LBytes);
+    match String::from_utf8((*bytes).clone()) {
+        Ok(s) => Ok(string!(s).to_obj_ref()),
+        Err(e) => argument_error!("bytes->string: invalid UTF-8: {}", e),
+    }
+}
+
+pub const STRING_TO_BYTES_DOCSTR: &'static str = "Encodes a string as UTF-8 bytes.
+
+Examples:
+
+(string->bytes \"hi\")
+=> #<bytes:104 105>";
+pub fn string_to_bytes(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => s: LString);
+    Ok(LispObj::make_bytes(s.as_bytes().to_vec()).to_obj_ref())
+}
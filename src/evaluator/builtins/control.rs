@@ -0,0 +1,358 @@
+//! Escape continuations, for early exits from nested calls, and generators.
+//!
+//! `call-with-escape-continuation` calls a procedure with a continuation `k`. Calling
+//! `(k value)` unwinds back to the call, which then returns `value`. The unwinding is
+//! an 'escape-request error, which `catch-error` does not catch. A continuation can
+//! only be used while its call is running; afterwards, calling it throws an
+//! 'argument-error.
+//!
+//! Generators run their producer in a thread of its own, which takes turns with the
+//! thread calling `next`: `next` waits while the producer runs until its next `yield`,
+//! and `yield` waits until the following `next`. As only one of them runs at a time, the
+//! producer counts against the limits of the `next` it runs for, writes to its output,
+//! and can escape to its continuations. A generator which is dropped while its producer
+//! waits in `yield` unwinds the producer. Generators can't be saved in images.
+//!
+//! As the producer shares lisp values with the thread calling `next`, generators are
+//! only available with the `sync` feature, like `spawn`; otherwise `make-generator`
+//! throws an 'environment-error.
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use ::core::{Environment, LispObj, LispObjRef, AsLispObjRef, RuntimeError, EnvironmentRef, EvalResult};
+use ::core::procedure::{ArityObj, Procedure};
+
+/// Shared by all threads, as generators' producers make continuations too
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    /// The continuations which can currently be escaped to
+    static ACTIVE: RefCell<Vec<i64>> = RefCell::new(vec![]);
+}
+
+/// The body of an escape continuation, which calls this with its id and value.
+pub fn escape(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => id: LInteger, value: Any);
+    if !ACTIVE.with(|a| a.borrow().contains(&id)) {
+        argument_error!("escape continuation called after its call-with-escape-continuation returned")
+    }
+    Err(RuntimeError::escape(id, value))
+}
+
+fn next_id() -> i64 {
+    NEXT_ID.fetch_add(1, Ordering::SeqCst) as i64
+}
+
+/// Makes the continuation `(lambda ((value nil)) (escape id value))`, with escape and
+/// id bound in its closure.
+fn make_continuation(id: i64, env: EnvironmentRef, name: &str, escape: LispObjRef) -> LispObjRef {
+    let frame = Environment::from_parent(env).to_env_ref();
+    let _ = frame.borrow_mut().let_new(String::from("escape"), escape);
+    let _ = frame.borrow_mut().let_new(String::from("escape-id"), int!(id).to_obj_ref());
+
+    let arity = ArityObj::new(vec![], None).with_defaults(vec![(String::from("value"), nil!().to_obj_ref())], vec![]);
+    let body = vec![lisp_list![symbol!("escape"), symbol!("escape-id"), symbol!("value")].to_obj_ref()];
+    let procd = Procedure::single_arity(frame, arity, body).with_name(name);
+    LispObj::make_proc(procd).to_obj_ref()
+}
+
+/// Applies `func` to `k` with the continuation `id` active. Returns Ok with the value
+/// it escaped with, or Err with the value `func` returned normally.
+fn call_escapable(id: i64, func: LispObjRef, k: LispObjRef, env: EnvironmentRef) -> EvalResult<Result<LispObjRef, LispObjRef>> {
+    ACTIVE.with(|a| a.borrow_mut().push(id));
+    let res = super::super::apply(func, lisp_list![k], env);
+    ACTIVE.with(|a| a.borrow_mut().retain(|&active| active != id));
+
+    match res {
+        Ok(val) => Ok(Err(val)),
+        Err(err) => match err.escape_value(id) {
+            Some(val) => Ok(Ok(val)),
+            None => Err(err),
+        },
+    }
+}
+
+pub const CALL_WITH_ESCAPE_CONTINUATION_DOCSTR: &'static str = "Calls a procedure with an escape continuation.
+
+Calling the continuation with a value, while the procedure is running, makes
+call-with-escape-continuation return that value immediately. Otherwise, it
+returns what the procedure returns. Also called call/ec.
+
+Examples:
+
+(call/ec (lambda (k) (+ 1 (k 42))))
+=> 42
+(call/ec (lambda (k) 1))
+=> 1";
+pub fn call_with_escape_continuation(args: &[LispObjRef], env: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => func: LProc);
+
+    let id = next_id();
+    let escape = LispObj::make_native("escape", escape, None).to_obj_ref();
+    let k = make_continuation(id, env.clone(), "escape-continuation", escape);
+    match try!(call_escapable(id, func, k, env)) {
+        Ok(escaped) => Ok(escaped),
+        Err(returned) => Ok(returned),
+    }
+}
+
+#[cfg(feature = "sync")]
+mod generator {
+    use std::cell::RefCell;
+    use std::sync::Mutex;
+    use std::sync::mpsc::{self, Receiver, Sender};
+    use std::thread::{self, JoinHandle};
+
+    use ::core::{LispObj, LispObjRef, AsLispObjRef, RuntimeError, EnvironmentRef, EvalResult};
+    use ::core::obj::{Port, Record};
+    use ::core::obj::port::Resumable;
+    use ::core::shared::Shared;
+    use super::super::super::{limits, output};
+    use super::super::super::output::Stream;
+    use super::super::super::flags::Flags;
+    use super::{ACTIVE, next_id, make_continuation};
+
+    thread_local! {
+        /// The generator whose producer runs on this thread, if any
+        static PRODUCER: RefCell<Option<ProducerEnd>> = RefCell::new(None);
+    }
+
+    /// The state of the thread calling `next`, which the producer continues with.
+    struct Context {
+        limits: limits::Saved,
+        escapes: Vec<i64>,
+        output: (Option<Stream>, Option<Stream>),
+        flags: Flags,
+    }
+
+    impl Context {
+        fn current() -> Self {
+            Context {
+                limits: limits::save(),
+                escapes: ACTIVE.with(|a| a.borrow().clone()),
+                output: output::current(),
+                flags: Flags::current(),
+            }
+        }
+
+        /// Continues with this state on the producer's thread, replacing the continuations
+        /// of the previous `next`, `outer`, with these ones.
+        fn enter(self, outer: &mut Vec<i64>) {
+            let Context { limits: saved, escapes, output: streams, flags } = self;
+            limits::restore(saved);
+            output::set_current(streams);
+            flags.set_current();
+            ACTIVE.with(|a| {
+                let mut active = a.borrow_mut();
+                active.retain(|id| !outer.contains(id));
+                active.extend(escapes.iter().cloned());
+            });
+            *outer = escapes;
+        }
+    }
+
+    /// What a producer hands back to `next`, along with the limits it has used up.
+    enum Yielded {
+        Value(LispObjRef),
+        Returned(EvalResult),
+    }
+
+    type Values = (Yielded, limits::Saved);
+
+    /// The producer's end of a generator, kept by the producer's thread.
+    struct ProducerEnd {
+        id: i64,
+        resumes: Receiver<Context>,
+        values: Sender<Values>,
+        /// The continuations of the `next` which the producer is running for
+        outer_escapes: Vec<i64>,
+    }
+
+    /// The end of a generator which `next` resumes. Its producer's thread is started by
+    /// the first `next`.
+    struct Ends {
+        start: Option<(i64, LispObjRef, LispObjRef, EnvironmentRef, Receiver<Context>, Sender<Values>)>,
+        resumes: Option<Sender<Context>>,
+        values: Receiver<Values>,
+        thread: Option<JoinHandle<()>>,
+    }
+
+    /// Resuming a generator needs its port's lock, so it is only used through `&mut`;
+    /// the mutex lets ports, which are shared between threads, hold its receivers.
+    struct Generator(Mutex<Ends>);
+
+    impl Generator {
+        fn ends(&mut self) -> &mut Ends {
+            self.0.get_mut().unwrap_or_else(|e| e.into_inner())
+        }
+    }
+
+    /// Runs a generator's producer, once the first `next` hands over to it.
+    fn run_producer(id: i64, producer: LispObjRef, yield_k: LispObjRef, env: EnvironmentRef,
+                    resumes: Receiver<Context>, values: Sender<Values>) {
+        let mut outer_escapes = vec![];
+        match resumes.recv() {
+            Ok(context) => context.enter(&mut outer_escapes),
+            Err(_) => return,
+        }
+        PRODUCER.with(|p| *p.borrow_mut() = Some(ProducerEnd {
+            id: id, resumes: resumes, values: values, outer_escapes: outer_escapes,
+        }));
+
+        let res = super::super::super::apply(producer, lisp_list![yield_k], env);
+        if let Some(end) = PRODUCER.with(|p| p.borrow_mut().take()) {
+            let _ = end.values.send((Yielded::Returned(res), limits::save()));
+        }
+    }
+
+    impl Resumable for Generator {
+        fn resume(&mut self) -> EvalResult<Option<LispObjRef>> {
+            let ends = self.ends();
+            if let Some((id, producer, yield_k, env, resumes, values)) = ends.start.take() {
+                let thread = try!(thread::Builder::new().name(String::from("generator")).spawn(move || {
+                    run_producer(id, producer, yield_k, env, resumes, values)
+                }));
+                ends.thread = Some(thread);
+            }
+
+            let sent = match ends.resumes {
+                Some(ref resumes) => resumes.send(Context::current()).is_ok(),
+                None => false,
+            };
+            match ends.values.recv() {
+                Ok((yielded, used)) if sent => {
+                    limits::restore(used);
+                    match yielded {
+                        Yielded::Value(value) => Ok(Some(value)),
+                        Yielded::Returned(res) => res.map(|_| None),
+                    }
+                },
+                _ => internal_error!("generator's producer stopped without returning"),
+            }
+        }
+    }
+
+    /// Stops the producer, if it is waiting in yield, and waits for it to unwind.
+    impl Drop for Generator {
+        fn drop(&mut self) {
+            let ends = self.ends();
+            ends.resumes = None;
+            if let Some(thread) = ends.thread.take() {
+                let _ = thread.join();
+            }
+        }
+    }
+
+    /// The body of a generator's yield, which hands a value to the `next` the producer is
+    /// running for, then waits for the following `next`.
+    pub fn generator_yield(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+        unpack_args!(args => id: LInteger, value: Any);
+        PRODUCER.with(|producer| {
+            let mut producer = producer.borrow_mut();
+            let end = match *producer {
+                Some(ref mut end) if end.id == id => end,
+                _ => argument_error!("yield called outside of its generator's next"),
+            };
+
+            let _ = end.values.send((Yielded::Value(value), limits::save()));
+            match end.resumes.recv() {
+                Ok(context) => {
+                    context.enter(&mut end.outer_escapes);
+                    Ok(nil!().to_obj_ref())
+                },
+                // The generator was dropped. Escapes aren't caught by catch-error, so this
+                // unwinds the whole producer.
+                Err(_) => Err(RuntimeError::escape(id, nil!().to_obj_ref())),
+            }
+        })
+    }
+
+    pub fn make_generator(args: &[LispObjRef], env: EnvironmentRef) -> EvalResult {
+        unpack_args!(args => producer: LProc);
+
+        let id = next_id();
+        let escape = LispObj::make_native("yield", generator_yield, None).to_obj_ref();
+        let yield_k = make_continuation(id, env.clone(), "yield", escape);
+        let (resumes_in, resumes_out) = mpsc::channel();
+        let (values_in, values_out) = mpsc::channel();
+        let gen = Generator(Mutex::new(Ends {
+            start: Some((id, producer, yield_k, env, resumes_out, values_in)),
+            resumes: Some(resumes_in),
+            values: values_out,
+            thread: None,
+        }));
+
+        let rec = Record::new(String::from("generator"), Shared::new(vec![String::from("producer")]),
+                              vec![LispObj::LPort(Port::generator(Box::new(gen))).to_obj_ref()]);
+        Ok(LispObj::make_record(rec).to_obj_ref())
+    }
+
+    pub fn next(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+        unpack_args!(args => gen: Any, opt default: Any);
+        let default = default.unwrap_or_else(|| nil!().to_obj_ref());
+
+        let producer = match gen.record_ref() {
+            Some(rec) if rec.type_name == "generator" => rec.values[0].clone(),
+            _ => type_error!(args vec![gen.clone()], expected "generator"; "next: expected a generator, not {}", gen),
+        };
+        let port = match producer.port_ref() {
+            Some(port) => port,
+            None => type_error!(args vec![gen.clone()], expected "generator"; "next: expected a generator, not {}", gen),
+        };
+        if port.is_closed() {
+            return Ok(default)
+        }
+        match try!(try!(port.resume())) {
+            Some(value) => Ok(value),
+            None => Ok(default),
+        }
+    }
+}
+
+#[cfg(feature = "sync")]
+pub use self::generator::generator_yield;
+
+#[cfg(not(feature = "sync"))]
+pub fn generator_yield(_: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    argument_error!("yield called outside of its generator's next")
+}
+
+pub const MAKE_GENERATOR_DOCSTR: &'static str = "Makes a generator from a procedure, which is passed a yield procedure.
+
+Each call to next runs the procedure until it calls yield with a value, and returns
+that value. The procedure is suspended in yield until the following call to next.
+Once the procedure returns, next returns its default. Only available when built with
+the sync feature.
+
+Examples:
+
+(define g (make-generator (lambda (yield) (yield 1) (yield 2))))
+(next g)
+=> 1
+(next g)
+=> 2
+(next g 'done)
+=> done";
+#[cfg(feature = "sync")]
+pub use self::generator::make_generator;
+
+#[cfg(not(feature = "sync"))]
+pub fn make_generator(_: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    environment_error!("make-generator: generators need rustylisp to be built with the sync feature")
+}
+
+pub const NEXT_DOCSTR: &'static str = "Returns the next value of a generator, or the default (nil if not given) once it is exhausted.
+
+Examples:
+
+(next (make-generator (lambda (yield) (yield 'a))))
+=> a
+(next (make-generator (lambda (yield) nil)) 'empty)
+=> empty";
+#[cfg(feature = "sync")]
+pub use self::generator::next;
+
+#[cfg(not(feature = "sync"))]
+pub fn next(_: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    environment_error!("next: generators need rustylisp to be built with the sync feature")
+}
@@ -0,0 +1,164 @@
+//! Builtins for f64vectors, vectors of floats for numeric work.
+//!
+//! The floats are stored unboxed, so arithmetic over a whole vector doesn't allocate a
+//! lisp value per element. Integers given as elements are converted to floats. Like
+//! byte vectors, f64vectors are immutable, and the arithmetic builtins return new ones.
+use ::core::{LispObj, LispObjRef, AsLispObjRef, EnvironmentRef, EvalResult};
+use ::core::convert::FromLisp;
+use super::check_index;
+
+/// The floats of a list or vector of numbers.
+fn floats_of(fname: &str, seq: &LispObjRef) -> EvalResult<Vec<f64>> {
+    let items: Vec<LispObjRef> = match seq.vec_ref() {
+        Some(vec) => vec.iter().cloned().collect(),
+        None => match seq.list_iter().collect() {
+            Ok(items) => items,
+            Err(()) => type_error!(args vec![seq.clone()], expected "list"; "{}: expected a list or vector, got {}", fname, seq),
+        },
+    };
+    items.iter().map(f64::from_lisp).collect()
+}
+
+/// Combines an f64vector elementwise with another of the same length, or with a number.
+fn elementwise(fname: &str, args: &[LispObjRef], op: fn(f64, f64) -> f64) -> EvalResult {
+    unpack_args!(args => floats: LF64Vector, other: Any);
+    let out = match other.f64vector_ref() {
+        Some(others) => {
+            if others.len() != floats.len() {
+                argument_error!("{}: lengths {} and {} differ", fname, floats.len(), others.len())
+            }
+            floats.iter().zip(others.iter()).map(|(a, b)| op(*a, *b)).collect()
+        },
+        None => {
+            let n = try!(f64::from_lisp(&other));
+            floats.iter().map(|a| op(*a, n)).collect()
+        },
+    };
+    Ok(LispObj::make_f64vector(out).to_obj_ref())
+}
+
+pub const MAKE_F64VECTOR_DOCSTR: &'static str = "Creates an f64vector of a given length, filled with an optional number.
+
+The fill defaults to 0.0.
+
+Examples:
+
+(make-f64vector 3 1.5)
+=> #<f64vector:1.5 1.5 1.5>";
+pub fn make_f64vector(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => len: LInteger, opt fill: Any);
+    if len < 0 {
+        argument_error!("make-f64vector: negative length {}", len)
+    }
+    let fill = match fill {
+        Some(fill) => try!(f64::from_lisp(&fill)),
+        None => 0.0,
+    };
+    Ok(LispObj::make_f64vector(vec![fill; len as usize]).to_obj_ref())
+}
+
+pub const IS_F64VECTOR_DOCSTR: &'static str = "Tests if an object is an f64vector.
+
+Examples:
+
+(f64vector? (make-f64vector 1))
+=> true
+(f64vector? [1.0])
+=> false";
+pub fn is_f64vector(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => arg: Any);
+    Ok(lisp_bool_ref!(arg.is_f64vector()))
+}
+
+pub const F64VECTOR_LENGTH_DOCSTR: &'static str = "Returns the number of elements of an f64vector.
+
+Examples:
+
+(f64vector-length (make-f64vector 3))
+=> 3";
+pub fn f64vector_length(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => floats: LF64Vector);
+    Ok(int!(floats.len()).to_obj_ref())
+}
+
+pub const F64VECTOR_REF_DOCSTR: &'static str = "Returns the float at an index of an f64vector.
+
+Negative indices count back from the end. Throws a 'bounds-error if the index is
+out of range.
+
+Examples:
+
+(f64vector-ref (list->f64vector '(1 2 3)) -1)
+=> 3.0";
+pub fn f64vector_ref(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => floats: LF64Vector, ind: LInteger);
+    let ind = try!(check_index("f64vector-ref", ind, floats.len()));
+    Ok(float!(floats[ind]).to_obj_ref())
+}
+
+pub const LIST_TO_F64VECTOR_DOCSTR: &'static str = "Converts a list or vector of numbers to an f64vector.
+
+Throws a 'type-error if an element is not a number.
+
+Examples:
+
+(list->f64vector '(1 2.5))
+=> #<f64vector:1.0 2.5>";
+pub fn list_to_f64vector(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => seq: Any);
+    Ok(LispObj::make_f64vector(try!(floats_of("list->f64vector", &seq))).to_obj_ref())
+}
+
+pub const F64VECTOR_TO_LIST_DOCSTR: &'static str = "Converts an f64vector to a list of floats.
+
+Examples:
+
+(f64vector->list (make-f64vector 2 1))
+=> (1.0 1.0)";
+pub fn f64vector_to_list(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => floats: LF64Vector);
+    Ok(LispObj::to_lisp_list(floats.iter().map(|f| float!(*f))).to_obj_ref())
+}
+
+pub const F64VECTOR_ADD_DOCSTR: &'static str = "Adds an f64vector to another of the same length, element by element, or adds
+a number to each of its elements.
+
+Throws an 'argument-error if the lengths differ.
+
+Examples:
+
+(f64vector-add (list->f64vector '(1 2)) (list->f64vector '(10 20)))
+=> #<f64vector:11.0 22.0>
+(f64vector-add (list->f64vector '(1 2)) 0.5)
+=> #<f64vector:1.5 2.5>";
+pub fn f64vector_add(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    elementwise("f64vector-add", args, |a, b| a + b)
+}
+
+pub const F64VECTOR_MUL_DOCSTR: &'static str = "Multiplies an f64vector by another of the same length, element by element,
+or multiplies each of its elements by a number.
+
+Throws an 'argument-error if the lengths differ.
+
+Examples:
+
+(f64vector-mul (list->f64vector '(1 2)) (list->f64vector '(10 20)))
+=> #<f64vector:10.0 40.0>
+(f64vector-mul (list->f64vector '(1 2)) 2)
+=> #<f64vector:2.0 4.0>";
+pub fn f64vector_mul(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    elementwise("f64vector-mul", args, |a, b| a * b)
+}
+
+pub const F64VECTOR_SUM_DOCSTR: &'static str = "Adds up the elements of an f64vector.
+
+Examples:
+
+(f64vector-sum (list->f64vector '(1 2 3.5)))
+=> 6.5
+(f64vector-sum (make-f64vector 0))
+=> 0.0";
+pub fn f64vector_sum(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => floats: LF64Vector);
+    Ok(float!(floats.iter().sum::<f64>()).to_obj_ref())
+}
@@ -0,0 +1,337 @@
+//! A minimal HTTP/1.1 client, written over std's TCP sockets so that it needs no
+//! dependencies. Only plain `http://` URLs are supported.
+//!
+//! Responses are returned as an association list:
+//!
+//! ```text
+//! ((status . 200)
+//!  (headers . (("content-type" . "text/plain") ...))
+//!  (body . "..."))
+//! ```
+//!
+//! Header names are lowercased. A body longer than `*http-max-body*` bytes throws an
+//! 'io-error rather than being read, whatever length the server gives.
+//!
+//! While waiting on the server, requests can be interrupted and count against the
+//! evaluation time limit, as `recv` does. A server which sends nothing for
+//! `IDLE_TIMEOUT_SECS` seconds gets an 'io-error.
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+use ::core::{LispObj, LispObjRef, AsLispObjRef, RuntimeError, EnvironmentRef, EvalResult};
+use super::super::{interrupt, limits};
+
+/// The name of the largest response body accepted, in bytes, or nil for no limit
+pub static HTTP_MAX_BODY_NAME: &'static str = "*http-max-body*";
+
+/// The default for `*http-max-body*`: 64 MiB
+pub const DEFAULT_MAX_BODY: i64 = 64 * 1024 * 1024;
+
+/// How long connecting, or waiting for the server to accept or send data, may take
+const IDLE_TIMEOUT_SECS: u64 = 30;
+
+/// How long socket reads and writes wait between checks for interrupts
+const POLL_MILLIS: u64 = 50;
+
+struct Url {
+    host: String,
+    port: u16,
+    path: String,
+}
+
+fn parse_url(url: &str) -> EvalResult<Url> {
+    let rest = if url.starts_with("http://") {
+        &url["http://".len()..]
+    } else if url.contains("://") {
+        argument_error!("only http:// URLs are supported, not {:?}", url)
+    } else {
+        url
+    };
+
+    let (authority, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+
+    let (host, port) = match authority.rfind(':') {
+        Some(i) => match authority[i+1..].parse::<u16>() {
+            Ok(port) => (&authority[..i], port),
+            Err(_) => argument_error!("invalid port in URL {:?}", url),
+        },
+        None => (authority, 80),
+    };
+
+    if host.is_empty() {
+        argument_error!("no host in URL {:?}", url)
+    }
+
+    Ok(Url { host: String::from(host), port: port, path: String::from(path) })
+}
+
+/// Converts a method, or a header name or value, given as a string or symbol, to a string.
+fn header_part(obj: &LispObjRef) -> EvalResult<String> {
+    let part = if let Some(s) = obj.string_ref() {
+        (*s).clone()
+    } else if let Some(s) = obj.symbol_ref() {
+        String::from(s)
+    } else {
+        type_error!(args vec![obj.clone()]; "expected string or symbol, not {}", obj)
+    };
+    try!(check_line(&part));
+    Ok(part)
+}
+
+/// Throws an 'argument-error if `text`, which is written into the request, would end
+/// its line early, and so add headers or split the request.
+fn check_line(text: &str) -> EvalResult<()> {
+    if text.contains(|c| c == '\r' || c == '\n') {
+        argument_error!("HTTP request lines cannot contain CR or LF: {:?}", text)
+    }
+    Ok(())
+}
+
+/// A connection to the server, whose reads and writes check every `POLL_MILLIS` for
+/// interrupts and the evaluation limits, and fail after `IDLE_TIMEOUT_SECS` without
+/// progress.
+struct Connection {
+    stream: TcpStream,
+    /// The error which stopped the last read or write early, if any
+    stopped: Option<RuntimeError>,
+}
+
+impl Connection {
+    fn open(host: &str, port: u16) -> EvalResult<Self> {
+        let timeout = Duration::from_secs(IDLE_TIMEOUT_SECS);
+        let mut last_err = None;
+        for addr in try!((host, port).to_socket_addrs()) {
+            match TcpStream::connect_timeout(&addr, timeout) {
+                Ok(stream) => {
+                    try!(stream.set_read_timeout(Some(Duration::from_millis(POLL_MILLIS))));
+                    try!(stream.set_write_timeout(Some(Duration::from_millis(POLL_MILLIS))));
+                    return Ok(Connection { stream: stream, stopped: None })
+                },
+                Err(err) => last_err = Some(err),
+            }
+        }
+        match last_err {
+            Some(err) => Err(err.into()),
+            None => io_error!("cannot resolve host {}", host),
+        }
+    }
+
+    /// Retries `op` each time it times out, until it makes progress or it is stopped.
+    fn poll<T, F>(&mut self, mut op: F) -> io::Result<T>
+            where F: FnMut(&mut TcpStream) -> io::Result<T> {
+        let start = Instant::now();
+        loop {
+            match op(&mut self.stream) {
+                Err(ref err) if err.kind() == ErrorKind::WouldBlock || err.kind() == ErrorKind::TimedOut => {},
+                res => return res,
+            }
+
+            let stop = if interrupt::take() {
+                Err(RuntimeError::interrupt())
+            } else if start.elapsed() >= Duration::from_secs(IDLE_TIMEOUT_SECS) {
+                Err(RuntimeError::from(io::Error::new(ErrorKind::TimedOut, "HTTP server stopped responding")))
+            } else {
+                limits::wait_step()
+            };
+            if let Err(err) = stop {
+                // Not ErrorKind::Interrupted, which read_exact and write_all retry
+                self.stopped = Some(err);
+                return Err(io::Error::new(ErrorKind::Other, "HTTP request stopped"))
+            }
+        }
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.poll(|stream| stream.read(buf))
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.poll(|stream| stream.write(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+fn read_line<R: BufRead>(reader: &mut R) -> EvalResult<String> {
+    let mut line = String::new();
+    if try!(reader.read_line(&mut line)) == 0 {
+        io_error!("connection closed before the end of the response")
+    }
+    Ok(String::from(line.trim_right_matches(|c| c == '\r' || c == '\n')))
+}
+
+/// The largest body accepted, from `*http-max-body*`.
+fn max_body(env: &EnvironmentRef) -> EvalResult<Option<usize>> {
+    let max = env.borrow().lookup(HTTP_MAX_BODY_NAME).unwrap_or_else(|| nil!().to_obj_ref());
+    if max.is_nil() {
+        return Ok(None)
+    }
+    match *max {
+        LispObj::LInteger(n) if n >= 0 => Ok(Some(n as usize)),
+        _ => argument_error!("{} must be a non-negative integer or nil, not {}", HTTP_MAX_BODY_NAME, max),
+    }
+}
+
+fn check_body_size(size: usize, max: Option<usize>) -> EvalResult<()> {
+    match max {
+        Some(max) if size > max => io_error!("response body is larger than {} bytes ({})", max, HTTP_MAX_BODY_NAME),
+        _ => Ok(()),
+    }
+}
+
+fn read_chunked<R: BufRead>(reader: &mut R, max: Option<usize>) -> EvalResult<Vec<u8>> {
+    let mut body = vec![];
+    loop {
+        let size_line = try!(read_line(reader));
+        let size_str = size_line.split(';').next().unwrap_or("").trim();
+        let size = match usize::from_str_radix(size_str, 16) {
+            Ok(size) => size,
+            Err(_) => io_error!("invalid chunk size {:?}", size_line),
+        };
+
+        if size == 0 {
+            // Skip any trailers
+            while try!(read_line(reader)) != "" {}
+            return Ok(body)
+        }
+
+        let start = body.len();
+        try!(check_body_size(start.saturating_add(size), max));
+        body.resize(start + size, 0);
+        try!(reader.read_exact(&mut body[start..]));
+        try!(read_line(reader));
+    }
+}
+
+fn http_request_impl(method: &str, url: &str, headers: LispObjRef, body: &str, env: EnvironmentRef) -> EvalResult {
+    let url = try!(parse_url(url));
+    let max = try!(max_body(&env));
+
+    try!(check_line(method));
+    try!(check_line(&url.host));
+    try!(check_line(&url.path));
+    let host = if url.port == 80 {
+        url.host.clone()
+    } else {
+        format!("{}:{}", url.host, url.port)
+    };
+    let mut request = format!("{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n",
+                              method, url.path, host);
+    let headers = flatten_list!(headers, "http-request: headers must be a list");
+    for header in headers.iter() {
+        let (name, value) = check_type!(header.clone(), LCons);
+        request.push_str(&format!("{}: {}\r\n", try!(header_part(&name)), try!(header_part(&value))));
+    }
+    if !body.is_empty() {
+        request.push_str(&format!("Content-Length: {}\r\n", body.len()));
+    }
+    request.push_str("\r\n");
+    request.push_str(body);
+
+    let mut reader = BufReader::new(try!(Connection::open(&url.host, url.port)));
+    let res = exchange(&mut reader, &request, max);
+    match reader.get_mut().stopped.take() {
+        Some(err) => Err(err),
+        None => res,
+    }
+}
+
+/// Sends `request`, and reads the response.
+fn exchange(reader: &mut BufReader<Connection>, request: &str, max: Option<usize>) -> EvalResult {
+    try!(reader.get_mut().write_all(request.as_bytes()));
+    try!(reader.get_mut().flush());
+
+    // Status line: HTTP/1.1 200 OK
+    let status_line = try!(read_line(reader));
+    let status = match status_line.split(' ').nth(1).map(|s| s.parse::<i64>()) {
+        Some(Ok(status)) => status,
+        _ => io_error!("invalid HTTP status line {:?}", status_line),
+    };
+
+    let mut response_headers = vec![];
+    let mut content_length = None;
+    let mut chunked = false;
+    loop {
+        let line = try!(read_line(reader));
+        if line.is_empty() {
+            break
+        }
+
+        let (name, value) = match line.find(':') {
+            Some(i) => (line[..i].trim().to_lowercase(), String::from(line[i+1..].trim())),
+            None => io_error!("invalid HTTP header {:?}", line),
+        };
+
+        if name == "content-length" {
+            content_length = value.parse::<usize>().ok();
+        } else if name == "transfer-encoding" && value.to_lowercase().contains("chunked") {
+            chunked = true;
+        }
+        response_headers.push(cons!(string!(name), string!(value)));
+    }
+
+    let body = if chunked {
+        try!(read_chunked(reader, max))
+    } else if let Some(len) = content_length {
+        try!(check_body_size(len, max));
+        let mut body = vec![0; len];
+        try!(reader.read_exact(&mut body));
+        body
+    } else {
+        // Read one byte past the limit, to tell if the body is longer
+        let mut body = vec![];
+        match max {
+            Some(max) => try!(reader.by_ref().take(max as u64 + 1).read_to_end(&mut body)),
+            None => try!(reader.read_to_end(&mut body)),
+        };
+        try!(check_body_size(body.len(), max));
+        body
+    };
+
+    let response = vec![cons!(symbol!("status"), int!(status)),
+                        cons!(symbol!("headers"), LispObj::to_lisp_list(response_headers.into_iter())),
+                        cons!(symbol!("body"), string!(String::from_utf8_lossy(&body).into_owned()))];
+    Ok(LispObj::to_lisp_list(response.into_iter()).to_obj_ref())
+}
+
+pub const HTTP_GET_DOCSTR: &'static str = "Fetches a URL with an HTTP GET request.
+
+Returns an association list of the status code, the headers and the body.
+Only http:// URLs are supported. Throws an 'io-error if the body is longer than
+*http-max-body* bytes, or if the server sends nothing for 30 seconds.
+
+Examples:
+
+(http-get \"http://example.com/\")
+=> ((status . 200) (headers . ((\"content-type\" . \"text/html\") ...)) (body . \"...\"))";
+pub fn http_get(args: &[LispObjRef], env: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => url: LString);
+    http_request_impl("GET", &url, nil!().to_obj_ref(), "", env)
+}
+
+pub const HTTP_REQUEST_DOCSTR: &'static str = "Makes an HTTP request with a method, URL, headers and body.
+
+Headers are an association list of names to values. Returns an association
+list of the status code, the headers and the body, as for http-get, and like it
+throws an 'io-error if the body is longer than *http-max-body* bytes.
+
+Examples:
+
+(http-request 'POST \"http://localhost:8080/api\"
+              '((\"Content-Type\" . \"application/json\"))
+              \"{\\\"a\\\": 1}\")";
+pub fn http_request(args: &[LispObjRef], env: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => method: Any, url: LString, headers: Any, body: LString);
+    let method = try!(header_part(&method)).to_uppercase();
+    http_request_impl(&method, &url, headers, &body, env)
+}
@@ -1,15 +1,34 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
 use std::convert::AsRef;
 use std::env as std_env;
+use std::fs;
 use std::io::{self, Read, Write};
 use std::path;
 
-use ::core::{env, LispObj, LispObjRef, AsLispObjRef, EnvironmentRef};
-use ::parser::Parser;
-use ::evaluator::{self, EvalResult};
+use ::core::{env, LispObj, LispObjRef, AsLispObjRef, EnvironmentRef, RuntimeError};
+use ::parser::{LexError, Parser, ParserError};
+use ::evaluator::{self, image, input, output, EvalResult};
 
 
 pub const DIRECTORY_STACK_NAME: &'static str = "*directory-stack*";
 
+/// The name of the association list from the path of each loaded file to the names it
+/// defined at the top level
+pub const LOADED_FILES_NAME: &'static str = "*loaded-files*";
+
+/// A file being loaded: its canonical path, the names it has defined so far, and when it
+/// is being reloaded, the names it defined before, which it may rebind.
+struct Loading {
+    path: path::PathBuf,
+    defined: Vec<String>,
+    previous: Option<HashSet<String>>,
+}
+
+thread_local! {
+    static LOADING: RefCell<Vec<Loading>> = RefCell::new(vec![]);
+}
+
 pub fn get_current_dir() -> EvalResult {
     let dir =  try!(std_env::current_dir());
     from_os_path(dir.as_path())
@@ -17,7 +36,7 @@ pub fn get_current_dir() -> EvalResult {
 
 pub fn set_current_dir<P: AsRef<path::Path>>(path: P) -> EvalResult {
     try!(std_env::set_current_dir(path));
-    Ok(lisp_true!().to_obj_ref())
+    Ok(lisp_true_ref!())
 }
 
 fn from_os_path_to_str(input: &path::Path) -> EvalResult<&str> {
@@ -47,11 +66,12 @@ pub fn lisp_obj_to_path(obj: LispObjRef) -> EvalResult<path::PathBuf> {
     } else if let Some(string) = obj.string_ref() {
         Ok(path::PathBuf::from((*string).clone()))
     } else {
-        type_error!("{} is not a path", obj)
+        type_error!(args vec![obj.clone()], expected "path"; "{} is not a path", obj)
     }
 }
 
-fn pop_directory(env: EnvironmentRef) -> EvalResult<path::PathBuf> {
+/// Returns to the directory on top of `*directory-stack*`, removing it from the stack.
+pub fn pop_directory(env: EnvironmentRef) -> EvalResult<path::PathBuf> {
     let retval = env.borrow().lookup(DIRECTORY_STACK_NAME);
 
     if let Some(dir_stack) = retval {
@@ -72,7 +92,8 @@ fn pop_directory(env: EnvironmentRef) -> EvalResult<path::PathBuf> {
     }
 }
 
-fn push_directory<P: AsRef<path::Path>>(path: P, env: EnvironmentRef) -> EvalResult {
+/// Changes to `path`, saving the current directory on `*directory-stack*`.
+pub fn push_directory<P: AsRef<path::Path>>(path: P, env: EnvironmentRef) -> EvalResult {
     let dirstack = env.borrow().lookup(DIRECTORY_STACK_NAME);
     let new_path = path.as_ref();
 
@@ -107,6 +128,24 @@ pub fn lisp_set_current_dir(args: &[LispObjRef], _: EnvironmentRef) -> EvalResul
 }
 
 pub fn load_file_handler(args: &[LispObjRef], env: EnvironmentRef) -> EvalResult {
+    load_file(args, env, false)
+}
+
+pub const RELOAD_DOCSTR: &'static str = "Loads a file again, rebinding the names it defined when it was last loaded.
+
+Unlike load-file, this doesn't throw a 'redefine-error for names which the file
+defined before, so that a file can be changed and reloaded while developing it.
+*loaded-files* lists the names each loaded file defined.
+
+Examples:
+
+(load-file \"utils.lisp\")
+(reload \"utils.lisp\")";
+pub fn reload_handler(args: &[LispObjRef], env: EnvironmentRef) -> EvalResult {
+    load_file(args, env, true)
+}
+
+fn load_file(args: &[LispObjRef], env: EnvironmentRef, reload: bool) -> EvalResult {
     unpack_args!(args => lisp_path: Any);
 
     let mut file_path = try!(lisp_obj_to_path(lisp_path));
@@ -119,7 +158,7 @@ pub fn load_file_handler(args: &[LispObjRef], env: EnvironmentRef) -> EvalResult
         };
 
         evaluator::apply(handler, lisp_list!(obj), env.clone())
-            .map(|obj| (*obj).clone())
+            .map(LispObj::from_obj_ref)
             .map_err(|err| Some(err.into_lisp_obj()))
     };
 
@@ -129,56 +168,199 @@ pub fn load_file_handler(args: &[LispObjRef], env: EnvironmentRef) -> EvalResult
 
     let file_parser = match Parser::from_file(&file_path) {
         Ok(file) => file,
-        Err(errmsg) => io_error!("cannot open file: {:?}", errmsg),
+        Err(errmsg) => {
+            let abspath = try!(std_env::current_dir()).join(&file_path);
+            io_error!("cannot open file {}: {}", abspath.display(), errmsg)
+        },
     }.with_char_handler(char_handlers);
 
-    if file_path.is_file() {
-        let canon = try!(file_path.canonicalize());
+    let canon = try!(file_path.canonicalize());
+    let pushed = file_path.is_file();
+    if pushed {
         let _ = try!(push_directory(canon.parent().expect("all files should have parent dir"),
                                     global.clone()));
     }
 
-    let mut out = nil!().to_obj_ref();
+    let res = with_file_loading(&global, &canon, reload, || {
+        let mut out = nil!().to_obj_ref();
+        for parsed_obj in file_parser {
+            let obj = match parsed_obj {
+                Ok(obj) => obj,
+                Err(e) => io_error!("error parsing file {}: {:?}", canon.display(), e)
+            };
+            out = try!(evaluator::eval(obj, global.clone()))
+        }
+        Ok(out)
+    });
 
-    for parsed_obj in file_parser {
-        let obj = match parsed_obj {
-            Ok(obj) => obj,
-            Err(e) => io_error!("error parsing file: {:?}", e)
-        };
-        out = try!(evaluator::eval(obj, global.clone()))
+    if pushed {
+        let _ = try!(pop_directory(global));
+    }
+    res
+}
+
+/// Runs `f`, which loads the file at `path`, recording the names it defines in
+/// `*loaded-files*`. If `reload` is true, the file may rebind the names it defined when
+/// it was last loaded. Afterwards, restores the settings a file may change for just
+/// itself, currently `*allow-redefine*`.
+///
+/// `path` should be canonical. An error from `f` gets a traceback entry naming it, as
+/// `(load-file "/path/to/file.lisp")`. Throws an 'io-error without running `f` if the
+/// file is already being loaded, as it would load itself forever.
+pub fn with_file_loading<F>(global: &EnvironmentRef, path: &path::Path, reload: bool, f: F) -> EvalResult
+        where F: FnOnce() -> EvalResult {
+    if let Some(cycle) = loading_cycle(path) {
+        io_error!("circular load: {}", cycle)
+    }
+
+    let key = path.to_string_lossy().into_owned();
+    let recorded = loaded_definitions(global, &key);
+    let previous = if reload { Some(recorded.iter().cloned().collect()) } else { None };
+    LOADING.with(|loading| loading.borrow_mut().push(Loading { path: path.to_path_buf(), defined: vec![], previous: previous }));
+
+    let allow_redefine = global.borrow().lookup(super::ALLOW_REDEFINE_NAME);
+    let res = f();
+    if let Some(allow_redefine) = allow_redefine {
+        let _ = global.borrow_mut().swap_values(super::ALLOW_REDEFINE_NAME, allow_redefine);
     }
 
-    let _ = try!(pop_directory(global));
+    // Names the file no longer defines are kept, as they are still bound
+    let file = LOADING.with(|loading| loading.borrow_mut().pop()).expect("loading stack should not be empty");
+    let mut names = recorded;
+    for name in file.defined.into_iter() {
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+    record_definitions(global, &key, names);
+
+    res.map_err(|err| {
+        if err.is_catchable() {
+            let name = if reload { "reload" } else { "load-file" };
+            RuntimeError::new_from(err, lisp_list!(symbol!(name), string!(key)).to_obj_ref())
+        } else {
+            err
+        }
+    })
+}
+
+/// If `path` is already being loaded, the chain of loads from it back to itself, as
+/// "a.lisp -> b.lisp -> a.lisp".
+fn loading_cycle(path: &path::Path) -> Option<String> {
+    LOADING.with(|loading| {
+        let loading = loading.borrow();
+        loading.iter().position(|file| file.path == path).map(|start| {
+            let mut chain: Vec<_> = loading[start..].iter().map(|file| file.path.display().to_string()).collect();
+            chain.push(path.display().to_string());
+            chain.join(" -> ")
+        })
+    })
+}
 
-    Ok(out)
+/// The canonical path of the file being loaded, if any.
+pub fn current_source_file() -> Option<path::PathBuf> {
+    LOADING.with(|loading| loading.borrow().last().map(|file| file.path.clone()))
 }
 
-pub fn print(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+pub const SOURCE_FILE_DOCSTR: &'static str = "Returns the absolute path of the file being loaded, or nil outside of one.
+
+Files are loaded by load-file, reload and the command line. While a file loads
+another, this is the other file's path until it has been loaded.
+
+Examples:
+
+(source-file)
+=> \"/home/user/project/main.lisp\"";
+pub fn source_file(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args);
+
+    match current_source_file() {
+        Some(path) => from_os_path(&path),
+        None => Ok(nil!().to_obj_ref()),
+    }
+}
+
+/// Whether the file being loaded may rebind `name`: if it is being reloaded, and
+/// defined `name` before.
+pub fn file_may_rebind(name: &str) -> bool {
+    LOADING.with(|loading| match loading.borrow().last() {
+        Some(file) => file.previous.as_ref().map_or(false, |previous| previous.contains(name)),
+        None => false,
+    })
+}
+
+/// Records that the file being loaded, if any, defined `name` at the top level.
+pub fn note_definition(name: &str) {
+    LOADING.with(|loading| if let Some(file) = loading.borrow_mut().last_mut() {
+        if !file.defined.iter().any(|defined| defined == name) {
+            file.defined.push(String::from(name));
+        }
+    })
+}
+
+/// The names recorded in `*loaded-files*` as defined by the file at `key`.
+fn loaded_definitions(global: &EnvironmentRef, key: &str) -> Vec<String> {
+    let files = global.borrow().lookup(LOADED_FILES_NAME).unwrap_or_else(|| nil!().to_obj_ref());
+    for entry in files.list_iter().filter_map(|entry| entry.ok()) {
+        if let Some((path, names)) = entry.cons_split() {
+            if path.string_ref().map_or(false, |path| *path == key) {
+                return names.list_iter()
+                            .filter_map(|name| name.ok().and_then(|name| name.symbol_ref().map(String::from)))
+                            .collect()
+            }
+        }
+    }
+    vec![]
+}
+
+fn record_definitions(global: &EnvironmentRef, key: &str, names: Vec<String>) {
+    let files = global.borrow().lookup(LOADED_FILES_NAME).unwrap_or_else(|| nil!().to_obj_ref());
+    let others = files.list_iter().filter_map(|entry| entry.ok()).filter(|entry| {
+        !entry.cons_split().and_then(|(path, _)| path.string_ref()).map_or(false, |path| *path == key)
+    });
+    let entry = cons!(string!(String::from(key)), LispObj::to_lisp_list(names.into_iter().map(|name| symbol!(name))));
+    let files = Some(entry.to_obj_ref()).into_iter().chain(others).collect::<LispObj>().to_obj_ref();
+
+    let mut global = global.borrow_mut();
+    if global.swap_values(LOADED_FILES_NAME, files.clone()).is_none() {
+        let _ = global.let_new(String::from(LOADED_FILES_NAME), files);
+    }
+}
+
+pub fn load_image_handler(args: &[LispObjRef], env: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => lisp_path: Any);
+
+    let path = try!(lisp_obj_to_path(lisp_path));
+    try!(image::load_image(env, path));
+    Ok(lisp_true_ref!())
+}
+
+fn write_args(args: &[LispObjRef], out: &mut Write) -> io::Result<()> {
     for arg in args.iter() {
         match arg.as_ref() {
-            &LispObj::LString(ref s) => print!("{}", s),
-            other => print!("{}", other),
+            &LispObj::LString(ref s) => try!(write!(out, "{}", s)),
+            other => try!(write!(out, "{}", other)),
         }
     }
+    Ok(())
+}
 
-    match io::stdout().flush() {
+pub fn print(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    match output::write_stdout(|out| write_args(args, out)) {
         Ok(_) => {}
         Err(err) => io_error!("{:?}", err)
     }
 
-    Ok(lisp_true!().to_obj_ref())
+    Ok(lisp_true_ref!())
 }
 
-pub fn println(args: &[LispObjRef], env: EnvironmentRef) -> EvalResult {
-    let _ = try!(print(args, env));
-    println!("");
-
-    match io::stdout().flush() {
+pub fn println(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    match output::write_stdout(|out| write_args(args, out).and_then(|_| writeln!(out))) {
         Ok(_) => {}
         Err(err) => io_error!("{:?}", err)
     }
 
-    Ok(lisp_true!().to_obj_ref())
+    Ok(lisp_true_ref!())
 }
 
 pub fn lisp_pop_directory(args: &[LispObjRef], env: EnvironmentRef) -> EvalResult {
@@ -195,25 +377,184 @@ pub fn lisp_push_directory(args: &[LispObjRef], env: EnvironmentRef) -> EvalResu
     push_directory(&target_dir, env)
 }
 
-/// TODO handle special chars
-pub fn read_handler(_: &[LispObjRef], env: EnvironmentRef) -> EvalResult {
-    let instream = Parser::new(io::stdin().chars(), "<stdin>");
-    let top_level = env::get_top_level(env);
+pub fn save_image_handler(args: &[LispObjRef], env: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => lisp_path: Any);
 
-    let char_handler = |c, obj| {
-        match top_level.borrow().get_char_handler(c) {
-            Some(handler) => {
-                evaluator::apply(handler, lisp_list!(obj), top_level.clone())
-                           .map(|obj| (*obj).clone())
-                           .map_err(|err| Some(err.into_lisp_obj()))
-            },
-            None => Err(None),
-        }
+    let path = try!(lisp_obj_to_path(lisp_path));
+    try!(image::save_image(env, path));
+    Ok(lisp_true_ref!())
+}
+
+pub const READ_DOCSTR: &'static str = "Reads one form, without evaluating it.
+
+Reads from stdin, or from a port such as a socket if one is given. Input is read as
+it is needed, and anything read past the end of the form is kept for the next read.
+
+Throws a 'read-error if the input cannot be parsed, with the value eof at the end of
+the input, and an 'io-error if it cannot be read.
+
+Examples:
+
+(read)
+(+ 1 2)
+=> (+ 1 2)
+
+(read sock)
+=> (hello world)";
+pub fn read_handler(args: &[LispObjRef], env: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => opt port: LPort);
+
+    let reader = match port {
+        Some(ref port) => input::port_reader(port),
+        None => input::stdin(),
+    };
+    let res = match reader.try_borrow_mut() {
+        Ok(mut reader) => reader.read(&env),
+        Err(_) => read_error!("read: {} is already being read from", port.map_or(String::from("stdin"), |port| String::from(port.name()))),
     };
 
-    match instream.with_char_handler(char_handler).next() {
+    match res {
         Some(Ok(obj))   => Ok(obj.to_obj_ref()),
+        Some(Err(ParserError::LexError(LexError::ReadError(err)))) => io_error!("read: {}", err),
         Some(Err(err))  => read_error!("{:?}", err),
         None            => runtime_error!(value symbol!("eof"); super::super::err_msgs::READ_ERROR)
     }
 }
+
+/************************* File system ***************************/
+
+pub const ABSOLUTE_PATH_DOCSTR: &'static str = "Returns the canonical, absolute form of a path, resolving symlinks.
+
+Throws an 'io-error if the path does not exist.
+
+Examples:
+
+(absolute-path \".\")
+=> \"/home/user\"";
+pub fn absolute_path(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => lisp_path: Any);
+
+    let path = try!(lisp_obj_to_path(lisp_path));
+    let canon = try!(path.canonicalize());
+    from_os_path(&canon)
+}
+
+pub const DELETE_FILE_DOCSTR: &'static str = "Deletes a file.
+
+Examples:
+
+(delete-file \"out.txt\")";
+pub fn delete_file(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => lisp_path: Any);
+
+    let path = try!(lisp_obj_to_path(lisp_path));
+    try!(fs::remove_file(path));
+    Ok(lisp_true_ref!())
+}
+
+pub const IS_DIRECTORY_DOCSTR: &'static str = "Returns true if a path names an existing directory.
+
+Examples:
+
+(directory? \"/tmp\")
+=> true";
+pub fn is_directory(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => lisp_path: Any);
+
+    let path = try!(lisp_obj_to_path(lisp_path));
+    Ok(lisp_bool_ref!(path.is_dir()))
+}
+
+pub const FILE_SIZE_DOCSTR: &'static str = "Returns the size of a file in bytes.
+
+Examples:
+
+(file-size \"README.md\")
+=> 1024";
+pub fn file_size(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => lisp_path: Any);
+
+    let path = try!(lisp_obj_to_path(lisp_path));
+    let metadata = try!(fs::metadata(path));
+    Ok(int!(metadata.len()).to_obj_ref())
+}
+
+pub const LIST_DIRECTORY_DOCSTR: &'static str = "Returns the names of the entries in a directory, sorted.
+
+Throws an 'io-error if a name is not valid UTF-8.
+
+Examples:
+
+(list-directory \"src\")
+=> (\"lib.rs\" \"main.rs\")";
+pub fn list_directory(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => lisp_path: Any);
+
+    let path = try!(lisp_obj_to_path(lisp_path));
+    let mut names = vec![];
+    for entry in try!(fs::read_dir(path)) {
+        let entry = try!(entry);
+        match entry.file_name().into_string() {
+            Ok(name) => names.push(name),
+            Err(name) => io_error!("list-directory: the name {:?} is not valid UTF-8", name),
+        }
+    }
+
+    names.sort();
+    Ok(LispObj::to_lisp_list(names.into_iter().map(|n| string!(n))).to_obj_ref())
+}
+
+pub const MAKE_DIRECTORY_DOCSTR: &'static str = "Creates a directory, along with any missing parent directories.
+
+Examples:
+
+(make-directory '(\"build\" \"out\"))";
+pub fn make_directory(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => lisp_path: Any);
+
+    let path = try!(lisp_obj_to_path(lisp_path));
+    try!(fs::create_dir_all(path));
+    Ok(lisp_true_ref!())
+}
+
+pub const RENAME_FILE_DOCSTR: &'static str = "Renames a file or directory, replacing the destination if it is a file.
+
+Examples:
+
+(rename-file \"old.txt\" \"new.txt\")";
+pub fn rename_file(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => from: Any, to: Any);
+
+    let from = try!(lisp_obj_to_path(from));
+    let to = try!(lisp_obj_to_path(to));
+    try!(fs::rename(from, to));
+    Ok(lisp_true_ref!())
+}
+
+pub const READ_FILE_BYTES_DOCSTR: &'static str = "Reads the whole of a file as a byte vector.
+
+Examples:
+
+(bytes-length (read-file-bytes \"image.png\"))
+=> 1024";
+pub fn read_file_bytes(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => lisp_path: Any);
+
+    let path = try!(lisp_obj_to_path(lisp_path));
+    let mut contents = vec![];
+    try!(try!(fs::File::open(path)).read_to_end(&mut contents));
+    Ok(LispObj::make_bytes(contents).to_obj_ref())
+}
+
+pub const WRITE_FILE_BYTES_DOCSTR: &'static str = "Writes a byte vector to a file, replacing its contents.
+
+Examples:
+
+(write-file-bytes \"out.bin\" (make-bytes 4 255))";
+pub fn write_file_bytes(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => lisp_path: Any, bytes: LBytes);
+
+    let path = try!(lisp_obj_to_path(lisp_path));
+    try!(try!(fs::File::create(path)).write_all(&bytes));
+    Ok(lisp_true_ref!())
+}
@@ -0,0 +1,420 @@
+//! Conversion between lisp data and JSON.
+//!
+//! The mapping is:
+//!
+//! | JSON          | lisp                                   |
+//! |---------------|----------------------------------------|
+//! | object        | association list of `("key" . value)`  |
+//! | `{}`          | `()`                                   |
+//! | array         | vector                                 |
+//! | string        | string                                 |
+//! | number        | integer, or float if it has a fraction |
+//! | `true/false`  | the symbols `true` and `false`         |
+//! | `null`        | the symbol `null`                      |
+//!
+//! When converting lisp to JSON, a list is written as an object if every element
+//! is a cons with a string or symbol key, and as an array otherwise. Other symbols
+//! and characters are written as strings.
+use std::iter::Peekable;
+use std::mem;
+use std::str::Chars;
+
+use ::core::{LispObj, LispObjRef, AsLispObjRef, EnvironmentRef, EvalResult};
+
+impl LispObj {
+    /// Parses a JSON document into a lisp object.
+    ///
+    /// Raises a `json-error` if `input` is not valid JSON.
+    pub fn from_json(input: &str) -> EvalResult {
+        let mut reader = JsonReader { chars: input.chars().peekable(), pos: 0 };
+        let out = try!(reader.read_value());
+        reader.skip_whitespace();
+        match reader.chars.peek() {
+            None    => Ok(out),
+            Some(c) => json_error!("unexpected trailing character {:?} at {}", c, reader.pos),
+        }
+    }
+
+    /// Serializes a lisp object as compact JSON.
+    ///
+    /// Raises a `json-error` for objects with no JSON representation, such as
+    /// procedures and errors.
+    pub fn to_json(&self) -> EvalResult<String> {
+        let mut out = String::new();
+        try!(write_json(self, &mut out));
+        Ok(out)
+    }
+}
+
+/*********************** Reading ************************/
+
+struct JsonReader<'a> {
+    chars: Peekable<Chars<'a>>,
+    pos: usize,
+}
+
+/// An array or object which the reader is inside.
+enum Open {
+    Array(Vec<LispObjRef>),
+    /// The pairs read so far, and the key of the value being read
+    Object(Vec<LispObj>, String),
+}
+
+impl<'a> JsonReader<'a> {
+    fn advance(&mut self) -> Option<char> {
+        let next = self.chars.next();
+        if next.is_some() {
+            self.pos += 1;
+        }
+        next
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(&c) = self.chars.peek() {
+            if c == ' ' || c == '\t' || c == '\n' || c == '\r' {
+                self.advance();
+            } else {
+                break
+            }
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> EvalResult<()> {
+        match self.advance() {
+            Some(c) if c == expected => Ok(()),
+            Some(c) => json_error!("expected {:?} at {}, found {:?}", expected, self.pos, c),
+            None    => json_error!("expected {:?}, found end of input", expected),
+        }
+    }
+
+    fn expect_word(&mut self, word: &str) -> EvalResult<()> {
+        for c in word.chars() {
+            try!(self.expect(c));
+        }
+        Ok(())
+    }
+
+    /// Reads a value. Arrays and objects are read with a stack of those still open,
+    /// rather than by recursing, so that deeply nested input can't overflow the stack.
+    fn read_value(&mut self) -> EvalResult {
+        let mut open = vec![];
+        loop {
+            self.skip_whitespace();
+            let mut value = match self.chars.peek() {
+                Some(&'[') => {
+                    self.advance();
+                    if !self.at_close(']') {
+                        open.push(Open::Array(vec![]));
+                        continue
+                    }
+                    LispObj::make_vector(Vec::<LispObjRef>::new().into_iter()).to_obj_ref()
+                },
+                Some(&'{') => {
+                    self.advance();
+                    if !self.at_close('}') {
+                        open.push(Open::Object(vec![], try!(self.read_key())));
+                        continue
+                    }
+                    nil!().to_obj_ref()
+                },
+                _ => try!(self.read_scalar()),
+            };
+
+            // Add the value to the innermost open array or object, and close those it ends
+            loop {
+                let closed = match open.last_mut() {
+                    None => return Ok(value),
+                    Some(&mut Open::Array(ref mut items)) => {
+                        items.push(value);
+                        try!(self.read_separator(']'))
+                    },
+                    Some(&mut Open::Object(ref mut pairs, ref mut key)) => {
+                        pairs.push(cons!(string!(mem::replace(key, String::new())), value));
+                        let closed = try!(self.read_separator('}'));
+                        if !closed {
+                            *key = try!(self.read_key());
+                        }
+                        closed
+                    },
+                };
+                if !closed {
+                    break
+                }
+                value = match open.pop() {
+                    Some(Open::Array(items)) => LispObj::make_vector(items.into_iter()).to_obj_ref(),
+                    Some(Open::Object(pairs, _)) => LispObj::to_lisp_list(pairs.into_iter()).to_obj_ref(),
+                    None => unreachable!("an array or object was open"),
+                };
+            }
+        }
+    }
+
+    /// Reads a value other than an array or object.
+    fn read_scalar(&mut self) -> EvalResult {
+        let next = match self.chars.peek() {
+            Some(&c) => c,
+            None     => json_error!("unexpected end of input"),
+        };
+
+        match next {
+            '"' => Ok(string!(try!(self.read_string())).to_obj_ref()),
+            't' => {
+                try!(self.expect_word("true"));
+                Ok(lisp_true_ref!())
+            },
+            'f' => {
+                try!(self.expect_word("false"));
+                Ok(lisp_false_ref!())
+            },
+            'n' => {
+                try!(self.expect_word("null"));
+                Ok(symbol!("null").to_obj_ref())
+            },
+            '-' | '0'...'9' => self.read_number(),
+            c => json_error!("unexpected character {:?} at {}", c, self.pos),
+        }
+    }
+
+    /// Skips the `close` of an empty array or object, returning whether it was there.
+    fn at_close(&mut self, close: char) -> bool {
+        self.skip_whitespace();
+        if self.chars.peek() == Some(&close) {
+            self.advance();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Reads an object's key, and the ':' after it.
+    fn read_key(&mut self) -> EvalResult<String> {
+        self.skip_whitespace();
+        let key = try!(self.read_string());
+        self.skip_whitespace();
+        try!(self.expect(':'));
+        Ok(key)
+    }
+
+    /// Reads the ',' after an element of an array or object, or the `close` which ends
+    /// it, returning whether it was `close`.
+    fn read_separator(&mut self, close: char) -> EvalResult<bool> {
+        self.skip_whitespace();
+        match self.advance() {
+            Some(',') => Ok(false),
+            Some(c) if c == close => Ok(true),
+            Some(c)   => json_error!("expected ',' or {:?} at {}, found {:?}", close, self.pos, c),
+            None      => json_error!("unterminated {}", if close == ']' { "array" } else { "object" }),
+        }
+    }
+
+    fn read_hex4(&mut self) -> EvalResult<u32> {
+        let mut code = 0;
+        for _ in 0..4 {
+            let digit = match self.advance().and_then(|c| c.to_digit(16)) {
+                Some(d) => d,
+                None    => json_error!("invalid \\u escape at {}", self.pos),
+            };
+            code = code * 16 + digit;
+        }
+        Ok(code)
+    }
+
+    fn read_string(&mut self) -> EvalResult<String> {
+        try!(self.expect('"'));
+        let mut out = String::new();
+
+        loop {
+            match self.advance() {
+                Some('"')  => return Ok(out),
+                Some('\\') => {
+                    let escaped = match self.advance() {
+                        Some('"')  => '"',
+                        Some('\\') => '\\',
+                        Some('/')  => '/',
+                        Some('b')  => '\u{8}',
+                        Some('f')  => '\u{c}',
+                        Some('n')  => '\n',
+                        Some('r')  => '\r',
+                        Some('t')  => '\t',
+                        Some('u')  => try!(self.read_unicode_escape()),
+                        Some(c)    => json_error!("unknown string escape \\{} at {}", c, self.pos),
+                        None       => json_error!("unterminated string"),
+                    };
+                    out.push(escaped);
+                },
+                Some(c) => out.push(c),
+                None    => json_error!("unterminated string"),
+            }
+        }
+    }
+
+    fn read_unicode_escape(&mut self) -> EvalResult<char> {
+        let high = try!(self.read_hex4());
+        let code = if high >= 0xD800 && high < 0xDC00 {
+            // Surrogate pair, must be followed by the low half
+            try!(self.expect_word("\\u"));
+            let low = try!(self.read_hex4());
+            if low < 0xDC00 || low >= 0xE000 {
+                json_error!("invalid surrogate pair at {}", self.pos)
+            }
+            0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+        } else {
+            high
+        };
+
+        match ::std::char::from_u32(code) {
+            Some(c) => Ok(c),
+            None    => json_error!("invalid unicode escape at {}", self.pos),
+        }
+    }
+
+    fn read_number(&mut self) -> EvalResult {
+        let mut s = String::new();
+        while let Some(&c) = self.chars.peek() {
+            match c {
+                '0'...'9' | '-' | '+' | '.' | 'e' | 'E' => {
+                    s.push(c);
+                    self.advance();
+                },
+                _ => break,
+            }
+        }
+
+        let is_float = s.contains(|c| c == '.' || c == 'e' || c == 'E');
+        if !is_float {
+            if let Ok(n) = s.parse::<i64>() {
+                return Ok(int!(n).to_obj_ref())
+            }
+        }
+
+        match s.parse::<f64>() {
+            Ok(n)  => Ok(float!(n).to_obj_ref()),
+            Err(_) => json_error!("invalid number {:?} at {}", s, self.pos),
+        }
+    }
+}
+
+/*********************** Writing ************************/
+
+fn is_json_object(obj: &LispObj) -> bool {
+    obj.is_list() && obj.list_iter().all(|item| {
+        match item.ok().and_then(|pair| pair.car()) {
+            Some(key) => key.is_string() || key.is_symbol(),
+            None      => false,
+        }
+    })
+}
+
+fn write_json_string(s: &str, out: &mut String) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"'  => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+fn write_json(obj: &LispObj, out: &mut String) -> EvalResult<()> {
+    match obj {
+        &LispObj::LInteger(n) => out.push_str(&n.to_string()),
+        &LispObj::LFloat(n) => {
+            if !n.is_finite() {
+                json_error!("cannot represent {} in JSON", n)
+            }
+            // Debug formatting keeps the decimal point, so the number reads back as a float
+            out.push_str(&format!("{:?}", n))
+        },
+        &LispObj::LString(ref s) => write_json_string(s, out),
+        &LispObj::LChar(c) => write_json_string(&c.to_string(), out),
+        &LispObj::LSymbol(ref s) => {
+            match &**s {
+                "true" | "false" | "null" => out.push_str(s),
+                _ => write_json_string(s, out),
+            }
+        },
+        &LispObj::LNil => out.push_str("{}"),
+        &LispObj::LCons(_, _) => {
+            if is_json_object(obj) {
+                out.push('{');
+                for (i, pair) in obj.list_iter().enumerate() {
+                    let (key, value) = pair.ok().and_then(|p| p.cons_split())
+                                           .expect("is_json_object checked pairs");
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    match key.symbol_ref() {
+                        Some(name) => write_json_string(name, out),
+                        None       => write_json_string(&key.string_ref().expect("is_json_object checked keys"), out),
+                    }
+                    out.push(':');
+                    try!(write_json(&value, out));
+                }
+                out.push('}');
+            } else if obj.is_list() {
+                out.push('[');
+                for (i, item) in obj.list_iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    try!(write_json(&item.expect("is_list checked structure"), out));
+                }
+                out.push(']');
+            } else {
+                json_error!("cannot represent improper list {} in JSON", obj)
+            }
+        },
+        &LispObj::LVector(ref vec) => {
+            out.push('[');
+            for (i, item) in vec.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                try!(write_json(item, out));
+            }
+            out.push(']');
+        },
+        other => json_error!("cannot represent {} in JSON", other),
+    }
+    Ok(())
+}
+
+/*********************** Lisp Functions ************************/
+
+pub const JSON_TO_LISP_DOCSTR: &'static str = "Parses a JSON string into lisp data.
+
+Objects become association lists with string keys, arrays become vectors,
+true/false/null become the symbols 'true, 'false and 'null.
+
+Throws a 'json-error if the string is not valid JSON.
+
+Examples:
+
+(json->lisp \"{\\\"a\\\": [1, 2.5]}\")
+;; => ((\"a\" . [1 2.5]))";
+pub fn json_to_lisp(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => input: LString);
+    LispObj::from_json(&input)
+}
+
+pub const LISP_TO_JSON_DOCSTR: &'static str = "Serializes lisp data as a JSON string.
+
+Association lists with string or symbol keys become objects, other lists and
+vectors become arrays.
+
+Throws a 'json-error for values with no JSON representation, like procedures.
+
+Examples:
+
+(lisp->json '((name . \"rusty\") (tags . [1 2])))
+;; => \"{\\\"name\\\":\\\"rusty\\\",\\\"tags\\\":[1,2]}\"";
+pub fn lisp_to_json(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => obj: Any);
+    Ok(string!(try!(obj.to_json())).to_obj_ref())
+}
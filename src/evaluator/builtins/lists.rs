@@ -0,0 +1,205 @@
+//! Builtins for positional access to lists, and for association lists.
+//!
+//! Indices count from 0, and negative ones count back from the end, as with vectors
+//! and strings. An index past either end throws a 'bounds-error, and something other
+//! than a list throws a 'type-error.
+//!
+//! An association list, or alist, is a list of `(key . value)` pairs, such as
+//! `*signal-handlers*`. Earlier pairs shadow later ones with the same key.
+use ::core::{LispObj, LispObjRef, AsLispObjRef, EnvironmentRef, EvalResult};
+use ::core::shared::Shared;
+
+fn check_list(fname: &str, ls: &LispObjRef) -> EvalResult<usize> {
+    match ls.list_length() {
+        Some(len) => Ok(len),
+        None => type_error!(args vec![ls.clone()], expected "list"; "{}: expected list, not {}", fname, ls),
+    }
+}
+
+/// Checks a count of elements to take or drop from a list of `len` elements. Negative
+/// counts leave that many elements at the end.
+fn check_count(fname: &str, n: i64, len: usize) -> EvalResult<usize> {
+    let resolved = if n < 0 { n + len as i64 } else { n };
+    if resolved < 0 || resolved as u64 > len as u64 {
+        bounds_error!(args vec![int!(n).to_obj_ref()]; "{}: count {} is out of bounds for length {}", fname, n, len)
+    }
+    Ok(resolved as usize)
+}
+
+/// The list after its first `n` elements, which it shares with `ls`.
+fn tail(ls: &LispObjRef, n: usize) -> LispObjRef {
+    let mut rest = ls.clone();
+    for _ in 0..n {
+        rest = rest.cons_split().expect("list length was checked").1;
+    }
+    rest
+}
+
+pub const NTH_DOCSTR: &'static str = "Returns the element of a list at an index, counting from 0.
+
+Negative indices count back from the end. Throws a 'bounds-error if the index is
+out of range.
+
+Examples:
+
+(nth '(a b c) 1)
+=> b
+(nth '(a b c) -1)
+=> c";
+pub fn nth(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => ls: Any, ind: LInteger);
+    let len = try!(check_list("nth", &ls));
+    let ind = try!(super::check_index("nth", ind, len));
+    Ok(ls.list_index(ind as i64).expect("list index was checked"))
+}
+
+pub const LAST_DOCSTR: &'static str = "Returns the last element of a list.
+
+Throws a 'bounds-error if the list is empty.
+
+Examples:
+
+(last '(a b c))
+=> c";
+pub fn last(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => ls: Any);
+    let len = try!(check_list("last", &ls));
+    if len == 0 {
+        bounds_error!("last: the list is empty")
+    }
+    Ok(tail(&ls, len - 1).cons_split().expect("list length was checked").0)
+}
+
+pub const TAKE_DOCSTR: &'static str = "Returns a list of the first n elements of a list.
+
+A negative n leaves out that many elements at the end. Throws a 'bounds-error if
+the list is too short.
+
+Examples:
+
+(take '(a b c) 2)
+=> (a b)
+(take '(a b c) -1)
+=> (a b)";
+pub fn take(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => ls: Any, n: LInteger);
+    let len = try!(check_list("take", &ls));
+    let n = try!(check_count("take", n, len));
+    let items = ls.list_iter().take(n).map(|item| item.expect("list was checked")).collect();
+    Ok(LispObj::from_vec(items).to_obj_ref())
+}
+
+pub const DROP_DOCSTR: &'static str = "Returns a list without its first n elements.
+
+The result shares its cells with the list. A negative n keeps that many elements at
+the end. Throws a 'bounds-error if the list is too short. list-tail is the same.
+
+Examples:
+
+(drop '(a b c) 2)
+=> (c)
+(drop '(a b c) -1)
+=> (c)";
+pub fn drop(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => ls: Any, n: LInteger);
+    let len = try!(check_list("drop", &ls));
+    let n = try!(check_count("drop", n, len));
+    Ok(tail(&ls, n))
+}
+
+/// Whether two values are the same: symbols, numbers, chars and nil are compared by
+/// value, and anything else only to itself.
+fn is_same(a: &LispObjRef, b: &LispObjRef) -> bool {
+    if Shared::ptr_eq(a, b) {
+        return true
+    }
+    match (&**a, &**b) {
+        (&LispObj::LSymbol(_), _) | (&LispObj::LInteger(_), _) | (&LispObj::LFloat(_), _)
+            | (&LispObj::LChar(_), _) | (&LispObj::LNil, _) => a == b,
+        _ => false,
+    }
+}
+
+/// The pairs of an alist, in order.
+fn alist_pairs(fname: &str, alist: &LispObjRef) -> EvalResult<Vec<LispObjRef>> {
+    try!(check_list(fname, alist));
+    let mut pairs = vec![];
+    for entry in alist.list_iter() {
+        let entry = entry.expect("list was checked");
+        if !entry.is_cons() {
+            type_error!(args vec![entry.clone()], expected "cons";
+                        "{}: expected an association list of pairs, but it contains {}", fname, entry)
+        }
+        pairs.push(entry);
+    }
+    Ok(pairs)
+}
+
+fn pair_key(pair: &LispObjRef) -> LispObjRef {
+    pair.cons_split().expect("alist pairs were checked").0
+}
+
+/// The first pair of an alist whose key matches, or false.
+fn find_pair<F>(fname: &str, alist: &LispObjRef, matches: F) -> EvalResult
+        where F: Fn(&LispObjRef) -> bool {
+    let pairs = try!(alist_pairs(fname, alist));
+    match pairs.into_iter().find(|pair| matches(&pair_key(pair))) {
+        Some(pair) => Ok(pair),
+        None => Ok(lisp_false_ref!()),
+    }
+}
+
+pub const ASSOC_DOCSTR: &'static str = "Returns the first pair of an association list whose key is equal? to a
+value, or false if there is none.
+
+Examples:
+
+(assoc \"b\" '((\"a\" . 1) (\"b\" . 2)))
+=> (\"b\" . 2)
+(assoc 'c '((a . 1)))
+=> false";
+pub fn assoc(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => key: Any, alist: Any);
+    find_pair("assoc", &alist, |k| *k == key)
+}
+
+pub const ASSQ_DOCSTR: &'static str = "Returns the first pair of an association list whose key is the same as
+a value, or false if there is none.
+
+Symbols, numbers, chars and nil are the same as equal ones; other values, such as
+strings and lists, are only the same as themselves. Use assoc to compare those by
+their contents.
+
+Examples:
+
+(assq 'b '((a . 1) (b . 2)))
+=> (b . 2)
+(assq \"b\" '((\"b\" . 2)))
+=> false";
+pub fn assq(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => key: Any, alist: Any);
+    find_pair("assq", &alist, |k| is_same(k, &key))
+}
+
+pub const ALIST_UPDATE_DOCSTR: &'static str = "Returns a copy of an association list with the value of a key replaced.
+
+The first pair whose key is equal? to the key is replaced, in place, and later ones
+are kept. If there is none, the new pair is added at the front. The original list is
+unchanged.
+
+Examples:
+
+(alist-update '((a . 1) (b . 2)) 'a 10)
+=> ((a . 10) (b . 2))
+(alist-update '((a . 1)) 'c 3)
+=> ((c . 3) (a . 1))";
+pub fn alist_update(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => alist: Any, key: Any, value: Any);
+    let mut pairs = try!(alist_pairs("alist-update", &alist));
+    let updated = cons!(key.clone(), value).to_obj_ref();
+    match pairs.iter().position(|pair| pair_key(pair) == key) {
+        Some(i) => pairs[i] = updated,
+        None => pairs.insert(0, updated),
+    }
+    Ok(LispObj::from_vec(pairs).to_obj_ref())
+}
@@ -0,0 +1,98 @@
+//! Logging builtins. See the evaluator's `log` module for where messages go.
+use ::core::{LispObj, LispObjRef, AsLispObjRef, EnvironmentRef, EvalResult};
+use ::core::obj::Port;
+use super::super::log::{self, Level, LOG_LEVEL_NAME, LOG_PORT_NAME};
+use super::super::output;
+
+/// Logs the arguments as one message, if `level` is at least `*log-level*`. Returns
+/// whether it was logged.
+fn log_at(level: Level, args: &[LispObjRef], env: EnvironmentRef) -> EvalResult {
+    let (min_level, port) = {
+        let env = env.borrow();
+        (env.lookup(LOG_LEVEL_NAME), env.lookup(LOG_PORT_NAME))
+    };
+    let min_level = match min_level.as_ref().and_then(|l| l.symbol_ref()).and_then(Level::from_name) {
+        Some(min_level) => min_level,
+        None => argument_error!("{} must be one of debug, info, warn or error, not {}", LOG_LEVEL_NAME,
+                                min_level.unwrap_or_else(|| nil!().to_obj_ref())),
+    };
+    if level < min_level {
+        return Ok(lisp_false_ref!())
+    }
+
+    let mut msg = String::new();
+    for arg in args.iter() {
+        match arg.as_ref() {
+            &LispObj::LString(ref s) => msg.push_str(s),
+            other => msg.push_str(&format!("{}", other)),
+        }
+    }
+
+    match port {
+        Some(ref port) if !port.is_nil() => {
+            let port = check_type!(port.clone(), LPort);
+            try!(port.write_str(&format!("[{}] {}\n", level.name(), msg)));
+        },
+        _ => if !log::handle(level, &msg) {
+            let _ = output::eprint(format_args!("[{}] {}\n", level.name(), msg));
+        },
+    }
+    Ok(lisp_true_ref!())
+}
+
+pub const LOG_DEBUG_DOCSTR: &'static str = "Logs a message at the debug level, the lowest.
+
+The arguments are joined as print would print them. Messages below *log-level*
+(debug, info, warn or error; info by default) are dropped, and the others are
+written to *log-port*, or stderr if it is nil. Returns whether the message was logged.
+
+Examples:
+
+(set! *log-level* 'debug)
+(log-debug \"cache has \" 3 \" entries\")
+=> true";
+pub fn log_debug(args: &[LispObjRef], env: EnvironmentRef) -> EvalResult {
+    log_at(Level::Debug, args, env)
+}
+
+pub const LOG_INFO_DOCSTR: &'static str = "Logs a message at the info level. See log-debug.
+
+Examples:
+
+(log-info \"loaded \" 'config)
+=> true";
+pub fn log_info(args: &[LispObjRef], env: EnvironmentRef) -> EvalResult {
+    log_at(Level::Info, args, env)
+}
+
+pub const LOG_WARN_DOCSTR: &'static str = "Logs a message at the warn level. See log-debug.
+
+Examples:
+
+(log-warn \"retrying request\")
+=> true";
+pub fn log_warn(args: &[LispObjRef], env: EnvironmentRef) -> EvalResult {
+    log_at(Level::Warn, args, env)
+}
+
+pub const LOG_ERROR_DOCSTR: &'static str = "Logs a message at the error level, the highest. See log-debug.
+
+Examples:
+
+(log-error \"could not connect\")
+=> true";
+pub fn log_error(args: &[LispObjRef], env: EnvironmentRef) -> EvalResult {
+    log_at(Level::Error, args, env)
+}
+
+pub const OPEN_LOG_FILE_DOCSTR: &'static str = "Opens a file for appending log messages to, returning a port for *log-port*.
+
+Examples:
+
+(set! *log-port* (open-log-file \"app.log\"))";
+pub fn open_log_file(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => path: Any);
+    let path = try!(super::io::lisp_obj_to_path(path));
+    let port = try!(Port::append_file(path));
+    Ok(LispObj::LPort(port).to_obj_ref())
+}
@@ -1,3 +1,5 @@
+use std::cmp::Ordering;
+
 use ::core::{LispObj, LispObjRef, AsLispObjRef, EvalResult, EnvironmentRef};
 
 enum Number {
@@ -10,22 +12,31 @@ impl Number {
         match obj {
             &LispObj::LInteger(n) => Ok(Number::Int(n)),
             &LispObj::LFloat(n)   => Ok(Number::Float(n)),
-            val => type_error!("expecting number, got {}", val)
+            val => type_error!(args vec![val.clone().to_obj_ref()], expected "number"; "expecting number, got {}", val)
+        }
+    }
+
+    fn to_float(&self) -> f64 {
+        match *self {
+            Number::Int(n)   => n as f64,
+            Number::Float(n) => n,
         }
     }
 
-    fn into_lisp_obj(self) -> LispObj {
+    fn into_obj_ref(self) -> LispObjRef {
         match self {
             Number::Int(n)   => LispObj::LInteger(n),
             Number::Float(n) => LispObj::LFloat(n),
-        }
+        }.to_obj_ref()
     }
 }
 
 fn add_two(a: &mut Number, b: &LispObj) -> EvalResult<()> {
     *a = match (&*a, b) {
-        (&Number::Int(an), &LispObj::LInteger(bn))
-            => Number::Int(an + bn),
+        (&Number::Int(an), &LispObj::LInteger(bn)) => match an.checked_add(bn) {
+            Some(n) => Number::Int(n),
+            None => arithmetic_error!("integer overflow: {} + {}", an, bn),
+        },
         (&Number::Int(an), &LispObj::LFloat(bn))
             => Number::Float((an as f64) + bn),
         (&Number::Float(an), &LispObj::LInteger(bn))
@@ -33,7 +44,7 @@ fn add_two(a: &mut Number, b: &LispObj) -> EvalResult<()> {
         (&Number::Float(an), &LispObj::LFloat(bn))
             => Number::Float(an + bn),
         (_, right) 
-            => type_error!("expecting number, got {}", right),
+            => type_error!(args vec![right.clone().to_obj_ref()], expected "number"; "expecting number, got {}", right),
     };
     Ok(())
 }
@@ -49,7 +60,7 @@ fn div_two(a: &mut Number, b: &LispObj) -> EvalResult<()> {
         (&Number::Float(an), &LispObj::LFloat(bn))
             => Number::Float(an / bn),
         (_, right) 
-            => type_error!("expecting number, got {}", right),
+            => type_error!(args vec![right.clone().to_obj_ref()], expected "number"; "expecting number, got {}", right),
     };
 
     Ok(())
@@ -57,8 +68,10 @@ fn div_two(a: &mut Number, b: &LispObj) -> EvalResult<()> {
 
 fn mult_two(a: &mut Number, b: &LispObj) -> EvalResult<()> {
     *a = match (&*a, b) {
-        (&Number::Int(an), &LispObj::LInteger(bn))
-            => Number::Int(an * bn),
+        (&Number::Int(an), &LispObj::LInteger(bn)) => match an.checked_mul(bn) {
+            Some(n) => Number::Int(n),
+            None => arithmetic_error!("integer overflow: {} * {}", an, bn),
+        },
         (&Number::Int(an), &LispObj::LFloat(bn))
             => Number::Float((an as f64) * bn),
         (&Number::Float(an), &LispObj::LInteger(bn))
@@ -66,7 +79,7 @@ fn mult_two(a: &mut Number, b: &LispObj) -> EvalResult<()> {
         (&Number::Float(an), &LispObj::LFloat(bn))
             => Number::Float(an * bn),
         (_, right) 
-            => type_error!("expecting number, got {}", right),
+            => type_error!(args vec![right.clone().to_obj_ref()], expected "number"; "expecting number, got {}", right),
     };
 
     Ok(())
@@ -74,8 +87,10 @@ fn mult_two(a: &mut Number, b: &LispObj) -> EvalResult<()> {
 
 fn sub_two(a: &mut Number, b: &LispObj) -> EvalResult<()> {
     *a = match (&*a, b) {
-        (&Number::Int(an), &LispObj::LInteger(bn))
-            => Number::Int(an - bn),
+        (&Number::Int(an), &LispObj::LInteger(bn)) => match an.checked_sub(bn) {
+            Some(n) => Number::Int(n),
+            None => arithmetic_error!("integer overflow: {} - {}", an, bn),
+        },
         (&Number::Int(an), &LispObj::LFloat(bn))
             => Number::Float((an as f64) - bn),
         (&Number::Float(an), &LispObj::LInteger(bn))
@@ -83,7 +98,7 @@ fn sub_two(a: &mut Number, b: &LispObj) -> EvalResult<()> {
         (&Number::Float(an), &LispObj::LFloat(bn))
             => Number::Float(an - bn),
         (_, right) 
-            => type_error!("expecting number, got {}", right),
+            => type_error!(args vec![right.clone().to_obj_ref()], expected "number"; "expecting number, got {}", right),
     };
 
     Ok(())
@@ -91,7 +106,8 @@ fn sub_two(a: &mut Number, b: &LispObj) -> EvalResult<()> {
 
 pub const ADD_DOCSTR: &'static str = "Performs addition.
 
-Throws a 'type-error if any arguments are not numbers.
+Throws a 'type-error if any arguments are not numbers, and an 'arithmetic-error if
+integers overflow; see +wrap.
 
 Examples:
 
@@ -104,12 +120,13 @@ pub fn add(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
         try!(add_two(&mut out, &**num));
     }
 
-    Ok(out.into_lisp_obj().to_obj_ref())
+    Ok(out.into_obj_ref())
 }
 
 pub const SUB_DOCSTR: &'static str = "Performs subtraction.
 
-Throws a type-error if an argument is not a number.
+Throws a type-error if an argument is not a number, and an 'arithmetic-error if
+integers overflow.
 
 (- a b c d e ...)
 is equivalent to:
@@ -132,7 +149,7 @@ pub fn sub(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
     } else if args.len() == 1 {
         let mut zero = Number::Int(0);
         try!(sub_two(&mut zero, &*args[0]));
-        Ok(zero.into_lisp_obj().to_obj_ref())
+        Ok(zero.into_obj_ref())
     } else {
         let mut out = try!(Number::from_lisp_obj(&*args[0]));
 
@@ -140,7 +157,7 @@ pub fn sub(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
             try!(sub_two(&mut out, &**num))
         }
 
-        Ok(out.into_lisp_obj().to_obj_ref())
+        Ok(out.into_obj_ref())
     }
 }
 
@@ -150,7 +167,7 @@ pub fn division(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
     } else if args.len() == 1 {
         let mut one = Number::Float(1.0);
         try!(div_two(&mut one, &*args[0]));
-        Ok(one.into_lisp_obj().to_obj_ref())
+        Ok(one.into_obj_ref())
     } else {
         let mut out = try!(Number::from_lisp_obj(&*args[0]));
 
@@ -158,13 +175,14 @@ pub fn division(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
             try!(div_two(&mut out, &**num))
         }
 
-        Ok(out.into_lisp_obj().to_obj_ref())
+        Ok(out.into_obj_ref())
     }
 }
 
 pub const PRODUCT_DOCSTR: &'static str = "Performs multiplication.
 
-Throws a type-error if an argument is not a number.
+Throws a type-error if an argument is not a number, and an 'arithmetic-error if
+integers overflow; see *wrap.
 
 Examples:
 
@@ -180,5 +198,499 @@ pub fn product(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
         try!(mult_two(&mut out, &**num))
     }
 
-    Ok(out.into_lisp_obj().to_obj_ref())
+    Ok(out.into_obj_ref())
+}
+
+/// Whether two numbers have the same value, comparing an integer and a float exactly
+fn same_value(a: &Number, b: &Number) -> bool {
+    match (a, b) {
+        (&Number::Int(a), &Number::Int(b))     => a == b,
+        (&Number::Float(a), &Number::Float(b)) => a == b,
+        (&Number::Int(n), &Number::Float(f)) | (&Number::Float(f), &Number::Int(n))
+            // Converting n to a float could round it, so convert f to an integer
+            => f.fract() == 0.0 && f >= -9223372036854775808.0 && f < 9223372036854775808.0 && f as i64 == n,
+    }
+}
+
+pub const NUM_EQ_DOCSTR: &'static str = "Tests if numbers are all equal in value.
+
+Unlike equal?, integers and floats are compared by value, and NaN is not equal to
+anything, even itself. Throws a 'type-error if any argument is not a number.
+
+Examples:
+
+(= 1 1.0)
+=> true
+(= 2 2 3)
+=> false";
+pub fn num_eq(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    if args.len() < 2 {
+        arity_error!("=: expected at least 2 arguments, got {}", LispObj::to_lisp_list(args.iter()))
+    }
+
+    let mut nums = Vec::with_capacity(args.len());
+    for arg in args {
+        nums.push(try!(Number::from_lisp_obj(arg)));
+    }
+    Ok(lisp_bool_ref!(nums.windows(2).all(|pair| same_value(&pair[0], &pair[1]))))
+}
+
+/// Combines integers with a wrapping operation, for +wrap and *wrap
+fn wrapping(fname: &str, args: &[LispObjRef], start: i64, op: fn(i64, i64) -> i64) -> EvalResult {
+    let mut out = start;
+    for arg in args {
+        match **arg {
+            LispObj::LInteger(n) => out = op(out, n),
+            ref other => type_error!(args vec![arg.clone()], expected "integer"; "{}: expected integer, got {}", fname, other),
+        }
+    }
+    Ok(int!(out).to_obj_ref())
+}
+
+pub const ADD_WRAPPING_DOCSTR: &'static str = "Adds integers, wrapping around on overflow.
+
++ throws an 'arithmetic-error if integers overflow; this is for code which wants
+them to wrap instead. Throws a 'type-error if any argument is not an integer.
+
+Examples:
+
+(+wrap 9223372036854775807 1)
+=> -9223372036854775808";
+pub fn add_wrapping(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    wrapping("+wrap", args, 0, i64::wrapping_add)
+}
+
+pub const PRODUCT_WRAPPING_DOCSTR: &'static str = "Multiplies integers, wrapping around on overflow.
+
+* throws an 'arithmetic-error if integers overflow; this is for code which wants
+them to wrap instead. Throws a 'type-error if any argument is not an integer.
+
+Examples:
+
+(*wrap 4611686018427387904 2)
+=> -9223372036854775808";
+pub fn product_wrapping(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    wrapping("*wrap", args, 1, i64::wrapping_mul)
+}
+
+pub const EXPT_DOCSTR: &'static str = "Raises a number to a power.
+
+An integer raised to a non-negative integer power is exact, and throws an
+'arithmetic-error if it overflows. Otherwise the result is a float.
+
+Examples:
+
+(expt 2 10)
+=> 1024
+(expt 2 -1)
+=> 0.5
+(expt 9 0.5)
+=> 3.0";
+pub fn expt(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => base: Any, power: Any);
+    let res = match (try!(Number::from_lisp_obj(&base)), try!(Number::from_lisp_obj(&power))) {
+        (Number::Int(b), Number::Int(p)) if p >= 0 => {
+            let exact = if p > u32::max_value() as i64 {
+                // Only 0, 1 and -1 don't overflow
+                match b {
+                    0 | 1 => Some(b),
+                    -1 => Some(if p % 2 == 0 { 1 } else { -1 }),
+                    _ => None,
+                }
+            } else {
+                b.checked_pow(p as u32)
+            };
+            match exact {
+                Some(n) => Number::Int(n),
+                None => arithmetic_error!("integer overflow: (expt {} {})", b, p),
+            }
+        },
+        (b, p) => Number::Float(b.to_float().powf(p.to_float())),
+    };
+    Ok(res.into_obj_ref())
+}
+
+/// The greatest common divisor of two magnitudes
+fn gcd_u64(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        let r = a % b;
+        a = b;
+        b = r;
+    }
+    a
+}
+
+/// The magnitudes of integer arguments
+fn magnitudes(fname: &str, args: &[LispObjRef]) -> EvalResult<Vec<u64>> {
+    let mut out = Vec::with_capacity(args.len());
+    for arg in args {
+        match **arg {
+            LispObj::LInteger(n) => out.push(if n < 0 { (n as u64).wrapping_neg() } else { n as u64 }),
+            ref other => type_error!(args vec![arg.clone()], expected "integer"; "{}: expected integer, got {}", fname, other),
+        }
+    }
+    Ok(out)
+}
+
+/// Converts a non-negative result back to an integer
+fn magnitude_to_int(fname: &str, n: u64) -> EvalResult {
+    if n > ::std::i64::MAX as u64 {
+        arithmetic_error!("integer overflow: {} of these integers is {}", fname, n)
+    }
+    Ok(int!(n as i64).to_obj_ref())
+}
+
+pub const GCD_DOCSTR: &'static str = "The greatest common divisor of integers, which is never negative.
+
+(gcd) is 0. Throws a 'type-error if any argument is not an integer.
+
+Examples:
+
+(gcd 12 18)
+=> 6
+(gcd -4 6 10)
+=> 2";
+pub fn gcd(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    let nums = try!(magnitudes("gcd", args));
+    magnitude_to_int("gcd", nums.into_iter().fold(0, gcd_u64))
+}
+
+pub const LCM_DOCSTR: &'static str = "The least common multiple of integers, which is never negative.
+
+(lcm) is 1, and the lcm of anything with 0 is 0. Throws a 'type-error if any
+argument is not an integer, and an 'arithmetic-error if the result overflows.
+
+Examples:
+
+(lcm 4 6)
+=> 12
+(lcm -3 5)
+=> 15";
+pub fn lcm(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    let nums = try!(magnitudes("lcm", args));
+    let mut out: u64 = 1;
+    for n in nums {
+        if n == 0 || out == 0 {
+            out = 0;
+            continue
+        }
+        out = match (out / gcd_u64(out, n)).checked_mul(n) {
+            Some(m) => m,
+            None => arithmetic_error!("integer overflow: lcm is too large"),
+        };
+    }
+    magnitude_to_int("lcm", out)
+}
+
+/// Compares two numbers by value, or None if either is NaN
+fn compare(a: &Number, b: &Number) -> Option<Ordering> {
+    match (a, b) {
+        (&Number::Int(a), &Number::Int(b)) => Some(a.cmp(&b)),
+        (a, b) => a.to_float().partial_cmp(&b.to_float()),
+    }
+}
+
+/// The argument which compares as `keep` to all the others, for min and max
+fn extreme(fname: &str, args: &[LispObjRef], keep: Ordering) -> EvalResult {
+    if args.len() == 0 {
+        arity_error!("{}: expected at least 1 argument", fname)
+    }
+
+    let mut best = &args[0];
+    let mut best_num = try!(Number::from_lisp_obj(best));
+    for arg in &args[1..] {
+        let num = try!(Number::from_lisp_obj(arg));
+        match compare(&num, &best_num) {
+            Some(ord) if ord == keep => {
+                best = arg;
+                best_num = num;
+            },
+            Some(_) => {},
+            None => argument_error!("{}: cannot compare NaN", fname),
+        }
+    }
+    Ok(best.clone())
+}
+
+pub const MIN_DOCSTR: &'static str = "The smallest of its arguments, which must be numbers.
+
+Throws a 'type-error if an argument is not a number, and an 'argument-error if one
+is NaN.
+
+Examples:
+
+(min 3 1 2)
+=> 1
+(min 1.5 2)
+=> 1.5";
+pub fn min(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    extreme("min", args, Ordering::Less)
+}
+
+pub const MAX_DOCSTR: &'static str = "The largest of its arguments, which must be numbers.
+
+Throws a 'type-error if an argument is not a number, and an 'argument-error if one
+is NaN.
+
+Examples:
+
+(max 3 1 2)
+=> 3";
+pub fn max(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    extreme("max", args, Ordering::Greater)
+}
+
+/// The items of a list or vector of numbers, for sum and product-of
+fn number_items(fname: &str, args: &[LispObjRef]) -> EvalResult<Vec<LispObjRef>> {
+    unpack_args!(args => seq: Any);
+    if let Some(vec) = seq.vec_ref() {
+        return Ok(vec.iter().cloned().collect())
+    }
+    match seq.list_iter().collect() {
+        Ok(items) => Ok(items),
+        Err(()) => type_error!(args vec![seq.clone()], expected "list"; "{}: expected a list or vector, got {}", fname, seq),
+    }
+}
+
+pub const SUM_DOCSTR: &'static str = "Adds up a list or vector of numbers.
+
+Throws a 'type-error if an element is not a number.
+
+Examples:
+
+(sum '(1 2 3))
+=> 6
+(sum [])
+=> 0";
+pub fn sum(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    let mut out = Number::Int(0);
+    for num in try!(number_items("sum", args)) {
+        try!(add_two(&mut out, &num));
+    }
+    Ok(out.into_obj_ref())
+}
+
+pub const PRODUCT_OF_DOCSTR: &'static str = "Multiplies together a list or vector of numbers.
+
+Throws a 'type-error if an element is not a number.
+
+Examples:
+
+(product-of '(1 2 3 4))
+=> 24
+(product-of [])
+=> 1";
+pub fn product_of(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    let mut out = Number::Int(1);
+    for num in try!(number_items("product-of", args)) {
+        try!(mult_two(&mut out, &num));
+    }
+    Ok(out.into_obj_ref())
+}
+
+/*********************** Predicates ************************/
+
+pub const IS_NUMBER_DOCSTR: &'static str = "Tests if an object is a number, either an integer or a float.
+
+Examples:
+
+(number? 1.5)
+=> true
+(number? \"1\")
+=> false";
+pub fn is_number(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => arg: Any);
+    Ok(lisp_bool_ref!(Number::from_lisp_obj(&arg).is_ok()))
+}
+
+pub const IS_INTEGER_DOCSTR: &'static str = "Tests if an object is an integer. Floats are never integers, even 2.0.
+
+Examples:
+
+(integer? 2)
+=> true
+(integer? 2.0)
+=> false";
+pub fn is_integer(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => arg: Any);
+    Ok(lisp_bool_ref!(arg.is_int()))
+}
+
+pub const IS_FLOAT_DOCSTR: &'static str = "Tests if an object is a float.
+
+Examples:
+
+(float? 2.0)
+=> true
+(float? 2)
+=> false";
+pub fn is_float(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => arg: Any);
+    Ok(lisp_bool_ref!(arg.is_float()))
+}
+
+pub const IS_EXACT_DOCSTR: &'static str = "Tests if a number is exact. Integers are exact, and floats are not.
+
+Throws a 'type-error if the argument is not a number.
+
+Examples:
+
+(exact? 1)
+=> true
+(exact? 1.0)
+=> false";
+pub fn is_exact(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => arg: Any);
+    match try!(Number::from_lisp_obj(&arg)) {
+        Number::Int(_)   => Ok(lisp_true_ref!()),
+        Number::Float(_) => Ok(lisp_false_ref!()),
+    }
+}
+
+pub const IS_INEXACT_DOCSTR: &'static str = "Tests if a number is inexact, i.e. a float.
+
+Throws a 'type-error if the argument is not a number.
+
+Examples:
+
+(inexact? 1.0)
+=> true";
+pub fn is_inexact(args: &[LispObjRef], env: EnvironmentRef) -> EvalResult {
+    let exact = try!(is_exact(args, env));
+    Ok(lisp_bool_ref!(exact.falsey()))
+}
+
+/// Compares a number to zero, for zero?, positive? and negative?
+fn sign(fname: &str, args: &[LispObjRef]) -> EvalResult<Ordering> {
+    unpack_args!(args => arg: Any);
+    match Number::from_lisp_obj(&arg) {
+        Ok(Number::Int(n))   => Ok(n.cmp(&0)),
+        Ok(Number::Float(n)) => match n.partial_cmp(&0.0) {
+            Some(ord) => Ok(ord),
+            // NaN is neither zero, positive nor negative
+            None => argument_error!("{}: cannot compare NaN to zero", fname),
+        },
+        Err(_) => type_error!(args vec![arg.clone()], expected "number"; "{}: expected number, not {}", fname, arg),
+    }
+}
+
+pub const IS_ZERO_DOCSTR: &'static str = "Tests if a number is zero.
+
+Examples:
+
+(zero? 0)
+=> true
+(zero? 0.0)
+=> true";
+pub fn is_zero(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    let ord = try!(sign("zero?", args));
+    Ok(lisp_bool_ref!(ord == Ordering::Equal))
+}
+
+pub const IS_POSITIVE_DOCSTR: &'static str = "Tests if a number is greater than zero.
+
+Examples:
+
+(positive? 3)
+=> true
+(positive? 0)
+=> false";
+pub fn is_positive(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    let ord = try!(sign("positive?", args));
+    Ok(lisp_bool_ref!(ord == Ordering::Greater))
+}
+
+pub const IS_NEGATIVE_DOCSTR: &'static str = "Tests if a number is less than zero.
+
+Examples:
+
+(negative? -1.5)
+=> true
+(negative? 0)
+=> false";
+pub fn is_negative(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    let ord = try!(sign("negative?", args));
+    Ok(lisp_bool_ref!(ord == Ordering::Less))
+}
+
+pub const IS_EVEN_DOCSTR: &'static str = "Tests if an integer is even.
+
+Throws a 'type-error if the argument is not an integer.
+
+Examples:
+
+(even? 4)
+=> true";
+pub fn is_even(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => n: LInteger);
+    Ok(lisp_bool_ref!(n % 2 == 0))
+}
+
+pub const IS_ODD_DOCSTR: &'static str = "Tests if an integer is odd.
+
+Throws a 'type-error if the argument is not an integer.
+
+Examples:
+
+(odd? -3)
+=> true";
+pub fn is_odd(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => n: LInteger);
+    Ok(lisp_bool_ref!(n % 2 != 0))
+}
+
+/// Writes the magnitude of `n` in `radix`, which must be from 2 to 36.
+fn digits_in_radix(mut n: u64, radix: u64) -> String {
+    let mut digits = vec![];
+    loop {
+        digits.push(::std::char::from_digit((n % radix) as u32, radix as u32).unwrap());
+        n /= radix;
+        if n == 0 {
+            break
+        }
+    }
+    digits.iter().rev().cloned().collect()
+}
+
+pub const NUMBER_TO_STRING_DOCSTR: &'static str = "Converts a number to a string, optionally in a radix and with a precision.
+
+Integers can be written in any radix from 2 to 36, and floats only in radix 10. With a
+precision, the number is written with that many digits after the decimal point. Without
+one, floats are written as they print, and read back as the same float.
+
+Examples:
+
+(number->string 1.0)
+=> \"1.0\"
+(number->string 255 16)
+=> \"ff\"
+(number->string -5 2)
+=> \"-101\"
+(number->string 3.14159 10 2)
+=> \"3.14\"";
+pub fn number_to_string(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => num: Any, opt radix: LInteger, opt precision: LInteger);
+    let num = try!(Number::from_lisp_obj(&num));
+    let radix = radix.unwrap_or(10);
+    if radix < 2 || radix > 36 {
+        argument_error!(args vec![int!(radix).to_obj_ref()]; "number->string: radix must be from 2 to 36, not {}", radix)
+    }
+    if let Some(precision) = precision {
+        if precision < 0 {
+            argument_error!("number->string: precision must not be negative, not {}", precision)
+        }
+    }
+
+    let s = match (num, precision) {
+        (Number::Int(n), None) => {
+            let sign = if n < 0 { "-" } else { "" };
+            format!("{}{}", sign, digits_in_radix(n.wrapping_abs() as u64, radix as u64))
+        },
+        (_, Some(_)) | (Number::Float(_), _) if radix != 10 =>
+            argument_error!("number->string: only integers can be written in radix {}", radix),
+        (Number::Int(n), Some(precision)) => format!("{:.*}", precision as usize, n as f64),
+        (Number::Float(n), Some(precision)) => format!("{:.*}", precision as usize, n),
+        (Number::Float(n), None) => format!("{}", LispObj::LFloat(n)),
+    };
+    Ok(string!(s).to_obj_ref())
 }
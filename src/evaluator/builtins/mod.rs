@@ -4,15 +4,32 @@
 //! lisp equivalents, but for some (like `+`) this is not possible, and so are named differently.
 //!
 //! Check BUILTIN_FUNCS to be sure.
+mod bytes;
+mod control;
+mod f64vector;
+mod http;
 mod io;
+mod json;
+mod lists;
+mod log;
 mod math;
+mod native;
+mod net;
+mod os;
+mod record;
+mod strings;
+mod threads;
+mod time;
+
+pub use self::io::{file_may_rebind, note_definition, pop_directory, push_directory, with_file_loading};
+pub use self::native::{NativeRegisterFn, NATIVE_REGISTER_SYMBOL};
 
 use std::convert::AsRef;
 
-use ::core::{LispObj, LispObjRef, AsLispObjRef, RuntimeError, EnvironmentRef};
-use ::core::obj::{NativeFuncSignature, Procedure};
+use ::core::{LispObj, LispObjRef, AsLispObjRef, RuntimeError, Environment, EnvironmentRef};
+use ::core::obj::{char_from_name, NativeFuncSignature, Procedure};
 use ::core::obj::vec::{self, PersistentVec};
-use super::EvalResult;
+use super::{output, EvalResult};
 
 // TODO add documentation for functions
 //
@@ -20,65 +37,270 @@ use super::EvalResult;
 //  Numeric comparison: =, <=, >=, <, >
 //  Absolute equality:  eq?
 
+/// The name of the list of tests defined with `deftest`, as `(name . procedure)` pairs
+pub static TESTS_NAME: &'static str = "*tests*";
+
+/// The name of the flag which lets define rebind names. Loading a file restores it
+/// afterwards, so a file can set it for just itself.
+pub static ALLOW_REDEFINE_NAME: &'static str = "*allow-redefine*";
+
+/// The name of the list of procedures registered with `at-exit`, most recent first
+pub static EXIT_HOOKS_NAME: &'static str = "*exit-hooks*";
+
+/// The name of the flag which makes only nil and `false` count as false
+pub static STRICT_BOOLEANS_NAME: &'static str = "*strict-booleans*";
+
+/// Native functions which are not bound in the namespace, but are captured by procedures
+/// that builtins make, so that images can refer to them
+pub static HIDDEN_NATIVES: &'static [(&'static str, NativeFuncSignature)] = &[
+    ("escape", control::escape),
+    ("yield", control::generator_yield),
+];
+
+/// Builtins left out of sandboxed environments, as they use the file system, processes,
+/// the network or threads, or could block past the sandbox's time limit
+pub static SANDBOX_EXCLUDED: &'static [&'static str] = &[
+    // I/O
+    "change-directory", "current-directory", "load-file", "load-image", "pop-directory",
+    "push-directory", "reload", "save-image",
+    // File system
+    "absolute-path", "delete-file", "directory?", "file-size", "list-directory",
+    "make-directory", "rename-file", "read-file-bytes", "write-file-bytes", "open-log-file",
+    // Networking
+    "http-get", "http-request", "socket-address", "socket-close", "socket-read-line",
+    "socket-write", "tcp-accept", "tcp-connect", "tcp-listen",
+    // OS
+    "exit", "getenv", "load-native", "on-signal", "process-output", "setenv", "system",
+    // Threads, including generators' producers, and sleep and reading stdin, which
+    // limits can't interrupt
+    "join", "make-generator", "next", "spawn", "sleep", "read",
+];
+
 /// Native functions defined in the default lisp namespace
 pub static BUILTIN_FUNCS: &'static [(&'static str, NativeFuncSignature, Option<&'static str>)] = &[
     // Arithmetic
     ("+", math::add, Some(math::ADD_DOCSTR)), ("-", math::sub, Some(math::SUB_DOCSTR)), 
     ("*", math::product, Some(math::PRODUCT_DOCSTR)), ("/", math::division, None),
+    ("+wrap", math::add_wrapping, Some(math::ADD_WRAPPING_DOCSTR)),
+    ("*wrap", math::product_wrapping, Some(math::PRODUCT_WRAPPING_DOCSTR)),
+    ("expt", math::expt, Some(math::EXPT_DOCSTR)),
+    ("gcd", math::gcd, Some(math::GCD_DOCSTR)), ("lcm", math::lcm, Some(math::LCM_DOCSTR)),
+    ("min", math::min, Some(math::MIN_DOCSTR)), ("max", math::max, Some(math::MAX_DOCSTR)),
+    ("sum", math::sum, Some(math::SUM_DOCSTR)), ("product-of", math::product_of, Some(math::PRODUCT_OF_DOCSTR)),
 
     // Meta
-    ("apply", apply, None), ("doc", doc, None), ("eval", eval, None), ("macro-expand", macro_expand, None),
+    ("apply", apply, None),
+    ("call-with-escape-continuation", control::call_with_escape_continuation, Some(control::CALL_WITH_ESCAPE_CONTINUATION_DOCSTR)),
+    ("call/ec", control::call_with_escape_continuation, Some(control::CALL_WITH_ESCAPE_CONTINUATION_DOCSTR)),
+    ("make-generator", control::make_generator, Some(control::MAKE_GENERATOR_DOCSTR)),
+    ("next", control::next, Some(control::NEXT_DOCSTR)),
+    ("completions", completions, Some(COMPLETIONS_DOCSTR)),
+    ("current-environment", current_environment, Some(CURRENT_ENVIRONMENT_DOCSTR)),
+    ("doc", doc, Some(DOC_DOCSTR)),
+    ("environment-define", environment_define, Some(ENVIRONMENT_DEFINE_DOCSTR)),
+    ("eval", eval, Some(EVAL_DOCSTR)), ("eval-string", eval_string, Some(EVAL_STRING_DOCSTR)),
+    ("format-string", format_string, Some(FORMAT_STRING_DOCSTR)),
+    ("gensym", gensym, Some(GENSYM_DOCSTR)),
+    ("make-environment", make_environment, Some(MAKE_ENVIRONMENT_DOCSTR)),
+    ("macro-expand", macro_expand, None), ("macro-expand-all", macro_expand_all, Some(MACRO_EXPAND_ALL_DOCSTR)),
+    ("meta", meta, Some(META_DOCSTR)), ("set-meta!", set_meta, Some(SET_META_DOCSTR)),
+    ("procedure->list", procedure_to_list, Some(PROCEDURE_TO_LIST_DOCSTR)),
+
+    // Logic
+    ("not", not, Some(NOT_DOCSTR)),
 
     // Predicates
+    ("boolean?", is_boolean, Some(IS_BOOLEAN_DOCSTR)),
     ("bound?",  is_bound, None),  ("cons?",   is_cons, None),
+    ("char?",   is_char, None),   ("lazy-cons?", is_lazy_cons, None),
     ("error?",  is_error, None),  ("list?",   is_list, None),
+    ("native?", is_native, None), ("procedure?", is_procedure, None),
     ("nil?",    is_nil, None),    ("symbol?", is_symbol, None),
+    ("keyword?", is_keyword, Some(IS_KEYWORD_DOCSTR)),
+    ("environment?", is_environment, None),
+    ("port?",   is_port, None),   ("record?", record::is_record, Some(record::IS_RECORD_DOCSTR)),
     ("string?", is_string, None), ("vector?", is_vector, None),
 
+    // Numeric predicates
+    ("number?",   math::is_number, Some(math::IS_NUMBER_DOCSTR)),
+    ("integer?",  math::is_integer, Some(math::IS_INTEGER_DOCSTR)),
+    ("float?",    math::is_float, Some(math::IS_FLOAT_DOCSTR)),
+    ("exact?",    math::is_exact, Some(math::IS_EXACT_DOCSTR)),
+    ("inexact?",  math::is_inexact, Some(math::IS_INEXACT_DOCSTR)),
+    ("zero?",     math::is_zero, Some(math::IS_ZERO_DOCSTR)),
+    ("positive?", math::is_positive, Some(math::IS_POSITIVE_DOCSTR)),
+    ("negative?", math::is_negative, Some(math::IS_NEGATIVE_DOCSTR)),
+    ("even?",     math::is_even, Some(math::IS_EVEN_DOCSTR)),
+    ("odd?",      math::is_odd, Some(math::IS_ODD_DOCSTR)),
+
     // Equality
+    ("equal?", equal, Some(EQUAL_DOCSTR)), ("hash", hash, Some(HASH_DOCSTR)),
+    ("=", math::num_eq, Some(math::NUM_EQ_DOCSTR)),
     ("symbol=?", symbol_eq, None), ("string=?", string_eq, None),
 
     // Accessors
     ("error-source",  get_error_source, None),
-    ("error-type",    get_error_type, None),
-    ("error-value",   get_error_value, None),
+    ("error-detail",  get_error_detail, Some(ERROR_DETAIL_DOCSTR)),
+    ("error-type",    get_error_type, Some(ERROR_TYPE_DOCSTR)),
+    ("error-value",   get_error_value, Some(ERROR_VALUE_DOCSTR)),
     ("vector-length", get_vector_length, None),
-    ("vector-ref",    get_vector_index, None),
+    ("vector-ref",    get_vector_index, Some(VECTOR_REF_DOCSTR)),
+    ("vector-ref-safe", get_vector_index_safe, Some(VECTOR_REF_SAFE_DOCSTR)),
     ("string-length", get_string_length, None),
-    ("string-ref",    get_string_index, None),
+    ("string-ref",    get_string_index, Some(STRING_REF_DOCSTR)),
 
     ("string", string_append_objects, None),
+    ("format", strings::format, Some(strings::FORMAT_DOCSTR)),
+
+    // String builders
+    ("builder-append!",     strings::builder_append, Some(strings::BUILDER_APPEND_DOCSTR)),
+    ("builder->string",     strings::builder_to_string, Some(strings::BUILDER_TO_STRING_DOCSTR)),
+    ("make-string-builder", strings::make_string_builder, Some(strings::MAKE_STRING_BUILDER_DOCSTR)),
 
     // Conversion
     ("list->vector",   list_to_vector, None),
+    ("number->string", math::number_to_string, Some(math::NUMBER_TO_STRING_DOCSTR)),
     ("vector->list",   vector_to_list, None),
     ("string->list",   string_to_list, None),
     ("string->symbol", string_to_symbol, None),
     ("symbol->char",   symbol_to_char, None),
     ("symbol->string", symbol_to_string, None),
+    ("json->lisp",     json::json_to_lisp, Some(json::JSON_TO_LISP_DOCSTR)),
+    ("lisp->json",     json::lisp_to_json, Some(json::LISP_TO_JSON_DOCSTR)),
 
     // Manipulation & creation
     ("car", car, None), ("cdr", cdr, None), ("cons", cons, None),
+    ("nth",  lists::nth, Some(lists::NTH_DOCSTR)), ("last", lists::last, Some(lists::LAST_DOCSTR)),
+    ("take", lists::take, Some(lists::TAKE_DOCSTR)), ("drop", lists::drop, Some(lists::DROP_DOCSTR)),
+    ("list-tail", lists::drop, Some(lists::DROP_DOCSTR)),
+
+    // Association lists
+    ("assoc", lists::assoc, Some(lists::ASSOC_DOCSTR)), ("assq", lists::assq, Some(lists::ASSQ_DOCSTR)),
+    ("alist-update", lists::alist_update, Some(lists::ALIST_UPDATE_DOCSTR)),
     ("make-vector",     make_vector, None),
     ("generate-vector", generate_vector, None),
     ("vector-assoc",    vector_assoc, None),
     ("vector-append",   vector_append, None),
     ("vector-map",      vector_map, None),
 
+    // Bytes
+    ("bytes?",        bytes::is_bytes, Some(bytes::IS_BYTES_DOCSTR)),
+    ("bytes-length",  bytes::bytes_length, Some(bytes::BYTES_LENGTH_DOCSTR)),
+    ("bytes-ref",     bytes::bytes_ref, Some(bytes::BYTES_REF_DOCSTR)),
+    ("bytes-set",     bytes::bytes_set, Some(bytes::BYTES_SET_DOCSTR)),
+    ("bytes->list",   bytes::bytes_to_list, Some(bytes::BYTES_TO_LIST_DOCSTR)),
+    ("bytes->string", bytes::bytes_to_string, Some(bytes::BYTES_TO_STRING_DOCSTR)),
+    ("list->bytes",   bytes::list_to_bytes, Some(bytes::LIST_TO_BYTES_DOCSTR)),
+    ("make-bytes",    bytes::make_bytes, Some(bytes::MAKE_BYTES_DOCSTR)),
+    ("string->bytes", bytes::string_to_bytes, Some(bytes::STRING_TO_BYTES_DOCSTR)),
+
+    // Float vectors
+    ("f64vector?",       f64vector::is_f64vector, Some(f64vector::IS_F64VECTOR_DOCSTR)),
+    ("f64vector-add",    f64vector::f64vector_add, Some(f64vector::F64VECTOR_ADD_DOCSTR)),
+    ("f64vector-length", f64vector::f64vector_length, Some(f64vector::F64VECTOR_LENGTH_DOCSTR)),
+    ("f64vector-mul",    f64vector::f64vector_mul, Some(f64vector::F64VECTOR_MUL_DOCSTR)),
+    ("f64vector-ref",    f64vector::f64vector_ref, Some(f64vector::F64VECTOR_REF_DOCSTR)),
+    ("f64vector-sum",    f64vector::f64vector_sum, Some(f64vector::F64VECTOR_SUM_DOCSTR)),
+    ("f64vector->list",  f64vector::f64vector_to_list, Some(f64vector::F64VECTOR_TO_LIST_DOCSTR)),
+    ("list->f64vector",  f64vector::list_to_f64vector, Some(f64vector::LIST_TO_F64VECTOR_DOCSTR)),
+    ("make-f64vector",   f64vector::make_f64vector, Some(f64vector::MAKE_F64VECTOR_DOCSTR)),
+
+    // Records
+    ("make-record",   record::make_record, Some(record::MAKE_RECORD_DOCSTR)),
+    ("record-fields", record::record_fields, Some(record::RECORD_FIELDS_DOCSTR)),
+    ("record-get",    record::record_get, Some(record::RECORD_GET_DOCSTR)),
+    ("record-set",    record::record_set, Some(record::RECORD_SET_DOCSTR)),
+    ("record-type",   record::record_type, Some(record::RECORD_TYPE_DOCSTR)),
+
     // Error
     ("make-error",  make_error, None),
     ("throw-error", throw_error, None),
 
+    // Control
+    ("exit", exit, Some(EXIT_DOCSTR)),
+    ("at-exit", at_exit, Some(AT_EXIT_DOCSTR)),
+
+    // Testing
+    ("assert",       assert, Some(ASSERT_DOCSTR)),
+    ("assert-equal", assert_equal, Some(ASSERT_EQUAL_DOCSTR)),
+    ("run-tests",    run_tests, Some(RUN_TESTS_DOCSTR)),
+
+    // Networking
+    ("http-get",         http::http_get, Some(http::HTTP_GET_DOCSTR)),
+    ("http-request",     http::http_request, Some(http::HTTP_REQUEST_DOCSTR)),
+    ("socket-address",   net::socket_address, Some(net::SOCKET_ADDRESS_DOCSTR)),
+    ("socket-close",     net::socket_close, Some(net::SOCKET_CLOSE_DOCSTR)),
+    ("socket-read-line", net::socket_read_line, Some(net::SOCKET_READ_LINE_DOCSTR)),
+    ("socket-write",     net::socket_write, Some(net::SOCKET_WRITE_DOCSTR)),
+    ("tcp-accept",       net::tcp_accept, Some(net::TCP_ACCEPT_DOCSTR)),
+    ("tcp-connect",      net::tcp_connect, Some(net::TCP_CONNECT_DOCSTR)),
+    ("tcp-listen",       net::tcp_listen, Some(net::TCP_LISTEN_DOCSTR)),
+
+    // Threads
+    ("channel", threads::channel, Some(threads::CHANNEL_DOCSTR)),
+    ("join",    threads::join, Some(threads::JOIN_DOCSTR)),
+    ("recv",    threads::recv, Some(threads::RECV_DOCSTR)),
+    ("send",    threads::send, Some(threads::SEND_DOCSTR)),
+    ("spawn",   threads::spawn, Some(threads::SPAWN_DOCSTR)),
+
+    // OS
+    ("getenv",         os::getenv, Some(os::GETENV_DOCSTR)),
+    ("load-native",    native::load_native, Some(native::LOAD_NATIVE_DOCSTR)),
+    ("on-signal",      os::on_signal, Some(os::ON_SIGNAL_DOCSTR)),
+    ("process-output", os::process_output, Some(os::PROCESS_OUTPUT_DOCSTR)),
+    ("setenv",         os::setenv, Some(os::SETENV_DOCSTR)),
+    ("system",         os::system, Some(os::SYSTEM_DOCSTR)),
+
+    // Time
+    ("current-milliseconds", time::current_milliseconds, Some(time::CURRENT_MILLISECONDS_DOCSTR)),
+    ("current-seconds",      time::current_seconds, Some(time::CURRENT_SECONDS_DOCSTR)),
+    ("date",                 time::date, Some(time::DATE_DOCSTR)),
+    ("sleep",                time::sleep, Some(time::SLEEP_DOCSTR)),
+
+    // Memory
+    ("gc",       gc, Some(GC_DOCSTR)),
+    ("gc-stats", gc_stats, Some(GC_STATS_DOCSTR)),
+
+    // Profiling
+    ("profile-report", profile_report, Some(PROFILE_REPORT_DOCSTR)),
+    ("profile-start",  profile_start, Some(PROFILE_START_DOCSTR)),
+
+    // Debugging
+    ("trace",   trace, Some(TRACE_DOCSTR)),
+    ("untrace", untrace, Some(UNTRACE_DOCSTR)),
+
+    // Logging
+    ("log-debug",     log::log_debug, Some(log::LOG_DEBUG_DOCSTR)),
+    ("log-error",     log::log_error, Some(log::LOG_ERROR_DOCSTR)),
+    ("log-info",      log::log_info, Some(log::LOG_INFO_DOCSTR)),
+    ("log-warn",      log::log_warn, Some(log::LOG_WARN_DOCSTR)),
+    ("open-log-file", log::open_log_file, Some(log::OPEN_LOG_FILE_DOCSTR)),
+
     // I/O
     ("change-directory",  io::lisp_set_current_dir, None),
     ("current-directory", io::lisp_get_current_dir, None),
     ("dump-traceback",    dump_traceback, None),
     ("load-file",         io::load_file_handler, None),
+    ("reload",            io::reload_handler, Some(io::RELOAD_DOCSTR)),
+    ("source-file",       io::source_file, Some(io::SOURCE_FILE_DOCSTR)),
+    ("load-image",        io::load_image_handler, None),
     ("pop-directory",     io::lisp_pop_directory, None),
     ("push-directory",    io::lisp_push_directory, None),
     ("print",             io::print, None),
     ("println",           io::println, None),
-    ("read",              io::read_handler, None),
+    ("read",              io::read_handler, Some(io::READ_DOCSTR)),
+    ("save-image",        io::save_image_handler, None),
+
+    // File system
+    ("absolute-path",  io::absolute_path, Some(io::ABSOLUTE_PATH_DOCSTR)),
+    ("delete-file",    io::delete_file, Some(io::DELETE_FILE_DOCSTR)),
+    ("directory?",     io::is_directory, Some(io::IS_DIRECTORY_DOCSTR)),
+    ("file-size",      io::file_size, Some(io::FILE_SIZE_DOCSTR)),
+    ("list-directory", io::list_directory, Some(io::LIST_DIRECTORY_DOCSTR)),
+    ("make-directory", io::make_directory, Some(io::MAKE_DIRECTORY_DOCSTR)),
+    ("rename-file",    io::rename_file, Some(io::RENAME_FILE_DOCSTR)),
+    ("read-file-bytes",  io::read_file_bytes, Some(io::READ_FILE_BYTES_DOCSTR)),
+    ("write-file-bytes", io::write_file_bytes, Some(io::WRITE_FILE_BYTES_DOCSTR)),
 ];
 
 
@@ -86,8 +308,19 @@ pub static BUILTIN_FUNCS: &'static [(&'static str, NativeFuncSignature, Option<&
 ///
 /// Currently, only maps the symbols true and false to themselves.
 pub fn builtin_vals() -> Vec<(&'static str, LispObj)> {
-    vec![("true", lisp_true!()), ("false", lisp_false!()), ("nil", nil!()), ("*allow-redefine*", lisp_false!()),
-         (io::DIRECTORY_STACK_NAME, lisp_list![])]
+    vec![("true", lisp_true!()), ("false", lisp_false!()), ("nil", nil!()), (ALLOW_REDEFINE_NAME, lisp_false!()),
+         (io::DIRECTORY_STACK_NAME, lisp_list![]), (io::LOADED_FILES_NAME, lisp_list![]),
+         ("*command-line-args*", lisp_list![]),
+         (super::trace::TRACE_ALL_NAME, lisp_false!()), (TESTS_NAME, lisp_list![]),
+         (STRICT_BOOLEANS_NAME, lisp_false!()),
+         (super::log::LOG_LEVEL_NAME, symbol!("info")), (super::log::LOG_PORT_NAME, nil!()),
+         (super::macros::TRACE_MACRO_EXPANSION_NAME, lisp_false!()),
+         (super::complete::COMPLETION_FUNCTION_NAME, nil!()),
+         (super::output::COLOR_OUTPUT_NAME, lisp_true!()), (super::output::ERROR_FORMATTER_NAME, nil!()),
+         (super::output::PROMPT_NAME, nil!()), (super::output::PRINT_ARITY_NAME, lisp_false!()),
+         (super::interrupt::SIGNAL_HANDLERS_NAME, lisp_list![]),
+         (http::HTTP_MAX_BODY_NAME, int!(http::DEFAULT_MAX_BODY)),
+         (EXIT_HOOKS_NAME, lisp_list![])]
 }
 
 pub fn apply(args: &[LispObjRef], env: EnvironmentRef) -> EvalResult {
@@ -95,6 +328,46 @@ pub fn apply(args: &[LispObjRef], env: EnvironmentRef) -> EvalResult {
     super::apply(func, arg, env)
 }
 
+pub const ASSERT_DOCSTR: &'static str = "Throws an 'assertion-error if a value is false, with an optional message.
+
+Examples:
+
+(assert (= 1 1))
+=> true
+(assert (= 1 2) \"numbers are broken\")
+=> assertion-error: \"numbers are broken\"";
+pub fn assert(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => val: Any, opt msg: LString);
+    if super::is_false(&val) {
+        match msg {
+            Some(msg) => assertion_error!("{}", msg),
+            None => assertion_error!("assertion failed"),
+        }
+    }
+    Ok(lisp_true_ref!())
+}
+
+pub const ASSERT_EQUAL_DOCSTR: &'static str = "Throws an 'assertion-error unless a value equals the expected value.
+
+Takes an optional message.
+
+Examples:
+
+(assert-equal (+ 1 1) 2)
+=> true
+(assert-equal (+ 1 1) 3)
+=> assertion-error: \"expected 3, got 2\"";
+pub fn assert_equal(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => actual: Any, expected: Any, opt msg: LString);
+    if actual != expected {
+        match msg {
+            Some(msg) => assertion_error!("{}: expected {}, got {}", msg, expected, actual),
+            None => assertion_error!("expected {}, got {}", expected, actual),
+        }
+    }
+    Ok(lisp_true_ref!())
+}
+
 pub fn car(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
     if args.len() != 1 {
         arity_error!("wrong number of arguments to car: {}", LispObj::to_lisp_list(args.iter()));
@@ -105,7 +378,7 @@ pub fn car(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
         } else if arg.is_lazy_cons() {
             Ok(arg.lazy_car().unwrap())
         } else {
-            type_error!("car: expected cons, got {}", arg)
+            type_error!(args vec![arg.clone()], expected "cons"; "car: expected cons, got {}", arg)
         }
     }
 }
@@ -121,7 +394,7 @@ pub fn cdr(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
             let procd = arg.lazy_cdr().unwrap();
             super::lambda::lambda_apply(procd, nil!().to_obj_ref())
         } else {
-            type_error!("cdr: expected cons, got {}", arg)
+            type_error!(args vec![arg.clone()], expected "cons"; "cdr: expected cons, got {}", arg)
         }
     }
 }
@@ -131,41 +404,279 @@ pub fn cons(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
     Ok(cons!(left, right).to_obj_ref())
 }
 
-pub fn doc(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+pub const DOC_DOCSTR: &'static str = "Returns the documentation of a procedure.
+
+A procedure without a docstring is described by how it is called instead, with a
+line for each clause of a case-lambda. Other values have no documentation, and
+give false.
+
+Given a symbol, returns the documentation of the name instead: either the
+docstring it was defined with, or the documentation of its value.
+
+Examples:
+
+(define x \"The answer\" 42)
+(doc 'x)
+=> \"The answer\"
+(define (add a (b 1)) (+ a b))
+(doc add)
+=> \"(add a (b 1))\"
+(doc car)
+=> \"(car ...)\"";
+pub fn doc(args: &[LispObjRef], env: EnvironmentRef) -> EvalResult {
     unpack_args!(args => obj: Any);
 
-    match *obj {
+    match obj.symbol_ref() {
+        Some(name) => {
+            let borrowed = env.borrow();
+            let meta_doc = borrowed.lookup_meta(name).and_then(|pairs| {
+                pairs.into_iter().find(|pair| pair.0 == ::core::env::DOC_META_KEY).map(|pair| pair.1)
+            });
+            match (meta_doc, borrowed.lookup(name)) {
+                (Some(doc), _) => Ok(doc),
+                (None, Some(val)) => Ok(object_doc(&val)),
+                (None, None) => Ok(lisp_false_ref!()),
+            }
+        },
+        None => Ok(object_doc(&obj)),
+    }
+}
+
+fn object_doc(obj: &LispObjRef) -> LispObjRef {
+    match **obj {
         LispObj::LNativeFunc(_, Some(ref docstr), _) => {
-            Ok(string!(docstr.as_ref().clone()).to_obj_ref())
+            string!(docstr.as_ref().clone()).to_obj_ref()
         },
         LispObj::LProcedure(box Procedure { documentation: Some(ref docstr), ..}) => {
-            Ok(string!(docstr.clone()).to_obj_ref())
+            string!(docstr.clone()).to_obj_ref()
         },
-        _ => Ok(lisp_false!().to_obj_ref())
+        LispObj::LNativeFunc(ref name, None, _) => string!(format!("({} ...)", name)).to_obj_ref(),
+        LispObj::LProcedure(ref procd) => string!(procd.signature()).to_obj_ref(),
+        _ => lisp_false_ref!()
+    }
+}
+
+pub const PROCEDURE_TO_LIST_DOCSTR: &'static str = "Returns the source of a procedure, as its argument list followed by its body.
+
+A procedure made by case-lambda with several clauses, or with a guard, gives a
+list of its clauses instead, as they are written in case-lambda.
+
+Examples:
+
+(define (add a (b 1)) (+ a b))
+(procedure->list add)
+=> ((a (b 1)) (+ a b))
+(procedure->list (case-lambda ((x) 'one) ((x y) 'two)))
+=> (((x) (quote one)) ((x y) (quote two)))";
+pub fn procedure_to_list(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => procd: LProc);
+    match *procd {
+        LispObj::LProcedure(ref procd) => Ok(procd.source()),
+        _ => type_error!(args vec![procd.clone()], expected "procedure"; "procedure->list: {} is native, so has no source", procd),
     }
 }
 
-pub fn dump_traceback(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+pub fn dump_traceback(args: &[LispObjRef], env: EnvironmentRef) -> EvalResult {
     for arg in args {
-        check_type!(arg, LError).dump_traceback()
+        match **arg {
+            LispObj::LError(ref err) => try!(output::write_error(err, &env)),
+            _ => type_error!(args vec![arg.clone()], expected "error"; "expected error, not {}", arg),
+        }
+    }
+
+    Ok(lisp_true_ref!())
+}
+
+pub const EQUAL_DOCSTR: &'static str = "Tests if all its arguments are equal.
+
+Lists, vectors, strings, records and errors are compared by their contents.
+Numbers are equal only if they are the same type, so 1 is not equal to 1.0, and
+NaN is equal to NaN; use = to compare numbers by value.
+Procedures are equal only to copies of themselves, e.g. the same lambda given a
+name by define, and native functions are equal if they call the same function.
+
+Examples:
+
+(equal? '(1 (2 \"three\")) '(1 (2 \"three\")))
+=> true
+(equal? car car)
+=> true
+(equal? (lambda (x) x) (lambda (x) x))
+=> false";
+pub fn equal(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    if args.len() < 2 {
+        arity_error!("equal?: expected at least 2 arguments, got {}", LispObj::to_lisp_list(args.iter()))
     }
 
-    Ok(lisp_true!().to_obj_ref())
+    let res = args.windows(2).all(|pair| pair[0] == pair[1]);
+    Ok(lisp_bool_ref!(res))
 }
 
+pub const EVAL_DOCSTR: &'static str = "Evaluates a form, in the current environment or the given one.
+
+Examples:
+
+(eval '(+ 1 2))
+=> 3
+(define env (make-environment))
+(environment-define env 'x 5)
+(eval 'x env)
+=> 5";
 pub fn eval(args: &[LispObjRef], env: EnvironmentRef) -> EvalResult {
-    if args.len() != 1 {
-        syntax_error!("eval expects 1 argument, got {}", args.len())
+    unpack_args!(args => form: Any, opt in_env: LEnvironment);
+    super::eval(&form, in_env.unwrap_or(env))
+}
+
+pub const MAKE_ENVIRONMENT_DOCSTR: &'static str = "Makes an environment, for use with eval.
+
+Without an argument, the environment is a new top level with only the builtins,
+and none of the current definitions. Given an environment, makes a new frame
+inside it, which sees its bindings; environment-define and define bind names in
+just the frame.
+
+Examples:
+
+(define env (make-environment))
+(eval '(define y 1) env)
+(bound? 'y)
+=> false
+(eval 'y env)
+=> 1";
+pub fn make_environment(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => opt parent: LEnvironment);
+    let env = match parent {
+        Some(parent) => Environment::from_parent(parent),
+        None => super::default_environment(),
+    };
+    Ok(LispObj::LEnvironment(env.to_env_ref()).to_obj_ref())
+}
+
+/// make-environment in sandboxed environments, which makes new top levels sandboxed too
+pub fn make_sandboxed_environment(args: &[LispObjRef], env: EnvironmentRef) -> EvalResult {
+    if args.is_empty() {
+        Ok(LispObj::LEnvironment(super::sandboxed_environment().to_env_ref()).to_obj_ref())
+    } else {
+        make_environment(args, env)
+    }
+}
+
+pub const COMPLETIONS_DOCSTR: &'static str = "Lists the names which start with a prefix, as strings.
+
+These are the bound names, macros and special forms, which the REPL completes to
+unless *completion-function* is set to a procedure taking the prefix.
+
+Examples:
+
+(completions \"string->l\")
+=> (\"string->list\")
+(set! *completion-function*
+  (lambda (prefix) (cons \"my-name\" (completions prefix))))";
+pub fn completions(args: &[LispObjRef], env: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => prefix: LString);
+    let names = super::complete::default_completions(&prefix, &env);
+    Ok(LispObj::to_lisp_list(names.into_iter().map(|name| string!(name))).to_obj_ref())
+}
+
+pub const CURRENT_ENVIRONMENT_DOCSTR: &'static str = "Returns the environment it is called from.
+
+Examples:
+
+(let ((x 1)) (eval 'x (current-environment)))
+=> 1";
+pub fn current_environment(args: &[LispObjRef], env: EnvironmentRef) -> EvalResult {
+    unpack_args!(args);
+    Ok(LispObj::LEnvironment(env).to_obj_ref())
+}
+
+pub const ENVIRONMENT_DEFINE_DOCSTR: &'static str = "Binds a name in an environment, replacing any binding it already has there.
+
+Returns the name.
+
+Examples:
+
+(define env (make-environment))
+(environment-define env 'double (lambda (x) (* 2 x)))
+(eval '(double 4) env)
+=> 8";
+pub fn environment_define(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => env: LEnvironment, name: LSymbol, value: Any);
+    let _ = env.borrow_mut().let_new(name, value);
+    Ok(args[1].clone())
+}
+
+pub const EVAL_STRING_DOCSTR: &'static str = "Reads and evaluates every form in a string.
+
+Forms are evaluated in order in the current environment, and the value of
+the last one is returned. An empty string evaluates to ().
+
+Throws a 'read-error if the string cannot be parsed.
+
+Examples:
+
+(eval-string \"(define x 2) (+ x 3)\")
+;; => 5";
+pub fn eval_string(args: &[LispObjRef], env: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => source: LString);
+    super::eval_str_in(&source, env)
+}
+
+pub const FORMAT_STRING_DOCSTR: &'static str = "Re-indents lisp source code, as `rustylisp fmt` does.
+
+Only the whitespace at the start and end of each line changes, so comments are kept.
+Throws a 'read-error if the parens don't balance.
+
+Examples:
+
+(format-string \"(define (f x)\\n(+ x 1))\")
+=> \"(define (f x)\\n  (+ x 1))\\n\"";
+pub fn format_string(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => source: LString);
+    match ::parser::format::format_source(&source) {
+        Ok(formatted) => Ok(string!(formatted).to_obj_ref()),
+        Err(e) => read_error!("format-string: error parsing {:?}: {:?}", source, e),
+    }
+}
+
+pub const EXIT_DOCSTR: &'static str = "Stops the interpreter with a status code, 0 by default.
+
+Raises an 'exit-request which unwinds to the host program, skipping
+any catch-error forms on the way. Throws an 'argument-error if the code
+is not between 0 and 255.
+
+Examples:
+
+(exit)
+(exit 1)";
+pub fn exit(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => opt code: LInteger);
+    let code = code.unwrap_or(0);
+    if code < 0 || code > 255 {
+        argument_error!("exit: status code {} is not between 0 and 255", code)
     }
+    Err(RuntimeError::exit_request(code as i32))
+}
+
+pub const AT_EXIT_DOCSTR: &'static str = "Registers a procedure of no arguments to call when the interpreter shuts down.
+
+The procedures are called when the program ends, including by exit, with the
+most recently registered first. An error one throws is shown, and the rest are still
+called. They are kept in *exit-hooks*.
+
+Examples:
+
+(at-exit (lambda () (socket-close sock)))
+=> true";
+pub fn at_exit(args: &[LispObjRef], env: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => thunk: LProc);
 
-    super::eval(&args[0], env)
+    let top_level = ::core::env::get_top_level(env);
+    let hooks = top_level.borrow().lookup(EXIT_HOOKS_NAME).unwrap_or_else(|| nil!().to_obj_ref());
+    let _ = top_level.borrow_mut().let_new(String::from(EXIT_HOOKS_NAME), cons!(thunk, hooks).to_obj_ref());
+    Ok(lisp_true_ref!())
 }
 
 pub fn generate_vector(args: &[LispObjRef], env: EnvironmentRef) -> EvalResult {
-    unpack_args!(args => len: LInteger, fun: Any);
-    if !(fun.is_proc() || fun.is_native()) {
-        type_error!("generate-vector: expected procedure, not {}", fun)
-    }
+    unpack_args!(args => len: LInteger, fun: LProc);
 
     let vec: EvalResult<PersistentVec<_>> = (0..len).map(|i| {
         let res = try!(super::apply(fun.clone(), lisp_list![int!(i)], env.clone()));
@@ -175,6 +686,30 @@ pub fn generate_vector(args: &[LispObjRef], env: EnvironmentRef) -> EvalResult {
     Ok(LispObj::LVector(try!(vec)).to_obj_ref())
 }
 
+pub const GENSYM_DOCSTR: &'static str = "Returns a new symbol, distinct from every other symbol.
+
+Takes an optional prefix, as a symbol or string. Useful in macros, to bind
+variables which cannot clash with the macro's arguments.
+
+Examples:
+
+(gensym)
+=> g#0
+(gensym 'tmp)
+=> tmp#1";
+pub fn gensym(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => opt prefix: Any);
+    let prefix = match prefix {
+        None => String::from("g"),
+        Some(prefix) => match (prefix.symbol_ref(), prefix.string_ref()) {
+            (Some(s), _) => String::from(s),
+            (_, Some(s)) => (*s).clone(),
+            _ => type_error!(args vec![prefix.clone()]; "gensym: expected symbol or string prefix, not {}", prefix),
+        },
+    };
+    Ok(symbol!(super::macros::gensym(&prefix)).to_obj_ref())
+}
+
 pub fn get_error_source(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
     unpack_args!(args => err: LError);
     match &err.source {
@@ -183,11 +718,29 @@ pub fn get_error_source(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
     }
 }
 
+pub const ERROR_TYPE_DOCSTR: &'static str = "Returns the type of an error, as a symbol.
+
+Examples:
+
+(error-type (catch-error (car 1)))
+=> type-error";
 pub fn get_error_type(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
     unpack_args!(args => err: LError);
     Ok(symbol!(err.errname).to_obj_ref())
 }
 
+pub const ERROR_VALUE_DOCSTR: &'static str = "Returns the value of an error, or nil if it has none.
+
+Errors raised by builtins have either a message, or details: an association list of
+the message, the offending arguments as args, and the type expected of them, if any,
+as expected. Use error-detail to look those up.
+
+Examples:
+
+(error-value (make-error 'oops 5))
+=> 5
+(error-value (catch-error (car 1)))
+=> ((message . \"car: expected cons, got 1\") (args 1) (expected . cons))";
 pub fn get_error_value(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
     unpack_args!(args => err: LError);
     match &err.value {
@@ -196,23 +749,53 @@ pub fn get_error_value(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
     }
 }
 
+pub const ERROR_DETAIL_DOCSTR: &'static str = "Returns one of the details of an error: message, args or expected.
+
+Returns nil if the error does not have that detail.
+
+Examples:
+
+(error-detail (catch-error (vector-length 'a)) 'expected)
+=> vector
+(error-detail (catch-error (vector-length 'a)) 'args)
+=> (a)";
+pub fn get_error_detail(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => err: LError, key: LSymbol);
+    Ok(err.detail(&key).unwrap_or_else(|| nil!().to_obj_ref()))
+}
+
 pub fn get_string_length(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
     unpack_args!(args => s: LString);
     Ok(int!(s.len()).to_obj_ref())
 }
 
+/// Checks an index into a sequence of `len` elements, for the builtin `fname`, and
+/// returns the position it refers to. Negative indices count back from the end.
+pub fn check_index(fname: &str, ind: i64, len: usize) -> EvalResult<usize> {
+    match ::core::obj::resolve_index(ind, len) {
+        Some(ind) => Ok(ind),
+        None => bounds_error!(args vec![int!(ind).to_obj_ref()];
+                              "{}: index {} is out of bounds for length {}", fname, ind, len),
+    }
+}
+
+pub const STRING_REF_DOCSTR: &'static str = "Returns the char at an index of a string.
+
+Negative indices count back from the end. Throws a 'bounds-error if the index is
+out of range.
+
+Examples:
+
+(string-ref \"abc\" 0)
+=> \\a
+(string-ref \"abc\" -1)
+=> \\c";
 pub fn get_string_index(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
     unpack_args!(args => s: LString, ind: LInteger);
-    if ind >= s.len() as i64 {
-        argument_error!("string-ref: index {} out of bound of string {:?}", ind, s)
-    } else if ind < 0 {
-        argument_error!("string-ref: cannot get char at negative index {}", ind)
-    } else {
-        let i = ind as usize;
-        Ok(char!(s.chars().nth(i)
-                  .expect("rustylisp::evaluator::builtins::get_string_index: index, len mismatch"))
-           .to_obj_ref())
-    }
+    let ind = try!(check_index("string-ref", ind, s.chars().count()));
+    Ok(char!(s.chars().nth(ind)
+              .expect("rustylisp::evaluator::builtins::get_string_index: index, len mismatch"))
+       .to_obj_ref())
 }
 
 pub fn get_vector_length(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
@@ -220,49 +803,159 @@ pub fn get_vector_length(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
     Ok(int!(vec.len()).to_obj_ref())
 }
 
+pub const VECTOR_REF_DOCSTR: &'static str = "Returns the element of a vector at an index, counting from 0.
+
+Negative indices count back from the end. Throws a 'bounds-error if the index is
+outside the vector. See vector-ref-safe to get a default value instead.
+
+Examples:
+
+(vector-ref [a b c] 1)
+=> b
+(vector-ref [a b c] -1)
+=> c
+(vector-ref [a b c] 3)
+=> bounds-error";
 pub fn get_vector_index(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
     unpack_args!(args => vec: LVector, ind: LInteger);
-    Ok(vec.lookup(ind as usize).map_or(lisp_false!(), |val| (**val).clone()).to_obj_ref())
+    let ind = try!(check_index("vector-ref", ind, vec.len()));
+    Ok(vec.lookup(ind).expect("vector-ref: index was checked").clone())
+}
+
+pub const VECTOR_REF_SAFE_DOCSTR: &'static str = "Returns the element of a vector at an index, or a default if the index
+is outside the vector. The default is false if it is not given.
+
+Examples:
+
+(vector-ref-safe [a b c] 1)
+=> b
+(vector-ref-safe [a b c] 3 'none)
+=> none";
+pub fn get_vector_index_safe(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => vec: LVector, ind: LInteger, opt default: Any);
+    match ::core::obj::resolve_index(ind, vec.len()).and_then(|ind| vec.lookup(ind)) {
+        Some(item) => Ok(item.clone()),
+        None => Ok(default.unwrap_or_else(|| lisp_false_ref!())),
+    }
+}
+
+pub const IS_BOOLEAN_DOCSTR: &'static str = "Tests if an object is one of the booleans true or false.
+
+Examples:
+
+(boolean? false)
+=> true
+(boolean? nil)
+=> false";
+pub const HASH_DOCSTR: &'static str = "Returns a hash of a value, as an integer. Equal values have equal hashes.
+
+Numbers, strings, symbols, chars, nil, and lists, vectors and records of them can
+be hashed. Throws a 'type-error for other values, such as procedures.
+
+Examples:
+
+(equal? (hash '(1 \"two\")) (hash '(1 \"two\")))
+=> true
+(hash car)
+=> type-error";
+pub fn hash(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => obj: Any);
+    match obj.hash_value() {
+        Ok(hash) => Ok(int!(hash as i64).to_obj_ref()),
+        Err(part) => type_error!("hash: cannot hash {}", part),
+    }
+}
+
+pub fn is_boolean(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => arg: Any);
+    Ok(lisp_bool_ref!(arg.is_boolean()))
 }
 
 pub fn is_bound(args: &[LispObjRef], env: EnvironmentRef) -> EvalResult {
     unpack_args!(args => name: LSymbol);
-    Ok(lisp_bool!(env.borrow().lookup(&name).is_some()).to_obj_ref())
+    Ok(lisp_bool_ref!(env.borrow().lookup(&name).is_some()))
+}
+
+pub fn is_char(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => arg: Any);
+    Ok(lisp_bool_ref!(arg.is_char()))
 }
 
 pub fn is_cons(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
     unpack_args!(args => arg: Any);
-    Ok(lisp_bool!(arg.is_cons() || arg.is_lazy_cons()).to_obj_ref())
+    Ok(lisp_bool_ref!(arg.is_cons() || arg.is_lazy_cons()))
 }
 
 pub fn is_error(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
     unpack_args!(args => arg: Any);
-    Ok(lisp_bool!(arg.is_err()).to_obj_ref())
+    Ok(lisp_bool_ref!(arg.is_err()))
+}
+
+pub fn is_lazy_cons(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => arg: Any);
+    Ok(lisp_bool_ref!(arg.is_lazy_cons()))
 }
 
 pub fn is_list(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
     unpack_args!(args => arg: Any);
-    Ok(lisp_bool!(arg.is_list()).to_obj_ref())
+    Ok(lisp_bool_ref!(arg.is_list()))
+}
+
+pub fn is_native(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => arg: Any);
+    Ok(lisp_bool_ref!(arg.is_native()))
 }
 
 pub fn is_nil(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
     unpack_args!(args => arg: Any);
-    Ok(lisp_bool!(arg.is_nil()).to_obj_ref())
+    Ok(lisp_bool_ref!(arg.is_nil()))
+}
+
+pub fn is_port(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => arg: Any);
+    Ok(lisp_bool_ref!(arg.is_port()))
+}
+
+pub fn is_environment(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => arg: Any);
+    Ok(lisp_bool_ref!(arg.is_env()))
+}
+
+/// Both lisp procedures and native functions are procedures
+pub fn is_procedure(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => arg: Any);
+    Ok(lisp_bool_ref!(arg.is_proc() || arg.is_native()))
 }
 
 pub fn is_string(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
     unpack_args!(args => arg: Any);
-    Ok(lisp_bool!(arg.is_string()).to_obj_ref())
+    Ok(lisp_bool_ref!(arg.is_string()))
 }
 
 pub fn is_symbol(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
     unpack_args!(args => arg: Any);
-    Ok(lisp_bool!(arg.is_symbol()).to_obj_ref())
+    Ok(lisp_bool_ref!(arg.is_symbol()))
+}
+
+pub const IS_KEYWORD_DOCSTR: &'static str = "Tests if a value is a keyword, a symbol written `:name` or `#:name`.
+
+Keywords evaluate to themselves.
+
+Examples:
+
+(keyword? :size)
+=> true
+(keyword? 'size)
+=> false";
+pub fn is_keyword(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => arg: Any);
+    let keyword = arg.symbol_ref().map_or(false, |s| s.starts_with(::core::procedure::KEYWORD_PREFIX));
+    Ok(lisp_bool_ref!(keyword))
 }
 
 pub fn is_vector(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
     unpack_args!(args => arg: Any);
-    Ok(lisp_bool!(arg.is_vector()).to_obj_ref())
+    Ok(lisp_bool_ref!(arg.is_vector()))
 }
 
 pub fn list_to_vector(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
@@ -285,14 +978,24 @@ pub fn macro_expand(args: &[LispObjRef], env: EnvironmentRef) -> EvalResult {
     }
 }
 
-pub fn raw_make_error(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult<RuntimeError> {
-    if args.len() == 0 {
-        arity_error!("make-error: no arguments")
-    } else if args.len() > 2 {
-        arity_error!("make-error: too many arguments")
-    }
+pub const MACRO_EXPAND_ALL_DOCSTR: &'static str = "Expands every macro call in a form, including in its subforms.
+
+Unlike macro-expand, which only expands the outermost form once, this expands
+each form until it is no longer a macro call. Quoted and quasiquoted forms are
+not expanded.
+
+Examples:
 
-    let err = args[0].clone();
+(define-macro (my-unless c x) `(if ,c nil ,x))
+(macro-expand-all '(my-unless a (my-unless b 'c)))
+=> (if a nil (if b nil (quote c)))";
+pub fn macro_expand_all(args: &[LispObjRef], env: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => val: Any);
+    super::macros::macro_expand_all(val, env)
+}
+
+pub fn raw_make_error(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult<RuntimeError> {
+    unpack_args!(args => err: Any, opt value: Any);
 
     let output_err = if err.is_symbol() {
         RuntimeError::error(err.symbol_ref().unwrap())
@@ -302,14 +1005,10 @@ pub fn raw_make_error(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult<Runt
         type_error!("make-error: not an error, {}", LispObj::to_lisp_list(args.iter()))
     };
 
-    if args.len() == 2 {
-        if args[1].is_err() {
-            Ok(output_err.with_cause(args[1].unwrap_err().clone()))
-        } else {
-            Ok(output_err.with_value(&args[1]))
-        }
-    } else {
-        Ok(output_err)
+    match value {
+        Some(ref value) if value.is_err() => Ok(output_err.with_cause(value.unwrap_err().clone())),
+        Some(ref value) => Ok(output_err.with_value(value)),
+        None => Ok(output_err),
     }
 }
 
@@ -328,6 +1027,178 @@ pub fn make_vector(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
     Ok(LispObj::make_vector((0..adjsize).map(|_| val.clone())).to_obj_ref())
 }
 
+pub const GC_DOCSTR: &'static str = "Frees environments leaked in reference cycles, returning how many were freed.
+
+Procedures hold the environment they were made in, so a procedure bound in its own
+environment, as made by rec or a let binding a lambda, is never freed otherwise.
+Only cycles through environments and the procedures bound directly in them are
+found. Do not use while other threads are running.
+
+Examples:
+
+(let ((f (lambda () f))) 'leaked)
+(gc)
+=> 1";
+pub fn gc(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args);
+    let stats = ::core::gc::collect(true);
+    Ok(int!(stats.garbage).to_obj_ref())
+}
+
+pub const GC_STATS_DOCSTR: &'static str = "Counts the live environments, and those leaked in reference cycles which (gc) would free.
+
+Examples:
+
+(gc-stats)
+=> ((environments . 12) (garbage . 1))";
+pub fn gc_stats(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args);
+    let stats = ::core::gc::collect(false);
+    Ok(lisp_list![cons!(symbol!("environments"), int!(stats.environments)),
+                  cons!(symbol!("garbage"), int!(stats.garbage))].to_obj_ref())
+}
+
+pub const PROFILE_REPORT_DOCSTR: &'static str = "Stops the profiler and prints its results.
+
+For each procedure called since (profile-start), prints the number of calls
+and the total time spent in it, slowest first.
+
+Examples:
+
+(profile-report)";
+pub const META_DOCSTR: &'static str = "Returns the metadata of a name, as an association list of keys to values.
+
+Given a key as well, returns just the value for that key. Returns nil if there
+is no metadata.
+
+Examples:
+
+(set-meta! 'x 'author \"me\")
+(meta 'x)
+=> ((author . \"me\"))
+(meta 'x 'author)
+=> \"me\"";
+pub fn meta(args: &[LispObjRef], env: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => name: LSymbol, opt key: LSymbol);
+
+    let pairs = env.borrow().lookup_meta(&name).unwrap_or_else(Vec::new);
+    match key {
+        Some(key) => match pairs.into_iter().find(|pair| pair.0 == key) {
+            Some((_, val)) => Ok(val),
+            None => Ok(nil!().to_obj_ref()),
+        },
+        None => {
+            let alist = pairs.into_iter().map(|(key, val)| cons!(symbol!(key), val));
+            Ok(LispObj::to_lisp_list(alist).to_obj_ref())
+        }
+    }
+}
+
+pub const NOT_DOCSTR: &'static str = "Returns true if its argument is false, and false otherwise.
+
+What counts as false depends on *strict-booleans*: normally nil, false, 0, \"\"
+and empty vectors are false, but with strict booleans only nil and false are.
+
+Examples:
+
+(not false)
+=> true
+(not 'a)
+=> false";
+pub fn not(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => arg: Any);
+    Ok(lisp_bool_ref!(super::is_false(&arg)))
+}
+
+pub fn profile_report(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args);
+    let results = match super::profile::stop() {
+        Some(results) => results,
+        None => environment_error!("profile-report: profiler is not running"),
+    };
+
+    try!(output::write_stdout(|out| {
+        try!(writeln!(out, "{:>10} {:>14}  {}", "calls", "total ms", "procedure"));
+        for (name, entry) in results {
+            try!(writeln!(out, "{:>10} {:>14.3}  {}", entry.calls,
+                          super::profile::duration_millis(entry.total), name));
+        }
+        Ok(())
+    }));
+
+    Ok(nil!().to_obj_ref())
+}
+
+pub const PROFILE_START_DOCSTR: &'static str = "Starts profiling calls to lisp procedures.
+
+Any previous results are discarded. Use (profile-report) to see the results.
+
+Examples:
+
+(profile-start)";
+pub fn profile_start(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args);
+    super::profile::start();
+    Ok(nil!().to_obj_ref())
+}
+
+pub const RUN_TESTS_DOCSTR: &'static str = "Runs every test defined with deftest, in the order they were defined.
+
+Prints whether each test passed, and the error of each that failed, followed
+by a summary. Returns true if every test passed.
+
+Examples:
+
+(deftest addition
+  (assert-equal (+ 1 1) 2))
+(run-tests)
+ok    addition
+1 passed, 0 failed
+=> true";
+pub fn run_tests(args: &[LispObjRef], env: EnvironmentRef) -> EvalResult {
+    unpack_args!(args);
+
+    let tests = env.borrow().lookup(TESTS_NAME).expect("cannot delete *tests*");
+    let tests = flatten_list!(tests, "{} must be a list", TESTS_NAME);
+
+    let (mut passed, mut failed) = (0, 0);
+    for test in tests {
+        let (name, procd) = check_type!(test, LCons);
+        match super::apply(procd, nil!().to_obj_ref(), env.clone()) {
+            Ok(_) => {
+                passed += 1;
+                try!(output::print(format_args!("ok    {}\n", name)));
+            },
+            // Exit requests and interrupts stop the test run
+            Err(err) => if err.is_catchable() {
+                failed += 1;
+                try!(output::print(format_args!("FAIL  {}: {}\n", name, err)));
+            } else {
+                return Err(err)
+            },
+        }
+    }
+
+    try!(output::print(format_args!("{} passed, {} failed\n", passed, failed)));
+    Ok(lisp_bool_ref!(failed == 0))
+}
+
+pub const SET_META_DOCSTR: &'static str = "Sets a metadata key of a name in the top-level environment. Returns the value.
+
+The name does not need to be bound. Metadata is kept by name, so it is not
+affected by rebinding the name.
+
+Examples:
+
+(set-meta! 'x 'author \"me\")
+=> \"me\"";
+pub fn set_meta(args: &[LispObjRef], env: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => name: LSymbol, key: LSymbol, val: Any);
+    let top_level = ::core::env::get_top_level(env);
+    let _ = top_level.borrow_mut().set_meta(name, key, val.clone());
+    Ok(val)
+}
+
 pub fn string_append_objects(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
     let mut out = String::new();
 
@@ -353,14 +1224,14 @@ pub fn string_eq(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
                     string = Some(obj);
                     true
                 } else {
-                    return Ok(lisp_false!().to_obj_ref())
+                    return Ok(lisp_false_ref!())
                 }
             },
-            (_, None) => return Ok(lisp_false!().to_obj_ref()),
+            (_, None) => return Ok(lisp_false_ref!()),
         };
     }
 
-    Ok(lisp_bool!(out).to_obj_ref())
+    Ok(lisp_bool_ref!(out))
 }
 
 pub fn string_to_list(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
@@ -389,29 +1260,21 @@ pub fn symbol_eq(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
                     symb = Some(obj);
                     true
                 } else {
-                    return Ok(lisp_false!().to_obj_ref())
+                    return Ok(lisp_false_ref!())
                 }
             },
-            (_, None) => return Ok(lisp_false!().to_obj_ref()),
+            (_, None) => return Ok(lisp_false_ref!()),
         };
     }
 
-    Ok(lisp_bool!(out).to_obj_ref())
+    Ok(lisp_bool_ref!(out))
 }
 
 pub fn symbol_to_char(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
     unpack_args!(args => sym: LSymbol);
-    match &sym as &str {
-        "space"   => return Ok(LispObj::LChar(' ').to_obj_ref()),
-        "tab"     => return Ok(LispObj::LChar('\t').to_obj_ref()),
-        "newline" => return Ok(LispObj::LChar('\n').to_obj_ref()),
-        _ => {},
-    };
-    let mut iter = sym.chars();
-    let ch = iter.next().unwrap();
-    match iter.next() {
-        Some(_) => argument_error!("symbol '{} has length >1", sym),
-        None => Ok(LispObj::LChar(ch).to_obj_ref()),
+    match char_from_name(&sym) {
+        Some(ch) => Ok(LispObj::LChar(ch).to_obj_ref()),
+        None => argument_error!("symbol '{} is not a character name", sym),
     }
 }
 
@@ -425,15 +1288,73 @@ pub fn throw_error(args: &[LispObjRef], env: EnvironmentRef) -> EvalResult {
     Err(err)
 }
 
-pub fn vector_assoc(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
-    unpack_args!(args => arg: LVector, index: LInteger, item: Any);
-    match arg.insert(index as usize, item.clone()) {
-        Some(new) => Ok(LispObj::LVector(new).to_obj_ref()),
-        None      => {
-            runtime_error!("bounds-error", "vector-assoc: index {} is out of bounds of vector {}",
-                           index, item)
+pub const TRACE_DOCSTR: &'static str = "Prints each call to, and return from, the named procedures.
+
+Takes procedures or their names. Calls are indented by their depth.
+Set *trace-all* to true, with set! or define!, to trace every procedure.
+
+Examples:
+
+(trace fact)
+(trace 'fact 'fib)";
+pub fn trace(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    let names = try!(traced_names("trace", args));
+    for name in names.iter() {
+        super::trace::trace(name);
+    }
+
+    Ok(LispObj::to_lisp_list(names.into_iter().map(|n| LispObj::LSymbol(n))).to_obj_ref())
+}
+
+pub const UNTRACE_DOCSTR: &'static str = "Stops tracing the named procedures, or all of them when given no arguments.
+
+Examples:
+
+(untrace fact)
+(untrace)";
+pub fn untrace(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    if args.len() == 0 {
+        super::trace::untrace_all();
+        return Ok(nil!().to_obj_ref())
+    }
+
+    let names = try!(traced_names("untrace", args));
+    let untraced = names.into_iter()
+                        .filter(|name| super::trace::untrace(name))
+                        .map(|n| LispObj::LSymbol(n));
+    Ok(LispObj::to_lisp_list(untraced).to_obj_ref())
+}
+
+fn traced_names(fname: &str, args: &[LispObjRef]) -> EvalResult<Vec<String>> {
+    let mut names = Vec::with_capacity(args.len());
+    for arg in args.iter() {
+        if let Some(name) = arg.symbol_ref() {
+            names.push(String::from(name));
+        } else if arg.is_proc() {
+            match try!(arg.try_unwrap_proc()).name {
+                Some(ref name) => names.push(name.clone()),
+                None => argument_error!("{}: cannot trace anonymous procedure {}", fname, arg),
+            }
+        } else {
+            type_error!(args vec![arg.clone()]; "{}: expected procedure or symbol, not {}", fname, arg)
         }
     }
+
+    Ok(names)
+}
+
+/// The accessors set! can change the value of, each with the builtin which returns an
+/// updated copy: `(set! (vector-ref v i) x)` rebinds v to `(vector-assoc v i x)`.
+/// Arguments after the key, like record-get's expected type, are passed after the value.
+pub static SETTABLE_PLACES: &'static [(&'static str, NativeFuncSignature, NativeFuncSignature)] = &[
+    ("vector-ref", get_vector_index, vector_assoc),
+    ("record-get", record::record_get, record::record_set),
+];
+
+pub fn vector_assoc(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => arg: LVector, index: LInteger, item: Any);
+    let index = try!(check_index("vector-assoc", index, arg.len()));
+    Ok(LispObj::LVector(arg.insert(index, item.clone()).expect("vector-assoc: index was checked")).to_obj_ref())
 }
 
 pub fn vector_append(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
@@ -441,7 +1362,7 @@ pub fn vector_append(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
         if v.is_vector() {
             Ok(v.unwrap_vec())
         } else {
-            type_error!("expected vector, not {}", v)
+            type_error!(args vec![v.clone()], expected "vector"; "expected vector, not {}", v)
         }
     }).collect();
 
@@ -462,7 +1383,7 @@ pub fn vector_map(args: &[LispObjRef], env: EnvironmentRef) -> EvalResult {
         if v.is_vector() {
             Ok(v.unwrap_vec().iter())
         } else {
-            type_error!("expected vector, not {}", v)
+            type_error!(args vec![v.clone()], expected "vector"; "expected vector, not {}", v)
         }
     }).collect::<Result<Vec<_>,_>>());
 
@@ -493,5 +1414,5 @@ pub fn vector_map(args: &[LispObjRef], env: EnvironmentRef) -> EvalResult {
 
 pub fn vector_to_list(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
     unpack_args!(args => arg: LVector);
-    Ok(LispObj::to_lisp_list(arg.iter()).to_obj_ref())
+    Ok(LispObj::from_vec(arg.iter().cloned().collect()).to_obj_ref())
 }
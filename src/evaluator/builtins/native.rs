@@ -0,0 +1,119 @@
+//! Loading native extensions from shared libraries.
+//!
+//! `(load-native "libfoo")` opens a shared library and calls the function it exports as
+//! `rustylisp_register`, which has the type `NativeRegisterFn`, with the global
+//! environment. The extension binds its procedures there, and can throw an error to
+//! fail the load. For example:
+//!
+//! ```text
+//! #[no_mangle]
+//! pub fn rustylisp_register(env: &EnvironmentRef) -> EvalResult<()> {
+//!     env.borrow_mut().let_new(String::from("foo/hello"), LispObj::make_native("foo/hello", hello, None).to_obj_ref());
+//!     Ok(())
+//! }
+//! ```
+//!
+//! As Rust types are passed across, the library must be built with the same compiler
+//! and version of rustylisp as the interpreter. Libraries are never unloaded, since
+//! the procedures they bind point into them.
+//!
+//! Only available on unix, when built with the `native-extensions` feature; otherwise
+//! `load-native` throws an 'environment-error.
+use ::core::{LispObjRef, AsLispObjRef, EnvironmentRef, EvalResult};
+
+/// The name of the function a native extension exports
+pub static NATIVE_REGISTER_SYMBOL: &'static str = "rustylisp_register";
+
+/// The type of a native extension's `rustylisp_register` function
+pub type NativeRegisterFn = fn(&EnvironmentRef) -> EvalResult<()>;
+
+#[cfg(all(unix, feature = "native-extensions"))]
+mod sys {
+    use std::ffi::{CStr, CString};
+    use std::os::raw::{c_char, c_int, c_void};
+
+    const RTLD_NOW: c_int = 2;
+
+    #[cfg_attr(target_os = "linux", link(name = "dl"))]
+    extern "C" {
+        fn dlopen(filename: *const c_char, flags: c_int) -> *mut c_void;
+        fn dlsym(handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
+        fn dlerror() -> *mut c_char;
+    }
+
+    fn last_error() -> String {
+        let err = unsafe { dlerror() };
+        if err.is_null() {
+            String::from("unknown error")
+        } else {
+            unsafe { CStr::from_ptr(err) }.to_string_lossy().into_owned()
+        }
+    }
+
+    /// Opens the library at `path`, and looks up `symbol` in it. The library stays
+    /// loaded for the rest of the process.
+    pub fn load_symbol(path: &str, symbol: &str) -> Result<*mut c_void, String> {
+        let path = try!(CString::new(path).map_err(|_| String::from("path contains a nul byte")));
+        let symbol = CString::new(symbol).expect("symbol names have no nul bytes");
+        let handle = unsafe { dlopen(path.as_ptr(), RTLD_NOW) };
+        if handle.is_null() {
+            return Err(last_error())
+        }
+        let sym = unsafe { dlsym(handle, symbol.as_ptr()) };
+        if sym.is_null() {
+            return Err(last_error())
+        }
+        Ok(sym)
+    }
+}
+
+/// Where to load `name` from: as a path, if it names an existing file, with the
+/// platform's library extension added if it has none; otherwise the name itself, which
+/// the system's library search path is searched for.
+#[cfg(all(unix, feature = "native-extensions"))]
+fn library_path(name: &str) -> String {
+    use std::path::Path;
+
+    let suffix = if cfg!(target_os = "macos") { ".dylib" } else { ".so" };
+    let name = if Path::new(name).extension().is_some() {
+        String::from(name)
+    } else {
+        format!("{}{}", name, suffix)
+    };
+    match Path::new(&name).canonicalize() {
+        Ok(path) => path.to_string_lossy().into_owned(),
+        Err(_) => name,
+    }
+}
+
+pub const LOAD_NATIVE_DOCSTR: &'static str = "Loads a native extension from a shared library.
+
+The library's rustylisp_register function is called with the global environment, and
+binds the extension's procedures. The platform's library extension, such as .so, is
+added if the name has none. Throws an 'io-error if the library can't be loaded.
+
+Only available on unix, when built with the native-extensions feature.
+
+Examples:
+
+(load-native \"libsqlite-lisp\")
+=> true";
+#[cfg(all(unix, feature = "native-extensions"))]
+pub fn load_native(args: &[LispObjRef], env: EnvironmentRef) -> EvalResult {
+    use std::mem;
+
+    unpack_args!(args => name: LString);
+    let path = library_path(&name);
+    let sym = match sys::load_symbol(&path, NATIVE_REGISTER_SYMBOL) {
+        Ok(sym) => sym,
+        Err(err) => io_error!("load-native: cannot load {}: {}", path, err),
+    };
+    let register: NativeRegisterFn = unsafe { mem::transmute(sym) };
+    try!(register(&::core::env::get_top_level(env)));
+    Ok(lisp_true_ref!())
+}
+
+#[cfg(not(all(unix, feature = "native-extensions")))]
+pub fn load_native(_: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    environment_error!("load-native: native extensions need rustylisp to be built on unix with the native-extensions feature")
+}
@@ -0,0 +1,118 @@
+//! TCP networking builtins.
+//!
+//! Sockets are represented as ports. Failures, including using a closed port,
+//! throw an 'io-error.
+use std::net::{TcpListener, TcpStream};
+
+use ::core::{LispObj, LispObjRef, AsLispObjRef, EnvironmentRef, EvalResult};
+use ::core::obj::Port;
+
+fn check_port_number(fname: &str, port: i64) -> EvalResult<u16> {
+    if port < 0 || port > 65535 {
+        argument_error!("{}: invalid port number {}", fname, port)
+    }
+    Ok(port as u16)
+}
+
+pub const TCP_CONNECT_DOCSTR: &'static str = "Opens a TCP connection to a host and port, returning a socket port.
+
+Examples:
+
+(define sock (tcp-connect \"example.com\" 80))";
+pub fn tcp_connect(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => host: LString, port: LInteger);
+    let port = try!(check_port_number("tcp-connect", port));
+
+    let stream = try!(TcpStream::connect((&*host as &str, port)));
+    Ok(LispObj::LPort(Port::tcp_stream(stream)).to_obj_ref())
+}
+
+pub const TCP_LISTEN_DOCSTR: &'static str = "Listens for TCP connections on an address and port.
+
+Port 0 picks any free port; use socket-address to find out which.
+
+Examples:
+
+(define server (tcp-listen \"127.0.0.1\" 8080))";
+pub fn tcp_listen(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => host: LString, port: LInteger);
+    let port = try!(check_port_number("tcp-listen", port));
+
+    let listener = try!(TcpListener::bind((&*host as &str, port)));
+    Ok(LispObj::LPort(Port::tcp_listener(listener)).to_obj_ref())
+}
+
+pub const TCP_ACCEPT_DOCSTR: &'static str = "Waits for a connection on a listening port, returning a socket port for it.
+
+Examples:
+
+(tcp-accept server)";
+pub fn tcp_accept(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => listener: LPort);
+
+    let stream = try!(listener.accept());
+    Ok(LispObj::LPort(stream).to_obj_ref())
+}
+
+pub const SOCKET_ADDRESS_DOCSTR: &'static str = "Returns the local address of a socket port as a string.
+
+Examples:
+
+(socket-address (tcp-listen \"127.0.0.1\" 8080))
+=> \"127.0.0.1:8080\"";
+pub fn socket_address(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => port: LPort);
+
+    let addr = try!(port.local_addr());
+    Ok(string!(addr).to_obj_ref())
+}
+
+pub const SOCKET_READ_LINE_DOCSTR: &'static str = "Reads a line from a socket port, without its line terminator.
+
+Returns nil when the other end has closed the connection.
+
+Examples:
+
+(socket-read-line sock)
+=> \"HTTP/1.0 200 OK\"";
+pub fn socket_read_line(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => port: LPort);
+
+    match try!(port.read_line()) {
+        Some(line) => Ok(string!(line).to_obj_ref()),
+        None => Ok(nil!().to_obj_ref()),
+    }
+}
+
+pub const SOCKET_WRITE_DOCSTR: &'static str = "Writes its arguments to a socket port.
+
+Strings are written as they are, and other objects as if by print.
+
+Examples:
+
+(socket-write sock \"GET / HTTP/1.0\\r\\n\\r\\n\")";
+pub fn socket_write(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => port: LPort, rest values);
+    let mut out = String::new();
+    for arg in values.iter() {
+        match arg.as_ref() {
+            &LispObj::LString(ref s) => out.push_str(s),
+            other => out.push_str(&format!("{}", other)),
+        }
+    }
+
+    try!(port.write_str(&out));
+    Ok(lisp_true_ref!())
+}
+
+pub const SOCKET_CLOSE_DOCSTR: &'static str = "Closes a port. Closing a closed port does nothing.
+
+Examples:
+
+(socket-close sock)";
+pub fn socket_close(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => port: LPort);
+
+    port.close();
+    Ok(lisp_true_ref!())
+}
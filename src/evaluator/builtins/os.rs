@@ -0,0 +1,160 @@
+//! Builtins for interacting with the operating system: environment variables,
+//! running other programs and handling signals.
+use std::env as std_env;
+use std::process::{Command, Stdio};
+
+use ::core::{LispObj, LispObjRef, AsLispObjRef, EnvironmentRef, EvalResult};
+use ::core::env::get_top_level;
+use ::evaluator::interrupt;
+
+fn check_var_name(fname: &str, name: &str) -> EvalResult<()> {
+    if name.is_empty() || name.contains('=') || name.contains('\0') {
+        argument_error!("{}: invalid environment variable name {:?}", fname, name)
+    }
+    Ok(())
+}
+
+pub const GETENV_DOCSTR: &'static str = "Returns the value of an environment variable, or nil if it is not set.
+
+Examples:
+
+(getenv \"HOME\")
+=> \"/home/user\"";
+pub fn getenv(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => name: LString);
+    try!(check_var_name("getenv", &name));
+
+    match std_env::var(&*name) {
+        Ok(val) => Ok(string!(val).to_obj_ref()),
+        Err(std_env::VarError::NotPresent) => Ok(nil!().to_obj_ref()),
+        Err(std_env::VarError::NotUnicode(val)) => io_error!("getenv: value of {} is not valid unicode: {:?}", name, val),
+    }
+}
+
+pub const SETENV_DOCSTR: &'static str = "Sets an environment variable for this process and the programs it runs.
+
+Setting a variable to nil removes it.
+
+Examples:
+
+(setenv \"EDITOR\" \"vi\")
+(setenv \"EDITOR\" nil)";
+pub fn setenv(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => name: LString, val: Any);
+    try!(check_var_name("setenv", &name));
+
+    if val.is_nil() {
+        std_env::remove_var(&*name);
+    } else {
+        let val = check_type!(val, LString);
+        if val.contains('\0') {
+            argument_error!("setenv: value may not contain a NUL character")
+        }
+        std_env::set_var(&*name, &*val);
+    }
+
+    Ok(lisp_true_ref!())
+}
+
+#[cfg(unix)]
+fn shell_command(cmd: &str) -> Command {
+    let mut command = Command::new("sh");
+    command.arg("-c").arg(cmd);
+    command
+}
+
+#[cfg(windows)]
+fn shell_command(cmd: &str) -> Command {
+    let mut command = Command::new("cmd");
+    command.arg("/C").arg(cmd);
+    command
+}
+
+pub const SYSTEM_DOCSTR: &'static str = "Runs a command with the system shell and returns its exit status.
+
+The command shares this process's standard input and output. Returns nil
+if the command was killed by a signal.
+
+Examples:
+
+(system \"ls -l\")
+=> 0";
+pub fn system(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => cmd: LString);
+
+    let status = try!(shell_command(&cmd).status());
+    match status.code() {
+        Some(code) => Ok(int!(code).to_obj_ref()),
+        None => Ok(nil!().to_obj_ref()),
+    }
+}
+
+pub const PROCESS_OUTPUT_DOCSTR: &'static str = "Runs a program with the given arguments and returns its standard output as a string.
+
+The program is run directly, not through a shell. Arguments which are not
+strings are converted as if by print.
+
+Examples:
+
+(process-output \"echo\" \"hello\" 1 2)
+=> \"hello 1 2\\n\"";
+pub fn process_output(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => program: LString, rest program_args);
+    let mut command = Command::new(&*program);
+    for arg in program_args.iter() {
+        match arg.as_ref() {
+            &LispObj::LString(ref s) => command.arg(&**s),
+            other => command.arg(format!("{}", other)),
+        };
+    }
+
+    let output = try!(command.stdin(Stdio::inherit())
+                             .stderr(Stdio::inherit())
+                             .output());
+    Ok(string!(String::from_utf8_lossy(&output.stdout).into_owned()).to_obj_ref())
+}
+
+pub const ON_SIGNAL_DOCSTR: &'static str = "Sets the procedure called when the process receives a signal.
+
+The signal is one of 'sighup, 'sigint or 'sigterm, and the handler is called with its
+name at the next evaluation step, in place of the signal's usual action; an error it
+throws is thrown from whatever was being evaluated. A handler of nil removes it, and
+anything else which isn't a procedure throws a 'type-error.
+Returns the previous handler, or nil.
+
+Handlers are kept in *signal-handlers*, and run by the thread which set them. Only
+available on unix; elsewhere, throws an 'environment-error.
+
+Examples:
+
+(on-signal 'sigterm (lambda (sig) (println \"cleaning up\") (exit 1)))
+=> nil";
+pub fn on_signal(args: &[LispObjRef], env: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => signal: LSymbol, handler: Any);
+    let signum = match interrupt::signal_number(&signal) {
+        Some(signum) => signum,
+        None => argument_error!(args vec![args[0].clone()]; "on-signal: unknown signal {}, expected one of {}", signal,
+                                interrupt::SIGNALS.iter().map(|&(name, _)| name).collect::<Vec<_>>().join(", ")),
+    };
+    if !handler.is_nil() {
+        let _ = check_type!(handler.clone(), LProc);
+    }
+    if !cfg!(unix) {
+        environment_error!("on-signal: signals can only be handled on unix")
+    }
+
+    let top_level = get_top_level(env);
+    let previous = interrupt::signal_handler(&top_level, &signal);
+    let handlers = top_level.borrow().lookup(interrupt::SIGNAL_HANDLERS_NAME).unwrap_or_else(|| nil!().to_obj_ref());
+    let mut entries: Vec<LispObjRef> = handlers.list_iter().filter_map(Result::ok)
+                                               .filter(|entry| !entry.cons_split().map_or(false, |(sig, _)| sig.symbol_equal(&signal)))
+                                               .collect();
+    if !handler.is_nil() {
+        entries.push(cons!(symbol!(signal.clone()), handler.clone()).to_obj_ref());
+    }
+    let _ = top_level.borrow_mut().let_new(String::from(interrupt::SIGNAL_HANDLERS_NAME),
+                                           LispObj::to_lisp_list(entries.into_iter()).to_obj_ref());
+
+    interrupt::set_signal_handled(signum, !handler.is_nil());
+    Ok(previous.unwrap_or_else(|| nil!().to_obj_ref()))
+}
@@ -0,0 +1,121 @@
+//! Builtins for records, the instances of types made with `define-record`.
+//!
+//! These are what the procedures generated by `define-record` are built from, and can
+//! also be used to inspect records generically. Accessors take an optional expected
+//! type, and throw a 'type-error if the record is of another type.
+use ::core::{LispObj, LispObjRef, AsLispObjRef, EnvironmentRef, EvalResult};
+use ::core::obj::Record;
+use ::core::shared::Shared;
+
+fn check_record_type(fname: &str, rec: &Record, expected: Option<String>) -> EvalResult<()> {
+    if let Some(expected) = expected {
+        if rec.type_name != expected {
+            type_error!(args vec![LispObj::make_record(rec.clone()).to_obj_ref()], expected &expected;
+                        "{}: expected {}, not {}", fname, expected, LispObj::make_record(rec.clone()))
+        }
+    }
+    Ok(())
+}
+
+pub const MAKE_RECORD_DOCSTR: &'static str = "Creates a record of a type, with a list of field names and a value for each field.
+
+Examples:
+
+(make-record 'point '(x y) 1 2)
+=> #<point x:1 y:2>";
+pub fn make_record(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => type_name: LSymbol, fields: Any, rest values);
+    let fields = flatten_list!(fields, "make-record: fields must be a list");
+    let mut names = Vec::with_capacity(fields.len());
+    for field in fields.iter() {
+        let name = check_type!(field.clone(), LSymbol);
+        if names.contains(&name) {
+            argument_error!("make-record: duplicate field {}", name)
+        }
+        names.push(name);
+    }
+
+    if values.len() != names.len() {
+        arity_error!("make-record: {} has {} fields, given {} values", type_name, names.len(), values.len())
+    }
+
+    let rec = Record::new(type_name, Shared::new(names), values.to_vec());
+    Ok(LispObj::make_record(rec).to_obj_ref())
+}
+
+pub const IS_RECORD_DOCSTR: &'static str = "Tests if an object is a record, optionally of the given type.
+
+Examples:
+
+(record? (make-point 1 2))
+=> true
+(record? (make-point 1 2) 'color)
+=> false";
+pub fn is_record(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => obj: Any, opt expected: LSymbol);
+    let res = match (obj.record_ref(), expected) {
+        (Some(rec), Some(expected)) => rec.type_name == expected,
+        (Some(_), None) => true,
+        (None, _) => false,
+    };
+    Ok(lisp_bool_ref!(res))
+}
+
+pub const RECORD_TYPE_DOCSTR: &'static str = "Returns the type of a record, as a symbol.
+
+Examples:
+
+(record-type (make-point 1 2))
+=> point";
+pub fn record_type(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => rec: LRecord);
+    Ok(symbol!(rec.type_name.clone()).to_obj_ref())
+}
+
+pub const RECORD_FIELDS_DOCSTR: &'static str = "Returns the field names of a record, as a list of symbols.
+
+Examples:
+
+(record-fields (make-point 1 2))
+=> (x y)";
+pub fn record_fields(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => rec: LRecord);
+    let fields = rec.fields.iter().map(|f| symbol!(f.clone()));
+    Ok(LispObj::to_lisp_list(fields).to_obj_ref())
+}
+
+pub const RECORD_GET_DOCSTR: &'static str = "Returns the value of a field of a record.
+
+Takes an optional expected record type.
+
+Examples:
+
+(record-get (make-point 1 2) 'y)
+=> 2";
+pub fn record_get(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => rec: LRecord, field: LSymbol, opt expected: LSymbol);
+    try!(check_record_type("record-get", &rec, expected));
+
+    match rec.get(&field) {
+        Some(val) => Ok(val),
+        None => argument_error!("record-get: {} has no field {}", rec.type_name, field),
+    }
+}
+
+pub const RECORD_SET_DOCSTR: &'static str = "Returns a copy of a record with one field changed.
+
+Takes an optional expected record type. The original record is unchanged.
+
+Examples:
+
+(record-set (make-point 1 2) 'y 3)
+=> #<point x:1 y:3>";
+pub fn record_set(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => rec: LRecord, field: LSymbol, value: Any, opt expected: LSymbol);
+    try!(check_record_type("record-set", &rec, expected));
+
+    match rec.with(&field, value) {
+        Some(rec) => Ok(LispObj::make_record(rec).to_obj_ref()),
+        None => argument_error!("record-set: {} has no field {}", rec.type_name, field),
+    }
+}
@@ -0,0 +1,176 @@
+//! String builtins.
+//!
+//! String builders are ports which collect everything written to them, so that
+//! long strings can be built up in linear time rather than by repeated `string` calls.
+use std::cmp;
+
+use ::core::{LispObj, LispObjRef, AsLispObjRef, EnvironmentRef, EvalResult};
+use ::core::obj::Port;
+use ::evaluator::output;
+
+pub const MAKE_STRING_BUILDER_DOCSTR: &'static str = "Creates an empty string builder.
+
+Examples:
+
+(define b (make-string-builder))
+(builder-append! b \"x = \" 1)
+(builder->string b)
+=> \"x = 1\"";
+pub fn make_string_builder(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args);
+    Ok(LispObj::LPort(Port::string_builder()).to_obj_ref())
+}
+
+pub const BUILDER_APPEND_DOCSTR: &'static str = "Appends its arguments to a string builder, returning the builder.
+
+Strings and chars are appended as they are, and other objects as if by print.
+
+Examples:
+
+(builder-append! b \"line \" 1 \\\\newline)";
+pub fn builder_append(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => builder: LPort, rest values);
+    let mut out = String::new();
+    for arg in values.iter() {
+        push_display(&mut out, arg);
+    }
+
+    try!(builder.write_str(&out));
+    Ok(args[0].clone())
+}
+
+pub const BUILDER_TO_STRING_DOCSTR: &'static str = "Returns everything appended to a string builder so far.
+
+The builder can still be appended to afterwards.
+
+Examples:
+
+(builder->string b)
+=> \"line 1\\n\"";
+pub fn builder_to_string(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => builder: LPort);
+    Ok(string!(try!(builder.contents())).to_obj_ref())
+}
+
+/// Appends `obj` to `out` as print would write it: strings and chars as they are
+fn push_display(out: &mut String, obj: &LispObj) {
+    match obj {
+        &LispObj::LString(ref s) => out.push_str(s),
+        &LispObj::LChar(c) => out.push(c),
+        other => out.push_str(&format!("{}", other)),
+    }
+}
+
+/// The most digits after the point which `~f` gives
+const MAX_PRECISION: usize = 100;
+
+/// Expands the directives in a format string
+fn format_directives(control: &str, args: &[LispObjRef]) -> EvalResult<String> {
+    let mut out = String::new();
+    let mut args = args.iter();
+    let mut chars = control.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '~' {
+            out.push(c);
+            continue
+        }
+
+        let mut digits = String::new();
+        while chars.peek().map_or(false, |c| c.is_digit(10)) {
+            digits.push(chars.next().unwrap());
+        }
+        let precision = if digits.is_empty() {
+            None
+        } else {
+            match digits.parse::<usize>() {
+                Ok(precision) => Some(cmp::min(precision, MAX_PRECISION)),
+                Err(_) => argument_error!("format: invalid precision {} in {:?}", digits, control),
+            }
+        };
+        let directive = match chars.next() {
+            Some(d) => d.to_ascii_lowercase(),
+            None => argument_error!("format: directive missing after ~ in {:?}", control),
+        };
+        if precision.is_some() && directive != 'f' {
+            argument_error!("format: only ~f takes a precision, not ~{}", directive)
+        }
+
+        let arg = match directive {
+            '%' => { out.push('\n'); continue },
+            '~' => { out.push('~'); continue },
+            'a' | 's' | 'd' | 'f' => match args.next() {
+                Some(arg) => arg,
+                None => argument_error!("format: not enough arguments for {:?}", control),
+            },
+            other => argument_error!("format: unknown directive ~{}", other),
+        };
+
+        match (directive, arg.as_ref()) {
+            ('a', obj) => push_display(&mut out, obj),
+            ('s', obj) => out.push_str(&format!("{}", obj)),
+            ('d', &LispObj::LInteger(n)) => out.push_str(&n.to_string()),
+            ('f', &LispObj::LInteger(n)) => match precision {
+                Some(precision) => out.push_str(&format!("{:.*}", precision, n as f64)),
+                None => out.push_str(&format!("{}", LispObj::LFloat(n as f64))),
+            },
+            ('f', obj @ &LispObj::LFloat(n)) => match precision {
+                Some(precision) => out.push_str(&format!("{:.*}", precision, n)),
+                None => out.push_str(&format!("{}", obj)),
+            },
+            ('d', obj) => type_error!(args vec![arg.clone()], expected "integer"; "format: ~d expects an integer, got {}", obj),
+            (_, obj) => type_error!(args vec![arg.clone()], expected "number"; "format: ~f expects a number, got {}", obj),
+        }
+    }
+
+    if args.next().is_some() {
+        argument_error!("format: too many arguments for {:?}", control)
+    }
+    Ok(out)
+}
+
+pub const FORMAT_DOCSTR: &'static str = "Formats its arguments into a string, following directives in a control string.
+
+The directives are:
+  ~a   the next argument as print writes it, e.g. strings without quotes
+  ~s   the next argument as it is read, e.g. strings with quotes
+  ~d   the next argument, an integer
+  ~f   the next argument, a number, as a float; ~2f gives 2 digits after the point,
+       and precisions over 100 give 100
+  ~%   a newline
+  ~~   a tilde
+
+If the first argument is true, the result is printed instead of returned, and the
+control string comes second. Throws an 'argument-error if there are too few or too
+many arguments for the directives.
+
+Examples:
+
+(format \"x=~a y=~s\" \"one\" \"two\")
+=> \"x=one y=\\\"two\\\"\"
+(format \"~2f%\" 12.345)
+=> \"12.35%\"
+(format true \"~d items~%\" 3)
+;; 3 items
+=> true";
+pub fn format(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    let (print, args) = match args.first() {
+        Some(dest) if dest.symbol_equal("true") => (true, &args[1..]),
+        _ => (false, args),
+    };
+    let control = match args.first().map(|arg| arg.as_ref()) {
+        Some(&LispObj::LString(ref control)) => control.clone(),
+        Some(other) => type_error!(args vec![args[0].clone()], expected "string"; "format: expected a control string, got {}", other),
+        None => arity_error!("format: expected a control string"),
+    };
+
+    let out = try!(format_directives(&control, &args[1..]));
+    if print {
+        if let Err(err) = output::write_stdout(|stdout| write!(stdout, "{}", out)) {
+            io_error!("{:?}", err)
+        }
+        Ok(lisp_true_ref!())
+    } else {
+        Ok(string!(out).to_obj_ref())
+    }
+}
@@ -0,0 +1,111 @@
+//! Threads and channels.
+//!
+//! Threads and channels are represented as ports. A spawned thread shares the
+//! environment it was spawned from, so it can only be used when values can be sent
+//! between threads, with the `sync` feature; otherwise `spawn` throws an
+//! 'environment-error. Channels work either way. Failures, including using a closed
+//! port, throw an 'io-error.
+#[cfg(feature = "sync")]
+use std::thread;
+use std::time::Duration;
+
+use ::core::{LispObj, LispObjRef, AsLispObjRef, RuntimeError, EnvironmentRef, EvalResult};
+use ::core::obj::Port;
+#[cfg(feature = "sync")]
+use super::super::output;
+#[cfg(feature = "sync")]
+use super::super::flags::Flags;
+
+/// How long recv waits between checks for interrupts
+const RECV_POLL_MILLIS: u64 = 50;
+
+pub const SPAWN_DOCSTR: &'static str = "Calls a procedure of no arguments in a new thread, returning the thread.
+
+The thread shares the global environment. Only available when built with the sync feature.
+
+Examples:
+
+(define t (spawn (lambda () (+ 1 2))))
+(join t)
+=> 3";
+#[cfg(feature = "sync")]
+pub fn spawn(args: &[LispObjRef], env: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => func: LProc);
+    let (stdout, stderr) = output::current();
+    let flags = Flags::current();
+    let handle = thread::spawn(move || output::with_output(stdout, stderr, || {
+        flags.set_current();
+        super::super::apply(func, nil!(), env)
+    }));
+    Ok(LispObj::LPort(Port::thread(handle)).to_obj_ref())
+}
+
+#[cfg(not(feature = "sync"))]
+pub fn spawn(_: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    environment_error!("spawn: threads need rustylisp to be built with the sync feature")
+}
+
+pub const JOIN_DOCSTR: &'static str = "Waits for a thread to finish, and returns its result.
+
+If the thread threw an error, join throws it. A thread can only be joined once.
+
+Examples:
+
+(join (spawn (lambda () 'done)))
+=> done";
+#[cfg(feature = "sync")]
+pub fn join(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => thread: LPort);
+    try!(thread.join())
+}
+
+#[cfg(not(feature = "sync"))]
+pub fn join(_: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    environment_error!("join: threads need rustylisp to be built with the sync feature")
+}
+
+pub const CHANNEL_DOCSTR: &'static str = "Makes a channel, a queue which threads can send values to and receive them from.
+
+Examples:
+
+(define ch (channel))
+(send ch 'hello)
+(recv ch)
+=> hello";
+pub fn channel(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args);
+    Ok(LispObj::LPort(Port::channel()).to_obj_ref())
+}
+
+pub const SEND_DOCSTR: &'static str = "Sends a value on a channel. Returns the channel.
+
+Examples:
+
+(send ch '(1 2 3))";
+pub fn send(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => chan: LPort, value: Any);
+    try!(chan.send(value));
+    Ok(args[0].clone())
+}
+
+pub const RECV_DOCSTR: &'static str = "Receives the oldest value sent on a channel, waiting for one if there are none.
+
+Waiting counts against any limits on the evaluation, so it can be cut short by a
+time limit.
+
+Examples:
+
+(recv ch)
+=> (1 2 3)";
+pub fn recv(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => chan: LPort);
+    loop {
+        if super::super::interrupt::take() {
+            return Err(RuntimeError::interrupt())
+        }
+        try!(super::super::limits::wait_step());
+        if let Some(value) = try!(chan.recv_timeout(Duration::from_millis(RECV_POLL_MILLIS))) {
+            return Ok(value)
+        }
+    }
+}
@@ -0,0 +1,116 @@
+//! Clock and calendar builtins.
+//!
+//! All times are measured from the unix epoch, and dates are given in UTC.
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use ::core::{LispObj, LispObjRef, AsLispObjRef, EnvironmentRef, EvalResult};
+use ::evaluator::interrupt;
+
+/// Sleeps are split into slices of this many milliseconds, so they can be interrupted.
+const SLEEP_SLICE_MS: u64 = 50;
+
+fn since_epoch() -> EvalResult<Duration> {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(d)  => Ok(d),
+        Err(_) => internal_error!("system clock is set before the unix epoch"),
+    }
+}
+
+pub const CURRENT_SECONDS_DOCSTR: &'static str = "Returns the number of whole seconds since the unix epoch.
+
+Examples:
+
+(current-seconds)
+=> 1792137600";
+pub fn current_seconds(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args);
+    let now = try!(since_epoch());
+    Ok(int!(now.as_secs() as i64).to_obj_ref())
+}
+
+pub const CURRENT_MILLISECONDS_DOCSTR: &'static str = "Returns the number of milliseconds since the unix epoch.
+
+Examples:
+
+(- (current-milliseconds) start)";
+pub fn current_milliseconds(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args);
+    let now = try!(since_epoch());
+    let millis = now.as_secs() as i64 * 1000 + (now.subsec_nanos() / 1_000_000) as i64;
+    Ok(int!(millis).to_obj_ref())
+}
+
+pub const SLEEP_DOCSTR: &'static str = "Pauses for the given number of milliseconds.
+
+Throws an 'argument-error if the duration is negative.
+
+Examples:
+
+(sleep 500)";
+pub fn sleep(args: &[LispObjRef], env: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => ms: LInteger);
+    if ms < 0 {
+        argument_error!("sleep: expected a non-negative duration, not {}", ms)
+    }
+
+    let mut remaining = ms as u64;
+    while remaining > 0 {
+        try!(interrupt::poll(&env));
+
+        let slice = if remaining < SLEEP_SLICE_MS { remaining } else { SLEEP_SLICE_MS };
+        thread::sleep(Duration::from_millis(slice));
+        remaining -= slice;
+    }
+
+    Ok(nil!().to_obj_ref())
+}
+
+pub const DATE_DOCSTR: &'static str = "Returns the UTC date and time as an association list.
+
+Takes an optional number of seconds since the unix epoch, and uses the
+current time otherwise. Weekdays count from 0 for Sunday.
+
+Examples:
+
+(date 0)
+=> ((year . 1970) (month . 1) (day . 1) (hour . 0) (minute . 0) (second . 0) (weekday . 4))";
+pub fn date(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => opt secs: LInteger);
+    let secs = match secs {
+        Some(secs) => secs,
+        None => try!(since_epoch()).as_secs() as i64,
+    };
+
+    let days = div_floor(secs, 86400);
+    let time_of_day = secs - days * 86400;
+    let (year, month, day) = civil_from_days(days);
+    // 1970-01-01 was a Thursday
+    let weekday = (days + 4) - div_floor(days + 4, 7) * 7;
+
+    let fields = vec![("year", year), ("month", month), ("day", day),
+                      ("hour", time_of_day / 3600), ("minute", time_of_day / 60 % 60),
+                      ("second", time_of_day % 60), ("weekday", weekday)];
+    let alist = fields.into_iter().map(|(name, val)| cons!(symbol!(name), int!(val)));
+    Ok(LispObj::to_lisp_list(alist).to_obj_ref())
+}
+
+fn div_floor(a: i64, b: i64) -> i64 {
+    let q = a / b;
+    if (a % b != 0) && ((a < 0) != (b < 0)) { q - 1 } else { q }
+}
+
+/// Converts days since the unix epoch to a (year, month, day) in the proleptic
+/// Gregorian calendar, after Howard Hinnant's `civil_from_days`.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = div_floor(z, 146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
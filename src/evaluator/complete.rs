@@ -0,0 +1,67 @@
+//! Completing names, for line editors.
+//!
+//! The names a symbol can complete to are the bindings, macros and special forms visible
+//! from an environment. Lisp code can change this by binding `*completion-function*` to
+//! a procedure, which is called with the text being completed and returns a list of
+//! strings. `(completions prefix)` gives the default completions, for such a procedure
+//! to add to or filter.
+use ::core::{LispObj, AsLispObjRef, EnvironmentRef, EvalResult};
+
+/// The name of the procedure used instead of the default completions, or nil
+pub static COMPLETION_FUNCTION_NAME: &'static str = "*completion-function*";
+
+/// Characters which end a symbol, so completion starts after them
+fn is_delimiter(c: char) -> bool {
+    c.is_whitespace() || "()[]'`,\";".contains(c)
+}
+
+/// Where the symbol at the end of `line` starts, as a byte offset.
+pub fn symbol_start(line: &str) -> usize {
+    line.char_indices().rev()
+        .find(|&(_, c)| is_delimiter(c))
+        .map_or(0, |(i, c)| i + c.len_utf8())
+}
+
+/// The names visible from `env` which start with `prefix`, sorted and without
+/// duplicates.
+pub fn default_completions(prefix: &str, env: &EnvironmentRef) -> Vec<String> {
+    let mut names: Vec<String> = super::special_form_handlers::names().into_iter()
+                                     .filter(|name| name.starts_with(prefix))
+                                     .map(String::from)
+                                     .collect();
+
+    let mut frame = Some(env.clone());
+    while let Some(env) = frame {
+        let env = env.borrow();
+        let local = env.local_bindings().into_iter().chain(env.local_macros().into_iter());
+        names.extend(local.map(|(name, _)| name).filter(|name| name.starts_with(prefix)));
+        frame = env.parent();
+    }
+
+    names.sort();
+    names.dedup();
+    names
+}
+
+/// The completions of `prefix`, from `*completion-function*` if it is set.
+pub fn completions(prefix: &str, env: &EnvironmentRef) -> EvalResult<Vec<String>> {
+    let func = match env.borrow().lookup(COMPLETION_FUNCTION_NAME) {
+        Some(ref func) if !func.is_nil() => func.clone(),
+        _ => return Ok(default_completions(prefix, env)),
+    };
+
+    let res = try!(super::apply(func, lisp_list!(string!(prefix)), env.clone()));
+    let mut names = vec![];
+    for name in res.list_iter() {
+        let name = match name {
+            Ok(name) => name,
+            Err(_) => type_error!("{} must return a list, not {}", COMPLETION_FUNCTION_NAME, res),
+        };
+        match name.as_ref() {
+            &LispObj::LString(ref s) => names.push((**s).clone()),
+            other => type_error!(args vec![name.clone()], expected "string";
+                                 "{} must return a list of strings, not {}", COMPLETION_FUNCTION_NAME, other),
+        }
+    }
+    Ok(names)
+}
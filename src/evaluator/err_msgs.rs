@@ -1,5 +1,16 @@
+//! Error-raising macros, and the names of the errors the evaluator raises.
+//!
+//! Each macro returns an error with a formatted message as its value. Prefixed with
+//! `args vec![...];`, or `args vec![...], expected "type";`, the value is instead an
+//! association list of the message and those details; see `RuntimeError::with_details`.
 #[macro_export]
 macro_rules! argument_error {
+    ( args $args:expr, expected $expected:expr; $( $msg:expr ),* ) => {
+        runtime_error!( args $args, expected $expected; $crate::evaluator::err_msgs::ARGUMENT_ERROR  $(, $msg )* )
+    };
+    ( args $args:expr; $( $msg:expr ),* ) => {
+        runtime_error!( args $args; $crate::evaluator::err_msgs::ARGUMENT_ERROR  $(, $msg )* )
+    };
     ( $( $msg:expr ),* ) => {
         runtime_error!( $crate::evaluator::err_msgs::ARGUMENT_ERROR  $(, $msg )* )
     }
@@ -7,6 +18,12 @@ macro_rules! argument_error {
 
 #[macro_export]
 macro_rules! arithmetic_error {
+    ( args $args:expr, expected $expected:expr; $( $msg:expr ),* ) => {
+        runtime_error!( args $args, expected $expected; $crate::evaluator::err_msgs::ARITHMETIC_ERROR  $(, $msg )* )
+    };
+    ( args $args:expr; $( $msg:expr ),* ) => {
+        runtime_error!( args $args; $crate::evaluator::err_msgs::ARITHMETIC_ERROR  $(, $msg )* )
+    };
     ( $( $msg:expr ),* ) => {
         runtime_error!( $crate::evaluator::err_msgs::ARITHMETIC_ERROR  $(, $msg )* )
     }
@@ -14,18 +31,47 @@ macro_rules! arithmetic_error {
 
 #[macro_export]
 macro_rules! arity_error {
+    ( args $args:expr, expected $expected:expr; $( $msg:expr ),* ) => {
+        runtime_error!( args $args, expected $expected; $crate::evaluator::err_msgs::ARITY_ERROR  $(, $msg )* )
+    };
+    ( args $args:expr; $( $msg:expr ),* ) => {
+        runtime_error!( args $args; $crate::evaluator::err_msgs::ARITY_ERROR  $(, $msg )* )
+    };
     ( $( $msg:expr ),* ) => {
         runtime_error!( $crate::evaluator::err_msgs::ARITY_ERROR  $(, $msg )* )
     }
 }
 
+#[macro_export]
+macro_rules! assertion_error {
+    ( $( $msg:expr ),* ) => {
+        runtime_error!( $crate::evaluator::err_msgs::ASSERTION_ERROR  $(, $msg )* )
+    }
+}
+
 #[macro_export]
 macro_rules! bound_error {
-    ( $( $msg:expr ),*) => {
+    ( args $args:expr, expected $expected:expr; $( $msg:expr ),* ) => {
+        runtime_error!( args $args, expected $expected; $crate::evaluator::err_msgs::BOUND_ERROR  $(, $msg )* )
+    };
+    ( args $args:expr; $( $msg:expr ),* ) => {
+        runtime_error!( args $args; $crate::evaluator::err_msgs::BOUND_ERROR  $(, $msg )* )
+    };
+    ( $( $msg:expr ),* ) => {
         runtime_error!( $crate::evaluator::err_msgs::BOUND_ERROR  $(, $msg )* )
     }
 }
 
+#[macro_export]
+macro_rules! bounds_error {
+    ( args $args:expr; $( $msg:expr ),* ) => {
+        runtime_error!( args $args; $crate::evaluator::err_msgs::BOUNDS_ERROR  $(, $msg )* )
+    };
+    ( $( $msg:expr ),* ) => {
+        runtime_error!( $crate::evaluator::err_msgs::BOUNDS_ERROR  $(, $msg )* )
+    }
+}
+
 #[macro_export]
 macro_rules! environment_error {
     ( $( $msg:expr ),*) => {
@@ -47,16 +93,33 @@ macro_rules! io_error {
     }
 }
 
+#[macro_export]
+macro_rules! json_error {
+    ( $( $msg:expr ),*) => {
+        runtime_error!( $crate::evaluator::err_msgs::JSON_ERROR  $(, $msg )* )
+    }
+}
+
 #[macro_export]
 macro_rules! macro_error {
     ( cause $cause:expr; $( $msg:expr ),* ) => {
         runtime_error!( cause $cause; $crate::evaluator::err_msgs::MACRO_ERROR  $(, $msg )* )
     };
+    ( args $args:expr, expected $expected:expr; $( $msg:expr ),* ) => {
+        runtime_error!( args $args, expected $expected; $crate::evaluator::err_msgs::MACRO_ERROR  $(, $msg )* )
+    };
     ( $( $msg:expr ),* ) => {
         runtime_error!( $crate::evaluator::err_msgs::MACRO_ERROR  $(, $msg )* )
     }
 }
 
+#[macro_export]
+macro_rules! match_error {
+    ( $( $msg:expr ),* ) => {
+        runtime_error!( $crate::evaluator::err_msgs::MATCH_ERROR  $(, $msg )* )
+    }
+}
+
 #[macro_export]
 macro_rules! read_error {
     ( $( $msg:expr ),* ) => {
@@ -74,6 +137,13 @@ macro_rules! redefine_error {
     }
 }
 
+#[macro_export]
+macro_rules! resource_error {
+    ( $( $msg:expr ),* ) => {
+        runtime_error!( $crate::evaluator::err_msgs::RESOURCE_ERROR  $(, $msg )* )
+    }
+}
+
 #[macro_export]
 macro_rules! syntax_error {
     ( $( $msg:expr ),* ) => {
@@ -83,6 +153,12 @@ macro_rules! syntax_error {
 
 #[macro_export]
 macro_rules! type_error {
+    ( args $args:expr, expected $expected:expr; $( $msg:expr ),* ) => {
+        runtime_error!( args $args, expected $expected; $crate::evaluator::err_msgs::TYPE_ERROR  $(, $msg )* )
+    };
+    ( args $args:expr; $( $msg:expr ),* ) => {
+        runtime_error!( args $args; $crate::evaluator::err_msgs::TYPE_ERROR  $(, $msg )* )
+    };
     ( $( $msg:expr ),* ) => {
         runtime_error!( $crate::evaluator::err_msgs::TYPE_ERROR  $(, $msg )* )
     }
@@ -91,12 +167,20 @@ macro_rules! type_error {
 pub static ARGUMENT_ERROR:      &'static str = "argument-error";
 pub static ARITHMETIC_ERROR:    &'static str = "arithmetic-error";
 pub static ARITY_ERROR:         &'static str = "arity-error";
+pub static ASSERTION_ERROR:     &'static str = "assertion-error";
 pub static BOUND_ERROR:         &'static str = "bound-error";
+pub static BOUNDS_ERROR:        &'static str = "bounds-error";
+pub use core::error::ESCAPE_REQUEST;
+pub use core::error::EXIT_REQUEST;
 pub static ENVIRONMENT_ERROR:   &'static str = "environment-error";
 pub static INTERNAL_ERROR:      &'static str = "internal-error";
+pub use core::error::INTERRUPT_ERROR;
 pub static IO_ERROR:            &'static str = "io-error";
+pub static JSON_ERROR:          &'static str = "json-error";
 pub static MACRO_ERROR:         &'static str = "macro-expansion-error";
+pub static MATCH_ERROR:         &'static str = "match-error";
 pub static READ_ERROR:          &'static str = "read-error";
 pub static REDEFINE_ERROR:      &'static str = "redefine-error";
+pub static RESOURCE_ERROR:      &'static str = "resource-error";
 pub static SYNTAX_ERROR:        &'static str = "syntax-error";
 pub static TYPE_ERROR:          &'static str = "type-error";
@@ -0,0 +1,80 @@
+//! Global variables which the evaluator checks constantly, cached per thread.
+//!
+//! `*strict-booleans*` is consulted by every conditional, and `*trace-all*` by every
+//! procedure call, so rather than looking them up in the environment each time, their
+//! values are kept here. `set!` and top-level `define` of either name update the cache, and each evaluation through a `run::Evaluator` starts
+//! from the values bound in that evaluator's environment. Spawned threads and generators
+//! start with the flags of the thread which created them.
+use std::cell::Cell;
+
+use ::core::{LispObj, EnvironmentRef};
+use super::builtins::STRICT_BOOLEANS_NAME;
+use super::trace::TRACE_ALL_NAME;
+
+/// The cached values.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Flags {
+    /// Whether `*strict-booleans*` is true
+    pub strict_booleans: bool,
+    /// Whether `*trace-all*` is true
+    pub trace_all: bool,
+}
+
+thread_local! {
+    static FLAGS: Cell<Flags> = Cell::new(Flags::default());
+}
+
+fn is_set(env: &EnvironmentRef, name: &str) -> bool {
+    env.borrow().lookup(name).map_or(false, |val| !val.falsey())
+}
+
+impl Flags {
+    /// The flags as bound in `env`.
+    pub fn from_env(env: &EnvironmentRef) -> Self {
+        Flags {
+            strict_booleans: is_set(env, STRICT_BOOLEANS_NAME),
+            trace_all: is_set(env, TRACE_ALL_NAME),
+        }
+    }
+
+    /// The flags in place on this thread.
+    pub fn current() -> Self {
+        FLAGS.with(|f| f.get())
+    }
+
+    /// Puts these flags in place on this thread, such as on a thread spawned by lisp code.
+    pub fn set_current(self) {
+        FLAGS.with(|f| f.set(self))
+    }
+}
+
+/// Runs `f` with `flags` in place, restoring the previous flags afterwards.
+pub fn with_flags<T, F>(flags: Flags, f: F) -> T
+        where F: FnOnce() -> T {
+    let outer = Flags::current();
+    flags.set_current();
+    let res = f();
+    outer.set_current();
+    res
+}
+
+/// Updates the cache after the global `name` is set to `val`.
+pub fn note_set(name: &str, val: &LispObj) {
+    let mut flags = Flags::current();
+    if name == STRICT_BOOLEANS_NAME {
+        flags.strict_booleans = !val.falsey();
+    } else if name == TRACE_ALL_NAME {
+        flags.trace_all = !val.falsey();
+    }
+    flags.set_current()
+}
+
+/// Whether `*strict-booleans*` is true.
+pub fn strict_booleans() -> bool {
+    FLAGS.with(|f| f.get().strict_booleans)
+}
+
+/// Whether `*trace-all*` is true.
+pub fn trace_all() -> bool {
+    FLAGS.with(|f| f.get().trace_all)
+}
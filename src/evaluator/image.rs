@@ -0,0 +1,668 @@
+//! Saving and restoring the state of a top-level environment.
+//!
+//! An image records every binding, macro, special character handler and piece of
+//! metadata of a top-level environment, so that a session can be resumed later. Native functions are not
+//! serialized; they are stored by name and re-linked to the natives available when the
+//! image is loaded.
+//!
+//! Procedures keep their closures: every environment frame reachable from a saved
+//! procedure is stored in a frame table, and every procedure in a procedure table, so
+//! that frames and procedures shared between several bindings are still shared after
+//! loading.
+//!
+//! The image itself is written as JSON. Each lisp value is encoded as a tagged vector,
+//! e.g. `["i", 1]` for an integer or `["y", "foo"]` for a symbol, which keeps the
+//! distinctions (symbol vs. string, float vs. integer) that plain JSON would lose.
+//! Values nested more than `MAX_DEPTH` deep, other than along the tails of lists, can't
+//! be saved.
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use ::core::{LispObj, LispObjRef, AsLispObjRef, Environment, EnvironmentRef,
+             EvalResult, RuntimeError};
+use ::core::env::get_top_level;
+use ::core::obj::Record;
+use ::core::procedure::{ArityObj, Procedure};
+use ::core::shared::Shared;
+
+static IMAGE_TAG: &'static str = "rustylisp-image";
+static IMAGE_VERSION: i64 = 1;
+
+/// How deeply a value may be nested in an image. Values are encoded and decoded by
+/// recursing, so deeper ones throw an error rather than overflowing the stack.
+pub const MAX_DEPTH: usize = 100;
+
+/// Writes an image of the top-level environment of `env` to the file at `path`.
+pub fn save_image<P: AsRef<Path>>(env: EnvironmentRef, path: P) -> EvalResult<()> {
+    let contents = try!(image_to_string(env));
+    let mut file = try!(File::create(path));
+    try!(file.write_all(contents.as_bytes()));
+    Ok(())
+}
+
+/// Loads an image from `path` into the top-level environment of `env`.
+///
+/// Existing bindings with the same names are overwritten, others are left alone.
+pub fn load_image<P: AsRef<Path>>(env: EnvironmentRef, path: P) -> EvalResult<()> {
+    let mut contents = String::new();
+    let mut file = try!(File::open(path));
+    try!(file.read_to_string(&mut contents));
+    image_from_string(env, &contents)
+}
+
+/// Encodes the top-level environment of `env` as an image string.
+pub fn image_to_string(env: EnvironmentRef) -> EvalResult<String> {
+    let top_level = get_top_level(env);
+    let mut encoder = Encoder { frames: vec![], frame_ids: HashMap::new(),
+                                procs: vec![], proc_ids: HashMap::new(), depth: 0 };
+
+    let top_index = try!(encoder.frame(&top_level));
+    debug_assert!(top_index == 0);
+
+    let macros = {
+        let macros = top_level.borrow().local_macros();
+        try!(macros.into_iter().map(|(name, mac)| {
+            Ok(vector(vec![string!(name).to_obj_ref(), try!(encoder.value(&mac))]))
+        }).collect::<EvalResult<Vec<_>>>())
+    };
+
+    let char_handlers = {
+        let handlers = top_level.borrow().local_char_handlers();
+        try!(handlers.into_iter().map(|(c, handler)| {
+            Ok(vector(vec![string!(c.to_string()).to_obj_ref(), try!(encoder.value(&handler))]))
+        }).collect::<EvalResult<Vec<_>>>())
+    };
+
+    let metadata = {
+        let metadata = top_level.borrow().local_metadata();
+        try!(metadata.into_iter().map(|(name, pairs)| {
+            let pairs = try!(pairs.into_iter().map(|(key, val)| {
+                Ok(vector(vec![string!(key).to_obj_ref(), try!(encoder.value(&val))]))
+            }).collect::<EvalResult<Vec<_>>>());
+            Ok(vector(vec![string!(name).to_obj_ref(), vector(pairs)]))
+        }).collect::<EvalResult<Vec<_>>>())
+    };
+
+    let frames: Vec<_> = encoder.frames.into_iter()
+                                .map(|f| f.expect("all frames encoded"))
+                                .collect();
+    let procs: Vec<_> = encoder.procs.into_iter()
+                               .map(|p| p.expect("all procedures encoded"))
+                               .collect();
+
+    let image = vector(vec![string!(IMAGE_TAG).to_obj_ref(), int!(IMAGE_VERSION).to_obj_ref(),
+                            vector(frames), vector(procs), vector(macros), vector(char_handlers),
+                            vector(metadata)]);
+    image.to_json()
+}
+
+/// Loads an image string, as produced by `image_to_string`, into the top-level
+/// environment of `env`.
+pub fn image_from_string(env: EnvironmentRef, contents: &str) -> EvalResult<()> {
+    let top_level = get_top_level(env);
+    let image = try!(LispObj::from_json(contents));
+    let header = try!(fields(&image, 6));
+
+    if header[0].string_ref().map_or(true, |tag| *tag != IMAGE_TAG) {
+        read_error!("not a rustylisp image")
+    }
+    match *header[1] {
+        LispObj::LInteger(v) if v == IMAGE_VERSION => {},
+        _ => read_error!("unsupported image version {}", header[1]),
+    }
+
+    let mut decoder = Decoder {
+        frame_data: try!(fields(&header[2], 0)),
+        frames: HashMap::new(),
+        proc_data: try!(fields(&header[3], 0)),
+        procs: HashMap::new(),
+        top_level: top_level.clone(),
+        depth: 0,
+    };
+
+    if decoder.frame_data.is_empty() {
+        read_error!("image has no top-level frame")
+    }
+
+    // Create every frame before filling any of them, since bindings can
+    // refer to procedures closing over any frame
+    for i in 0..decoder.frame_data.len() {
+        let _ = try!(decoder.frame(i));
+    }
+
+    for i in 0..decoder.frame_data.len() {
+        let frame = decoder.frames[&i].clone();
+        let frame_fields = try!(fields(&decoder.frame_data[i], 2));
+        for binding in try!(fields(&frame_fields[1], 0)) {
+            let (name, value) = try!(decoder.named_pair(&binding));
+            let value = try!(decoder.value(&value));
+            let _ = frame.borrow_mut().let_new(name, value);
+        }
+    }
+
+    for mac in try!(fields(&header[4], 0)) {
+        let (name, value) = try!(decoder.named_pair(&mac));
+        let value = try!(decoder.value(&value));
+        let _ = try!(top_level.borrow_mut().let_macro(name, value));
+    }
+
+    for handler in try!(fields(&header[5], 0)) {
+        let (name, value) = try!(decoder.named_pair(&handler));
+        let mut chars = name.chars();
+        let c = match (chars.next(), chars.next()) {
+            (Some(c), None) => c,
+            _ => read_error!("invalid char handler name {:?} in image", name),
+        };
+        let value = try!(decoder.value(&value));
+        let _ = top_level.borrow_mut().set_char_handler(c, value);
+    }
+
+    // Metadata is absent from older images
+    if let Some(metadata) = header.get(6) {
+        for entry in try!(fields(metadata, 0)) {
+            let (name, pairs) = try!(decoder.named_pair(&entry));
+            for pair in try!(fields(&pairs, 0)) {
+                let (key, value) = try!(decoder.named_pair(&pair));
+                let value = try!(decoder.value(&value));
+                let _ = top_level.borrow_mut().set_meta(name.clone(), key, value);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn vector(items: Vec<LispObjRef>) -> LispObjRef {
+    LispObj::make_vector(items.into_iter()).to_obj_ref()
+}
+
+fn tagged(tag: &str, mut items: Vec<LispObjRef>) -> LispObjRef {
+    items.insert(0, string!(tag).to_obj_ref());
+    vector(items)
+}
+
+fn optional(obj: Option<LispObjRef>) -> LispObjRef {
+    obj.unwrap_or(symbol!("null").to_obj_ref())
+}
+
+/// The elements of an encoded vector, checking there are at least `min_len` of them
+fn fields(obj: &LispObjRef, min_len: usize) -> EvalResult<Vec<LispObjRef>> {
+    match obj.vec_ref() {
+        Some(vec) if vec.len() >= min_len => Ok(vec.iter().cloned().collect()),
+        _ => read_error!("malformed image entry {}", obj),
+    }
+}
+
+fn field_string(obj: &LispObjRef) -> EvalResult<String> {
+    match obj.string_ref() {
+        Some(s) => Ok((*s).clone()),
+        None    => read_error!("expected string in image, not {}", obj),
+    }
+}
+
+fn field_optional_string(obj: &LispObjRef) -> EvalResult<Option<String>> {
+    if obj.symbol_equal("null") {
+        Ok(None)
+    } else {
+        field_string(obj).map(Some)
+    }
+}
+
+fn field_index(obj: &LispObjRef) -> EvalResult<usize> {
+    match **obj {
+        LispObj::LInteger(n) if n >= 0 => Ok(n as usize),
+        _ => read_error!("expected index in image, not {}", obj),
+    }
+}
+
+/*********************** Encoding ************************/
+
+struct Encoder {
+    // Entries are None while being encoded, so that cycles can refer to them
+    frames: Vec<Option<LispObjRef>>,
+    frame_ids: HashMap<usize, usize>,
+    procs: Vec<Option<LispObjRef>>,
+    proc_ids: HashMap<u32, usize>,
+    /// How deeply the value being encoded is nested in its frame or procedure
+    depth: usize,
+}
+
+impl Encoder {
+    /// Runs `f` at the top of a frame or procedure entry, which are stored in their own
+    /// tables rather than nested in the value referring to them.
+    fn entry<T, F: FnOnce(&mut Self) -> EvalResult<T>>(&mut self, f: F) -> EvalResult<T> {
+        let depth = self.depth;
+        self.depth = 0;
+        let res = f(self);
+        self.depth = depth;
+        res
+    }
+
+    /// Runs `f` one level deeper, throwing once values are nested too deeply to read back.
+    fn nested<T, F: FnOnce(&mut Self) -> EvalResult<T>>(&mut self, f: F) -> EvalResult<T> {
+        if self.depth == MAX_DEPTH {
+            resource_error!("cannot save values nested more than {} deep in an image", MAX_DEPTH)
+        }
+        self.depth += 1;
+        let res = f(self);
+        self.depth -= 1;
+        res
+    }
+
+    fn frame(&mut self, env: &EnvironmentRef) -> EvalResult<usize> {
+        self.entry(|enc| enc.encode_frame(env))
+    }
+
+    fn encode_frame(&mut self, env: &EnvironmentRef) -> EvalResult<usize> {
+        let key = &**env as *const _ as usize;
+        if let Some(&index) = self.frame_ids.get(&key) {
+            return Ok(index)
+        }
+
+        let index = self.frames.len();
+        self.frames.push(None);
+        let _ = self.frame_ids.insert(key, index);
+
+        let (parent, bindings) = {
+            let borrowed = env.borrow();
+            (borrowed.parent(), borrowed.local_bindings())
+        };
+
+        let parent = match parent {
+            Some(ref par) => int!(try!(self.frame(par))).to_obj_ref(),
+            None          => symbol!("null").to_obj_ref(),
+        };
+
+        let bindings = try!(bindings.into_iter().map(|(name, val)| {
+            Ok(vector(vec![string!(name).to_obj_ref(), try!(self.value(&val))]))
+        }).collect::<EvalResult<Vec<_>>>());
+
+        self.frames[index] = Some(vector(vec![parent, vector(bindings)]));
+        Ok(index)
+    }
+
+    fn procedure(&mut self, procd: &Procedure) -> EvalResult<usize> {
+        self.entry(|enc| enc.encode_procedure(procd))
+    }
+
+    fn encode_procedure(&mut self, procd: &Procedure) -> EvalResult<usize> {
+        if let Some(&index) = self.proc_ids.get(&procd.id) {
+            return Ok(index)
+        }
+
+        let index = self.procs.len();
+        self.procs.push(None);
+        let _ = self.proc_ids.insert(procd.id, index);
+
+        let env = try!(self.frame(&procd.env));
+        let mut clauses = vec![];
+        for &(ref arity, ref body) in procd.body.iter() {
+            let argnames = arity.argnames.iter().map(|name| string!(name.clone()).to_obj_ref()).collect();
+            let rest = optional(arity.rest.clone().map(|r| string!(r).to_obj_ref()));
+            let body = try!(body.iter().map(|form| self.value(form)).collect::<EvalResult<Vec<_>>>());
+            let guard = match arity.guard {
+                Some(ref guard) => try!(self.value(guard)),
+                None => optional(None),
+            };
+            let optional = try!(self.defaults(&arity.optional));
+            let keys = try!(self.defaults(&arity.keys));
+            let patterns = try!(self.defaults(&arity.patterns));
+            clauses.push(vector(vec![vector(argnames), rest, vector(body), optional, keys, patterns, guard]));
+        }
+
+        self.procs[index] = Some(vector(vec![
+            optional(procd.name.clone().map(|n| string!(n).to_obj_ref())),
+            optional(procd.documentation.clone().map(|d| string!(d).to_obj_ref())),
+            int!(env).to_obj_ref(),
+            vector(clauses)
+        ]));
+        Ok(index)
+    }
+
+    /// Optional or keyword parameters, as a vector of [name, default] pairs
+    fn defaults(&mut self, params: &[(String, LispObjRef)]) -> EvalResult {
+        let mut pairs = vec![];
+        for &(ref name, ref default) in params.iter() {
+            pairs.push(vector(vec![string!(name.clone()).to_obj_ref(), try!(self.value(default))]));
+        }
+        Ok(vector(pairs))
+    }
+
+    fn value(&mut self, obj: &LispObjRef) -> EvalResult {
+        self.nested(|enc| enc.encode_value(obj))
+    }
+
+    fn encode_value(&mut self, obj: &LispObjRef) -> EvalResult {
+        Ok(match **obj {
+            LispObj::LInteger(n) => tagged("i", vec![int!(n).to_obj_ref()]),
+            // Store the bits, so NaN and infinities survive the JSON encoding
+            LispObj::LFloat(n)   => tagged("f", vec![int!(n.to_bits()).to_obj_ref()]),
+            LispObj::LString(_)   => tagged("s", vec![obj.clone()]),
+            LispObj::LSymbol(ref s) => tagged("y", vec![string!(s.clone()).to_obj_ref()]),
+            LispObj::LChar(c)    => tagged("c", vec![string!(c.to_string()).to_obj_ref()]),
+            LispObj::LNil        => tagged("n", vec![]),
+            LispObj::LCons(_, _) => {
+                // Flatten lists, to avoid recursing on the length of the list
+                let mut items = vec![];
+                let mut rest = obj.clone();
+                while let Some((hd, tl)) = rest.cons_split() {
+                    items.push(try!(self.value(&hd)));
+                    rest = tl;
+                }
+                let tail = try!(self.value(&rest));
+                tagged("l", vec![vector(items), tail])
+            },
+            LispObj::LLazyCons(ref car, ref cdr) => {
+                let car = try!(self.value(car));
+                let cdr = try!(self.procedure(cdr));
+                tagged("z", vec![car, int!(cdr).to_obj_ref()])
+            },
+            LispObj::LVector(ref vec) => {
+                let items = try!(vec.iter().map(|item| self.value(item)).collect::<EvalResult<Vec<_>>>());
+                tagged("v", vec![vector(items)])
+            },
+            LispObj::LNativeFunc(ref name, _, _) => tagged("x", vec![string!(name.clone()).to_obj_ref()]),
+            LispObj::LProcedure(ref procd) => {
+                let index = try!(self.procedure(procd));
+                tagged("p", vec![int!(index).to_obj_ref()])
+            },
+            LispObj::LError(ref err) => try!(self.error(err)),
+            LispObj::LPort(_) => type_error!("cannot save {} in an image", obj),
+            LispObj::LRecord(ref rec) => {
+                let names = rec.fields.iter().map(|f| string!(f.clone()).to_obj_ref()).collect();
+                let values = try!(rec.values.iter().map(|v| self.value(v)).collect::<EvalResult<Vec<_>>>());
+                tagged("r", vec![string!(rec.type_name.clone()).to_obj_ref(), vector(names), vector(values)])
+            },
+            // Bytes are stored as a hex string, which is much smaller than a vector of integers
+            LispObj::LBytes(ref bytes) => {
+                let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+                tagged("b", vec![string!(hex).to_obj_ref()])
+            },
+            // Floats are stored by their bits, as single floats are
+            LispObj::LF64Vector(ref floats) => {
+                let bits = floats.iter().map(|f| int!(f.to_bits()).to_obj_ref()).collect();
+                tagged("d", vec![vector(bits)])
+            },
+            LispObj::LEnvironment(ref env) => {
+                let index = try!(self.frame(env));
+                tagged("m", vec![int!(index).to_obj_ref()])
+            },
+        })
+    }
+
+    fn error(&mut self, err: &RuntimeError) -> EvalResult {
+        let value = match err.value {
+            Some(ref val) => try!(self.value(val)),
+            None => symbol!("null").to_obj_ref(),
+        };
+        let cause = match err.cause {
+            Some(ref cause) => try!(self.nested(|enc| enc.error(cause))),
+            None => symbol!("null").to_obj_ref(),
+        };
+        let source = match err.source {
+            Some(ref source) => try!(self.value(source)),
+            None => symbol!("null").to_obj_ref(),
+        };
+        Ok(tagged("e", vec![string!(err.errname.clone()).to_obj_ref(), value, cause, source]))
+    }
+}
+
+/*********************** Decoding ************************/
+
+struct Decoder {
+    frame_data: Vec<LispObjRef>,
+    frames: HashMap<usize, EnvironmentRef>,
+    proc_data: Vec<LispObjRef>,
+    procs: HashMap<usize, LispObjRef>,
+    top_level: EnvironmentRef,
+    /// How deeply the value being decoded is nested in its frame or procedure
+    depth: usize,
+}
+
+impl Decoder {
+    fn named_pair(&self, obj: &LispObjRef) -> EvalResult<(String, LispObjRef)> {
+        let pair = try!(fields(obj, 2));
+        Ok((try!(field_string(&pair[0])), pair[1].clone()))
+    }
+
+    fn defaults(&mut self, obj: &LispObjRef) -> EvalResult<Vec<(String, LispObjRef)>> {
+        let mut params = vec![];
+        for pair in try!(fields(obj, 0)) {
+            let (name, default) = try!(self.named_pair(&pair));
+            params.push((name, try!(self.value(&default))));
+        }
+        Ok(params)
+    }
+
+    fn frame(&mut self, index: usize) -> EvalResult<EnvironmentRef> {
+        if let Some(frame) = self.frames.get(&index) {
+            return Ok(frame.clone())
+        }
+
+        // The first frame is always the top level
+        let frame = if index == 0 {
+            self.top_level.clone()
+        } else {
+            let data = match self.frame_data.get(index) {
+                Some(data) => data.clone(),
+                None => read_error!("image refers to missing frame {}", index),
+            };
+            let parent = try!(fields(&data, 2))[0].clone();
+            if parent.symbol_equal("null") {
+                read_error!("image frame {} has no parent", index)
+            }
+            let parent = try!(self.frame(try!(field_index(&parent))));
+            Environment::from_parent(parent).to_env_ref()
+        };
+
+        let _ = self.frames.insert(index, frame.clone());
+        Ok(frame)
+    }
+
+    /// Decodes a procedure, which is stored in its own table rather than nested in the
+    /// value referring to it.
+    fn procedure(&mut self, index: usize) -> EvalResult {
+        let depth = self.depth;
+        self.depth = 0;
+        let res = self.decode_procedure(index);
+        self.depth = depth;
+        res
+    }
+
+    fn decode_procedure(&mut self, index: usize) -> EvalResult {
+        if let Some(procd) = self.procs.get(&index) {
+            return Ok(procd.clone())
+        }
+
+        let data = match self.proc_data.get(index) {
+            Some(data) => try!(fields(data, 4)),
+            None => read_error!("image refers to missing procedure {}", index),
+        };
+
+        let name = try!(field_optional_string(&data[0]));
+        let doc  = try!(field_optional_string(&data[1]));
+        let env  = try!(self.frame(try!(field_index(&data[2]))));
+
+        let mut body = vec![];
+        for clause in try!(fields(&data[3], 1)) {
+            let clause = try!(fields(&clause, 3));
+            let argnames = try!(try!(fields(&clause[0], 0)).iter()
+                                .map(field_string).collect::<EvalResult<Vec<_>>>());
+            let rest = try!(field_optional_string(&clause[1]));
+            let forms = try!(try!(fields(&clause[2], 0)).iter()
+                             .map(|form| self.value(form)).collect::<EvalResult<Vec<_>>>());
+            // Optional, keyword and pattern parameters and guards are absent from older images
+            let optional = match clause.get(3) {
+                Some(params) => try!(self.defaults(params)),
+                None => vec![],
+            };
+            let keys = match clause.get(4) {
+                Some(params) => try!(self.defaults(params)),
+                None => vec![],
+            };
+            let patterns = match clause.get(5) {
+                Some(params) => try!(self.defaults(params)),
+                None => vec![],
+            };
+            let mut arity = ArityObj::new(argnames, rest).with_defaults(optional, keys).with_patterns(patterns);
+            match clause.get(6) {
+                Some(guard) if !guard.symbol_equal("null") => arity = arity.with_guard(try!(self.value(guard))),
+                _ => {},
+            }
+            body.push((arity, forms));
+        }
+
+        let procd = LispObj::make_proc(Procedure::new(env, name, doc, body)).to_obj_ref();
+        let _ = self.procs.insert(index, procd.clone());
+        Ok(procd)
+    }
+
+    fn native(&self, name: &str) -> EvalResult {
+        // Prefer natives already installed in the target environment, which
+        // includes any registered by an embedder
+        let installed = {
+            let top_level = self.top_level.borrow();
+            top_level.local_bindings().into_iter().map(|(_, val)| val)
+                     .chain(top_level.local_char_handlers().into_iter().map(|(_, val)| val))
+                     .find(|val| match **val {
+                         LispObj::LNativeFunc(ref native_name, _, _) => native_name == name,
+                         _ => false,
+                     })
+        };
+
+        match installed.or_else(|| super::find_builtin_native(name)) {
+            Some(native) => Ok(native),
+            None => read_error!("image refers to unknown native function {}", name),
+        }
+    }
+
+    /// Runs `f` one level deeper, throwing once values are nested too deeply.
+    fn nested<T, F: FnOnce(&mut Self) -> EvalResult<T>>(&mut self, f: F) -> EvalResult<T> {
+        if self.depth == MAX_DEPTH {
+            read_error!("image has values nested more than {} deep", MAX_DEPTH)
+        }
+        self.depth += 1;
+        let res = f(self);
+        self.depth -= 1;
+        res
+    }
+
+    fn value(&mut self, obj: &LispObjRef) -> EvalResult {
+        self.nested(|dec| dec.decode_value(obj))
+    }
+
+    fn decode_value(&mut self, obj: &LispObjRef) -> EvalResult {
+        let data = try!(fields(obj, 1));
+        let tag = try!(field_string(&data[0]));
+        let arg = |i: usize| -> EvalResult {
+            match data.get(i) {
+                Some(val) => Ok(val.clone()),
+                None => read_error!("malformed image entry {}", obj),
+            }
+        };
+
+        match &tag as &str {
+            "i" => match *try!(arg(1)) {
+                LispObj::LInteger(n) => Ok(int!(n).to_obj_ref()),
+                _ => read_error!("malformed integer in image {}", obj),
+            },
+            "f" => match *try!(arg(1)) {
+                LispObj::LInteger(n) => Ok(float!(f64::from_bits(n as u64)).to_obj_ref()),
+                _ => read_error!("malformed float in image {}", obj),
+            },
+            "s" => Ok(string!(try!(field_string(&try!(arg(1))))).to_obj_ref()),
+            "y" => Ok(symbol!(try!(field_string(&try!(arg(1))))).to_obj_ref()),
+            "c" => {
+                let s = try!(field_string(&try!(arg(1))));
+                match s.chars().next() {
+                    Some(c) => Ok(char!(c).to_obj_ref()),
+                    None => read_error!("malformed char in image {}", obj),
+                }
+            },
+            "n" => Ok(nil!().to_obj_ref()),
+            "l" => {
+                let items = try!(fields(&try!(arg(1)), 0));
+                let mut out = try!(self.value(&try!(arg(2))));
+                for item in items.iter().rev() {
+                    out = cons!(try!(self.value(item)), out).to_obj_ref();
+                }
+                Ok(out)
+            },
+            "z" => {
+                let car = try!(self.value(&try!(arg(1))));
+                let cdr = try!(self.procedure(try!(field_index(&try!(arg(2))))));
+                Ok(LispObj::lazy_cons(car, try!(cdr.try_unwrap_proc()).clone()).to_obj_ref())
+            },
+            "v" => {
+                let items = try!(fields(&try!(arg(1)), 0));
+                let items = try!(items.iter().map(|item| self.value(item)).collect::<EvalResult<Vec<_>>>());
+                Ok(LispObj::make_vector(items.into_iter()).to_obj_ref())
+            },
+            "x" => self.native(&try!(field_string(&try!(arg(1))))),
+            "p" => self.procedure(try!(field_index(&try!(arg(1))))),
+            "e" => Ok(LispObj::make_error(try!(self.error(obj))).to_obj_ref()),
+            "r" => {
+                let type_name = try!(field_string(&try!(arg(1))));
+                let names = try!(try!(fields(&try!(arg(2)), 0)).iter().map(field_string)
+                                                               .collect::<EvalResult<Vec<_>>>());
+                let values = try!(fields(&try!(arg(3)), 0));
+                if names.len() != values.len() {
+                    read_error!("malformed record in image {}", obj)
+                }
+                let values = try!(values.iter().map(|v| self.value(v)).collect::<EvalResult<Vec<_>>>());
+                Ok(LispObj::make_record(Record::new(type_name, Shared::new(names), values)).to_obj_ref())
+            },
+            "b" => {
+                let hex = try!(field_string(&try!(arg(1))));
+                if hex.len() % 2 != 0 || !hex.chars().all(|c| c.is_digit(16)) {
+                    read_error!("malformed bytes in image {}", obj)
+                }
+                let mut bytes = Vec::with_capacity(hex.len() / 2);
+                for i in 0..(hex.len() / 2) {
+                    match u8::from_str_radix(&hex[2*i..2*i+2], 16) {
+                        Ok(b) => bytes.push(b),
+                        Err(_) => read_error!("malformed bytes in image {}", obj),
+                    }
+                }
+                Ok(LispObj::make_bytes(bytes).to_obj_ref())
+            },
+            "d" => {
+                let mut floats = vec![];
+                for bits in try!(fields(&try!(arg(1)), 0)) {
+                    match *bits {
+                        LispObj::LInteger(n) => floats.push(f64::from_bits(n as u64)),
+                        _ => read_error!("malformed f64vector in image {}", obj),
+                    }
+                }
+                Ok(LispObj::make_f64vector(floats).to_obj_ref())
+            },
+            "m" => Ok(LispObj::LEnvironment(try!(self.frame(try!(field_index(&try!(arg(1))))))).to_obj_ref()),
+            _ => read_error!("unknown tag {:?} in image", tag),
+        }
+    }
+
+    fn error(&mut self, obj: &LispObjRef) -> EvalResult<RuntimeError> {
+        let data = try!(fields(obj, 5));
+        let errname = try!(field_string(&data[1]));
+
+        let value = if data[2].symbol_equal("null") {
+            None
+        } else {
+            Some(try!(self.value(&data[2])))
+        };
+        let cause = if data[3].symbol_equal("null") {
+            None
+        } else {
+            Some(try!(self.nested(|dec| dec.error(&data[3]))))
+        };
+        let source = if data[4].symbol_equal("null") {
+            None
+        } else {
+            Some(try!(self.value(&data[4])))
+        };
+
+        Ok(RuntimeError::new(errname, value, cause, source))
+    }
+}
@@ -0,0 +1,146 @@
+//! Where lisp input is read from.
+//!
+//! `read` parses forms with a `Reader`, which is kept between calls so that input the
+//! parser has looked ahead at is not lost. There is one for each port which has been
+//! read from, and one for the current input stream. That is the process's stdin unless a
+//! host program has substituted its own reader with `with_input`, as
+//! `run::Evaluator::with_stdin` does; the REPL reads its forms from the same reader, so
+//! that a program it runs can read the lines typed after it. Input streams apply to the
+//! current thread, and threads started by `spawn` read from the process's stdin.
+use std::cell::{Cell, RefCell};
+use std::io::{self, Read};
+use std::rc::Rc;
+
+use ::core::{LispObj, EnvironmentRef};
+use ::core::obj::Port;
+use ::parser::Parser;
+use ::parser::parser::ParseResult;
+
+/// The characters a reader parses.
+pub type CharStream = Box<Iterator<Item=io::Result<char>>>;
+
+/// A parser which is kept between reads, along with its look-ahead.
+pub struct Reader {
+    // Only None while a form is being read
+    parser: Option<Parser<CharStream, io::Error>>,
+}
+
+/// A reader shared by everything which reads from its stream.
+pub type ReaderRef = Rc<RefCell<Reader>>;
+
+thread_local! {
+    static STDIN: RefCell<Option<ReaderRef>> = RefCell::new(None);
+    static DEFAULT_STDIN: RefCell<Option<ReaderRef>> = RefCell::new(None);
+    static PORT_READERS: RefCell<Vec<(Port, ReaderRef)>> = RefCell::new(vec![]);
+}
+
+impl Reader {
+    pub fn new<I, S>(chars: I, source_name: S) -> Self
+            where I: Iterator<Item=io::Result<char>> + 'static, S: Into<String> {
+        Reader { parser: Some(Parser::new(Box::new(chars) as CharStream, source_name)) }
+    }
+
+    /// A reader of the process's stdin.
+    pub fn stdin() -> Self {
+        Self::new(read_chars(io::stdin()), "<stdin>")
+    }
+
+    /// A reader of a port, such as a socket.
+    pub fn port(port: Port) -> Self {
+        let name = String::from(port.name());
+        Self::new(PortChars(port), name)
+    }
+
+    /// Keeps `cell` up to date with the number of lists still open, as with
+    /// `Parser::with_depth_cell`.
+    pub fn with_depth_cell(mut self, cell: Rc<Cell<usize>>) -> Self {
+        self.parser = self.parser.take().map(|parser| parser.with_depth_cell(cell));
+        self
+    }
+
+    /// Reads the next form, using the reader character handlers of `env`'s top level.
+    /// Returns None at the end of the input.
+    pub fn read(&mut self, env: &EnvironmentRef) -> Option<ParseResult<io::Error>> {
+        let top_level = ::core::env::get_top_level(env.clone());
+        let mut parser = self.parser.take().expect("reader is already reading")
+                             .with_char_handler(|c, obj| apply_char_handler(c, obj, &top_level));
+        let res = parser.next();
+        self.parser = Some(parser.without_char_handler());
+        res
+    }
+}
+
+/// The UTF-8 characters of `input`.
+pub fn read_chars<R: Read + 'static>(input: R) -> CharStream {
+    Box::new(input.chars().map(|res| res.map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))))
+}
+
+struct PortChars(Port);
+
+impl Iterator for PortChars {
+    type Item = io::Result<char>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.0.read_char() {
+            Ok(Some(c)) => Some(Ok(c)),
+            Ok(None) => None,
+            Err(err) => Some(Err(err)),
+        }
+    }
+}
+
+/// Applies the reader character handler `env` has for `c`, in the form a parser's
+/// char handler returns.
+pub fn apply_char_handler(c: char, obj: LispObj, env: &EnvironmentRef) -> Result<LispObj, Option<LispObj>> {
+    let handler = match env.borrow().get_char_handler(c) {
+        Some(handler) => handler,
+        None => return Err(None),
+    };
+
+    super::apply(handler, lisp_list!(obj), env.clone())
+          .map(LispObj::from_obj_ref)
+          .map_err(|err| Some(err.into_lisp_obj()))
+}
+
+/// The current input stream's reader. This is the current thread's reader of the
+/// process's stdin, made when it is first used, unless another has been set by
+/// `with_input`.
+pub fn stdin() -> ReaderRef {
+    if let Some(reader) = STDIN.with(|s| s.borrow().clone()) {
+        return reader
+    }
+    DEFAULT_STDIN.with(|s| s.borrow_mut()
+                            .get_or_insert_with(|| Rc::new(RefCell::new(Reader::stdin())))
+                            .clone())
+}
+
+/// Runs `f` with input read by `stdin`. If it is None, the current input is left as
+/// it is.
+pub fn with_input<T, F>(stdin: Option<ReaderRef>, f: F) -> T
+        where F: FnOnce() -> T {
+    let outer = STDIN.with(|s| s.borrow().clone());
+    if stdin.is_some() {
+        STDIN.with(|s| *s.borrow_mut() = stdin);
+    }
+
+    let res = f();
+
+    STDIN.with(|s| *s.borrow_mut() = outer);
+    res
+}
+
+/// The reader of `port`, made when it is first read from. Readers of ports which have
+/// since been closed are dropped.
+pub fn port_reader(port: &Port) -> ReaderRef {
+    PORT_READERS.with(|readers| {
+        let mut readers = readers.borrow_mut();
+        readers.retain(|&(ref p, _)| !p.is_closed());
+        if let Some(&(_, ref reader)) = readers.iter().find(|&&(ref p, _)| p.same_port(port)) {
+            return reader.clone()
+        }
+
+        let reader = Rc::new(RefCell::new(Reader::port(port.clone())));
+        readers.push((port.clone(), reader.clone()));
+        reader
+    })
+}
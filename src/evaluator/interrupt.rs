@@ -0,0 +1,180 @@
+//! Interrupting evaluation from outside the evaluator.
+//!
+//! The evaluator polls a process-wide flag at every evaluation step. When the flag has
+//! been set, by `interrupt()` or by the SIGINT handler installed with
+//! `install_sigint_handler()`, the current evaluation is abandoned with an
+//! `interrupt-error`, which `catch-error` does not catch.
+//!
+//! Lisp code can handle signals itself with `on-signal`, which records the handler in
+//! `*signal-handlers*`. When such a signal arrives it is marked as pending, and the
+//! handler is called at the next evaluation step of a thread which has called
+//! `on-signal`. A handled SIGINT no longer interrupts evaluation.
+use std::cell::Cell;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use ::core::{EnvironmentRef, EvalResult, RuntimeError};
+use ::core::env::get_top_level;
+
+/// The name of the association list from each signal's name to its lisp handler
+pub static SIGNAL_HANDLERS_NAME: &'static str = "*signal-handlers*";
+
+/// The signals which lisp code can handle, by name and number
+pub static SIGNALS: &'static [(&'static str, i32)] = &[("sighup", 1), ("sigint", SIGINT), ("sigterm", 15)];
+
+const SIGINT: i32 = 2;
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+// Bit n of each is for signal n: whether it has a lisp handler, and whether it has
+// arrived since handlers were last run
+static HANDLED: AtomicUsize = AtomicUsize::new(0);
+static PENDING: AtomicUsize = AtomicUsize::new(0);
+
+// Whether SIGINT interrupts evaluation when it has no lisp handler, rather than
+// having its default action
+static SIGINT_INTERRUPTS: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    static RUNS_HANDLERS: Cell<bool> = Cell::new(false);
+}
+
+/// Requests that the running evaluation stops at its next step.
+///
+/// Safe to call from a signal handler or another thread.
+pub fn interrupt() {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+/// Forgets any pending interrupt request.
+pub fn clear() {
+    INTERRUPTED.store(false, Ordering::SeqCst);
+}
+
+/// Returns true, and clears the request, if an interrupt is pending.
+pub fn take() -> bool {
+    INTERRUPTED.swap(false, Ordering::SeqCst)
+}
+
+/// Throws an 'interrupt-error if an interrupt is pending, and otherwise runs the lisp
+/// handlers of any signals which have arrived, if this thread runs them. Called at
+/// every evaluation step.
+pub fn poll(env: &EnvironmentRef) -> EvalResult<()> {
+    if take() {
+        return Err(RuntimeError::interrupt())
+    }
+    if PENDING.load(Ordering::Relaxed) != 0 && RUNS_HANDLERS.with(|runs| runs.get()) {
+        try!(run_signal_handlers(env));
+    }
+    Ok(())
+}
+
+// Called from the signal handler, so may only touch atomics
+fn on_signal(signum: i32) {
+    let bit = 1 << signum;
+    if HANDLED.load(Ordering::SeqCst) & bit != 0 {
+        PENDING.fetch_or(bit, Ordering::SeqCst);
+    } else if signum == SIGINT {
+        interrupt();
+    }
+}
+
+/// Calls the handlers in `*signal-handlers*` of each signal which has arrived, with
+/// the signal's name. A SIGINT whose handler has been removed interrupts evaluation.
+fn run_signal_handlers(env: &EnvironmentRef) -> EvalResult<()> {
+    let pending = PENDING.swap(0, Ordering::SeqCst);
+    let top_level = get_top_level(env.clone());
+    for &(name, signum) in SIGNALS {
+        if pending & (1 << signum) == 0 {
+            continue
+        }
+
+        match signal_handler(&top_level, name) {
+            Some(handler) => { try!(super::apply(handler, lisp_list!(symbol!(name)), env.clone())); },
+            None if signum == SIGINT => return Err(RuntimeError::interrupt()),
+            None => {},
+        }
+    }
+    Ok(())
+}
+
+/// The number of the signal named `name`, such as `sigint`, if lisp code can handle it.
+pub fn signal_number(name: &str) -> Option<i32> {
+    SIGNALS.iter().find(|&&(sig, _)| sig == name).map(|&(_, signum)| signum)
+}
+
+/// The handler in `*signal-handlers*` for the signal named `name`.
+pub fn signal_handler(env: &EnvironmentRef, name: &str) -> Option<::core::LispObjRef> {
+    let handlers = match env.borrow().lookup(SIGNAL_HANDLERS_NAME) {
+        Some(handlers) => handlers,
+        None => return None,
+    };
+    handlers.list_iter()
+            .filter_map(|entry| entry.ok().and_then(|entry| entry.cons_split()))
+            .find(|&(ref sig, _)| sig.symbol_equal(name))
+            .map(|(_, handler)| handler)
+}
+
+/// Sets whether signal `signum` has a lisp handler, which the current thread runs.
+/// Without one, it has its default action again, except that SIGINT interrupts
+/// evaluation if `install_sigint_handler` has been called.
+///
+/// Returns false, and does nothing, on non-unix platforms.
+pub fn set_signal_handled(signum: i32, handled: bool) -> bool {
+    if !cfg!(unix) {
+        return false
+    }
+
+    let bit = 1 << signum;
+    if handled {
+        RUNS_HANDLERS.with(|runs| runs.set(true));
+        HANDLED.fetch_or(bit, Ordering::SeqCst);
+        sys::catch_signal(signum);
+    } else {
+        HANDLED.fetch_and(!bit, Ordering::SeqCst);
+        if signum != SIGINT || !SIGINT_INTERRUPTS.load(Ordering::SeqCst) {
+            sys::default_signal(signum);
+        }
+    }
+    true
+}
+
+#[cfg(unix)]
+mod sys {
+    use std::os::raw::c_int;
+
+    const SIG_DFL: usize = 0;
+
+    extern "C" {
+        fn signal(signum: c_int, handler: usize) -> usize;
+    }
+
+    extern "C" fn on_signal(signum: c_int) {
+        super::on_signal(signum as i32);
+    }
+
+    pub fn catch_signal(signum: i32) {
+        unsafe {
+            let _ = signal(signum as c_int, on_signal as extern "C" fn(c_int) as usize);
+        }
+    }
+
+    pub fn default_signal(signum: i32) {
+        unsafe {
+            let _ = signal(signum as c_int, SIG_DFL);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+mod sys {
+    pub fn catch_signal(_: i32) {}
+    pub fn default_signal(_: i32) {}
+}
+
+/// Makes Ctrl-C (SIGINT) interrupt evaluation rather than kill the process.
+///
+/// Currently a no-op on non-unix platforms.
+pub fn install_sigint_handler() {
+    SIGINT_INTERRUPTS.store(true, Ordering::SeqCst);
+    sys::catch_signal(SIGINT)
+}
@@ -1,108 +1,204 @@
 //! Utilities for working with lisp procedures, both for creation and execution.
 
-use std::rc::Rc;
-
 use ::core::EvalResult;
-use ::core::procedure::{ArityObj, Procedure};
+use ::core::shared::Shared;
+use ::core::procedure::{ArityObj, Procedure, GUARD_MARKER, KEYWORD_PREFIX, KEY_MARKER};
+use ::core::obj::NativeFuncSignature;
 use ::core::{LispObjRef, AsLispObjRef,
              Environment, EnvironmentRef};
 
 /************************** Procedure application ***********************/
 
-// no need to reinvent the wheel, let our special form tco handler
-// do this
 /// Evaluates the application of a lisp procedurre to its argument list
 ///
-/// If func has a name, does Tail Call Optimization
+/// Calls in tail position, to this or any other procedure, continue in this loop
+/// instead of recursing into eval, so they run in constant stack. This includes
+/// calls made through the `apply` builtin. Calls to traced
+/// procedures, and all calls while profiling, are made normally instead, so that
+/// they are reported; self tail calls are still optimized.
 // TODO clean this!
-// TODO reuse environments on tco
 pub fn lambda_apply(func: &Procedure, arg: LispObjRef) -> EvalResult {
     let (mut env, mut last_to_eval) = try!(lambda_apply_until_last(func, arg));
 
-    let fname = func.id;
+    // The procedure whose body is being evaluated, once a tail call has left func
+    let mut current: Option<LispObjRef> = None;
     loop {
-        let (new_env, new_lte) = match last_to_eval.cons_split() {
-            Some((hd, tl)) => {
+        // Tail calls never re-enter eval, so check for interrupts here too
+        try!(super::interrupt::poll(&env));
+        try!(super::limits::step());
 
-                let env_borrow = env.clone();
-                if let Some(sname) = hd.symbol_ref() {
-
-                    // Lookup the symbol and try to get its procedure id
-                    let lookup_attempt = env_borrow.borrow().lookup(sname);
-                    match lookup_attempt.and_then(|v| v.procedure_id()) {
-                        Some(id) => {
-
-                            if id == fname {
-                                // Tail call, perform tco!
-                                let args = try!(super::map_eval(tl, env.clone()));
-                                /* Reuse environment if possible */
-                                match Rc::try_unwrap(env) {
-                                    Ok(new_env) => {
-                                        try!(lambda_apply_until_last_from(func, 
-                                                                          args.to_obj_ref(), 
-                                                                          new_env.into_inner()))
-                                    },
-                                    Err(_) => try!(lambda_apply_until_last(func, args.to_obj_ref()))
-                                }
-                            } 
-
-                            else {
-                                // Not a tail-call, normal eval
-                                return super::eval(last_to_eval, env)
-                            }
-                        },
-
-                        // Not a function, do normal eval
-                        None => return super::eval(last_to_eval, env)
-                    }
-                } 
+        let (hd, tl) = match last_to_eval.cons_split() {
+            Some(split) => split,
+            // Non-call
+            None => return super::eval(last_to_eval, env)
+        };
+
+        if let Some(s) = hd.symbol_ref() {
+            if super::tco::is_tco_form(s) {
+                let args = flatten_list!(tl, "({}) invalid syntax (ill-formed arg list)", s);
+                let (new_env, new_lte) = try!(super::tco::special_form_tco_until_last(s, &args, env));
+                env = new_env;
+                last_to_eval = new_lte;
+                continue
+            } else if super::special_form_handlers::get_handler(s).is_some() {
+                return super::eval(last_to_eval, env)
+            } else if let Some(expanded) = try!(super::macros::try_macro_expand(s, tl.clone(), env.clone())) {
+                last_to_eval = expanded;
+                continue
+            }
+        }
+
+        let callee = try!(super::eval(hd, env.clone()));
+        if callee.is_native() && !is_apply_builtin(&callee) {
+            let args = try!(super::eval_args(tl, env.clone()));
+            return super::apply_native(callee, &args, env)
+        }
+        let args = try!(super::map_eval(tl, env.clone()));
+        let (callee, args) = resolve_apply(callee, args);
+        if !callee.is_proc() {
+            return super::apply(callee, args, env)
+        }
+
+        let (new_env, new_lte) = {
+            let procd = match current {
+                Some(ref current) => try!(current.try_unwrap_proc()),
+                None => func,
+            };
+            let next = try!(callee.try_unwrap_proc());
 
-                else {
-                    // Non-tail call, vanilla eval
-                    return super::eval(last_to_eval, env)
+            if next == procd {
+                if super::trace::is_traced(next) {
+                    super::trace::tail_call(next, &args);
+                }
+                /* Reuse environment if possible: it must be the procedure's own
+                 * frame, not that of a let or match in its body */
+                let own_frame = env.borrow().parent().map_or(false, |p| Shared::ptr_eq(&p, &next.env));
+                match Shared::try_unwrap(env) {
+                    Ok(old_env) if own_frame => try!(lambda_apply_until_last_from(next, args, old_env.into_inner())),
+                    _ => try!(lambda_apply_until_last(next, args))
                 }
+            } else if super::profile::is_running() || super::trace::is_traced(next) {
+                return super::apply(callee.clone(), args, env)
+            } else {
+                try!(lambda_apply_until_last(next, args))
+            }
+        };
+
+        current = Some(callee);
+        env = new_env;
+        last_to_eval = new_lte;
+    }
+}
+
+/// Whether `obj` is the `apply` builtin
+fn is_apply_builtin(obj: &LispObjRef) -> bool {
+    let apply_builtin = super::builtins::apply as NativeFuncSignature as usize;
+    // Checked first, as try_unwrap_native formats an error for anything else
+    obj.is_native() && obj.try_unwrap_native().ok().map_or(false, |f| *f as usize == apply_builtin)
+}
+
+/// Sees through calls to the `apply` builtin, returning the procedure and arguments
+/// it would call. Malformed calls to apply are returned as they are, for apply to
+/// report.
+fn resolve_apply(mut callee: LispObjRef, mut args: LispObjRef) -> (LispObjRef, LispObjRef) {
+    while is_apply_builtin(&callee) {
+        let (func, rest) = match args.cons_split() {
+            Some(split) => split,
+            None => break,
+        };
+        match rest.cons_split() {
+            Some((arglist, ref end)) if end.is_nil() => {
+                callee = func;
+                args = arglist;
             },
+            _ => break,
+        }
+    }
+    (callee, args)
+}
 
-            // Non-call
-            None => return super::eval(last_to_eval, env)
+/// Binds the names a body `define`s in its frame before it is evaluated, as letrec does.
+pub fn declare_internal_defines(body: &[LispObjRef], env: &EnvironmentRef) {
+    // Procedures defined in the body can then refer to each other, and the names shadow
+    // outer bindings throughout the body. Names are bound to nil until their define is
+    // evaluated, except parameters, which keep their values.
+    for form in body.iter() {
+        let target = match form.cons_split() {
+            Some((hd, tl)) => if hd.symbol_equal("define") {
+                tl.cons_split().map(|(target, _)| target)
+            } else {
+                None
+            },
+            None => None,
+        };
+        // (define name ...) or (define (name args...) ...)
+        let name = match target {
+            Some(target) => match target.cons_split() {
+                Some((name, _)) => name.symbol_ref().map(String::from),
+                None => target.symbol_ref().map(String::from),
+            },
+            None => None,
         };
 
-        env = new_env; 
-        last_to_eval = new_lte;
+        if let Some(name) = name {
+            let mut frame = env.borrow_mut();
+            if !frame.binds_locally(&name) {
+                let _ = frame.let_new(name, nil!().to_obj_ref());
+            }
+        }
     }
 }
 
 /// Same style as tco functions, check module `rustylisp::evaluator::tco`
 pub fn lambda_apply_until_last(func: &Procedure, arg: LispObjRef) -> EvalResult<(EnvironmentRef, LispObjRef)> {
     let (env, body) = try!(start_procedure(func, arg));
-    super::tco::special_form_tco_until_last("begin", body, env.to_env_ref())
+    declare_internal_defines(body, &env);
+    super::tco::special_form_tco_until_last("begin", body, env)
 }
 
 fn lambda_apply_until_last_from(func: &Procedure, arg: LispObjRef, env: Environment) -> EvalResult<(EnvironmentRef, LispObjRef)> {
     let (env, body) = try!(start_procedure_from(func, arg, env));
-    super::tco::special_form_tco_until_last("begin", body, env.to_env_ref())
+    declare_internal_defines(body, &env);
+    super::tco::special_form_tco_until_last("begin", body, env)
 }
 
-pub fn start_procedure(procd: &Procedure, args: LispObjRef) -> EvalResult<(Environment, &[LispObjRef])> {
+pub fn start_procedure(procd: &Procedure, args: LispObjRef) -> EvalResult<(EnvironmentRef, &[LispObjRef])> {
     let new_env = Environment::from_parent(procd.env.clone());
     start_procedure_from(procd, args, new_env)
 }
 
-fn start_procedure_from(procd: &Procedure, args: LispObjRef, mut reuse_env: Environment) -> EvalResult<(Environment, &[LispObjRef])> {
+/// Binds the arguments for the first clause which accepts them: the first whose
+/// arity matches, and whose guard, if it has one, is true.
+fn start_procedure_from(procd: &Procedure, args: LispObjRef, mut reuse_env: Environment) -> EvalResult<(EnvironmentRef, &[LispObjRef])> {
     assert!(procd.body.len() > 0, "Procedure needs at least 1 body");
 
-    for i in 0..(procd.body.len()-1) {
-        match parse_args_into(&procd.body[i].0, args.clone(), &mut reuse_env) {
+    let last = procd.body.len() - 1;
+    for (i, &(ref arity, ref body)) in procd.body.iter().enumerate() {
+        match parse_args_into(arity, args.clone(), &mut reuse_env) {
             Ok(()) => {},
-            Err(_) => continue,
+            // The last clause's error explains why nothing matched
+            Err(err) => if i == last && arity.guard.is_none() { return Err(err) } else { continue },
         }
 
-        return Ok((reuse_env, &procd.body[i].1))
+        let guard = match arity.guard {
+            Some(ref guard) => guard,
+            None => return Ok((reuse_env.to_env_ref(), body)),
+        };
+
+        let env = reuse_env.to_env_ref();
+        let passed = try!(super::eval(guard, env.clone()));
+        if !super::is_false(&passed) {
+            return Ok((env, body))
+        }
+
+        // The guard may have kept a reference to the environment
+        reuse_env = match Shared::try_unwrap(env) {
+            Ok(env) => env.into_inner(),
+            Err(_) => Environment::from_parent(procd.env.clone()),
+        };
     }
 
-    let last = procd.body.len() - 1;
-    try!(parse_args_into(&procd.body[last].0, args, &mut reuse_env));
-    Ok((reuse_env, &procd.body[last].1))
+    argument_error!("no clause of {} accepts the arguments {}", procd.display_name(), args)
 }
 
 #[allow(dead_code)]
@@ -115,6 +211,10 @@ pub fn parse_args(arity: &ArityObj, args: LispObjRef, env: EnvironmentRef) -> Ev
 
 /// Attempts to parse an argument list into an existing environment, based on an arity object.
 /// Clears the input environment before loading new names.
+///
+/// Default values of optional and keyword parameters are evaluated in the environment's
+/// parent, i.e. the procedure's closure. A rest parameter also receives any keyword
+/// arguments, and allows keywords which the procedure does not name.
 pub fn parse_args_into<'a>(arity: &ArityObj, mut args: LispObjRef, env: &'a mut Environment) -> EvalResult<()> {
     env.clear_bindings();
 
@@ -128,18 +228,82 @@ pub fn parse_args_into<'a>(arity: &ArityObj, mut args: LispObjRef, env: &'a mut
         };
     }
 
-    match arity.rest {
-        Some(ref rest_name) => {
-            assert!(env.let_new(rest_name.clone(), args).is_none());
-            Ok(())
-        },
-        None => {
-            if args.is_nil() {
-                Ok(())
-            } else {
-                arity_error!("Extra args: {}", args)
+    for &(ref name, ref pattern) in arity.patterns.iter() {
+        let val = env.lookup(name).expect("pattern arguments are bound");
+        try!(super::pattern::destructure(pattern, &val, env));
+    }
+
+    // Optional arguments stop at the first keyword, if there are keyword parameters
+    let mut defaults = vec![];
+    for &(ref name, ref default) in arity.optional.iter() {
+        args = match args.cons_split() {
+            Some((ref hd, _)) if !arity.keys.is_empty() && is_keyword(hd) => {
+                defaults.push((name, default));
+                continue
+            },
+            Some((hd, tl)) => {
+                assert!(env.let_new(name.clone(), hd).is_none());
+                tl
+            },
+            None => {
+                defaults.push((name, default));
+                continue
+            },
+        };
+    }
+
+    if let Some(ref rest_name) = arity.rest {
+        assert!(env.let_new(rest_name.clone(), args.clone()).is_none());
+    }
+
+    if !arity.keys.is_empty() {
+        let mut given = vec![];
+        while let Some((key, tl)) = args.cons_split() {
+            let name = match key.symbol_ref() {
+                Some(s) if s.starts_with(KEYWORD_PREFIX) => String::from(&s[KEYWORD_PREFIX.len()..]),
+                _ => arity_error!("expected keyword argument, not {}", key),
+            };
+            let (val, tl) = match tl.cons_split() {
+                Some(split) => split,
+                None => arity_error!("no value given for keyword {}", key),
+            };
+
+            if arity.keys.iter().any(|&(ref n, _)| *n == name) {
+                // The first value given for a keyword wins
+                if !given.contains(&name) {
+                    assert!(env.let_new(name.clone(), val).is_none());
+                    given.push(name);
+                }
+            } else if arity.rest.is_none() {
+                arity_error!("unknown keyword argument {}", key)
             }
-        },
+            args = tl;
+        }
+
+        for &(ref name, ref default) in arity.keys.iter() {
+            if !given.contains(name) {
+                defaults.push((name, default));
+            }
+        }
+    } else if arity.rest.is_none() && !args.is_nil() {
+        arity_error!("Extra args: {}", args)
+    }
+
+    if !defaults.is_empty() {
+        let closure = env.parent().expect("procedure environments should have a parent");
+        for (name, default) in defaults {
+            let val = try!(super::eval(default.clone(), closure.clone()));
+            assert!(env.let_new(name.clone(), val).is_none());
+        }
+    }
+
+    Ok(())
+}
+
+fn is_keyword(obj: &LispObjRef) -> bool {
+    match obj.symbol_ref() {
+        Some(s) => s.starts_with(KEYWORD_PREFIX),
+        None => false,
     }
 }
 
@@ -194,11 +358,21 @@ pub fn parse_multiple_arity(args: &[LispObjRef], parent: EnvironmentRef) -> Eval
     }
 }
 
+/// Parses a clause of case-lambda, `(args body...)` or `(args #:when guard body...)`.
 fn parse_arglist_body(args: LispObjRef, _: EnvironmentRef) -> EvalResult<(ArityObj, Vec<LispObjRef>)> {
     if let Some((hd, tl)) = args.cons_split() {
         let arity = try!(parse_arglist(hd));
-        let body = flatten_list!(tl, "poorly-formed function body");
-        Ok((arity, body))
+        let mut body = flatten_list!(tl, "poorly-formed function body");
+        if body.len() > 0 && body[0].symbol_equal(GUARD_MARKER) {
+            if body.len() < 2 {
+                syntax_error!("{} without a guard in case-lambda clause {}", GUARD_MARKER, args)
+            }
+            let guard = body[1].clone();
+            body.drain(..2);
+            Ok((arity.with_guard(guard), body))
+        } else {
+            Ok((arity, body))
+        }
     } else {
         syntax_error!("invalid lambda expression: {}", cons!(symbol!("lambda"), args))
     }
@@ -206,7 +380,11 @@ fn parse_arglist_body(args: LispObjRef, _: EnvironmentRef) -> EvalResult<(ArityO
 
 fn parse_arglist(args: LispObjRef) -> EvalResult<ArityObj> {
     let mut argnames = vec![];
+    let mut optional = vec![];
+    let mut keys     = vec![];
+    let mut patterns = vec![];
     let mut rest     = None;
+    let mut in_keys  = false;
 
     let mut arglist = args.clone();
     loop {
@@ -218,10 +396,34 @@ fn parse_arglist(args: LispObjRef) -> EvalResult<ArityObj> {
         } else {
             arglist = match arglist.cons_split() {
                 Some((hd, tl)) => {
-                    match hd.symbol_ref() {
-                        Some(name) => argnames.push(String::from(name)),
-                        None       => syntax_error!("ill-formed argument list {}", args)
-                    };
+                    if hd.symbol_equal(KEY_MARKER) {
+                        if in_keys {
+                            syntax_error!("{} given twice in argument list {}", KEY_MARKER, args)
+                        }
+                        in_keys = true;
+                    } else if let Some(name) = hd.symbol_ref() {
+                        if in_keys {
+                            keys.push((String::from(name), nil!().to_obj_ref()));
+                        } else if optional.is_empty() {
+                            argnames.push(String::from(name));
+                        } else {
+                            syntax_error!("required argument {} after optional arguments in {}", name, args)
+                        }
+                    } else if hd.is_vector() && !in_keys && optional.is_empty() {
+                        // Vector patterns destructure required arguments. List patterns
+                        // would clash with (name default).
+                        let name = format!("#pattern{}", patterns.len());
+                        argnames.push(name.clone());
+                        patterns.push((name, hd.clone()));
+                    } else if let Some(param) = parse_default_param(&hd) {
+                        if in_keys {
+                            keys.push(param);
+                        } else {
+                            optional.push(param);
+                        }
+                    } else {
+                        syntax_error!("ill-formed argument list {}", args)
+                    }
                     tl
                 },
                 None => syntax_error!("invalid argument list {}", args)
@@ -229,5 +431,43 @@ fn parse_arglist(args: LispObjRef) -> EvalResult<ArityObj> {
         }
     }
 
-    Ok(ArityObj::new(argnames, rest))
+    // Duplicate names would make binding the arguments ambiguous
+    {
+        let pattern_vars: Vec<_> = patterns.iter().flat_map(|&(_, ref p)| super::pattern::pattern_vars(p)).collect();
+        let mut names: Vec<&String> = argnames.iter()
+                                              .chain(pattern_vars.iter())
+                                              .chain(optional.iter().map(|&(ref n, _)| n))
+                                              .chain(keys.iter().map(|&(ref n, _)| n))
+                                              .chain(rest.iter())
+                                              .collect();
+        names.sort();
+        for pair in names.windows(2) {
+            if pair[0] == pair[1] {
+                syntax_error!("duplicate argument {} in {}", pair[0], args)
+            }
+        }
+    }
+
+    Ok(ArityObj::new(argnames, rest).with_defaults(optional, keys).with_patterns(patterns))
+}
+
+/// Parses `(name default)` or `(name)` into a parameter with a default value.
+fn parse_default_param(param: &LispObjRef) -> Option<(String, LispObjRef)> {
+    let (name, tl) = match param.cons_split() {
+        Some(split) => split,
+        None => return None,
+    };
+    let name = match name.symbol_ref() {
+        Some(name) => String::from(name),
+        None => return None,
+    };
+
+    if tl.is_nil() {
+        Some((name, nil!().to_obj_ref()))
+    } else {
+        match tl.cons_split() {
+            Some((default, ref end)) if end.is_nil() => Some((name, default)),
+            _ => None,
+        }
+    }
 }
@@ -0,0 +1,110 @@
+//! Limits on how much work an evaluation may do.
+//!
+//! While limits are in place, the evaluator counts a step every time it polls for
+//! interrupts, and throws a 'resource-error once the allowed steps or time are used
+//! up. Limits apply to the current thread, and nested limits only ever tighten the
+//! enclosing ones. Once a limit is exceeded, every further step throws, so the error
+//! cannot usefully be caught from inside the limited evaluation.
+use std::cell::Cell;
+use std::cmp;
+use std::time::{Duration, Instant};
+
+use ::core::{AsLispObjRef, EvalResult};
+
+/// The clock is only read every this many steps, as reading it is slow.
+const TIME_CHECK_INTERVAL: u32 = 1024;
+
+/// Limits on one evaluation. None means unlimited.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Limits {
+    /// The number of evaluation steps allowed
+    pub steps: Option<u64>,
+    /// The wall-clock time allowed
+    pub time: Option<Duration>,
+}
+
+#[derive(Clone, Copy)]
+struct Active {
+    steps_left: Option<u64>,
+    deadline: Option<Instant>,
+    ticks: u32,
+}
+
+thread_local! {
+    static ACTIVE: Cell<Active> = Cell::new(Active { steps_left: None, deadline: None, ticks: 0 });
+}
+
+fn tightest<T: Ord>(a: Option<T>, b: Option<T>) -> Option<T> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(cmp::min(a, b)),
+        (a, b) => a.or(b),
+    }
+}
+
+/// Runs `f` under `limits`, as well as any limits already in place. Steps used by `f`
+/// count against the enclosing limits too.
+pub fn with_limits<T, F>(limits: Limits, f: F) -> T
+        where F: FnOnce() -> T {
+    let outer = ACTIVE.with(|a| a.get());
+    let start_steps = tightest(outer.steps_left, limits.steps);
+    let deadline = tightest(outer.deadline, limits.time.map(|time| Instant::now() + time));
+    ACTIVE.with(|a| a.set(Active { steps_left: start_steps, deadline: deadline, ticks: 0 }));
+
+    let res = f();
+
+    let used = match (start_steps, ACTIVE.with(|a| a.get()).steps_left) {
+        (Some(start), Some(left)) => start - left,
+        _ => 0,
+    };
+    let steps_left = outer.steps_left.map(|steps| steps.saturating_sub(used));
+    ACTIVE.with(|a| a.set(Active { steps_left: steps_left, deadline: outer.deadline, ticks: outer.ticks }));
+    res
+}
+
+/// The limits in place on a thread, and how much of them has been used, as saved by
+/// `save`.
+#[derive(Clone, Copy)]
+pub struct Saved(Active);
+
+/// Saves the limits in place on this thread, so that another thread which runs on its
+/// behalf, such as a generator's producer, can continue counting against them.
+pub fn save() -> Saved {
+    Saved(ACTIVE.with(|a| a.get()))
+}
+
+/// Puts limits saved by `save` in place on this thread.
+pub fn restore(saved: Saved) {
+    ACTIVE.with(|a| a.set(saved.0))
+}
+
+/// Counts one evaluation step, throwing a 'resource-error if a limit has been exceeded.
+pub fn step() -> EvalResult<()> {
+    ACTIVE.with(|active| {
+        let mut state = active.get();
+        if let Some(steps) = state.steps_left {
+            if steps == 0 {
+                resource_error!("evaluation step limit exceeded")
+            }
+            state.steps_left = Some(steps - 1);
+        }
+        if let Some(deadline) = state.deadline {
+            state.ticks = state.ticks.wrapping_add(1);
+            if state.ticks % TIME_CHECK_INTERVAL == 0 && Instant::now() >= deadline {
+                resource_error!("evaluation time limit exceeded")
+            }
+        }
+        active.set(state);
+        Ok(())
+    })
+}
+
+/// Counts a step of a builtin which is blocked waiting, such as for a value on a
+/// channel. Unlike `step`, the time limit is checked every time, as the wait between
+/// steps makes reading the clock cheap.
+pub fn wait_step() -> EvalResult<()> {
+    try!(step());
+    match ACTIVE.with(|active| active.get().deadline) {
+        Some(deadline) if Instant::now() >= deadline => resource_error!("evaluation time limit exceeded"),
+        _ => Ok(()),
+    }
+}
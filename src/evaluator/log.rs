@@ -0,0 +1,65 @@
+//! Logging from lisp code.
+//!
+//! `log-debug`, `log-info`, `log-warn` and `log-error` log a message if its level is at
+//! least `*log-level*`. Messages are written to `*log-port*` if it is set. Otherwise,
+//! they go to the handler installed with `set_handler`, so that a host program can route
+//! them into its own logger, or to stderr if there is none.
+use std::sync::Mutex;
+
+/// The name of the variable holding the lowest level which is logged
+pub static LOG_LEVEL_NAME: &'static str = "*log-level*";
+
+/// The name of the variable holding the port logs are written to, or nil
+pub static LOG_PORT_NAME: &'static str = "*log-port*";
+
+/// How important a log message is, least important first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl Level {
+    /// The level's name, as used for `*log-level*`.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Level::Debug => "debug",
+            Level::Info => "info",
+            Level::Warn => "warn",
+            Level::Error => "error",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Level> {
+        [Level::Debug, Level::Info, Level::Warn, Level::Error].iter()
+            .find(|level| level.name() == name)
+            .cloned()
+    }
+}
+
+/// A function which receives log messages, with their level.
+pub type Handler = fn(Level, &str);
+
+/// The installed handler, shared by all threads
+static HANDLER: Mutex<Option<Handler>> = Mutex::new(None);
+
+/// Sends lisp log messages to `handler` instead of stderr, or back to stderr if None.
+/// Messages logged while `*log-port*` is set still go to the port.
+pub fn set_handler(handler: Option<Handler>) {
+    *HANDLER.lock().unwrap_or_else(|e| e.into_inner()) = handler;
+}
+
+/// Passes a message to the installed handler. Returns false if there is none.
+pub fn handle(level: Level, msg: &str) -> bool {
+    // Copied out, so the handler can log or replace itself without deadlocking
+    let handler = *HANDLER.lock().unwrap_or_else(|e| e.into_inner());
+    match handler {
+        Some(handler) => {
+            handler(level, msg);
+            true
+        },
+        None => false,
+    }
+}
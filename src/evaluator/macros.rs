@@ -1,5 +1,25 @@
-use ::core::{EnvironmentRef, LispObjRef, AsLispObjRef, EvalResult};
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use ::core::{EnvironmentRef, LispObj, LispObjRef, AsLispObjRef, EvalResult};
+use ::core::env::get_top_level;
 use ::core::obj::NativeFuncSignature;
+use super::syntax_rules::SyntaxRules;
+use super::output;
+
+/// When true, every macro expansion is printed
+pub static TRACE_MACRO_EXPANSION_NAME: &'static str = "*trace-macro-expansion*";
+
+/// The metadata key marking a macro as hygienic, i.e. defined with define-hygienic-macro
+pub static HYGIENIC_META_KEY: &'static str = "hygienic-macro";
+
+static GENSYM_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Makes a fresh symbol name starting with `prefix`. The `#` in it means the reader
+/// can never produce the same symbol.
+pub fn gensym(prefix: &str) -> String {
+    format!("{}#{}", prefix, GENSYM_COUNTER.fetch_add(1, Ordering::SeqCst))
+}
 
 pub fn get_handler(name: &str, env: EnvironmentRef) -> Option<LispObjRef> {
     env.borrow().lookup_macro(name)
@@ -19,25 +39,283 @@ pub fn try_macro_expand_obj(obj: LispObjRef, env: EnvironmentRef) -> EvalResult<
 
 pub fn try_macro_expand(macro_name: &str, args: LispObjRef, env: EnvironmentRef) -> EvalResult<Option<LispObjRef>> {
     if let Some(handler) = get_handler(macro_name, env.clone()) {
-        let macro_expander = handler.unwrap_proc();
-        match super::lambda::lambda_apply(macro_expander, args) {
-            Ok(val)  => Ok(Some(val.to_obj_ref())),
+        // Macros are procedures, except those defined by syntax-rules, which are always hygienic
+        let is_hygienic = || env.borrow().lookup_meta(macro_name).map_or(false, |meta| {
+            meta.iter().any(|&(ref key, ref val)| key == HYGIENIC_META_KEY && !val.falsey())
+        });
+        let (expanded, hygienic) = if handler.is_proc() {
+            let expanded = super::lambda::lambda_apply(try!(handler.try_unwrap_proc()), args.clone()).map(|val| val.to_obj_ref());
+            (expanded, is_hygienic())
+        } else if handler.is_native() {
+            // Native macros receive the unevaluated arguments, like any other macro
+            let native_args = flatten_list!(args, "macro {}: ill-formed argument list", macro_name);
+            let expanded = super::call_native(*try!(handler.try_unwrap_native()), &native_args, env.clone());
+            (expanded, is_hygienic())
+        } else if handler.is_cons() {
+            let expanded = SyntaxRules::parse(&handler).and_then(|rules| rules.expand(macro_name, &args));
+            (expanded, true)
+        } else {
+            macro_error!(args vec![handler.clone()], expected "procedure";
+                         "macro {} is bound to {}, which is not a procedure", macro_name, handler)
+        };
+        let expanded = match expanded {
+            Ok(val)  => val,
             Err(err) => macro_error!(cause err; "error in expansion of macro {}", macro_name)
+        };
+        let expanded = if hygienic {
+            let mut input = HashSet::new();
+            collect_input(&args, &mut input);
+            let mut binders = HashSet::new();
+            collect_binders(&expanded, &input, &mut binders);
+            let mut renamed = HashMap::new();
+            rename_introduced(expanded, &input, &binders, &mut renamed, &get_top_level(env.clone()))
+        } else {
+            expanded
+        };
+
+        let tracing = env.borrow().lookup(TRACE_MACRO_EXPANSION_NAME).map_or(false, |t| !t.falsey());
+        if tracing {
+            try!(output::print(format_args!("macro-expand: {} => {}\n", cons!(symbol!(macro_name), args), expanded)));
         }
+        Ok(Some(expanded))
     } else {
         Ok(None)
     }
 }
 
+/// Collects the address of every object in a macro's input, so that they can be told
+/// apart from equal objects the macro introduced.
+fn collect_input(obj: &LispObjRef, out: &mut HashSet<*const LispObj>) {
+    let mut obj = obj.clone();
+    loop {
+        let _ = out.insert(&*obj as *const LispObj);
+        match obj.cons_split() {
+            Some((hd, tl)) => {
+                collect_input(&hd, out);
+                obj = tl;
+            },
+            None => return,
+        }
+    }
+}
+
+/// The elements of a possibly improper list, and its final cdr.
+fn split_list(obj: &LispObjRef) -> (Vec<LispObjRef>, LispObjRef) {
+    let mut items = vec![];
+    let mut rest = obj.clone();
+    while let Some((hd, tl)) = rest.cons_split() {
+        items.push(hd);
+        rest = tl;
+    }
+    (items, rest)
+}
+
+/// Collects the variables of a binding pattern, a symbol or a list or vector of
+/// patterns, which the macro introduced.
+fn collect_pattern(pattern: &LispObjRef, input: &HashSet<*const LispObj>, out: &mut HashSet<String>) {
+    if input.contains(&(&**pattern as *const LispObj)) {
+        return
+    }
+    if let Some(s) = pattern.symbol_ref() {
+        if s != "_" && !s.starts_with(::core::procedure::KEYWORD_PREFIX) {
+            let _ = out.insert(String::from(s));
+        }
+    } else if let Some(items) = pattern.vec_ref() {
+        for item in items.iter() {
+            collect_pattern(item, input, out);
+        }
+    } else if let Some((hd, _)) = pattern.cons_split() {
+        if hd.symbol_equal("quote") {
+            return
+        }
+        let (items, rest) = split_list(pattern);
+        for item in items.iter() {
+            collect_pattern(item, input, out);
+        }
+        collect_pattern(&rest, input, out);
+    }
+}
+
+/// Collects the variables of a lambda list which the macro introduced. Optional and
+/// keyword arguments are given as `(name default)`.
+fn collect_arglist(args: &LispObjRef, input: &HashSet<*const LispObj>, out: &mut HashSet<String>) {
+    let (items, rest) = split_list(args);
+    for item in items.iter() {
+        match item.cons_split() {
+            Some((name, _)) => collect_pattern(&name, input, out),
+            None => collect_pattern(item, input, out),
+        }
+    }
+    collect_pattern(&rest, input, out);
+}
+
+/// Collects the names a hygienic macro's expansion introduced in binding positions: the
+/// variables of let, lambda and case-lambda forms, and the names rec and define bind.
+fn collect_binders(obj: &LispObjRef, input: &HashSet<*const LispObj>, out: &mut HashSet<String>) {
+    if input.contains(&(&**obj as *const LispObj)) {
+        return
+    }
+    let hd = match obj.cons_split() {
+        Some((ref hd, _)) if hd.symbol_equal("quote") || hd.symbol_equal("quasiquote") => return,
+        Some((hd, _)) => hd,
+        None => return,
+    };
+
+    let (items, rest) = split_list(obj);
+    let first = items.get(1);
+    if hd.symbol_equal("lambda") {
+        if let Some(args) = first {
+            collect_arglist(args, input, out);
+        }
+    } else if hd.symbol_equal("case-lambda") {
+        for clause in items[1..].iter() {
+            if let Some((args, _)) = clause.cons_split() {
+                collect_arglist(&args, input, out);
+            }
+        }
+    } else if hd.symbol_equal("let") {
+        for binding in first.map_or(vec![], |bindings| split_list(bindings).0) {
+            if let Some((pattern, _)) = binding.cons_split() {
+                collect_pattern(&pattern, input, out);
+            }
+        }
+    } else if hd.symbol_equal("define") || hd.symbol_equal("define!") || hd.symbol_equal("rec") {
+        match first.map(|target| (target, target.cons_split())) {
+            Some((_, Some((name, args)))) => {
+                collect_pattern(&name, input, out);
+                collect_arglist(&args, input, out);
+            },
+            Some((name, None)) => collect_pattern(name, input, out),
+            None => {},
+        }
+    }
+
+    for item in items.iter() {
+        collect_binders(item, input, out);
+    }
+    collect_binders(&rest, input, out);
+}
+
+/// Renames the symbols a hygienic macro's expansion introduced, so that they cannot
+/// capture or be captured by the caller's variables.
+///
+/// Forms taken from the macro's input, macros and special forms are kept, as are quoted
+/// forms. So are free references to globals, but not `binders`, the names the expansion
+/// binds, even if there is a global of the same name. Every other symbol is replaced by
+/// a gensym, the same one for every occurrence within the expansion.
+fn rename_introduced(obj: LispObjRef, input: &HashSet<*const LispObj>, binders: &HashSet<String>,
+                     renamed: &mut HashMap<String, String>, top_level: &EnvironmentRef) -> LispObjRef {
+    if input.contains(&(&*obj as *const LispObj)) {
+        return obj
+    }
+
+    if let Some(s) = obj.symbol_ref() {
+        if let Some(new_name) = renamed.get(s) {
+            return symbol!(new_name.clone()).to_obj_ref()
+        }
+
+        let keep = s.starts_with(::core::procedure::KEYWORD_PREFIX)
+                   || super::special_form_handlers::get_handler(s).is_some()
+                   || (!binders.contains(s) && (top_level.borrow().lookup(s).is_some()
+                                                || top_level.borrow().lookup_macro(s).is_some()));
+        if keep {
+            return obj.clone()
+        }
+
+        let new_name = gensym(s);
+        let _ = renamed.insert(String::from(s), new_name.clone());
+        return symbol!(new_name).to_obj_ref()
+    }
+
+    match obj.cons_split() {
+        Some((ref hd, _)) if hd.symbol_equal("quote") || hd.symbol_equal("quasiquote") => return obj.clone(),
+        Some(_) => {},
+        None => return obj.clone(),
+    }
+
+    let mut items = vec![];
+    let mut rest = obj.clone();
+    while let Some((hd, tl)) = rest.cons_split() {
+        items.push(rename_introduced(hd, input, binders, renamed, top_level));
+        rest = tl;
+    }
+
+    let mut out = rename_introduced(rest, input, binders, renamed, top_level);
+    for item in items.into_iter().rev() {
+        out = LispObj::cons(item, out).to_obj_ref();
+    }
+    out
+}
+
+/// Expands a form until it is no longer a macro call, then expands each of its
+/// subforms the same way. Quoted and quasiquoted forms are left alone.
+pub fn macro_expand_all(obj: LispObjRef, env: EnvironmentRef) -> EvalResult {
+    let mut obj = obj;
+    while let Some(expanded) = try!(try_macro_expand_obj(obj.clone(), env.clone())) {
+        obj = expanded;
+    }
+
+    match obj.cons_split() {
+        Some((ref hd, _)) if hd.symbol_equal("quote") || hd.symbol_equal("quasiquote") => return Ok(obj),
+        Some(_) => {},
+        None => return Ok(obj),
+    }
+
+    let mut items = vec![];
+    let mut rest = obj.clone();
+    while let Some((hd, tl)) = rest.cons_split() {
+        items.push(try!(macro_expand_all(hd, env.clone())));
+        rest = tl;
+    }
+
+    // Rebuild the list, keeping any improper tail
+    let mut out = rest;
+    for item in items.into_iter().rev() {
+        out = LispObj::cons(item, out).to_obj_ref();
+    }
+    Ok(out)
+}
+
 
 /***************** Special Character Handlers ****************/
 
 pub static SPECIAL_CHAR_DEFAULTS: &'static [(char, NativeFuncSignature)] = 
     &[('\'', quote_handler), ('\\', backslash_handler), 
-      ('`', quasiquote_handler), (',', unquote_handler)];
+      ('`', quasiquote_handler), (',', unquote_handler),
+      ('#', hash_handler), ('@', at_handler)];
 
+/// `\c` reads as a character, named as `symbol->char` names them. Digits read as numbers,
+/// so `\5` is handled here.
 fn backslash_handler(args: &[LispObjRef], env: EnvironmentRef) -> EvalResult {
-    super::builtins::symbol_to_char(args, env)
+    match args.get(0).map(|arg| &**arg) {
+        Some(&LispObj::LInteger(n)) if args.len() == 1 && n >= 0 && n <= 9 =>
+            Ok(LispObj::LChar((b'0' + n as u8) as char).to_obj_ref()),
+        _ => super::builtins::symbol_to_char(args, env),
+    }
+}
+
+/// `#:name` reads as the keyword symbol `#:name`, and `#(a b c)` as the vector `[a b c]`
+fn hash_handler(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => arg: Any);
+    if arg.is_nil() || arg.is_cons() {
+        let items: Result<Vec<_>, ()> = arg.list_iter().collect();
+        return match items {
+            Ok(items) => Ok(LispObj::make_vector(items.into_iter()).to_obj_ref()),
+            Err(()) => syntax_error!("improper list in vector literal #{}", arg),
+        }
+    }
+
+    match arg.symbol_ref() {
+        // The reader has already made `:name` a keyword
+        Some(name) if name.starts_with(::core::procedure::KEYWORD_PREFIX) => Ok(arg.clone()),
+        _ => syntax_error!("unknown syntax #{}", arg),
+    }
+}
+
+/// `@x` reads as `(unquote-splicing x)`, so that `,@x` splices `x` in a quasiquote
+fn at_handler(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    unpack_args!(args => arg: Any);
+    Ok(cons!(symbol!("unquote-splicing"), cons!(arg, nil!())).to_obj_ref())
 }
 
 fn quasiquote_handler(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
@@ -52,5 +330,9 @@ fn quote_handler(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
 
 fn unquote_handler(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
     unpack_args!(args => arg: Any);
+    // `,@x` is already `(unquote-splicing x)`
+    if arg.cons_split().map_or(false, |(hd, _)| hd.symbol_equal("unquote-splicing")) {
+        return Ok(arg.clone())
+    }
     Ok(cons!(symbol!("unquote"), cons!(arg, nil!())).to_obj_ref())
 }
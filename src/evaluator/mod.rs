@@ -2,29 +2,59 @@
 /*********************** Macros *************************/
 /* (at top so they are available in imports) */
 
+/// Unpacks the arguments to a native function, type-checking each with `check_type!` and
+/// binding it to a name. Throws an 'arity-error if there are too few or too many.
+///
+/// Required parameters are written `name: Type`. They may be followed by optional ones,
+/// `opt name: Type`, which are bound to an `Option`, and lastly by `rest name`, which is
+/// bound to a slice of the remaining arguments:
+///
+/// unpack_args!(args => port: LPort, opt timeout: LInteger, rest values);
 #[macro_export]
 macro_rules! unpack_args {
-    ( $args:expr ) => {
-        if $args.len() != 0 {
-            arity_error!("expected no args, not {}", 
+    ( @params $args:expr, $i:ident; ) => {
+        if $args.len() > $i {
+            arity_error!(args $args.to_vec(); "too many args: expected {}, not {}", $i,
                          $crate::core::obj::LispObj::to_lisp_list($args.iter()))
         }
     };
-    ( $args:expr => $( $argname:ident: $expect:ident ),+ ) => {
-        let mut i = 0;
-        $(
-            if $args.len() <= i {
-                arity_error!("too few args")
-            }
-            let $argname = check_type!( $args[i].clone(), $expect );
-            i += 1;
-        )+
-
-        if $args.len() != i {
-            arity_error!("too many args: expected {}, not {}", i, 
+    ( @params $args:expr, $i:ident; rest $name:ident ) => {
+        let $name = &$args[::std::cmp::min($i, $args.len())..];
+    };
+    ( @params $args:expr, $i:ident; opt $name:ident: $expect:ident ) => {
+        unpack_args!(@params $args, $i; opt $name: $expect,);
+    };
+    ( @params $args:expr, $i:ident; opt $name:ident: $expect:ident, $( $rest:tt )* ) => {
+        let $name = if $args.len() > $i {
+            Some(check_type!( $args[$i].clone(), $expect ))
+        } else {
+            None
+        };
+        $i += 1;
+        unpack_args!(@params $args, $i; $( $rest )*);
+    };
+    ( @params $args:expr, $i:ident; $name:ident: $expect:ident ) => {
+        unpack_args!(@params $args, $i; $name: $expect,);
+    };
+    ( @params $args:expr, $i:ident; $name:ident: $expect:ident, $( $rest:tt )* ) => {
+        if $args.len() <= $i {
+            arity_error!(args $args.to_vec(); "too few args")
+        }
+        let $name = check_type!( $args[$i].clone(), $expect );
+        $i += 1;
+        unpack_args!(@params $args, $i; $( $rest )*);
+    };
+    ( $args:expr ) => {
+        if $args.len() != 0 {
+            arity_error!(args $args.to_vec(); "expected no args, not {}", 
                          $crate::core::obj::LispObj::to_lisp_list($args.iter()))
         }
-    }
+    };
+    ( $args:expr => $( $params:tt )+ ) => {
+        #[allow(unused_mut)]
+        let mut unpack_args_i = 0;
+        unpack_args!(@params $args, unpack_args_i; $( $params )+);
+    };
 }
 
 #[macro_export]
@@ -33,44 +63,128 @@ macro_rules! check_type {
         $val
     };
     ( $val:expr, LInteger ) => {
-        match *($val) {
-            $crate::core::LispObj::LInteger(n) => n,
-            _ => type_error!("expected int, not {}", $val),
+        {
+            let macro_val = $val;
+            match *macro_val {
+                $crate::core::LispObj::LInteger(n) => n,
+                _ => type_error!(args vec![macro_val.clone()], expected "int"; "expected int, not {}", macro_val),
+            }
         }
     };
     ( $val:expr, LFloat ) => {
-        match *($val) {
-            $crate::core::LispObj::LFloat(n) => n,
-            _ => type_error!("expected float, not {}", $val),
+        {
+            let macro_val = $val;
+            match *macro_val {
+                $crate::core::LispObj::LFloat(n) => n,
+                _ => type_error!(args vec![macro_val.clone()], expected "float"; "expected float, not {}", macro_val),
+            }
+        }
+    };
+    ( $val:expr, LChar ) => {
+        {
+            let macro_val = $val;
+            match *macro_val {
+                $crate::core::LispObj::LChar(c) => c,
+                _ => type_error!(args vec![macro_val.clone()], expected "char"; "expected char, not {}", macro_val),
+            }
         }
     };
     ( $val:expr, LSymbol ) => {
-        match *($val) {
-            $crate::core::LispObj::LSymbol(ref name) => name.clone(),
-            _ => type_error!("expected symbol, not {}", $val),
+        {
+            let macro_val = $val;
+            let macro_ret = match *macro_val {
+                $crate::core::LispObj::LSymbol(ref name) => name.clone(),
+                _ => type_error!(args vec![macro_val.clone()], expected "symbol"; "expected symbol, not {}", macro_val),
+            };
+
+            macro_ret
         }
     };
     ( $val:expr, LString ) => {
-        match $val.string_ref() {
-            Some(name) => name.clone(),
-            _ => type_error!("expected string, not {}", $val),
+        {
+            let macro_val = $val;
+            let macro_ret = match macro_val.string_ref() {
+                Some(name) => name.clone(),
+                _ => type_error!(args vec![macro_val.clone()], expected "string"; "expected string, not {}", macro_val),
+            };
+
+            macro_ret
         }
     };
     ( $val:expr, LCons ) => { 
         {
-            let macro_ret: (LispObjRef, LispObjRef) = match $val.cons_split() {
+            let macro_val = $val;
+            let macro_ret: (LispObjRef, LispObjRef) = match macro_val.cons_split() {
                 Some(v) => v,
-                None => type_error!("expected cons, not {}", $val),
+                None => type_error!(args vec![macro_val.clone()], expected "cons"; "expected cons, not {}", macro_val),
             };
 
             macro_ret
         }
     };
     ( $val:expr, LVector ) => {
-        if $val.is_vector() {
-            $val.unwrap_vec().clone()
-        } else {
-            type_error!("expected vec, not {}", $val)
+        {
+            let macro_val = $val;
+            if macro_val.is_vector() {
+                macro_val.unwrap_vec().clone()
+            } else {
+                type_error!(args vec![macro_val.clone()], expected "vector"; "expected vec, not {}", macro_val)
+            }
+        }
+    };
+    ( $val:expr, LPort ) => {
+        {
+            let macro_val = $val;
+            let macro_ret = match macro_val.port_ref() {
+                Some(port) => port.clone(),
+                _ => type_error!(args vec![macro_val.clone()], expected "port"; "expected port, not {}", macro_val),
+            };
+
+            macro_ret
+        }
+    };
+    ( $val:expr, LRecord ) => {
+        {
+            let macro_val = $val;
+            let macro_ret = match macro_val.record_ref() {
+                Some(rec) => rec.clone(),
+                _ => type_error!(args vec![macro_val.clone()], expected "record"; "expected record, not {}", macro_val),
+            };
+
+            macro_ret
+        }
+    };
+    ( $val:expr, LBytes ) => {
+        {
+            let macro_val = $val;
+            let macro_ret = match macro_val.bytes_ref() {
+                Some(bytes) => bytes.clone(),
+                _ => type_error!(args vec![macro_val.clone()], expected "bytes"; "expected bytes, not {}", macro_val),
+            };
+
+            macro_ret
+        }
+    };
+    ( $val:expr, LF64Vector ) => {
+        {
+            let macro_val = $val;
+            let macro_ret = match macro_val.f64vector_ref() {
+                Some(floats) => floats.clone(),
+                _ => type_error!(args vec![macro_val.clone()], expected "f64vector"; "expected f64vector, not {}", macro_val),
+            };
+
+            macro_ret
+        }
+    };
+    ( $val:expr, LEnvironment ) => {
+        {
+            let macro_val = $val;
+            let macro_ret = match macro_val.env_ref() {
+                Some(env) => env.clone(),
+                _ => type_error!(args vec![macro_val.clone()], expected "environment"; "expected environment, not {}", macro_val),
+            };
+
+            macro_ret
         }
     };
     ( $val:expr, LError ) => {
@@ -79,12 +193,22 @@ macro_rules! check_type {
             let macro_ret = if macro_val.is_err() {
                 macro_val.unwrap_err().clone()
             } else {
-                type_error!("expected error, not {}", macro_val)
+                type_error!(args vec![macro_val.clone()], expected "error"; "expected error, not {}", macro_val)
             };
 
             macro_ret
         }
     };
+    // Lisp procedures and native functions, returned as they are
+    ( $val:expr, LProc ) => {
+        {
+            let macro_val = $val;
+            if !(macro_val.is_proc() || macro_val.is_native()) {
+                type_error!(args vec![macro_val.clone()], expected "procedure"; "expected procedure, not {}", macro_val)
+            }
+            macro_val
+        }
+    };
 }
 
 /// Flattens a lisp list into a Rust vector.
@@ -127,6 +251,20 @@ macro_rules! try_rethrow {
 
 #[macro_export]
 macro_rules! runtime_error {
+    ( args $args:expr, expected $expected:expr; $name:expr, $( $msg: expr ),+ ) => {
+        {
+            let runtime_err_msg = format!( $( $msg ),+ );
+            return Err($crate::core::error::RuntimeError::new($name, Some(string!(runtime_err_msg).to_obj_ref()), None, None)
+                           .with_details($args, Some($expected)))
+        }
+    };
+    ( args $args:expr; $name:expr, $( $msg: expr ),+ ) => {
+        {
+            let runtime_err_msg = format!( $( $msg ),+ );
+            return Err($crate::core::error::RuntimeError::new($name, Some(string!(runtime_err_msg).to_obj_ref()), None, None)
+                           .with_details($args, None))
+        }
+    };
     ( $name:expr ) => {
         return Err($crate::core::error::RuntimeError::new($name, None, None, None))
     };
@@ -158,21 +296,59 @@ macro_rules! runtime_error {
 pub mod err_msgs;
 
 mod builtins;
+pub mod complete;
+pub mod flags;
+pub mod image;
+pub mod input;
+pub mod interrupt;
 mod lambda;
+pub mod limits;
+pub mod log;
 mod macros;
+pub mod output;
+mod pattern;
+pub mod profile;
 mod special_form_handlers;
+mod syntax_rules;
 mod tco;
+pub mod trace;
 
 pub use core::{self, LispObj, LispObjRef, 
                Environment, EnvironmentRef, AsLispObjRef};
 pub use core::{RuntimeError, EvalResult};
+pub use self::builtins::{pop_directory, push_directory, with_file_loading};
+pub use self::builtins::{NativeRegisterFn, NATIVE_REGISTER_SYMBOL};
+pub use self::builtins::EXIT_HOOKS_NAME;
+
+use std::panic::{self, AssertUnwindSafe};
+use std::time::Instant;
+
+use core::obj::NativeFuncSignature;
+use parser::Parser;
 
 /******************** Environment Utilities ************************/
 
 pub fn default_environment() -> Environment {
+    builtin_environment(false)
+}
+
+/// An environment like the default one, but without the builtins which use the file
+/// system, processes, the network or threads, for evaluating untrusted code. Its
+/// `make-environment` only makes sandboxed environments.
+pub fn sandboxed_environment() -> Environment {
+    builtin_environment(true)
+}
+
+fn builtin_environment(sandboxed: bool) -> Environment {
     let bindings = builtins::BUILTIN_FUNCS.iter()
+                .filter(|&&(name, _, _)| !(sandboxed && builtins::SANDBOX_EXCLUDED.contains(&name)))
                 .map(|&(ref name, ref func, ref doc)| {
-                    (String::from(*name), LispObj::make_native(*name, *func, *doc).to_obj_ref())
+                    let func = if sandboxed && *name == "make-environment" {
+                        builtins::make_sandboxed_environment
+                    } else {
+                        *func
+                    };
+                    (String::from(*name), LispObj::make_native(*name, func, *doc).to_obj_ref())
                 })
                 .chain(builtins::builtin_vals().into_iter()
                     .map(|(name, obj)| (String::from(name), obj.to_obj_ref()))
@@ -180,27 +356,71 @@ pub fn default_environment() -> Environment {
 
     let char_handlers = macros::SPECIAL_CHAR_DEFAULTS.iter()
         .map(|&(name, ref func)| {
-            (name, LispObj::make_native(format!("char-handler({})", name), *func, None).to_obj_ref())
+            (name, LispObj::make_native(char_handler_name(name), *func, None).to_obj_ref())
         });
 
     Environment::new_with_bindings(bindings).with_special_chars(char_handlers)
 }
 
+fn char_handler_name(c: char) -> String {
+    format!("char-handler({})", c)
+}
+
+/// Looks up a native function of the default environment by its name, including the
+/// default special character handlers.
+pub fn find_builtin_native(name: &str) -> Option<LispObjRef> {
+    for &(fname, func, doc) in builtins::BUILTIN_FUNCS.iter() {
+        if fname == name {
+            return Some(LispObj::make_native(fname, func, doc).to_obj_ref())
+        }
+    }
+
+    for &(fname, func) in builtins::HIDDEN_NATIVES.iter() {
+        if fname == name {
+            return Some(LispObj::make_native(fname, func, None).to_obj_ref())
+        }
+    }
+
+    for &(c, func) in macros::SPECIAL_CHAR_DEFAULTS.iter() {
+        let handler_name = char_handler_name(c);
+        if handler_name == name {
+            return Some(LispObj::make_native(handler_name, func, None).to_obj_ref())
+        }
+    }
+
+    None
+}
+
 
 /******************** The evaluation functions *********************/
 
-fn is_self_evaluating(obj: LispObjRef) -> bool {
+fn is_self_evaluating(obj: &LispObj) -> bool {
     match *obj {
         LispObj::LInteger(_)        => true,
         LispObj::LFloat(_)          => true,
         LispObj::LString(_)         => true,
+        LispObj::LChar(_)           => true,
         LispObj::LNil               => true,
+        // Keywords, like #:size
+        LispObj::LSymbol(ref s)     => s.starts_with(core::procedure::KEYWORD_PREFIX),
 //        LispObj::LProcedure(_)      => true,
 //        LispObj::LNativeFunc(_,_,_) => true,
         _ => false,
     }
 }
 
+/// Tests if a value counts as false in conditionals.
+///
+/// Normally nil, `false`, 0, "" and empty vectors are false. When `*strict-booleans*` is
+/// true, only nil and `false` are.
+pub fn is_false(val: &LispObj) -> bool {
+    if flags::strict_booleans() {
+        val.strict_falsey()
+    } else {
+        val.falsey()
+    }
+}
+
 pub fn eval_all<It, Obj>(forms: It, env: EnvironmentRef) -> Result<Vec<LispObjRef>, RuntimeError> 
         where It: Iterator<Item=Obj>, Obj: AsLispObjRef {
     let mut out = vec![];
@@ -213,16 +433,53 @@ pub fn eval_all<It, Obj>(forms: It, env: EnvironmentRef) -> Result<Vec<LispObjRe
     Ok(out)
 }
 
+/// Evaluates each form in a proper list, into a new list.
 pub fn map_eval(ls: LispObjRef, env: EnvironmentRef) -> EvalResult {
-    if ls.is_nil() {
-        Ok(nil!().to_obj_ref())
-    } else if let Some((hd, tl)) = ls.cons_split() {
-        let this = try!(eval(hd, env.clone()));
-        let rest = try!(map_eval(tl, env));
-        Ok(cons!(this, rest).to_obj_ref())
-    } else {
+    Ok(LispObj::from_vec(try!(eval_args(ls, env))).to_obj_ref())
+}
+
+/// Evaluates each form in a proper list, into a Vec rather than a new list.
+pub fn eval_args(ls: LispObjRef, env: EnvironmentRef) -> EvalResult<Vec<LispObjRef>> {
+    let mut out = vec![];
+    let mut rest = ls.clone();
+    while let Some((hd, tl)) = rest.cons_split() {
+        out.push(try!(eval(hd, env.clone())));
+        rest = tl;
+    }
+    if !rest.is_nil() {
         syntax_error!("not a proper list: {}", ls)
     }
+    Ok(out)
+}
+
+/// Reads and evaluates every form in `source` in `env`, returning the value of the last,
+/// or nil if there are none. Reader characters use `env`'s handlers, and a form which
+/// can't be parsed throws a 'read-error.
+///
+/// Malformed input gives an error rather than a panic, so this can be used as a fuzzing
+/// target; run it within `limits::with_limits` to stop code which doesn't halt.
+pub fn eval_str_in(source: &str, env: EnvironmentRef) -> EvalResult {
+    let char_handlers = |c: char, obj: LispObj| {
+        let handler = match env.borrow().get_char_handler(c) {
+            Some(handler) => handler,
+            None => return Err(None)
+        };
+
+        apply(handler, lisp_list!(obj), env.clone())
+            .map(LispObj::from_obj_ref)
+            .map_err(|err| Some(err.into_lisp_obj()))
+    };
+
+    let parser = Parser::from_string(source, "<string>").with_char_handler(char_handlers);
+    let mut out = nil!().to_obj_ref();
+    for parsed_obj in parser {
+        let obj = match parsed_obj {
+            Ok(obj) => obj,
+            Err(e) => read_error!("error parsing {:?}: {:?}", source, e)
+        };
+        out = try!(eval(obj, env.clone()))
+    }
+    Ok(out)
 }
 
 /// The core of the lisp system: the evaluator
@@ -231,15 +488,18 @@ pub fn eval<Obj>(form_input: Obj, env: EnvironmentRef) -> EvalResult
     let mut form = form_input.to_obj_ref();
 
     loop {
+        try!(interrupt::poll(&env));
+        try!(limits::step());
+
         // If form is self evaluating, we have nothing to do
-        if is_self_evaluating(form.clone()) {
-            return Ok(form.clone());
+        if is_self_evaluating(&form) {
+            return Ok(form);
         }
 
         // If form is symbol, do lookup
         if let Some(name) = form.symbol_ref() {
             if let Some(val) = env.borrow().lookup(name) {
-                return Ok(val.clone());
+                return Ok(val);
             } else {
                 bound_error!("symbol '{} is not bound", name)
             }
@@ -268,6 +528,11 @@ pub fn eval<Obj>(form_input: Obj, env: EnvironmentRef) -> EvalResult
             }
 
             let func = try!(eval(hd, env.clone()));
+            // Native functions take a slice of arguments, so don't build a list of them
+            if func.is_native() {
+                let args = try!(eval_args(tl, env.clone()));
+                return apply_native(func, &args, env)
+            }
             let args = try!(map_eval(tl, env.clone()));
             return apply(func, args, env)
         }
@@ -280,6 +545,33 @@ pub fn eval<Obj>(form_input: Obj, env: EnvironmentRef) -> EvalResult
     }
 }
 
+/// Calls a native function with arguments which are already evaluated.
+pub fn apply_native(procedure: LispObjRef, args: &[LispObjRef], env: EnvironmentRef) -> EvalResult {
+    match call_native(*try!(procedure.try_unwrap_native()), args, env) {
+        Ok(obj) => Ok(obj),
+        Err(err) => {
+            Err(if err.source.is_some() {
+                RuntimeError::new_from(err, procedure)
+            } else {
+                err.with_source(procedure)
+            })
+        },
+    }
+}
+
+/// Calls a native function, turning a panic in it into an 'internal-error, so that a
+/// buggy builtin or extension doesn't bring down the interpreter.
+fn call_native(func: NativeFuncSignature, args: &[LispObjRef], env: EnvironmentRef) -> EvalResult {
+    let payload = match panic::catch_unwind(AssertUnwindSafe(|| func(args, env))) {
+        Ok(res) => return res,
+        Err(payload) => payload,
+    };
+    let msg = payload.downcast_ref::<&str>().map(|msg| String::from(*msg))
+                     .or_else(|| payload.downcast_ref::<String>().cloned())
+                     .unwrap_or_else(|| String::from("unknown cause"));
+    internal_error!("native function panicked: {}", msg)
+}
+
 /// Good ole' apply
 pub fn apply<Obj1, Obj2>(proc_input: Obj1, arg_input: Obj2, env: EnvironmentRef) -> EvalResult
             where Obj1: AsLispObjRef, Obj2: AsLispObjRef {
@@ -288,22 +580,31 @@ pub fn apply<Obj1, Obj2>(proc_input: Obj1, arg_input: Obj2, env: EnvironmentRef)
 
     if procedure.is_native() {
         let args = flatten_list!(arg.clone(), "(apply) ill-formed argument list");
-        match procedure.unwrap_native()(&args, env) {
-            Ok(obj) => Ok(obj),
-            Err(err) => {
-                Err(if err.source.is_some() {
-                    RuntimeError::new_from(err, procedure)
-                } else {
-                    err.with_source(procedure)
-                })
-            },
-        }
+        apply_native(procedure, &args, env)
     } 
 
     else if procedure.is_proc() {
         let err = {
-            let procd = procedure.unwrap_proc();
-            match self::lambda::lambda_apply(procd, arg) {
+            let procd = try!(procedure.try_unwrap_proc());
+            let traced = trace::is_traced(procd);
+            if traced {
+                trace::enter(procd, &arg);
+            }
+
+            let res = if profile::is_running() {
+                let start = Instant::now();
+                let res = self::lambda::lambda_apply(procd, arg);
+                profile::record(procd, start.elapsed());
+                res
+            } else {
+                self::lambda::lambda_apply(procd, arg)
+            };
+
+            if traced {
+                trace::exit(procd, &res);
+            }
+
+            match res {
                 Ok(obj) => return Ok(obj),
                 Err(err) => err,
             }
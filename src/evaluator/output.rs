@@ -0,0 +1,250 @@
+//! Where lisp output is written.
+//!
+//! Builtins which print, such as `print`, `trace` and `time`, write to the current
+//! output stream, and tracebacks and logs to the current error stream. These are the
+//! process's stdout and stderr unless a host program has substituted its own streams
+//! with `with_output`, as `run::Evaluator::with_stdout` does. Streams apply to the
+//! current thread, and threads started by `spawn` inherit them.
+//!
+//! When `*color-output*` is true and output goes to a terminal, the REPL colours the
+//! values it prints by their type. When `*print-arity*` is true, it prints procedures
+//! with the argument list of each of their clauses, as in
+//! `#<named-procedure:add (a (b 1))>`.
+//!
+//! The REPL's prompt is `> `, or within an unfinished form, the number of lists still
+//! open. `*prompt*` replaces the first: it may be a string, or a procedure which is
+//! called with no arguments before each form and returns the prompt to show, e.g. to
+//! include the current directory.
+//!
+//! Errors are shown by their traceback, unless `*error-formatter*` is bound to a
+//! procedure. It is called with the error object and returns the string to show instead,
+//! so that applications can reword or translate messages.
+use std::cell::RefCell;
+use std::fmt;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+use ::core::{LispObj, EnvironmentRef, RuntimeError};
+
+/// Whether the REPL colours the values it prints, when output goes to a terminal
+pub static COLOR_OUTPUT_NAME: &'static str = "*color-output*";
+
+/// The name of the procedure used to render errors instead of their traceback, or nil
+pub static ERROR_FORMATTER_NAME: &'static str = "*error-formatter*";
+
+/// The REPL's prompt, as a string or a procedure returning one, or nil for the default
+pub static PROMPT_NAME: &'static str = "*prompt*";
+
+/// Whether the REPL prints procedures with their argument lists
+pub static PRINT_ARITY_NAME: &'static str = "*print-arity*";
+
+/// A stream which output can be sent to, shared with the host which set it.
+pub type Stream = Arc<Mutex<Box<Write + Send>>>;
+
+/// Makes a stream which writes to `out`.
+pub fn stream<W: Write + Send + 'static>(out: W) -> Stream {
+    Arc::new(Mutex::new(Box::new(out)))
+}
+
+thread_local! {
+    static STDOUT: RefCell<Option<Stream>> = RefCell::new(None);
+    static STDERR: RefCell<Option<Stream>> = RefCell::new(None);
+}
+
+/// The current output and error streams, or None for the process's own.
+pub fn current() -> (Option<Stream>, Option<Stream>) {
+    (STDOUT.with(|s| s.borrow().clone()), STDERR.with(|s| s.borrow().clone()))
+}
+
+/// Runs `f` with output sent to `stdout` and `stderr`. A stream which is None is left
+/// as it is.
+pub fn with_output<T, F>(stdout: Option<Stream>, stderr: Option<Stream>, f: F) -> T
+        where F: FnOnce() -> T {
+    let (outer_stdout, outer_stderr) = current();
+    if stdout.is_some() {
+        STDOUT.with(|s| *s.borrow_mut() = stdout);
+    }
+    if stderr.is_some() {
+        STDERR.with(|s| *s.borrow_mut() = stderr);
+    }
+
+    let res = f();
+
+    STDOUT.with(|s| *s.borrow_mut() = outer_stdout);
+    STDERR.with(|s| *s.borrow_mut() = outer_stderr);
+    res
+}
+
+/// Sets the current output and error streams, as returned by `current`.
+pub fn set_current((stdout, stderr): (Option<Stream>, Option<Stream>)) {
+    STDOUT.with(|s| *s.borrow_mut() = stdout);
+    STDERR.with(|s| *s.borrow_mut() = stderr);
+}
+
+fn write_with<F>(stream: Option<Stream>, default: &mut Write, f: F) -> io::Result<()>
+        where F: FnOnce(&mut Write) -> io::Result<()> {
+    match stream {
+        Some(stream) => {
+            let mut out = stream.lock().expect("output stream poisoned");
+            try!(f(&mut **out));
+            out.flush()
+        },
+        None => {
+            try!(f(default));
+            default.flush()
+        },
+    }
+}
+
+/// Calls `f` with the current output stream, flushing it afterwards.
+pub fn write_stdout<F>(f: F) -> io::Result<()>
+        where F: FnOnce(&mut Write) -> io::Result<()> {
+    let stdout = io::stdout();
+    let mut default = stdout.lock();
+    write_with(current().0, &mut default, f)
+}
+
+/// Calls `f` with the current error stream, flushing it afterwards.
+pub fn write_stderr<F>(f: F) -> io::Result<()>
+        where F: FnOnce(&mut Write) -> io::Result<()> {
+    let stderr = io::stderr();
+    let mut default = stderr.lock();
+    write_with(current().1, &mut default, f)
+}
+
+/// Writes formatted text to the current output stream.
+pub fn print(args: fmt::Arguments) -> io::Result<()> {
+    write_stdout(|out| out.write_fmt(args))
+}
+
+/// Writes formatted text to the current error stream.
+pub fn eprint(args: fmt::Arguments) -> io::Result<()> {
+    write_stderr(|out| out.write_fmt(args))
+}
+
+/// `err` as it is shown to the user: the string returned by `*error-formatter*`, if it
+/// is set, or else its traceback. If the formatter fails, this is the traceback followed
+/// by the formatter's own error.
+pub fn render_error(err: &RuntimeError, env: &EnvironmentRef) -> String {
+    let func = match env.borrow().lookup(ERROR_FORMATTER_NAME) {
+        Some(ref func) if !func.is_nil() => func.clone(),
+        _ => return err.traceback_string(),
+    };
+
+    let res = super::apply(func, lisp_list!(err.clone().into_lisp_obj()), env.clone());
+    let mut out = match res.as_ref().map(|res| &**res) {
+        Ok(&LispObj::LString(ref s)) => (**s).clone(),
+        Ok(other) => format!("{}{} must return a string, not {}\n",
+                             err.traceback_string(), ERROR_FORMATTER_NAME, other),
+        Err(fmt_err) => format!("{}error in {}:\n{}", err.traceback_string(), ERROR_FORMATTER_NAME, fmt_err.traceback_string()),
+    };
+    if !out.ends_with('\n') {
+        out.push('\n');
+    }
+    out
+}
+
+/// The REPL's prompt when `depth` lists are still open. If `*prompt*` is a procedure
+/// which fails, or returns something which isn't a string, the error is written to the
+/// current error stream and the default prompt is used.
+pub fn prompt(depth: usize, env: &EnvironmentRef) -> String {
+    if depth > 0 {
+        return format!("..{}> ", depth)
+    }
+
+    let prompt = match env.borrow().lookup(PROMPT_NAME) {
+        Some(ref prompt) if !prompt.is_nil() => prompt.clone(),
+        _ => return String::from("> "),
+    };
+    let res = match *prompt {
+        LispObj::LString(_) => Ok(prompt.clone()),
+        _ => super::apply(prompt, lisp_list!(), env.clone()),
+    };
+    match res.as_ref().map(|res| &**res) {
+        Ok(&LispObj::LString(ref s)) => (**s).clone(),
+        Ok(other) => {
+            let _ = eprint(format_args!("{} must return a string, not {}\n", PROMPT_NAME, other));
+            String::from("> ")
+        },
+        Err(err) => {
+            let _ = eprint(format_args!("error in {}:\n", PROMPT_NAME));
+            let _ = write_error(err, env);
+            String::from("> ")
+        },
+    }
+}
+
+/// Writes `err` to the current error stream, as `render_error` renders it.
+pub fn write_error(err: &RuntimeError, env: &EnvironmentRef) -> io::Result<()> {
+    let text = render_error(err, env);
+    write_stderr(|out| out.write_all(text.as_bytes()))
+}
+
+#[cfg(unix)]
+mod sys {
+    use std::os::raw::c_int;
+
+    extern "C" {
+        fn isatty(fd: c_int) -> c_int;
+    }
+
+    pub fn is_terminal(fd: c_int) -> bool {
+        unsafe { isatty(fd) == 1 }
+    }
+}
+
+#[cfg(not(unix))]
+mod sys {
+    pub fn is_terminal(_: i32) -> bool {
+        false
+    }
+}
+
+/// Whether the process's stdin is a terminal. Always false on non-unix platforms.
+pub fn stdin_is_terminal() -> bool {
+    sys::is_terminal(0)
+}
+
+/// Whether the current output stream is the process's stdout, and that is a terminal.
+/// Always false on non-unix platforms.
+pub fn stdout_is_terminal() -> bool {
+    current().0.is_none() && sys::is_terminal(1)
+}
+
+/// The ANSI colour code for values of `obj`'s type, if they are coloured
+fn color_code(obj: &LispObj) -> Option<&'static str> {
+    match obj {
+        &LispObj::LInteger(_) | &LispObj::LFloat(_) => Some("36"),
+        &LispObj::LString(_) | &LispObj::LChar(_) => Some("32"),
+        &LispObj::LSymbol(_) => Some("33"),
+        &LispObj::LNativeFunc(..) | &LispObj::LProcedure(_) => Some("34"),
+        &LispObj::LError(_) => Some("31"),
+        &LispObj::LPort(_) | &LispObj::LRecord(_) | &LispObj::LBytes(_)
+            | &LispObj::LF64Vector(_) | &LispObj::LEnvironment(_) => Some("35"),
+        &LispObj::LCons(..) | &LispObj::LLazyCons(..) | &LispObj::LNil
+            | &LispObj::LVector(_) => None,
+    }
+}
+
+/// `obj` as the REPL prints it: with the argument lists of procedures if `*print-arity*`
+/// is true, and coloured by its type if `color` is.
+pub fn show_value(obj: &LispObj, color: bool, env: &EnvironmentRef) -> String {
+    let print_arity = env.borrow().lookup(PRINT_ARITY_NAME);
+    let text = match print_arity {
+        Some(ref flag) if !super::is_false(flag) => format!("{:#}", obj),
+        _ => format!("{}", obj),
+    };
+    if color {
+        colorize(obj, text)
+    } else {
+        text
+    }
+}
+
+/// The printed text of `obj`, wrapped in ANSI escapes colouring it by its type.
+pub fn colorize(obj: &LispObj, text: String) -> String {
+    match color_code(obj) {
+        Some(code) => format!("\x1b[{}m{}\x1b[0m", code, text),
+        None => text,
+    }
+}
@@ -0,0 +1,98 @@
+//! Destructuring patterns, as used by `match`, `let` and procedure parameters.
+//!
+//! * `_` matches anything
+//! * `nil`, `true`, `false` and keywords like `#:foo` match only themselves
+//! * any other symbol matches anything, and is bound to it
+//! * `'datum` matches objects equal to datum
+//! * `(a b . rest)` matches a list or cons whose elements match each subpattern
+//! * `[a b]` matches a vector of the same length whose elements match each subpattern
+//! * anything else, such as numbers and strings, matches only an equal object
+use ::core::{Environment, LispObjRef, AsLispObjRef, EvalResult};
+use ::core::procedure::KEYWORD_PREFIX;
+
+static WILDCARD: &'static str = "_";
+
+/// Matches `value` against `pattern`, pushing a binding for each variable in the pattern
+/// onto `out`. Returns whether it matched; bindings pushed before a failed match are left
+/// in `out`.
+///
+/// Throws a 'syntax-error if a variable appears twice in the pattern.
+pub fn match_pattern(pattern: &LispObjRef, value: &LispObjRef, out: &mut Vec<(String, LispObjRef)>) -> EvalResult<bool> {
+    if let Some(s) = pattern.symbol_ref() {
+        return if s == WILDCARD {
+            Ok(true)
+        } else if s == "nil" {
+            Ok(value.is_nil())
+        } else if s == "true" || s == "false" || s.starts_with(KEYWORD_PREFIX) {
+            Ok(value.symbol_equal(s))
+        } else if out.iter().any(|&(ref name, _)| name == s) {
+            syntax_error!("pattern variable {} appears more than once", s)
+        } else {
+            out.push((String::from(s), value.clone()));
+            Ok(true)
+        }
+    }
+
+    if let Some((hd, tl)) = pattern.cons_split() {
+        if hd.symbol_equal("quote") {
+            return match tl.cons_split() {
+                Some((datum, ref rest)) if rest.is_nil() => Ok(*datum == **value),
+                _ => syntax_error!("invalid quote pattern {}", pattern),
+            }
+        }
+
+        return match value.cons_split() {
+            Some((val_hd, val_tl)) => Ok(try!(match_pattern(&hd, &val_hd, out)) && try!(match_pattern(&tl, &val_tl, out))),
+            None => Ok(false),
+        }
+    }
+
+    if let Some(pats) = pattern.vec_ref() {
+        let vals = match value.vec_ref() {
+            Some(vals) if vals.len() == pats.len() => vals,
+            _ => return Ok(false),
+        };
+        for (pat, val) in pats.iter().zip(vals.iter()) {
+            if !try!(match_pattern(pat, val, out)) {
+                return Ok(false)
+            }
+        }
+        return Ok(true)
+    }
+
+    Ok(**pattern == **value)
+}
+
+/// The variables a pattern binds, in order.
+pub fn pattern_vars(pattern: &LispObjRef) -> Vec<String> {
+    if let Some(s) = pattern.symbol_ref() {
+        let literal = s == WILDCARD || s == "nil" || s == "true" || s == "false" || s.starts_with(KEYWORD_PREFIX);
+        return if literal { vec![] } else { vec![String::from(s)] }
+    }
+
+    let mut vars = vec![];
+    if let Some((hd, tl)) = pattern.cons_split() {
+        if !hd.symbol_equal("quote") {
+            vars.extend(pattern_vars(&hd));
+            vars.extend(pattern_vars(&tl));
+        }
+    } else if let Some(pats) = pattern.vec_ref() {
+        for pat in pats.iter() {
+            vars.extend(pattern_vars(pat));
+        }
+    }
+    vars
+}
+
+/// Matches a value against a pattern and binds its variables in `env`, throwing a
+/// 'match-error if it does not match.
+pub fn destructure(pattern: &LispObjRef, value: &LispObjRef, env: &mut Environment) -> EvalResult<()> {
+    let mut bindings = vec![];
+    if !try!(match_pattern(pattern, value, &mut bindings)) {
+        match_error!("{} does not match the pattern {}", value, pattern)
+    }
+    for (name, val) in bindings.into_iter() {
+        let _ = env.let_new(name, val);
+    }
+    Ok(())
+}
@@ -0,0 +1,58 @@
+//! A simple profiler for lisp procedures.
+//!
+//! While running, `apply` records the number of calls to each procedure and the total
+//! wall-clock time spent in them, keyed by procedure name (or id, for anonymous
+//! procedures). Times are inclusive of nested calls, and self tail calls count as part
+//! of the original call.
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::time::Duration;
+
+use ::core::obj::Procedure;
+
+/// Calls to, and time spent in, a single procedure.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProfileEntry {
+    pub calls: u64,
+    pub total: Duration,
+}
+
+thread_local! {
+    static PROFILE: RefCell<Option<HashMap<String, ProfileEntry>>> = RefCell::new(None);
+}
+
+/// Starts profiling, discarding any previous results.
+pub fn start() {
+    PROFILE.with(|p| *p.borrow_mut() = Some(HashMap::new()));
+}
+
+pub fn is_running() -> bool {
+    PROFILE.with(|p| p.borrow().is_some())
+}
+
+/// Stops profiling, returning the results sorted by total time, longest first.
+///
+/// Returns None if the profiler was not running.
+pub fn stop() -> Option<Vec<(String, ProfileEntry)>> {
+    PROFILE.with(|p| p.borrow_mut().take()).map(|results| {
+        let mut results: Vec<_> = results.into_iter().collect();
+        results.sort_by(|a, b| b.1.total.cmp(&a.1.total).then(a.0.cmp(&b.0)));
+        results
+    })
+}
+
+/// Records one call to `procd` which took `elapsed`, if the profiler is running.
+pub fn record(procd: &Procedure, elapsed: Duration) {
+    PROFILE.with(|p| {
+        if let Some(ref mut results) = *p.borrow_mut() {
+            let entry = results.entry(procd.display_name())
+                               .or_insert(ProfileEntry { calls: 0, total: Duration::new(0, 0) });
+            entry.calls += 1;
+            entry.total += elapsed;
+        }
+    })
+}
+
+pub fn duration_millis(d: Duration) -> f64 {
+    d.as_secs() as f64 * 1000.0 + d.subsec_nanos() as f64 / 1_000_000.0
+}
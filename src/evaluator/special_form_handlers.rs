@@ -1,6 +1,8 @@
 use ::core::{self, LispObj, LispObjRef, AsLispObjRef, EnvironmentRef, EvalResult};
 use core::obj::NativeFuncSignature;
-use super::{eval, lambda};
+use super::{builtins, eval, lambda, limits, macros, output, profile, syntax_rules};
+
+use std::time::Instant;
 
 /// # Special Form Handlers
 ///
@@ -18,44 +20,68 @@ use super::{eval, lambda};
  * case-lambda          - yes
  * catch-error          - yes
  * define               - yes
+ * define!              - yes
  * define-macro         - partial - need multiple-arity
+ * define-record        - yes
+ * deftest              - yes
  * define-char-handler
- * gensym
+ * define-hygienic-macro - yes
+ * define-syntax        - yes
+ * gensym               - yes, as a builtin
  * if                   - yes
  * let                  - partial, need named let
  * lambda               - yes
  * lazy-cons
+ * match                - yes
  * modify!
  * or                   - yes
  * quote                - yes
  * quasiquote
+ * rec                  - yes
  * set!                 - yes
+ * time                 - yes
+ * with-limit           - yes
  */
 
-pub fn get_handler(s: &str) -> Option<NativeFuncSignature> {
-    for &(name, handler) in HANDLERS.iter() {
-        if s == name {
-            return Some(handler);
+/// Defines the table of special forms, and `get_handler`, which finds a form's handler
+/// with a match rather than searching the table, as it is called for every list
+/// evaluated.
+macro_rules! special_forms {
+    ( $( $name:tt => $handler:ident ),* ) => {
+        pub fn get_handler(s: &str) -> Option<NativeFuncSignature> {
+            match s {
+                $( $name => Some($handler as NativeFuncSignature), )*
+                _ => None,
+            }
         }
-    }
 
-    None
+        static HANDLERS: &'static [(&'static str, NativeFuncSignature)] = &[ $( ($name, $handler) ),* ];
+    }
 }
 
 // TODO make scheme for documentation
-static HANDLERS: &'static [(&'static str, NativeFuncSignature)] =
-      &[("and", and_handler), ("begin", begin_handler), ("case-lambda", case_lambda_handler), ("catch-error", catch_error_handler),  
-        ("define", define_handler), ("define-macro", define_macro_handler),
-        ("if", if_handler), ("lambda", lambda_handler), ("lazy-cons", lazy_cons_handler), ("let", let_handler), ("or", or_handler), 
-        ("quote", quote_handler), ("quasiquote", quasiquote_handler),
-        ("set!", set_handler)];
+special_forms! {
+    "and" => and_handler, "begin" => begin_handler, "case-lambda" => case_lambda_handler,
+    "catch-error" => catch_error_handler, "define" => define_handler, "define!" => define_bang_handler,
+    "define-macro" => define_macro_handler, "define-hygienic-macro" => define_hygienic_macro_handler,
+    "define-record" => define_record_handler, "define-syntax" => define_syntax_handler,
+    "deftest" => deftest_handler, "if" => if_handler, "lambda" => lambda_handler,
+    "lazy-cons" => lazy_cons_handler, "let" => let_handler, "match" => match_handler, "or" => or_handler,
+    "quote" => quote_handler, "quasiquote" => quasiquote_handler, "rec" => rec_handler,
+    "set!" => set_handler, "time" => time_handler, "with-limit" => with_limit_handler
+}
+
+/// The names of the special forms.
+pub fn names() -> Vec<&'static str> {
+    HANDLERS.iter().map(|&(name, _)| name).collect()
+}
 
 pub fn and_handler(args: &[LispObjRef], env: EnvironmentRef) -> EvalResult {
-    let mut val = lisp_true!().to_obj_ref();
+    let mut val = lisp_true_ref!();
 
     for arg in args.iter() {
         val = try!(super::eval(arg, env.clone()));
-        if val.falsey() {
+        if super::is_false(&val) {
             return Ok(val)
         }
     }
@@ -75,21 +101,52 @@ pub fn case_lambda_handler(args: &[LispObjRef], env: EnvironmentRef) -> EvalResu
 pub fn catch_error_handler(args: &[LispObjRef], env: EnvironmentRef) -> EvalResult {
     match begin_handler(args, env) {
         Ok(obj)  => Ok(obj),
-        Err(err) => Ok(err.into_lisp_obj().to_obj_ref()),
+        // Exit requests and interrupts must reach the host
+        Err(err) => if err.is_catchable() {
+            Ok(err.into_lisp_obj().to_obj_ref())
+        } else {
+            Err(err)
+        },
     }
 }
 
 pub fn define_handler(args: &[LispObjRef], env: EnvironmentRef) -> EvalResult {
+    define_impl("define", args, env, false)
+}
+
+/// `(define! ...)` is `define`, except that it rebinds a name that is already bound at the
+/// top level, whatever `*allow-redefine*` is.
+pub fn define_bang_handler(args: &[LispObjRef], env: EnvironmentRef) -> EvalResult {
+    define_impl("define!", args, env, true)
+}
+
+fn define_impl(form: &str, args: &[LispObjRef], env: EnvironmentRef, always_rebind: bool) -> EvalResult {
     if args.len() < 2 {
-        syntax_error!("Not enough arguments to define {}", *args[0]);
+        syntax_error!("Not enough arguments to {} {}", form, LispObj::to_lisp_list(args.iter()));
     }
 
-    let (name, value) = if args[0].is_symbol() && args.len() == 2 {
-        let name = (*args[0]).clone().unwrap_symbol();
-        match (*try!(eval(args[1].clone(), env.clone()))).clone() {
-            LispObj::LProcedure(p) => (name.clone(), LispObj::make_proc(p.with_name(name))
-                                                                         .to_obj_ref()),
-            val => (name, val.to_obj_ref())
+    // (define name "docstring" value)
+    let docstr = if args[0].is_symbol() && args.len() == 3 {
+        match args[1].string_ref() {
+            Some(doc) => Some((*doc).clone()),
+            None => syntax_error!("{}: expected docstring, not {}", form, args[1]),
+        }
+    } else {
+        None
+    };
+
+    let (name, value) = if args[0].is_symbol() && (args.len() == 2 || docstr.is_some()) {
+        let name = try!(args[0].try_unwrap_symbol());
+        let value = try!(eval(args[args.len() - 1].clone(), env.clone()));
+        match *value {
+            LispObj::LProcedure(ref p) => {
+                let p = match docstr {
+                    Some(ref doc) => (**p).clone().with_name(name.clone()).with_doc(doc.clone()),
+                    None => (**p).clone().with_name(name.clone()),
+                };
+                (name, LispObj::make_proc(p).to_obj_ref())
+            },
+            _ => (name, value.clone())
         }
     } else if let Some((hd, tl)) = args[0].cons_split() {
         if hd.is_symbol() {
@@ -100,56 +157,132 @@ pub fn define_handler(args: &[LispObjRef], env: EnvironmentRef) -> EvalResult {
 
             (func_name, value)
         } else {
-            syntax_error!("invalid argumetnts to define: {}", LispObj::to_lisp_list(args.iter()))
+            syntax_error!("invalid argumetnts to {}: {}", form, LispObj::to_lisp_list(args.iter()))
         }
     } else {
-        syntax_error!("define must have symbol name to define, not {}", *args[0])
+        syntax_error!("{} must have symbol name to define, not {}", form, *args[0])
     };
 
-    let top_level = core::env::get_top_level(env);
+    // Inside a body, define binds in the body's frame; see lambda::declare_internal_defines
+    if !env.borrow().is_top_level() {
+        let mut frame = env.borrow_mut();
+        if let Some(doc) = docstr {
+            let _ = frame.set_meta(name.clone(), String::from(core::env::DOC_META_KEY),
+                                   string!(doc).to_obj_ref());
+        }
+        let _ = frame.let_new(name.clone(), value);
+        return Ok(symbol!(name).to_obj_ref())
+    }
+
     {
         let allow_red = {
-            let borrowed = top_level.borrow();
-            borrowed.lookup("*allow-redefine*").expect("cannot delete *allow-redefine*")
+            let borrowed = env.borrow();
+            borrowed.lookup(builtins::ALLOW_REDEFINE_NAME).expect("cannot delete *allow-redefine*")
         };
 
-        let mut borrowed_mut = top_level.borrow_mut();
-        match borrowed_mut.let_new(name.clone(), value.to_obj_ref()) {
-            Some(_) => {
-                if allow_red.falsey() {
-                    redefine_error!("symbol {} is already bound", name)
-                } else {
-                    Ok(symbol!(name).to_obj_ref())
-                }
-            },
-            None => Ok(symbol!(name).to_obj_ref()),
+        let mut borrowed_mut = env.borrow_mut();
+        if let Some(doc) = docstr {
+            let _ = borrowed_mut.set_meta(name.clone(), String::from(core::env::DOC_META_KEY),
+                                          string!(doc).to_obj_ref());
         }
+        let reloading = builtins::file_may_rebind(&name);
+        let rebound = borrowed_mut.let_new(name.clone(), value.clone()).is_some();
+        if rebound && allow_red.falsey() && !always_rebind && !reloading {
+            redefine_error!("symbol {} is already bound, use define! to rebind it", name)
+        }
+        builtins::note_definition(&name);
+        super::flags::note_set(&name, &value);
+        Ok(symbol!(name).to_obj_ref())
+    }
+}
+
+/// `(deftest name body...)` adds a test to `*tests*`, replacing any test with the same
+/// name. Tests are run by `run-tests`.
+pub fn deftest_handler(args: &[LispObjRef], env: EnvironmentRef) -> EvalResult {
+    if args.len() < 2 {
+        syntax_error!("deftest must have a name and a body, given {}", LispObj::to_lisp_list(args.iter()))
     }
+    let name = match args[0].symbol_ref() {
+        Some(name) => String::from(name),
+        None => syntax_error!("deftest must have symbol name, not {}", args[0]),
+    };
+
+    let test = try!(lambda::parse_lambda_args_body(nil!().to_obj_ref(), &args[1..], env.clone()));
+    let test = cons!(symbol!(name.clone()), LispObj::make_proc(test.with_name(name.clone())));
+
+    let top_level = core::env::get_top_level(env);
+    let tests = top_level.borrow().lookup(builtins::TESTS_NAME).expect("cannot delete *tests*");
+    let mut tests = flatten_list!(tests, "{} must be a list", builtins::TESTS_NAME);
+
+    let existing = tests.iter().position(|t| t.cons_split().map_or(false, |(n, _)| n.symbol_equal(&name)));
+    match existing {
+        Some(i) => tests[i] = test.to_obj_ref(),
+        None => tests.push(test.to_obj_ref()),
+    }
+    let _ = top_level.borrow_mut().swap_values(builtins::TESTS_NAME, LispObj::to_lisp_list(tests.into_iter()).to_obj_ref());
+    Ok(symbol!(name).to_obj_ref())
 }
 
 pub fn define_macro_handler(args: &[LispObjRef], env: EnvironmentRef) -> EvalResult {
+    define_macro_impl(args, env, false)
+}
+
+/// `(define-hygienic-macro (name args...) body...)` defines a macro whose expansions
+/// have the symbols they introduce renamed, so that e.g. a temporary variable bound by
+/// the macro cannot capture a variable of the same name in the macro's arguments.
+pub fn define_hygienic_macro_handler(args: &[LispObjRef], env: EnvironmentRef) -> EvalResult {
+    define_macro_impl(args, env, true)
+}
+
+/// `(define-syntax name (syntax-rules (literals...) (pattern template)...))` defines a
+/// macro by pattern matching; see the `syntax_rules` module.
+pub fn define_syntax_handler(args: &[LispObjRef], env: EnvironmentRef) -> EvalResult {
+    if args.len() != 2 {
+        syntax_error!("define-syntax: expected a name and a syntax-rules form, got {}", LispObj::to_lisp_list(args.iter()))
+    }
+
+    let name = match args[0].symbol_ref() {
+        Some(name) => String::from(name),
+        None => syntax_error!("define-syntax: invalid macro name {}", args[0]),
+    };
+    let _ = try!(syntax_rules::SyntaxRules::parse(&args[1]));
+    bind_macro(name, args[1].clone(), false, env)
+}
+
+/// Binds a macro at the top level, marking whether define-hygienic-macro made it.
+fn bind_macro(macro_name: String, value: LispObjRef, hygienic: bool, env: EnvironmentRef) -> EvalResult {
+    let top_level = core::env::get_top_level(env.clone());
+    let allow_red = env.borrow().lookup(builtins::ALLOW_REDEFINE_NAME).expect("cannot delete *allow-redefine*");
+
+    let mut borrowed_mut = top_level.borrow_mut();
+    let was_hygienic = borrowed_mut.lookup_meta(&macro_name).map_or(false, |meta| {
+        meta.iter().any(|&(ref key, _)| key == macros::HYGIENIC_META_KEY)
+    });
+    if hygienic || was_hygienic {
+        let _ = borrowed_mut.set_meta(macro_name.clone(), String::from(macros::HYGIENIC_META_KEY),
+                                      lisp_bool_ref!(hygienic));
+    }
+
+    let reloading = builtins::file_may_rebind(&macro_name);
+    let rebound = try!(borrowed_mut.let_macro(macro_name.clone(), value)).is_some();
+    if rebound && allow_red.falsey() && !reloading {
+        redefine_error!("macro {} is already bound", macro_name)
+    }
+    builtins::note_definition(&macro_name);
+    Ok(symbol!(macro_name).to_obj_ref())
+}
+
+fn define_macro_impl(args: &[LispObjRef], env: EnvironmentRef, hygienic: bool) -> EvalResult {
     if args.len() < 2 {
         syntax_error!("Not enough arguments to define-macro: {}", LispObj::to_lisp_list(args.iter()))
     }
 
-    let top_level = core::env::get_top_level(env.clone());
     if let Some((hd, tl)) = args[0].cons_split() {
         if hd.is_symbol() {
-            let macro_name = (*hd).clone().unwrap_symbol();
+            let macro_name = try!(hd.try_unwrap_symbol());
             let func       = try!(lambda::parse_lambda_args_body(tl, &args[1..], env.clone()));
             let value      = LispObj::make_proc(func.with_name(macro_name.clone()));
-            let allow_red  = env.borrow().lookup("*allow-redefine*").expect("cannot delete *allow-redefine*");
-
-            match top_level.borrow_mut().let_macro(macro_name.clone(), value.to_obj_ref()) {
-                Some(_) => {
-                    if allow_red.falsey() {
-                        redefine_error!("macro {} is already bound", macro_name)
-                    } else {
-                        Ok(symbol!(macro_name).to_obj_ref())
-                    }
-                },
-                None    => Ok(symbol!(macro_name).to_obj_ref())
-            }
+            bind_macro(macro_name, value.to_obj_ref(), hygienic, env)
         } else {
             syntax_error!("invalid macro definition: {}", LispObj::to_lisp_list(args.iter()))
         }
@@ -158,6 +291,74 @@ pub fn define_macro_handler(args: &[LispObjRef], env: EnvironmentRef) -> EvalRes
     }
 }
 
+/// `(define-record point (x y))` defines a record type `point`, with a constructor
+/// `make-point`, a predicate `point?`, and for each field an accessor `point-x` and an
+/// updater `point-set-x` which returns an updated copy.
+pub fn define_record_handler(args: &[LispObjRef], env: EnvironmentRef) -> EvalResult {
+    if args.len() != 2 {
+        syntax_error!("define-record: expected a name and a list of fields, not {}",
+                      LispObj::to_lisp_list(args.iter()))
+    }
+
+    let type_name = match args[0].symbol_ref() {
+        Some(name) => String::from(name),
+        None => syntax_error!("define-record: type name must be a symbol, not {}", args[0]),
+    };
+
+    let fields = flatten_list!(args[1], "define-record: fields must be a list");
+    let mut field_names: Vec<String> = vec![];
+    for field in fields.iter() {
+        match field.symbol_ref() {
+            Some(name) if !field_names.iter().any(|f| f == name) => field_names.push(String::from(name)),
+            Some(name) => syntax_error!("define-record: duplicate field {}", name),
+            None => syntax_error!("define-record: field must be a symbol, not {}", field),
+        }
+    }
+
+    // The generated procedures call the natives directly, so that they keep working
+    // if the names of the natives are rebound
+    let quoted = |obj: LispObjRef| lisp_list![symbol!("quote"), obj].to_obj_ref();
+    let native = |name: &str| {
+        quoted(super::find_builtin_native(name).expect("record builtins should be defined"))
+    };
+    let type_sym = quoted(symbol!(type_name.clone()).to_obj_ref());
+    let record_sym = symbol!("record").to_obj_ref();
+    let value_sym = symbol!("value").to_obj_ref();
+
+    let mut definitions = vec![];
+
+    // (define (make-point x y) (make-record 'point '(x y) x y))
+    let mut constructor = vec![native("make-record"), type_sym.clone(), quoted(args[1].clone())];
+    constructor.extend(fields.iter().cloned());
+    definitions.push((cons!(symbol!(format!("make-{}", type_name)), args[1].clone()).to_obj_ref(),
+                      LispObj::to_lisp_list(constructor.into_iter()).to_obj_ref()));
+
+    // (define (point? record) (record? record 'point))
+    definitions.push((lisp_list![symbol!(format!("{}?", type_name)), record_sym.clone()].to_obj_ref(),
+                      lisp_list![native("record?"), record_sym.clone(), type_sym.clone()].to_obj_ref()));
+
+    for field in field_names.iter() {
+        let field_sym = quoted(symbol!(field.clone()).to_obj_ref());
+
+        // (define (point-x record) (record-get record 'x 'point))
+        definitions.push((lisp_list![symbol!(format!("{}-{}", type_name, field)), record_sym.clone()].to_obj_ref(),
+                          lisp_list![native("record-get"), record_sym.clone(), field_sym.clone(),
+                                     type_sym.clone()].to_obj_ref()));
+
+        // (define (point-set-x record value) (record-set record 'x value 'point))
+        definitions.push((lisp_list![symbol!(format!("{}-set-{}", type_name, field)),
+                                     record_sym.clone(), value_sym.clone()].to_obj_ref(),
+                          lisp_list![native("record-set"), record_sym.clone(), field_sym,
+                                     value_sym.clone(), type_sym.clone()].to_obj_ref()));
+    }
+
+    for (signature, body) in definitions {
+        try!(define_handler(&[signature, body], env.clone()));
+    }
+
+    Ok(symbol!(type_name).to_obj_ref())
+}
+
 pub fn if_handler(args: &[LispObjRef], env: EnvironmentRef) -> EvalResult {
     super::tco::handle_special_form_tco("if", args, env)
 }
@@ -181,12 +382,16 @@ pub fn let_handler(args: &[LispObjRef], env: EnvironmentRef) -> EvalResult {
     super::tco::handle_special_form_tco("let", args, env)
 }
 
+pub fn match_handler(args: &[LispObjRef], env: EnvironmentRef) -> EvalResult {
+    super::tco::handle_special_form_tco("match", args, env)
+}
+
 pub fn or_handler(args: &[LispObjRef], env: EnvironmentRef) -> EvalResult {
-    let mut val = lisp_false!().to_obj_ref();
+    let mut val = lisp_false_ref!();
 
     for arg in args.iter() {
         val = try!(super::eval(arg, env.clone()));
-        if !val.falsey() {
+        if !super::is_false(&val) {
             return Ok(val)
         }
     }
@@ -194,33 +399,158 @@ pub fn or_handler(args: &[LispObjRef], env: EnvironmentRef) -> EvalResult {
     Ok(val)
 }
 
-fn quasiquote_helper(obj: LispObjRef, env: EnvironmentRef) -> EvalResult {
-    if let Some((hd, tl)) = obj.cons_split() {
-        match (hd.symbol_ref(), tl.cons_split()) {
-            (Some(s), Some((hd2, tl2))) => {
-                if s == "unquote" {
-                    if tl2.is_nil() {
-                        super::eval(hd2, env)
+/// The expression in `(unquote-splicing expr)`, if `obj` is one
+fn spliced_expr(obj: &LispObjRef) -> Option<LispObjRef> {
+    match obj.cons_split() {
+        Some((hd, tl)) if hd.symbol_equal("unquote-splicing") => match tl.cons_split() {
+            Some((expr, rest)) if rest.is_nil() => Some(expr),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Evaluates the expression in `,@expr`, to the items to splice in
+fn splice_items(expr: LispObjRef, env: EnvironmentRef) -> EvalResult<Vec<LispObjRef>> {
+    let val = try!(eval(expr.clone(), env));
+    if let Some(vec) = val.vec_ref() {
+        return Ok(vec.iter().cloned().collect())
+    }
+    match val.list_iter().collect() {
+        Ok(items) => Ok(items),
+        Err(()) => type_error!(args vec![val.clone()], expected "list";
+                               "quasiquote: cannot splice ,@{}, {} is not a list", expr, val),
+    }
+}
+
+/// A list or vector which `quasiquote_helper` is part way through
+enum Quasiquoting {
+    /// The elements quasiquoted so far, the rest of the list, and the list's tail once
+    /// it has been quasiquoted. `in_tail` is set while a vector tail is quasiquoted.
+    List { items: Vec<LispObjRef>, rest: LispObjRef, tail: Option<LispObjRef>, in_tail: bool },
+    Vector { items: Vec<LispObjRef>, rest: ::std::vec::IntoIter<LispObjRef> },
+}
+
+impl Quasiquoting {
+    /// Starts on `obj`, if it is a list or vector
+    fn start(obj: &LispObjRef) -> Option<Quasiquoting> {
+        if obj.is_cons() {
+            Some(Quasiquoting::List { items: vec![], rest: obj.clone(), tail: None, in_tail: false })
+        } else if let Some(vec) = obj.vec_ref() {
+            let rest = vec.iter().cloned().collect::<Vec<_>>().into_iter();
+            Some(Quasiquoting::Vector { items: vec![], rest: rest })
+        } else {
+            None
+        }
+    }
+
+    /// Adds an element, once it has been quasiquoted
+    fn push(&mut self, value: LispObjRef) {
+        match self {
+            &mut Quasiquoting::List { ref mut tail, in_tail: true, .. } => *tail = Some(value),
+            &mut Quasiquoting::List { ref mut items, .. } |
+            &mut Quasiquoting::Vector { ref mut items, .. } => items.push(value),
+        }
+    }
+
+    /// Copies elements which are symbols, splices in `,@` elements and evaluates an
+    /// unquoted tail, up to the next element which needs quasiquoting. Returns None
+    /// once there are none left.
+    fn next_elem(&mut self, env: &EnvironmentRef) -> EvalResult<Option<LispObjRef>> {
+        match self {
+            &mut Quasiquoting::List { ref mut items, ref mut rest, ref mut tail, ref mut in_tail } => {
+                loop {
+                    let (hd, tl) = match rest.cons_split() {
+                        Some(split) => split,
+                        None if rest.is_vector() && tail.is_none() => {
+                            *in_tail = true;
+                            return Ok(Some(rest.clone()))
+                        },
+                        None => return Ok(None),
+                    };
+                    if let (Some(s), Some((hd2, tl2))) = (hd.symbol_ref(), tl.cons_split()) {
+                        if s == "unquote" {
+                            if !tl2.is_nil() {
+                                syntax_error!("quasiquote: invalid unquote, `,{}`", tl)
+                            }
+                            *tail = Some(try!(eval(hd2, env.clone())));
+                            *rest = nil!().to_obj_ref();
+                            return Ok(None)
+                        } else if s == "unquote-splicing" {
+                            syntax_error!("quasiquote: ,@ must be inside a list or vector, not `{}`", rest)
+                        }
+                    }
+                    *rest = tl;
+                    if hd.is_symbol() {
+                        items.push(hd);
+                    } else if let Some(expr) = spliced_expr(&hd) {
+                        items.extend(try!(splice_items(expr, env.clone())));
                     } else {
-                        syntax_error!("quasiquote: invalid unquote, `,{}`", tl)
+                        return Ok(Some(hd))
                     }
-                } else {
-                    Ok(cons!(hd.clone(), try!(quasiquote_helper(tl, env)))
-                       .to_obj_ref())
+                }
+            },
+            &mut Quasiquoting::Vector { ref mut items, ref mut rest } => {
+                for item in rest {
+                    match spliced_expr(&item) {
+                        Some(expr) => items.extend(try!(splice_items(expr, env.clone()))),
+                        None => return Ok(Some(item)),
+                    }
+                }
+                Ok(None)
+            },
+        }
+    }
+
+    /// Builds the quasiquoted list or vector
+    fn finish(self) -> LispObjRef {
+        match self {
+            Quasiquoting::List { items, rest, tail, .. } => {
+                let mut out = tail.unwrap_or(rest);
+                for item in items.into_iter().rev() {
+                    out = LispObj::cons(item, out).to_obj_ref();
+                }
+                out
+            },
+            Quasiquoting::Vector { items, .. } => LispObj::make_vector(items.into_iter()).to_obj_ref(),
+        }
+    }
+}
+
+/// Quasiquotes `obj`, keeping the lists and vectors it is inside on a stack rather
+/// than recursing, so that deeply nested forms can't overflow the stack.
+fn quasiquote_helper(obj: LispObjRef, env: EnvironmentRef) -> EvalResult {
+    let mut stack: Vec<Quasiquoting> = vec![];
+    let mut next = obj;
+    loop {
+        let mut value = match Quasiquoting::start(&next) {
+            Some(started) => {
+                stack.push(started);
+                None
+            },
+            None => Some(next.clone()),
+        };
+
+        // Finish lists and vectors until one has an element to quasiquote
+        loop {
+            if let Some(value) = value.take() {
+                match stack.last_mut() {
+                    Some(top) => top.push(value),
+                    None => return Ok(value),
                 }
             }
-            (Some(_), None) => {
-                let tail = try!(quasiquote_helper(tl, env));
-                Ok(cons!(hd.clone(), tail).to_obj_ref())
-            }
-            (None, _) => {
-                let head = try!(quasiquote_helper(hd.clone(), env.clone()));
-                let next = try!(quasiquote_helper(tl, env));
-                Ok(cons!(head, next).to_obj_ref())
+            let elem = match stack.last_mut() {
+                Some(top) => try!(top.next_elem(&env)),
+                None => unreachable!(),
+            };
+            match elem {
+                Some(elem) => {
+                    next = elem;
+                    break
+                },
+                None => value = stack.pop().map(Quasiquoting::finish),
             }
         }
-    } else {
-        Ok(obj.clone())
     }
 }
 
@@ -241,13 +571,125 @@ pub fn quote_handler(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
     }
 }
 
-// Set returns the old value of a var
+/// `(rec name expr)` evaluates expr in a new frame, in which name is bound to the
+/// result, so that an anonymous procedure can refer to itself. `(rec (name args...) body...)`
+/// is short for `(rec name (lambda (args...) body...))`.
+pub fn rec_handler(args: &[LispObjRef], env: EnvironmentRef) -> EvalResult {
+    let frame = core::Environment::from_parent(env).to_env_ref();
+
+    let (name, value) = if args.len() == 2 && args[0].is_symbol() {
+        let name = try!(args[0].try_unwrap_symbol());
+        let value = try!(eval(args[1].clone(), frame.clone()));
+        let value = match *value {
+            LispObj::LProcedure(ref p) => LispObj::make_proc((**p).clone().with_name(name.clone())).to_obj_ref(),
+            _ => value.clone(),
+        };
+        (name, value)
+    } else if let Some((hd, tl)) = args.get(0).and_then(|arg| arg.cons_split()) {
+        match hd.symbol_ref() {
+            Some(name) => {
+                let func = try!(lambda::parse_lambda_args_body(tl, &args[1..], frame.clone()));
+                (String::from(name), LispObj::make_proc(func.with_name(name)).to_obj_ref())
+            },
+            None => syntax_error!("rec: invalid name {}", hd),
+        }
+    } else {
+        syntax_error!("expected (rec name expr) or (rec (name args...) body...), not {}",
+                      cons!(symbol!("rec"), LispObj::to_lisp_list(args.iter())))
+    };
+
+    let _ = frame.borrow_mut().let_new(name, value.clone());
+    Ok(value)
+}
+
+/// `(set! place value)` changes the value of a place, and returns its old value.
+///
+/// A place is a variable, or an accessor listed in `builtins::SETTABLE_PLACES` applied
+/// to a place, such as `(vector-ref v 0)`. Vectors and records are persistent, so the
+/// variable holding the outermost one is rebound to a copy with the place changed.
 pub fn set_handler(args: &[LispObjRef], env: EnvironmentRef) -> EvalResult {
-    unpack_args!(args => name: LSymbol, val: Any);
+    unpack_args!(args => place: Any, val: Any);
     let new_value = try!(super::eval(val, env.clone()));
 
-    match env.borrow_mut().swap_values(&name, new_value.to_obj_ref()) {
-        Some(old_val) => Ok(old_val.clone()),
-        None => bound_error!("cannot set! unbound symbol {}", name),
+    let mut old_value = None;
+    try!(modify_place(&place, &env, &mut |old| {
+        old_value = Some(old);
+        Ok(new_value.clone())
+    }));
+    Ok(old_value.expect("modify_place always updates its place"))
+}
+
+/// Replaces the value of a place with the result of `update` on its old value.
+fn modify_place(place: &LispObjRef, env: &EnvironmentRef, update: &mut FnMut(LispObjRef) -> EvalResult)
+        -> EvalResult<()> {
+    if let Some(name) = place.symbol_ref() {
+        let old = match env.borrow().lookup(name) {
+            Some(old) => old,
+            None => bound_error!("cannot set! unbound symbol {}", name),
+        };
+        let new = try!(update(old));
+        super::flags::note_set(name, &new);
+        let _ = env.borrow_mut().swap_values(name, new);
+        return Ok(())
+    }
+
+    let parts = flatten_list!(place, "set!: invalid place {}", place);
+    let accessor = parts.first().and_then(|hd| hd.symbol_ref())
+                        .and_then(|name| builtins::SETTABLE_PLACES.iter().find(|&&(acc, _, _)| acc == name));
+    let (getter, setter) = match accessor {
+        Some(&(_, getter, setter)) if parts.len() >= 3 => (getter, setter),
+        _ => syntax_error!("set!: cannot set {}, expected a symbol or a place such as (vector-ref v 0)", place),
+    };
+
+    // (accessor container key extra...) is updated by (setter container key new extra...)
+    let mut keys = vec![];
+    for key in parts[2..].iter() {
+        keys.push(try!(super::eval(key, env.clone())));
+    }
+    modify_place(&parts[1], env, &mut |container| {
+        let res = {
+            let mut get_args = vec![container.clone()];
+            get_args.extend(keys.iter().cloned());
+            getter(&get_args, env.clone())
+        }.and_then(|old| update(old)).and_then(|new| {
+            let mut set_args = vec![container.clone(), keys[0].clone(), new];
+            set_args.extend(keys[1..].iter().cloned());
+            setter(&set_args, env.clone())
+        });
+
+        // Each enclosing place is added to the error's chain
+        res.map_err(|err| if err.source.is_some() {
+            core::RuntimeError::new_from(err, place.clone())
+        } else {
+            err.with_source(place.clone())
+        })
+    })
+}
+
+/// Evaluates its argument, printing how long it took
+pub fn time_handler(args: &[LispObjRef], env: EnvironmentRef) -> EvalResult {
+    if args.len() != 1 {
+        syntax_error!("time must have exactly 1 argument, given {}", LispObj::to_lisp_list(args.iter()))
+    }
+
+    let start = Instant::now();
+    let res = try!(eval(args[0].clone(), env));
+    try!(output::print(format_args!("time: {:.3} ms\n", profile::duration_millis(start.elapsed()))));
+    Ok(res)
+}
+
+/// `(with-limit steps body...)` evaluates body, throwing a 'resource-error if it takes
+/// more than the given number of evaluation steps.
+pub fn with_limit_handler(args: &[LispObjRef], env: EnvironmentRef) -> EvalResult {
+    if args.len() < 2 {
+        syntax_error!("with-limit needs a step limit and a body, given {}", LispObj::to_lisp_list(args.iter()))
+    }
+
+    let limit = try!(eval(args[0].clone(), env.clone()));
+    let steps = check_type!(limit, LInteger);
+    if steps < 0 {
+        argument_error!("with-limit: step limit must not be negative, not {}", steps)
     }
+    let limits = limits::Limits { steps: Some(steps as u64), time: None };
+    limits::with_limits(limits, || begin_handler(&args[1..], env))
 }
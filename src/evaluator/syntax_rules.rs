@@ -0,0 +1,327 @@
+//! Pattern-based macros, defined with `define-syntax` and `syntax-rules`:
+//!
+//! ```text
+//! (define-syntax my-or
+//!   (syntax-rules ()
+//!     ((_) false)
+//!     ((_ e) e)
+//!     ((_ e rest ...) (let ((t e)) (if t t (my-or rest ...))))))
+//! ```
+//!
+//! A call is expanded with the template of the first rule whose pattern matches it.
+//! The first element of a pattern stands for the macro's name and is ignored. In the
+//! rest, `_` matches anything, the literals match only the same symbol, and any other
+//! symbol is a pattern variable, matching anything. Lists, dotted lists and vectors
+//! match element by element, and a subpattern followed by `...` matches zero or more
+//! elements. Anything else matches only an equal object.
+//!
+//! Pattern variables are replaced by what they matched in the template. A variable
+//! matched under `...` must be followed by (at least) as many `...` in the template,
+//! and `(... ...)` produces a literal `...`.
+//!
+//! The `syntax-rules` form itself is stored as the macro, and parsed again on each
+//! expansion. Expansions are renamed as for `define-hygienic-macro`.
+use std::collections::HashMap;
+
+use ::core::{LispObj, LispObjRef, AsLispObjRef, EvalResult};
+
+pub static SYNTAX_RULES: &'static str = "syntax-rules";
+static ELLIPSIS: &'static str = "...";
+static WILDCARD: &'static str = "_";
+
+/// What a pattern variable matched. Variables under `n` ellipses are nested `n` deep.
+#[derive(Clone, Debug)]
+enum Binding {
+    One(LispObjRef),
+    Many(Vec<Binding>),
+}
+
+type Bindings = HashMap<String, Binding>;
+
+/// A parsed `(syntax-rules (literals...) (pattern template)...)` form.
+pub struct SyntaxRules {
+    literals: Vec<String>,
+    /// Each rule's pattern, without the macro name, and template
+    rules: Vec<(LispObjRef, LispObjRef)>,
+}
+
+/// Splits a possibly improper list into its elements and final cdr.
+fn split_list(obj: &LispObjRef) -> (Vec<LispObjRef>, LispObjRef) {
+    let mut items = vec![];
+    let mut rest = obj.clone();
+    while let Some((hd, tl)) = rest.cons_split() {
+        items.push(hd);
+        rest = tl;
+    }
+    (items, rest)
+}
+
+fn join_list(items: Vec<LispObjRef>, tail: LispObjRef) -> LispObjRef {
+    let mut out = tail;
+    for item in items.into_iter().rev() {
+        out = LispObj::cons(item, out).to_obj_ref();
+    }
+    out
+}
+
+/// The elements of a list or vector pattern or template, or None for anything else.
+fn sequence(obj: &LispObjRef) -> Option<(Vec<LispObjRef>, LispObjRef)> {
+    if obj.is_cons() {
+        Some(split_list(obj))
+    } else if let Some(vec) = obj.vec_ref() {
+        Some((vec.iter().cloned().collect(), nil!().to_obj_ref()))
+    } else {
+        None
+    }
+}
+
+impl SyntaxRules {
+    pub fn parse(spec: &LispObjRef) -> EvalResult<Self> {
+        let items = flatten_list!(spec, "invalid syntax-rules form");
+        if items.len() < 2 || !items[0].symbol_equal(SYNTAX_RULES) {
+            syntax_error!("expected (syntax-rules (literals...) rules...), not {}", spec)
+        }
+
+        let mut literals = vec![];
+        for lit in flatten_list!(items[1], "syntax-rules: invalid literals") {
+            match lit.symbol_ref() {
+                Some(s) => literals.push(String::from(s)),
+                None => syntax_error!("syntax-rules: literals must be symbols, not {}", lit),
+            }
+        }
+
+        let mut rules = vec![];
+        for rule in items[2..].iter() {
+            let parts = flatten_list!(rule, "syntax-rules: invalid rule");
+            if parts.len() != 2 {
+                syntax_error!("syntax-rules: a rule must be a pattern and a template, not {}", rule)
+            }
+            let pattern = match parts[0].cons_split() {
+                Some((_, pattern)) => pattern,
+                None => syntax_error!("syntax-rules: a pattern must be a list, not {}", parts[0]),
+            };
+            try!(check_pattern(&pattern));
+            rules.push((pattern, parts[1].clone()));
+        }
+
+        Ok(SyntaxRules { literals: literals, rules: rules })
+    }
+
+    /// Expands the arguments of a call to the macro `name`.
+    pub fn expand(&self, name: &str, args: &LispObjRef) -> EvalResult {
+        for &(ref pattern, ref template) in self.rules.iter() {
+            let mut bindings = HashMap::new();
+            if self.match_pattern(pattern, args, &mut bindings) {
+                return expand_template(template, &bindings)
+            }
+        }
+        syntax_error!("no syntax-rules pattern matches {}", cons!(symbol!(name), args.clone()))
+    }
+
+    fn match_pattern(&self, pattern: &LispObjRef, form: &LispObjRef, out: &mut Bindings) -> bool {
+        if let Some(s) = pattern.symbol_ref() {
+            if s == WILDCARD {
+                return true
+            } else if self.literals.iter().any(|lit| lit == s) {
+                return form.symbol_equal(s)
+            }
+            let _ = out.insert(String::from(s), Binding::One(form.clone()));
+            return true
+        }
+
+        if pattern.is_vector() != form.is_vector() {
+            return false
+        }
+        // An empty list can match a pattern like (x ...)
+        let form_items = if form.is_nil() { Some((vec![], form.clone())) } else { sequence(form) };
+        match (sequence(pattern), form_items) {
+            (Some((pats, tail_pat)), Some((items, tail))) => self.match_sequence(&pats, &tail_pat, &items, &tail, out),
+            (Some(_), None) => false,
+            (None, _) => **pattern == **form,
+        }
+    }
+
+    fn match_sequence(&self, pats: &[LispObjRef], tail_pat: &LispObjRef,
+                      items: &[LispObjRef], tail: &LispObjRef, out: &mut Bindings) -> bool {
+        let ellipsis = match pats.iter().position(|p| p.symbol_equal(ELLIPSIS)) {
+            Some(i) => i,
+            None => {
+                if items.len() < pats.len() {
+                    return false
+                }
+                for (pat, item) in pats.iter().zip(items.iter()) {
+                    if !self.match_pattern(pat, item, out) {
+                        return false
+                    }
+                }
+                let rest = join_list(items[pats.len()..].to_vec(), tail.clone());
+                return self.match_pattern(tail_pat, &rest, out)
+            },
+        };
+
+        // check_pattern ensures the ellipsis follows a subpattern
+        let (before, repeated, after) = (&pats[..ellipsis - 1], &pats[ellipsis - 1], &pats[ellipsis + 1..]);
+        if items.len() < before.len() + after.len() {
+            return false
+        }
+        let repeats = items.len() - before.len() - after.len();
+
+        for (pat, item) in before.iter().zip(items.iter()) {
+            if !self.match_pattern(pat, item, out) {
+                return false
+            }
+        }
+
+        let mut matches = Vec::with_capacity(repeats);
+        for item in items[before.len()..before.len() + repeats].iter() {
+            let mut bindings = HashMap::new();
+            if !self.match_pattern(repeated, item, &mut bindings) {
+                return false
+            }
+            matches.push(bindings);
+        }
+        for var in self.pattern_vars(repeated) {
+            let each = matches.iter().map(|m| m[&var].clone()).collect();
+            let _ = out.insert(var, Binding::Many(each));
+        }
+
+        for (pat, item) in after.iter().zip(items[before.len() + repeats..].iter()) {
+            if !self.match_pattern(pat, item, out) {
+                return false
+            }
+        }
+        self.match_pattern(tail_pat, tail, out)
+    }
+
+    fn pattern_vars(&self, pattern: &LispObjRef) -> Vec<String> {
+        if let Some(s) = pattern.symbol_ref() {
+            if s == WILDCARD || s == ELLIPSIS || self.literals.iter().any(|lit| lit == s) {
+                vec![]
+            } else {
+                vec![String::from(s)]
+            }
+        } else if let Some((items, tail)) = sequence(pattern) {
+            let mut vars = self.pattern_vars(&tail);
+            for item in items.iter() {
+                vars.extend(self.pattern_vars(item));
+            }
+            vars
+        } else {
+            vec![]
+        }
+    }
+}
+
+/// Checks that every `...` in a pattern follows a subpattern, and that no list has more than one.
+fn check_pattern(pattern: &LispObjRef) -> EvalResult<()> {
+    if let Some((items, tail)) = sequence(pattern) {
+        let ellipses: Vec<_> = items.iter().enumerate().filter(|&(_, p)| p.symbol_equal(ELLIPSIS)).collect();
+        match ellipses.len() {
+            0 => {},
+            1 if ellipses[0].0 > 0 => {},
+            1 => syntax_error!("syntax-rules: ... must follow a subpattern in {}", pattern),
+            _ => syntax_error!("syntax-rules: more than one ... in {}", pattern),
+        }
+        for item in items.iter().filter(|item| !item.symbol_equal(ELLIPSIS)) {
+            try!(check_pattern(item));
+        }
+        try!(check_pattern(&tail));
+    } else if pattern.symbol_equal(ELLIPSIS) {
+        syntax_error!("syntax-rules: ... must follow a subpattern")
+    }
+    Ok(())
+}
+
+fn template_symbols(template: &LispObjRef, out: &mut Vec<String>) {
+    if let Some(s) = template.symbol_ref() {
+        out.push(String::from(s));
+    } else if let Some((items, tail)) = sequence(template) {
+        for item in items.iter() {
+            template_symbols(item, out);
+        }
+        template_symbols(&tail, out);
+    }
+}
+
+fn expand_template(template: &LispObjRef, bindings: &Bindings) -> EvalResult {
+    if let Some(s) = template.symbol_ref() {
+        return match bindings.get(s) {
+            Some(&Binding::One(ref val)) => Ok(val.clone()),
+            Some(&Binding::Many(_)) => syntax_error!("syntax-rules: pattern variable {} used without ...", s),
+            None => Ok(template.clone()),
+        }
+    }
+
+    match sequence(template) {
+        Some((items, tail)) => {
+            if template.is_vector() {
+                let items = try!(expand_sequence(&items, bindings));
+                return Ok(LispObj::make_vector(items.into_iter()).to_obj_ref())
+            }
+
+            // (... ...) escapes the ellipsis
+            if items.len() == 2 && items[0].symbol_equal(ELLIPSIS) && tail.is_nil() {
+                return Ok(items[1].clone())
+            }
+            let items = try!(expand_sequence(&items, bindings));
+            Ok(join_list(items, try!(expand_template(&tail, bindings))))
+        },
+        None => Ok(template.clone()),
+    }
+}
+
+fn expand_sequence(items: &[LispObjRef], bindings: &Bindings) -> EvalResult<Vec<LispObjRef>> {
+    let mut out = vec![];
+    let mut i = 0;
+    while i < items.len() {
+        let mut depth = 0;
+        while items.get(i + 1 + depth).map_or(false, |item| item.symbol_equal(ELLIPSIS)) {
+            depth += 1;
+        }
+
+        if depth == 0 {
+            out.push(try!(expand_template(&items[i], bindings)));
+        } else {
+            try!(expand_repeated(&items[i], bindings, depth, &mut out));
+        }
+        i += 1 + depth;
+    }
+    Ok(out)
+}
+
+/// Expands a subtemplate followed by `depth` ellipses, once for each form its
+/// pattern variables matched.
+fn expand_repeated(template: &LispObjRef, bindings: &Bindings, depth: usize,
+                   out: &mut Vec<LispObjRef>) -> EvalResult<()> {
+    let mut symbols = vec![];
+    template_symbols(template, &mut symbols);
+
+    let mut repeated: Vec<(String, &Vec<Binding>)> = vec![];
+    for s in symbols {
+        if let Some(&Binding::Many(ref each)) = bindings.get(&s) {
+            repeated.push((s, each));
+        }
+    }
+
+    let count = match repeated.first() {
+        Some(&(_, each)) => each.len(),
+        None => syntax_error!("syntax-rules: no pattern variable to repeat in {} ...", template),
+    };
+    if repeated.iter().any(|&(_, each)| each.len() != count) {
+        syntax_error!("syntax-rules: pattern variables in {} matched different numbers of forms", template)
+    }
+
+    for i in 0..count {
+        let mut inner = bindings.clone();
+        for &(ref var, each) in repeated.iter() {
+            let _ = inner.insert(var.clone(), each[i].clone());
+        }
+
+        if depth == 1 {
+            out.push(try!(expand_template(template, &inner)));
+        } else {
+            try!(expand_repeated(template, &inner, depth - 1, out));
+        }
+    }
+    Ok(())
+}
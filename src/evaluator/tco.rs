@@ -1,11 +1,37 @@
 pub use super::{LispObj, LispObjRef, AsLispObjRef, 
-                Environment, EnvironmentRef, EvalResult, RuntimeError};
-
+                Environment, EnvironmentRef, EvalResult};
+use ::core::procedure::GUARD_MARKER;
+
+
+/// The special forms whose last expression `special_form_tco_until_last` returns
+/// unevaluated
+#[derive(Clone, Copy)]
+enum TcoForm {
+    Begin,
+    If,
+    Let,
+    Match,
+}
 
-static TCO_BUILTINS: &'static [&'static str] = &["begin", "if", "let"];
+impl TcoForm {
+    fn from_name(name: &str) -> Option<TcoForm> {
+        match name {
+            "begin" => Some(TcoForm::Begin),
+            "if"    => Some(TcoForm::If),
+            "let"   => Some(TcoForm::Let),
+            "match" => Some(TcoForm::Match),
+            _       => None,
+        }
+    }
+}
 
 // TODO account for macro-expansions
 
+/// Whether `special_form_tco_until_last` handles a special form
+pub fn is_tco_form(name: &str) -> bool {
+    TcoForm::from_name(name).is_some()
+}
+
 /// Full evaluate
 pub fn handle_special_form_tco(form_name: &str, initial_args: &[LispObjRef], env_input: EnvironmentRef) -> EvalResult {
     let (env, val) = try!(special_form_tco_until_last(form_name, initial_args, env_input));
@@ -49,7 +75,7 @@ pub fn handle_special_form_tco(form_name: &str, initial_args: &[LispObjRef], env
 // ```
 //
 // # Panics
-// Panics when form_name is not a special form. Currently only supports `begin`, `if`, and `let`.
+// Panics when form_name is not a special form. Currently only supports `begin`, `if`, `let`, and `match`.
 //
 // ```rust,should_panic
 // use rustylisp::evaluator::tco;
@@ -58,42 +84,43 @@ pub fn handle_special_form_tco(form_name: &str, initial_args: &[LispObjRef], env
 // tco::special_form_tco_until_last("bogus", &[], Environment::empty().to_env_ref());
 // ```
 pub fn special_form_tco_until_last(form_name: &str, initial_args: &[LispObjRef], env_input: EnvironmentRef) -> EvalResult<(EnvironmentRef, LispObjRef)> {
-    let mut name = form_name;
+    let mut form = match TcoForm::from_name(form_name) {
+        Some(form) => form,
+        None => panic!("bogus special form: {}", form_name),
+    };
     let mut env  = env_input;
     let mut args: Vec<LispObjRef> = initial_args.iter().map(|obj| obj.to_obj_ref()).collect();
 
     loop {
-        let last = match name {
-            "begin" => try!(begin_until_last(&args[..], env.clone())),
-            "if"    => try!(if_until_last(&args[..], env.clone())),
-            "let"   => {
+        let last = match form {
+            TcoForm::Begin => try!(begin_until_last(&args[..], env.clone())),
+            TcoForm::If    => try!(if_until_last(&args[..], env.clone())),
+            TcoForm::Let   => {
                 let (new_env, res) = try!(let_until_last(&args[..], env));
                 env = new_env;
                 res
             },
-            _       => panic!("bogus special form: {}", name)
+            TcoForm::Match => {
+                let (new_env, res) = try!(match_until_last(&args[..], env));
+                env = new_env;
+                res
+            },
         };
 
         /* handle last */
-        let (new_name, new_args) = match last.cons_split() {
+        let (new_form, new_args) = match last.cons_split() {
             Some((hd, tl)) => { 
-                if let Some(s) = hd.symbol_ref() {
-                    if let Ok(ind) = TCO_BUILTINS.binary_search(&s) {
-                        let vec = flatten_list!(tl, "ill-formed-list");
-                        (TCO_BUILTINS[ind], vec)
-                    } else {
-                        /* TODO check if s is macro,
-                         * check if we can tco its result */
-                        return Ok((env, last))
-                    }
-                } else {
-                    return Ok((env, last))
+                match hd.symbol_ref().and_then(TcoForm::from_name) {
+                    Some(next) => (next, flatten_list!(tl, "ill-formed-list")),
+                    /* TODO check if s is macro,
+                     * check if we can tco its result */
+                    None => return Ok((env, last)),
                 }
             },
             None => return Ok((env, last)),
         };
 
-        name = new_name; 
+        form = new_form;
         args = new_args;
     }
 }
@@ -124,9 +151,9 @@ pub fn if_until_last(args: &[LispObjRef], env: EnvironmentRef) -> EvalResult {
     let trueval  = args[1].clone();
     let falseval = args[2].clone();
 
-    let truth = try!(super::eval(cond, env));
+    let truth = try!(super::eval(cond, env.clone()));
 
-    if truth.falsey() {
+    if super::is_false(&truth) {
         Ok(falseval.clone())
     } else {
         Ok(trueval.clone())
@@ -149,21 +176,75 @@ pub fn let_until_last(args: &[LispObjRef], env: EnvironmentRef) -> EvalResult<(E
         let unwrapped = flatten_list!(binding, "malformed binding");
 
         unpack_args!(unwrapped => name: Any, value: Any);
-        if !name.is_symbol() {
-            syntax_error!("malformed binding: expected symbol, got {}", *name);
+        let destructuring = name.is_cons() || name.is_vector();
+        if !name.is_symbol() && !destructuring {
+            syntax_error!("malformed binding: expected symbol or pattern, got {}", *name);
         }
 
         let evaluated = try!(super::eval(value, new_env.clone()));
         /* match try!(super::eval(value, new_env.clone())) {
-            LispObj::LProcedure(func) => LispObj::LProcedure(func.with_name((*name).clone().unwrap_symbol())),
+            LispObj::LProcedure(func) => LispObj::LProcedure(func.with_name(name.unwrap_symbol())),
             other => other,
         }; */
 
-        /* Associate evaluated with name */
-        new_env.borrow_mut().let_new((*name).clone().unwrap_symbol(), evaluated.to_obj_ref());
+        /* Associate evaluated with name, or with the pattern's variables */
+        if destructuring {
+            try!(super::pattern::destructure(&name, &evaluated, &mut new_env.borrow_mut()));
+        } else {
+            new_env.borrow_mut().let_new(try!(name.try_unwrap_symbol()), evaluated.to_obj_ref());
+        }
     }
 
+    super::lambda::declare_internal_defines(&args[1..], &new_env);
     let last = try!(begin_until_last(&args[1..], new_env.clone()));
 
     Ok((new_env, last))
 }
+
+/// Like let, match binds a new frame: the variables of the first clause whose pattern
+/// matches, and whose guard, if any, is true.
+///
+/// `(match expr (pattern body...) (pattern #:when guard body...) ...)`
+pub fn match_until_last(args: &[LispObjRef], env: EnvironmentRef) -> EvalResult<(EnvironmentRef, LispObjRef)> {
+    if args.len() < 1 {
+        syntax_error!("match must have an expression to match");
+    }
+
+    let value = try!(super::eval(&args[0], env.clone()));
+
+    for clause in args[1..].iter() {
+        let parts = flatten_list!(clause, "malformed match clause");
+        if parts.len() == 0 {
+            syntax_error!("empty match clause");
+        }
+
+        let mut bindings = vec![];
+        if !try!(super::pattern::match_pattern(&parts[0], &value, &mut bindings)) {
+            continue
+        }
+
+        let new_env = Environment::from_parent(env.clone()).to_env_ref();
+        for (name, val) in bindings.into_iter() {
+            new_env.borrow_mut().let_new(name, val);
+        }
+
+        let body = if parts.len() > 1 && parts[1].symbol_equal(GUARD_MARKER) {
+            if parts.len() < 3 {
+                syntax_error!("match: {} without a guard in {}", GUARD_MARKER, clause);
+            }
+            let guard = try!(super::eval(&parts[2], new_env.clone()));
+            if super::is_false(&guard) {
+                continue
+            }
+            &parts[3..]
+        } else {
+            &parts[1..]
+        };
+
+        super::lambda::declare_internal_defines(body, &new_env);
+        let last = try!(begin_until_last(body, new_env.clone()));
+        return Ok((new_env, last))
+    }
+
+    match_error!("no match clause matches {}", value)
+}
@@ -0,0 +1,88 @@
+//! Tracing calls to lisp procedures.
+//!
+//! Calls to traced procedures, and their return values, are printed indented by the
+//! depth of nested traced calls. A procedure is traced if its name was passed to
+//! `(trace)`, or if `*trace-all*` is true.
+use std::cell::{Cell, RefCell};
+use std::collections::HashSet;
+
+use ::core::{LispObjRef, EvalResult};
+use ::core::obj::Procedure;
+use super::{flags, output};
+
+/// When bound to a true value, every lisp procedure is traced.
+pub const TRACE_ALL_NAME: &'static str = "*trace-all*";
+
+thread_local! {
+    static TRACED: RefCell<HashSet<String>> = RefCell::new(HashSet::new());
+    /// Whether `TRACED` is non-empty, so that untraced calls skip hashing their names
+    static ANY_TRACED: Cell<bool> = Cell::new(false);
+    static DEPTH: Cell<usize> = Cell::new(0);
+}
+
+/// Starts tracing procedures named `name`.
+pub fn trace(name: &str) {
+    TRACED.with(|t| t.borrow_mut().insert(String::from(name)));
+    ANY_TRACED.with(|a| a.set(true));
+}
+
+/// Stops tracing procedures named `name`, returning true if they were traced.
+pub fn untrace(name: &str) -> bool {
+    TRACED.with(|t| {
+        let mut traced = t.borrow_mut();
+        let removed = traced.remove(name);
+        ANY_TRACED.with(|a| a.set(!traced.is_empty()));
+        removed
+    })
+}
+
+/// Stops tracing every procedure, except through `*trace-all*`.
+pub fn untrace_all() {
+    TRACED.with(|t| t.borrow_mut().clear());
+    ANY_TRACED.with(|a| a.set(false));
+}
+
+pub fn is_traced(procd: &Procedure) -> bool {
+    if flags::trace_all() {
+        return true
+    }
+    match procd.name {
+        Some(ref name) if ANY_TRACED.with(|a| a.get()) => TRACED.with(|t| t.borrow().contains(name)),
+        _ => false,
+    }
+}
+
+fn indent() -> String {
+    DEPTH.with(|d| "  ".repeat(d.get()))
+}
+
+fn print_call(procd: &Procedure, args: &LispObjRef) {
+    let mut call = format!("{}({}", indent(), procd.display_name());
+    let mut rest = args.clone();
+    while let Some((hd, tl)) = rest.cons_split() {
+        call.push_str(&format!(" {}", hd));
+        rest = tl;
+    }
+    let _ = output::print(format_args!("{})\n", call));
+}
+
+/// Reports a call to a traced procedure, increasing the trace depth.
+pub fn enter(procd: &Procedure, args: &LispObjRef) {
+    print_call(procd, args);
+    DEPTH.with(|d| d.set(d.get() + 1));
+}
+
+/// Reports a self tail call, which replaces the current call at the same depth.
+pub fn tail_call(procd: &Procedure, args: &LispObjRef) {
+    DEPTH.with(|d| d.set(d.get().saturating_sub(1)));
+    enter(procd, args);
+}
+
+/// Reports the result of a call to a traced procedure, decreasing the trace depth.
+pub fn exit(procd: &Procedure, res: &EvalResult) {
+    DEPTH.with(|d| d.set(d.get().saturating_sub(1)));
+    match res {
+        &Ok(ref val) => output::print(format_args!("{}{} returned {}\n", indent(), procd.display_name(), val)),
+        &Err(ref err) => output::print(format_args!("{}{} raised {}\n", indent(), procd.display_name(), err.errname)),
+    }.unwrap_or(())
+}
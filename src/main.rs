@@ -1,21 +1,177 @@
 extern crate rustylisp;
 use rustylisp::run;
+use rustylisp::parser::Parser;
+use rustylisp::parser::format::format_source;
 
 use std::env;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::process;
+
+static USAGE: &'static str = "usage: rustylisp [options] [script [args...]]
+       rustylisp fmt [-w] file...
+
+With no script and no expressions, starts a REPL.
+Arguments after the script are bound to *command-line-args*. The value of the
+last form in the script, and in each loaded file, is printed.
+
+options:
+  -e, --eval EXPR   evaluate EXPR and print its value
+  -l, --load FILE   load FILE before the script
+  -i, --repl        start a REPL after loading files and evaluating expressions
+  --sandbox         leave out builtins which use files, processes, the network
+                    or threads, and limit how long each evaluation may run
+  -h, --help        print this message
+  --                treat the next argument as the script
+
+fmt prints each file re-indented. With -w, it rewrites the files instead.";
+
+enum Action {
+    Eval(String),
+    Load(String),
+}
+
+fn usage_error(msg: &str) -> ! {
+    eprintln!("rustylisp: {}\n\n{}", msg, USAGE);
+    process::exit(2)
+}
+
+fn read_file(path: &str) -> std::io::Result<String> {
+    let mut source = String::new();
+    try!(try!(File::open(path)).read_to_string(&mut source));
+    Ok(source)
+}
+
+fn write_file(path: &str, contents: &str) -> std::io::Result<()> {
+    try!(File::create(path)).write_all(contents.as_bytes())
+}
+
+/// `rustylisp fmt`: re-indents each file, printing it or rewriting it in place
+fn fmt<I: Iterator<Item=String>>(args: I) {
+    let mut write = false;
+    let mut files = vec![];
+    for arg in args {
+        match &arg as &str {
+            "-w" | "--write" => write = true,
+            opt if opt.starts_with('-') => usage_error(&format!("unknown fmt option {}", opt)),
+            _ => files.push(arg),
+        }
+    }
+    if files.is_empty() {
+        usage_error("fmt requires a file")
+    }
+
+    let mut failed = false;
+    for file in files {
+        let res = read_file(&file).map_err(|e| format!("{}", e)).and_then(|source| {
+            let formatted = try!(format_source(&source).map_err(|e| format!("{:?}", e)));
+            if !write {
+                print!("{}", formatted);
+                Ok(())
+            } else if formatted != source {
+                write_file(&file, &formatted).map_err(|e| format!("{}", e))
+            } else {
+                Ok(())
+            }
+        });
+        if let Err(err) = res {
+            eprintln!("rustylisp: cannot format {}: {}", file, err);
+            failed = true;
+        }
+    }
+
+    if failed {
+        process::exit(1)
+    }
+}
 
 fn main() {
-    let args: Vec<_> = env::args().collect();
-    let mut env = run::Evaluator::new();
+    if env::args().nth(1).map_or(false, |arg| arg == "fmt") {
+        return fmt(env::args().skip(2))
+    }
+
+    let mut args = env::args().skip(1);
+    let mut actions = vec![];
+    let mut repl = false;
+    let mut sandbox = false;
+    let mut script = None;
+
+    while let Some(arg) = args.next() {
+        match &arg as &str {
+            "-e" | "--eval" => match args.next() {
+                Some(expr) => actions.push(Action::Eval(expr)),
+                None => usage_error("-e requires an expression"),
+            },
+            "-l" | "--load" => match args.next() {
+                Some(file) => actions.push(Action::Load(file)),
+                None => usage_error("-l requires a file"),
+            },
+            "-i" | "--repl" => repl = true,
+            "--sandbox" => sandbox = true,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return
+            },
+            "--" => {
+                script = args.next();
+                break
+            },
+            opt if opt.starts_with('-') && opt.len() > 1 => {
+                usage_error(&format!("unknown option {}", opt))
+            },
+            _ => {
+                script = Some(arg);
+                break
+            },
+        }
+    }
 
-    // Read from stdin
-    if args.len() == 1 {
-        env.repl()
+    // Start a REPL if there is nothing else to do
+    if script.is_none() && actions.is_empty() {
+        repl = true;
+    }
+
+    let mut env = if sandbox {
+        run::Evaluator::new_sandboxed()
     } else {
-        for file in args.into_iter().skip(1) {
-            match env.load_from_file(file) {
-                Ok(obj) => println!("{}", obj),
-                Err(err) => err.dump_traceback()
+        run::Evaluator::new()
+    };
+    env.set_command_line_args(args);
+
+    if let Some(file) = script {
+        actions.push(Action::Load(file));
+    }
+
+    for action in actions {
+        let res = match action {
+            Action::Eval(expr) => {
+                env.eval_all_from_parser(Parser::from_string(expr, "<command-line>"))
+                   .map(|obj| println!("{}", obj))
+            },
+            Action::Load(file) => env.load_from_file(file).map(|obj| println!("{}", obj)),
+        };
+
+        if let Err(err) = res {
+            if let Some(code) = err.exit_code() {
+                shutdown(env, code)
+            }
+
+            env.write_error(&err);
+            if !repl {
+                shutdown(env, 1)
             }
         }
     }
+
+    if repl {
+        if let Some(code) = env.repl() {
+            shutdown(env, code)
+        }
+    }
+}
+
+/// Exits with `code`, after running the procedures registered with `at-exit`
+fn shutdown(mut env: run::Evaluator, code: i32) -> ! {
+    env.run_exit_hooks();
+    process::exit(code)
 }
@@ -0,0 +1,173 @@
+//! Re-indenting lisp source.
+//!
+//! The formatter only changes the whitespace at the start and end of each line, so
+//! comments and the text of every form are kept as they were written. A line is
+//! indented by the form it is in:
+//!
+//! * In a vector, or a list whose head isn't a symbol, it lines up with the first
+//!   element.
+//! * In a form with a body, such as `define`, `let` or `if`, it is indented two
+//!   columns past the open paren.
+//! * In other calls, it lines up with the first argument if that is on the same line
+//!   as the procedure, or is indented two columns past the paren if not.
+//!
+//! Lines inside a multi-line string are left alone.
+use super::lexer::{is_whitespace, Lexer, Token};
+use super::parser::{ParserError, ParserState};
+
+/// Forms whose arguments after the first line are a body, besides those starting with
+/// `def` or `with-`
+static BODY_FORMS: &'static [&'static str] =
+    &["begin", "case-lambda", "catch-error", "if", "lambda", "let", "let*", "letrec",
+      "match", "rec", "syntax-rules", "time", "unless", "when"];
+
+/// How many columns a body is indented past its form's open paren
+const BODY_INDENT: usize = 2;
+
+fn is_body_form(name: &str) -> bool {
+    BODY_FORMS.contains(&name) || name.starts_with("def") || name.starts_with("with-")
+}
+
+/// An open list or vector, in output columns
+struct Open {
+    state: ParserState,
+    // The token which closes it
+    close: Token,
+    col: usize,
+    // The head, if it is a symbol, and the line it is on
+    head: Option<(String, u32)>,
+    // How many elements have started
+    items: usize,
+    // The column of the first argument, if it is on the same line as the head
+    first_arg_col: Option<usize>,
+}
+
+impl Open {
+    fn indent(&self) -> usize {
+        match (self.state, &self.head) {
+            (ParserState::List, &Some((ref head, _))) if is_body_form(head)
+                => self.col + BODY_INDENT,
+            (ParserState::List, &Some(_))
+                => self.first_arg_col.unwrap_or(self.col + BODY_INDENT),
+            _ => self.col + 1,
+        }
+    }
+
+    fn start_item(&mut self, tok: &Token, line: u32, col: usize) {
+        match (self.items, tok) {
+            (0, &Token::Ident(ref name)) => self.head = Some((name.clone(), line)),
+            (1, _) => {
+                if let Some((_, head_line)) = self.head {
+                    if head_line == line {
+                        self.first_arg_col = Some(col);
+                    }
+                }
+            },
+            _ => {},
+        }
+        self.items += 1;
+    }
+}
+
+/// Re-indents `source`, failing if it doesn't lex or its parens don't balance.
+pub fn format_source(source: &str) -> Result<String, ParserError<()>> {
+    // Each token with the line and column it starts at, and the line it ends on
+    let mut lexer = Lexer::from_string(source, "<format>").with_comments(true);
+    let mut tokens = vec![];
+    while let Some(tok) = lexer.next() {
+        let tok = try!(tok.map_err(ParserError::LexError));
+        tokens.push((tok.tok, tok.line_no, tok.col_no, lexer.line_no));
+    }
+
+    // The lines multi-line strings start and end on
+    let strings: Vec<(u32, u32)> = tokens.iter()
+                                         .filter(|&&(ref tok, start, _, end)| match tok {
+                                             &Token::QuotedString(_) => end > start,
+                                             _ => false,
+                                         })
+                                         .map(|&(_, start, _, end)| (start, end))
+                                         .collect();
+
+    let mut out = String::with_capacity(source.len());
+    let mut stack: Vec<Open> = vec![];
+    // The reader character before the current token, if any
+    let mut prefix = None;
+    let mut tokens = tokens.into_iter().peekable();
+
+    for (line_no, line) in source.split('\n').enumerate() {
+        let line_no = line_no as u32;
+        let shift: isize = if strings.iter().any(|&(start, end)| start < line_no && line_no <= end) {
+            out.push_str(line);
+            0
+        } else {
+            let text_start = line.char_indices().find(|&(_, c)| !is_whitespace(c)).map_or(line.len(), |(i, _)| i);
+            let old_indent = line[..text_start].chars().count();
+            let new_indent = stack.last().map_or(0, Open::indent);
+            // Keep trailing whitespace which is in a string
+            let text = if strings.iter().any(|&(start, _)| start == line_no) {
+                &line[text_start..]
+            } else {
+                line[text_start..].trim_right()
+            };
+            if !text.is_empty() {
+                for _ in 0..new_indent {
+                    out.push(' ');
+                }
+                out.push_str(text);
+            }
+            new_indent as isize - old_indent as isize
+        };
+        out.push('\n');
+
+        while tokens.peek().map_or(false, |&(_, line, _, _)| line == line_no) {
+            let (tok, line, src_col, _) = tokens.next().unwrap();
+            // Lexer columns count from 1
+            let col = (src_col as isize - 1 + shift) as usize;
+
+            let starts_item = match tok {
+                Token::CloseParen | Token::CloseBracket | Token::Comment(_) => false,
+                _ => prefix.is_none(),
+            };
+            if starts_item {
+                if let Some(top) = stack.last_mut() {
+                    top.start_item(&tok, line, col);
+                }
+            }
+            let after_hash = prefix == Some('#');
+            prefix = match tok {
+                Token::SpecialChar(c) => Some(c),
+                Token::Comment(_) => prefix,
+                _ => None,
+            };
+
+            match tok {
+                Token::OpenParen | Token::OpenBracket => {
+                    // `#(...)` is a vector too
+                    let state = if tok == Token::OpenParen && !after_hash { ParserState::List } else { ParserState::Vector };
+                    let close = if tok == Token::OpenParen { Token::CloseParen } else { Token::CloseBracket };
+                    stack.push(Open { state: state, close: close, col: col, head: None, items: 0, first_arg_col: None });
+                },
+                Token::CloseParen | Token::CloseBracket => {
+                    match stack.pop() {
+                        Some(ref open) if open.close == tok => {},
+                        _ => return Err(ParserError::UnexpectedDelimiter(tok, line, src_col)),
+                    }
+                },
+                _ => {},
+            }
+        }
+    }
+
+    if let Some(open) = stack.pop() {
+        return Err(ParserError::UnexpectedEndOfInput(open.state, lexer.line_no, lexer.col_no))
+    }
+
+    // End with exactly one newline
+    while out.ends_with(char::is_whitespace) {
+        let _ = out.pop();
+    }
+    if !out.is_empty() {
+        out.push('\n');
+    }
+    Ok(out)
+}
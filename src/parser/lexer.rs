@@ -1,7 +1,7 @@
 use std::iter::Peekable;
 use std::{fmt, error, convert};
 use std::convert::{Into, From};
-use std::str;
+use std::{f64, str};
 use std::vec;
 
 macro_rules! opt_try {
@@ -26,7 +26,12 @@ pub enum Token {
     Float(f64),
     Ident(String),
     QuotedString(String),
-    SpecialChar(char)
+    SpecialChar(char),
+    /// A keyword, `:name`, without its colon
+    Keyword(String),
+    /// A comment, from its first `;` to the end of the line. Only produced by lexers
+    /// made `with_comments`.
+    Comment(String),
 }
 
 #[derive(Debug)]
@@ -54,6 +59,7 @@ pub struct Lexer<I: Iterator<Item=Result<char,E>>, E> {
     pub line_no: u32, 
     pub col_no: u32,
     special_chars: Vec<char>,
+    keep_comments: bool,
     source: Peekable<I>,
 }
 
@@ -92,6 +98,7 @@ impl<I: Iterator<Item=Result<char, E>>, E> Lexer<I, E> {
         Lexer { source_name: name,
                 line_no: 0, col_no: 0,
                 special_chars: Vec::from(SPECIAL_CHARS),
+                keep_comments: false,
                 source: it.peekable() }
     }
 
@@ -100,6 +107,11 @@ impl<I: Iterator<Item=Result<char, E>>, E> Lexer<I, E> {
         Lexer { special_chars: Vec::from(chars), ..self }
     }
 
+    /// Whether comments are produced as `Token::Comment`s, rather than skipped.
+    pub fn with_comments(self, keep_comments: bool) -> Self {
+        Lexer { keep_comments: keep_comments, ..self }
+    }
+
     pub fn to_vec(self) -> LexResult<Vec<LexedToken>, E> {
         let mut out = Vec::new();
 
@@ -203,6 +215,26 @@ impl<I: Iterator<Item=Result<char, E>>, E> Iterator for Lexer<I, E> {
             };
         }
 
+        // Skip a `#!` interpreter line at the very start of the input, so
+        // that lisp files can be run as scripts
+        if ch == '#' && self.get_location() == (0, 1) {
+            if let Ok(&'!') = self.peek() {
+                let mut next = self.advance();
+                while let Ok(c) = next {
+                    if c == '\n' {
+                        return self.next();
+                    }
+                    next = self.advance();
+                }
+
+                return match next {
+                    Ok(_) => unreachable!(),
+                    Err(LexError::EndOfInput) => None,
+                    Err(e) => Some(Err(e)),
+                };
+            }
+        }
+
         Some(match ch {
             '(' => Ok(self.make_token(Token::OpenParen)),
             ')' => Ok(self.make_token(Token::CloseParen)),
@@ -211,6 +243,21 @@ impl<I: Iterator<Item=Result<char, E>>, E> Iterator for Lexer<I, E> {
             ']' => Ok(self.make_token(Token::CloseBracket)),
 
             // Comment, read till newline
+            ';' if self.keep_comments => {
+                let (line, col) = self.get_location();
+                let mut s = String::from(";");
+                loop {
+                    match self.peek() {
+                        Ok(&'\n') | Err(LexError::EndOfInput) => break,
+                        Ok(&c) => s.push(c),
+                        Err(e) => return Some(Err(e)),
+                    }
+                    if let Err(e) = self.advance() {
+                        return Some(Err(e))
+                    }
+                }
+                Ok(self.make_token_with(Token::Comment(s), line, col))
+            },
             ';' => {
                 let mut next = self.advance();
 
@@ -239,20 +286,21 @@ impl<I: Iterator<Item=Result<char, E>>, E> Iterator for Lexer<I, E> {
                     if c == '"' {
                         break;
                     } else if c == '\\' {
-                        if let Ok(cn) = self.advance() {
-                            match cn {
-                                '\\' => s.push('\\'),
-                                't'  => s.push('\t'),
-                                'n'  => s.push('\n'),
-                                '"'  => s.push('"'),
-                                uc   => {
-                                    let errmsg = format!("Unknown string escape: \\{}", uc);
-                                    return Some(Err(LexError::UnknownEscape(errmsg)))
-                                },
-                            }
-                        } else {
-                            break;
-                        };
+                        match self.advance() {
+                            Ok('\\') => s.push('\\'),
+                            Ok('t')  => s.push('\t'),
+                            Ok('n')  => s.push('\n'),
+                            Ok('"')  => s.push('"'),
+                            Ok(uc)   => {
+                                let errmsg = format!("Unknown string escape: \\{}", uc);
+                                return Some(Err(LexError::UnknownEscape(errmsg)))
+                            },
+                            // The end of input, or a read error
+                            Err(e) => {
+                                next = Err(e);
+                                break;
+                            },
+                        }
                     } else {
                         s.push(c)
                     }
@@ -261,8 +309,7 @@ impl<I: Iterator<Item=Result<char, E>>, E> Iterator for Lexer<I, E> {
                 }
 
                 match next {
-                    Ok('"')  => {},
-                    Ok(_)    => unreachable!("should only break on closing quote"),
+                    Ok(_)    => {},
                     Err(LexError::EndOfInput) => {
                         let errmsg = String::from("EOF reached before string terminator");
                         return Some(Err(LexError::UnexpectedEndOfInput(errmsg)))
@@ -288,19 +335,22 @@ impl<I: Iterator<Item=Result<char, E>>, E> Iterator for Lexer<I, E> {
                     s.insert(0, ch);
                 }
 
+                // Parse with the sign, so that the most negative integer fits
+                let signed = if ch == '-' { format!("-{}", s) } else { s.clone() };
+
                 // Try and parse an integer
-                if let Ok(mut num) = s.parse::<i64>() {
-                    if ch == '-' {
-                        num = -num;
-                    }
+                if let Ok(num) = signed.parse::<i64>() {
                     Ok(self.make_token_with(Token::Number(num), line, col))
                 }
 
+                // Infinities and NaN, as the printer writes them
+                else if (ch == '+' || ch == '-') && (s == "inf" || s == "nan") {
+                    let num = if s == "nan" { f64::NAN } else if ch == '-' { f64::NEG_INFINITY } else { f64::INFINITY };
+                    Ok(self.make_token_with(Token::Float(num), line, col))
+                }
+
                 // Try and parse a floating-point
-                else if let Ok(mut num) = s.parse::<f64>() {
-                    if ch == '-' {
-                        num = -num;
-                    }
+                else if let Ok(num) = signed.parse::<f64>() {
                     Ok(self.make_token_with(Token::Float(num), line, col))
                 }
 
@@ -326,7 +376,11 @@ impl<I: Iterator<Item=Result<char, E>>, E> Iterator for Lexer<I, E> {
                                     wd.insert(0, ch);
                                     wd
                                 }).unwrap_or(s);
-                    Ok(self.make_token_with(Token::Ident(ident), line, col))
+                    if ch == ':' && ident.len() > 1 {
+                        Ok(self.make_token_with(Token::Keyword(String::from(&ident[1..])), line, col))
+                    } else {
+                        Ok(self.make_token_with(Token::Ident(ident), line, col))
+                    }
                 }
             }
         })
@@ -399,6 +453,7 @@ impl<I: Iterator<Item=char>> Iterator for CharIter<I> {
 
 /****************** Helper functions *******************/
 
-fn is_whitespace(c: char) -> bool {
+/// Whether `c` separates tokens. Other unicode whitespace is part of identifiers.
+pub fn is_whitespace(c: char) -> bool {
     c == ' ' || c == '\t' || c == '\n'
 }
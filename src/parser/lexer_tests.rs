@@ -126,3 +126,39 @@ fn test_handle_special_chars() {
         }
     );
 }
+
+#[test]
+fn test_skip_shebang() {
+    tests!(
+        "#!/usr/bin/env rustylisp\n(a)" => {
+            OpenParen, Ident(String::from("a")), CloseParen
+        },
+        "#!/usr/bin/env rustylisp" => { },
+        "(a) #!b" => {
+            OpenParen, Ident(String::from("a")), CloseParen,
+            SpecialChar('#'), Ident(String::from("!b"))
+        }
+    );
+}
+
+#[test]
+fn test_keep_comments() {
+    let input = "(a ; first\n;; second\nb)";
+    let toks: Vec<_> = Lexer::from_string(input, "<test>").with_comments(true)
+                              .map(|tok| { let tok = tok.unwrap(); (tok.tok, tok.line_no, tok.col_no) })
+                              .collect();
+    assert_eq!(toks, vec![(OpenParen, 0, 1), (Ident(String::from("a")), 0, 2),
+                          (Comment(String::from("; first")), 0, 4),
+                          (Comment(String::from(";; second")), 1, 1),
+                          (Ident(String::from("b")), 2, 1), (CloseParen, 2, 2)]);
+}
+
+#[test]
+fn test_keywords() {
+    tests!(
+        "(:a : b:c ::d)" => {
+            OpenParen, Keyword(String::from("a")), Ident(String::from(":")),
+            Ident(String::from("b:c")), Keyword(String::from(":d")), CloseParen
+        }
+    );
+}
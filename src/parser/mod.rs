@@ -1,8 +1,9 @@
 //! The lexing and parsing systems
 pub mod parser;
+pub mod format;
 mod lexer;
 
-pub use self::parser::{Parser, ParserError};
+pub use self::parser::{parse_str_all, Parser, ParserError, Span, Spanned};
 pub use self::lexer::{Lexer, StringIter, LexError};
 
 #[cfg(test)]
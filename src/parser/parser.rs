@@ -1,9 +1,12 @@
 pub use super::lexer::{Token, LexError};
 use super::lexer::{self, Lexer, StringIter};
 use ::core::obj::{LispObj, AsLispObjRef};
+use ::core::procedure::KEYWORD_PREFIX;
 
+use std::cell::Cell;
 use std::convert::{AsRef, Into};
 use std::io::{self, Read};
+use std::rc::Rc;
 use std::fmt;
 use std::fs::File;
 
@@ -13,6 +16,9 @@ pub enum ParserError<E> {
     UnexpectedToken(Token, Option<Token>, u32, u32),
     UnexpectedEndOfInput(ParserState, u32, u32),
     UnexpectedDelimiter(Token, u32, u32),
+    // A `.` which is not between the last two elements of a list,
+    // line no, col no
+    MisplacedDot(u32, u32),
     // If no handler for special character,
     // error the character, its argument
     SpecialCharError(char, LispObj),
@@ -22,6 +28,16 @@ pub enum ParserError<E> {
 
 pub type ParseResult<E> = Result<LispObj, ParserError<E>>;
 
+/// Where a parsed form came from in its source: the positions of its first and last
+/// characters, counted as the lexer does, with lines from 0 and columns from 1.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Span {
+    pub start_line: u32, pub start_col: u32,
+    pub end_line: u32, pub end_col: u32,
+}
+
+pub type SpannedResult<E> = Result<(LispObj, Span), ParserError<E>>;
+
 #[derive(Debug, Copy, Clone)]
 pub enum ParserState {
     Idle, ReaderChar(char), List, Vector
@@ -35,6 +51,30 @@ pub struct Parser<I, E, F=DummyFn>
     stack: Vec<(ParserState, Vec<LispObj>)>,
     stream: Lexer<I,E>,
     char_handler: Option<F>,
+    // The number of lists and vectors on the stack, shared so that
+    // the input stream can see it
+    depth: Rc<Cell<usize>>,
+    // Where the form being parsed started
+    form_start: (u32, u32),
+    // Comments read and not yet taken, with their lines and columns
+    comments: Vec<(String, u32, u32)>,
+}
+
+/// An iterator over a parser's forms along with their spans, made by `Parser::spanned`
+#[must_use]
+pub struct Spanned<I, E, F=DummyFn>
+        where I: Iterator<Item=Result<char,E>> {
+    parser: Parser<I, E, F>,
+}
+
+/// Parses every form in `source`. Never panics, whatever the input, so it can be used
+/// as a fuzzing target.
+///
+/// There are no reader character handlers, which the evaluator provides, so forms using
+/// reader characters such as `'` fail with `NoCharHandler`; `evaluator::eval_str_in`
+/// parses those too.
+pub fn parse_str_all(source: &str) -> Result<Vec<LispObj>, ParserError<()>> {
+    Parser::from_string(source, "<string>").parse_all()
 }
 
 impl<E: fmt::Debug> ParserError<E> {
@@ -46,6 +86,8 @@ impl<E: fmt::Debug> ParserError<E> {
                 => ParserError::UnexpectedEndOfInput(a, b, c),
             ParserError::UnexpectedDelimiter(a, b, c)
                 => ParserError::UnexpectedDelimiter(a, b, c),
+            ParserError::MisplacedDot(a, b)
+                => ParserError::MisplacedDot(a, b),
             ParserError::SpecialCharError(c, obj)
                 => ParserError::SpecialCharError(c, obj),
             ParserError::NoCharHandler(c)
@@ -82,9 +124,11 @@ impl<I: Iterator<Item=char>> Parser<lexer::CharIter<I>, ()> {
 }
 
 impl Parser<io::Chars<File>, io::CharsError> {
+    /// Parses a file, named by its canonical path.
     pub fn from_file<P: AsRef<::std::path::Path>>(path: P) -> Result<Self, io::Error> {
-        File::open(path.as_ref())
-              .map(|file| Self::new(file.chars(), format!("{:?}", path.as_ref())))
+        let file = try!(File::open(path.as_ref()));
+        let name = try!(path.as_ref().canonicalize());
+        Ok(Self::new(file.chars(), name.to_string_lossy().into_owned()))
     }
 }
 
@@ -102,39 +146,73 @@ impl<I, E, F> Parser<I, E, F>
         }
     }
 
-    fn push_obj(&mut self, obj: LispObj) -> Option<Result<LispObj, ParserError<E>>> {
-        let c = match self.stack.last_mut() {
-            Some(&mut (ParserState::List, ref mut stack)) |
-            Some(&mut (ParserState::Vector, ref mut stack)) => {
-                stack.push(obj);
-                return None
-            },
-            Some(&mut (ParserState::ReaderChar(c), ref mut stack)) => {
-                assert!(stack.len() == 0);
-                c
-            },
-            Some(&mut ref top) => panic!("Parser::push_obj on state {:?}", top),
-            None => return Some(Ok(obj))
-        };
+    fn push_obj(&mut self, mut obj: LispObj) -> Option<Result<LispObj, ParserError<E>>> {
+        // Reader characters apply to the object in turn, innermost first, without
+        // recursing, so that a long run of them can't overflow the stack
+        loop {
+            let c = match self.stack.last_mut() {
+                Some(&mut (ParserState::ReaderChar(c), _)) => c,
+                Some(&mut (_, ref mut stack)) => {
+                    stack.push(obj);
+                    return None
+                },
+                None => return Some(Ok(obj))
+            };
 
-        match self.try_apply_reader(c, obj.clone()) {
-            Ok(res)  => {
-                match self.pop().expect("at least have one character") {
-                    (ParserState::ReaderChar(_), _) => {},
-                    _ => unreachable!()
-                };
-                self.push_obj(res)
-            },
-            Err(err) => {
-                self.stack.clear();
-                Some(Err(err))
-            },
+            match self.try_apply_reader(c, obj) {
+                Ok(res) => {
+                    let _ = self.pop();
+                    obj = res;
+                },
+                Err(err) => {
+                    self.clear_stack();
+                    return Some(Err(err))
+                },
+            }
+        }
+    }
+
+    /// Checks that a `.` can go here, in a list after at least one element and any
+    /// other dot. Reader characters may also take one, as in the char `\.`.
+    fn check_dot(&self, line_no: u32, col_no: u32) -> Result<(), ParserError<E>> {
+        match self.stack.last() {
+            Some(&(ParserState::List, ref items)) if !items.is_empty() && !items.iter().any(is_dot) => Ok(()),
+            Some(&(ParserState::ReaderChar(_), _)) => Ok(()),
+            _ => Err(ParserError::MisplacedDot(line_no, col_no)),
+        }
+    }
+
+    /// Whether the list being read has its element after a dot, which must be its last.
+    fn has_dotted_tail(&self) -> bool {
+        match self.stack.last() {
+            Some(&(ParserState::List, ref items)) => items.len() >= 2 && is_dot(&items[items.len() - 2]),
+            _ => false,
         }
     }
 
     pub fn parse_all(self) -> Result<Vec<LispObj>, ParserError<E>> {
         self.collect()
     }
+
+    /// Parses the next form, along with where in the source it came from.
+    pub fn next_spanned(&mut self) -> Option<SpannedResult<E>> {
+        let obj = match self.next() {
+            Some(Ok(obj)) => obj,
+            Some(Err(err)) => return Some(Err(err)),
+            None => return None,
+        };
+        let (start_line, start_col) = self.form_start;
+        let span = Span {
+            start_line: start_line, start_col: start_col,
+            end_line: self.stream.line_no, end_col: self.stream.col_no,
+        };
+        Some(Ok((obj, span)))
+    }
+
+    /// Iterates over the remaining forms along with their spans.
+    pub fn spanned(self) -> Spanned<I, E, F> {
+        Spanned { parser: self }
+    }
 }
 
 impl<I, E> Parser<I, E>
@@ -143,6 +221,9 @@ impl<I, E> Parser<I, E>
         Parser { stack: Vec::new(),
                  stream: Lexer::new(source, source_name.into()),
                  char_handler: None,
+                 depth: Rc::new(Cell::new(0)),
+                 form_start: (0, 0),
+                 comments: Vec::new(),
         }
     }
 }
@@ -150,7 +231,46 @@ impl<I, E> Parser<I, E>
 impl<I, E, F> Parser<I, E, F> 
         where I: Iterator<Item=Result<char,E>> {
     pub fn with_char_handler<FNew>(self, f: FNew) -> Parser<I, E, FNew> {
-        Parser { char_handler: Some(f), stream: self.stream, stack: self.stack }
+        Parser { char_handler: Some(f), stream: self.stream, stack: self.stack,
+                 depth: self.depth, form_start: self.form_start, comments: self.comments }
+    }
+
+    /// Removes the parser's char handler, keeping what it has read so far. A parser
+    /// which outlives its handler, such as a reader kept between calls to `read`, is
+    /// given a new one each time it is used and has it removed afterwards.
+    pub fn without_char_handler(self) -> Parser<I, E> {
+        Parser { char_handler: None, stream: self.stream, stack: self.stack,
+                 depth: self.depth, form_start: self.form_start, comments: self.comments }
+    }
+
+    /// Keeps `cell` up to date with the parser's depth, so that the code
+    /// supplying its input can see it, e.g. to show it in a prompt.
+    pub fn with_depth_cell(self, cell: Rc<Cell<usize>>) -> Self {
+        cell.set(self.depth());
+        Parser { depth: cell, ..self }
+    }
+
+    /// Keeps the comments in the source, to be read with `take_comments`, rather than
+    /// skipping them.
+    pub fn with_comments(self) -> Self {
+        Parser { stream: self.stream.with_comments(true), ..self }
+    }
+
+    /// The comments read since the last call, with the line and column each starts
+    /// at. After `next_spanned`, these are the comments before and within the form
+    /// it returned. Always empty unless the parser was made `with_comments`.
+    pub fn take_comments(&mut self) -> Vec<(String, u32, u32)> {
+        ::std::mem::replace(&mut self.comments, Vec::new())
+    }
+
+    /// The number of lists and vectors which have been opened and not yet closed.
+    pub fn depth(&self) -> usize {
+        self.depth.get()
+    }
+
+    fn clear_stack(&mut self) {
+        self.stack.clear();
+        self.depth.set(0);
     }
 
     pub fn source_name(&self) -> &str {
@@ -162,6 +282,9 @@ impl<I, E, F> Parser<I, E, F>
     }
 
     fn push_state(&mut self, st: ParserState) {
+        if opens_list(st) {
+            self.depth.set(self.depth.get() + 1);
+        }
         self.stack.push((st, Vec::new()));
     }
 
@@ -170,7 +293,11 @@ impl<I, E, F> Parser<I, E, F>
     }
 
     fn pop(&mut self) -> Option<(ParserState, Vec<LispObj>)> {
-        self.stack.pop()
+        let top = self.stack.pop();
+        if top.as_ref().map_or(false, |&(st, _)| opens_list(st)) {
+            self.depth.set(self.depth.get() - 1);
+        }
+        top
     }
 }
 
@@ -197,15 +324,35 @@ impl<I, E, F> Iterator for Parser<I, E, F>
                 }
             };
 
+            if self.stack_empty() {
+                self.form_start = (tok.line_no, tok.col_no);
+            }
+
+            // Only one element may follow a dot, so report where a second one starts
+            let starts_element = match tok.tok {
+                Token::CloseParen | Token::CloseBracket | Token::Comment(_) => false,
+                _ => true,
+            };
+            if starts_element && self.has_dotted_tail() {
+                self.clear_stack();
+                return Some(Err(ParserError::MisplacedDot(tok.line_no, tok.col_no)));
+            }
+
             match tok.tok {
                 Token::OpenParen => self.push_state(ParserState::List),
 
                 Token::CloseParen => {
                     let list = match self.pop() {
+                        // A dot must be followed by an element
+                        Some((ParserState::List, ref vec)) if vec.last().map_or(false, is_dot) => {
+                            self.clear_stack();
+                            return Some(Err(ParserError::MisplacedDot(tok.line_no, tok.col_no)));
+                        },
                         Some((ParserState::List, vec)) => {
                             form_lisp_list(vec)
                         },
                         _ => {
+                            self.clear_stack();
                             let err = ParserError::UnexpectedDelimiter(Token::CloseParen,
                                                                         tok.line_no, tok.col_no);
                             return Some(Err(err));
@@ -226,6 +373,7 @@ impl<I, E, F> Iterator for Parser<I, E, F>
                             LispObj::make_vector(vec.into_iter())
                         },
                         _ => {
+                            self.clear_stack();
                             let err = ParserError::UnexpectedDelimiter(Token::CloseBracket,
                                                                         tok.line_no, tok.col_no);
                             return Some(Err(err));
@@ -253,12 +401,26 @@ impl<I, E, F> Iterator for Parser<I, E, F>
                 },
 
                 Token::Ident(name) => {
+                    if name == "." {
+                        if let Err(err) = self.check_dot(tok.line_no, tok.col_no) {
+                            self.clear_stack();
+                            return Some(Err(err));
+                        }
+                    }
                     match self.push_obj(symbol!(name)) {
                         Some(obj) => return Some(obj),
                         None => {}
                     }
                 },
 
+                // Keywords are symbols, so :name is the same as #:name
+                Token::Keyword(name) => {
+                    match self.push_obj(symbol!(format!("{}{}", KEYWORD_PREFIX, name))) {
+                        Some(obj) => return Some(obj),
+                        None => {}
+                    }
+                },
+
                 Token::QuotedString(string) => {
                     match self.push_obj(string!(string)) {
                         Some(obj) => return Some(obj),
@@ -267,11 +429,34 @@ impl<I, E, F> Iterator for Parser<I, E, F>
                 },
 
                 Token::SpecialChar(c) => self.push_state(ParserState::ReaderChar(c)),
+
+                Token::Comment(text) => self.comments.push((text, tok.line_no, tok.col_no)),
             };
         }
     }
 }
 
+impl<I, E, F> Iterator for Spanned<I, E, F>
+            where I: Iterator<Item=Result<char,E>>,
+                  F: Fn(char, LispObj) -> Result<LispObj, Option<LispObj>> {
+    type Item = SpannedResult<E>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.parser.next_spanned()
+    }
+}
+
+fn opens_list(st: ParserState) -> bool {
+    match st {
+        ParserState::List | ParserState::Vector => true,
+        ParserState::Idle | ParserState::ReaderChar(_) => false,
+    }
+}
+
+fn is_dot(obj: &LispObj) -> bool {
+    obj.symbol_equal(".")
+}
+
 // Make sure to parse:
 // (1 . 2)
 // as the result of: 
@@ -288,7 +473,8 @@ fn form_lisp_list(mut vec: Vec<LispObj>) -> LispObj {
 
         let (a, b): (LispObj, LispObj);
         b = end.pop().unwrap();
-        debug_assert!(end.pop().unwrap().eq(&symbol!(".")));
+        let dot = end.pop().unwrap();
+        debug_assert!(is_dot(&dot));
         a = end.pop().unwrap();
 
         let mut out = cons!(a, b); //LispObj::cons(a, b);
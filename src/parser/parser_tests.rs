@@ -73,3 +73,130 @@ fn test_parser_vec() {
         }
     };
 }
+
+#[test]
+fn test_parser_depth() {
+    let mut parser = Parser::from_string("(1 2) ([3 '(4", "<test>");
+    assert_eq!(parser.depth(), 0);
+    assert!(parser.next().unwrap().is_ok());
+    assert_eq!(parser.depth(), 0);
+    // End of input inside the unfinished form leaves it open
+    assert!(parser.next().unwrap().is_err());
+    assert_eq!(parser.depth(), 3);
+
+    let cell = ::std::rc::Rc::new(::std::cell::Cell::new(7));
+    let mut parser = Parser::from_string("[(", "<test>").with_depth_cell(cell.clone());
+    assert_eq!(cell.get(), 0);
+    assert!(parser.next().unwrap().is_err());
+    assert_eq!(cell.get(), 2);
+}
+
+#[test]
+fn test_parser_spans() {
+    let input = "(a b)\n  cd \"str\"\n[1\n 22]";
+    let spanned: Vec<_> = Parser::from_string(input, "<test>").spanned()
+                                 .map(|res| res.unwrap().1)
+                                 .map(|s| (s.start_line, s.start_col, s.end_line, s.end_col))
+                                 .collect();
+    assert_eq!(spanned, vec![(0, 1, 0, 5), (1, 3, 1, 4), (1, 6, 1, 10), (2, 1, 3, 4)]);
+
+    let mut parser = Parser::from_string("x (", "<test>");
+    assert_eq!(parser.next_spanned().unwrap().unwrap().0, symbol!("x"));
+    assert!(parser.next_spanned().unwrap().is_err());
+}
+
+#[test]
+fn test_format() {
+    use super::format::format_source;
+    let input = "(define (f x)   \n;; comment\n      (let ((y 1)\n(z 2))\n (g x\ny) ; why\n[1\n2]))\n\n\n";
+    let expected = "(define (f x)\n  ;; comment\n  (let ((y 1)\n        (z 2))\n    (g x\n       y) ; why\n    [1\n     2]))\n";
+    assert_eq!(format_source(input).unwrap(), expected);
+
+    // Lines in a multi-line string are untouched
+    let input = "(print \"a  \n   b\"\n'c)";
+    assert_eq!(format_source(input).unwrap(), "(print \"a  \n   b\"\n       'c)\n");
+
+    // `#(...)` is a vector, so isn't indented as a call
+    assert_eq!(format_source("(f #(a b\nc))").unwrap(), "(f #(a b\n     c))\n");
+
+    assert!(format_source("(a))").is_err());
+    assert!(format_source("(a]").is_err());
+    assert!(format_source("[(a)").is_err());
+}
+
+#[test]
+fn test_parser_comments() {
+    let input = ";; Adds one\n(define (inc x) ; TODO floats\n  (+ x 1))\n(f) ; trailing";
+    let mut parser = Parser::from_string(input, "<test>").with_comments();
+    let (_, span) = parser.next_spanned().unwrap().unwrap();
+    assert_eq!((span.start_line, span.end_line), (1, 2));
+    assert_eq!(parser.take_comments(), vec![(String::from(";; Adds one"), 0, 1),
+                                            (String::from("; TODO floats"), 1, 17)]);
+    assert!(parser.take_comments().is_empty());
+
+    assert_eq!(parser.next().unwrap().unwrap(), lisp_list![symbol!("f")]);
+    assert!(parser.next().is_none());
+    assert_eq!(parser.take_comments(), vec![(String::from("; trailing"), 3, 5)]);
+
+    // Comments are skipped by default
+    let mut parser = Parser::from_string(input, "<test>");
+    assert_eq!(parser.by_ref().count(), 2);
+    assert!(parser.take_comments().is_empty());
+}
+
+#[test]
+fn test_parser_dots() {
+    use super::parser::ParserError;
+    tests! {
+        "(1 2 . 3)" => {
+            cons!(int!(1), cons!(int!(2), int!(3)))
+        }
+    };
+
+    // The dot is taken out of the list whether or not debug assertions are on
+    let pair = Parser::from_string("(a b . c)", "<test>").next().unwrap().unwrap();
+    assert_eq!(pair.list_length(), None);
+    assert!(pair.list_iter().all(|item| !item.map_or(false, |item| item.symbol_equal("."))));
+
+    for input in ["(1 . 2 3)", "(. 2)", "(1 .)", "(1 . 2 . 3)", "[1 . 2]", "."].iter() {
+        match Parser::from_string(*input, "<test>").next() {
+            Some(Err(ParserError::MisplacedDot(..))) => {},
+            other => panic!("expected a misplaced dot in {}, got {:?}", input, other),
+        }
+    }
+
+    // The position is that of the extra element after the dot
+    for &(input, pos) in [("(a\n  . b c)", (1, 7)), ("(1 . 2 3)", (0, 8)), ("(1 . 2 (3))", (0, 8)),
+                          ("(1 . [2] y)", (0, 10)), ("(1 . 2 . 3)", (0, 8)), ("(1 .)", (0, 5))].iter() {
+        match Parser::from_string(input, "<test>").next() {
+            Some(Err(ParserError::MisplacedDot(line, col))) => assert_eq!((line, col), pos, "in {}", input),
+            other => panic!("expected a misplaced dot in {}, got {:?}", input, other),
+        }
+    }
+}
+
+#[test]
+fn test_parse_malformed_input() {
+    use super::format::format_source;
+    use super::parse_str_all;
+
+    assert_eq!(parse_str_all("(a b) 1").unwrap(), vec![lisp_list![symbol!("a"), symbol!("b")], int!(1)]);
+
+    // Malformed input is an error, never a panic
+    for input in ["\"abc\\", "\"\\", "(a ]", "[a )", ")", "'", "(1 . )", "\u{a0}(x\n\u{a0}y)", "#!"].iter() {
+        let _ = parse_str_all(input);
+        let _ = format_source(input);
+    }
+    assert!(parse_str_all("\"abc\\").is_err());
+
+    // A mismatched delimiter abandons the forms it was in
+    let mut parser = Parser::from_string("((a ] b", "<test>");
+    assert!(parser.next().unwrap().is_err());
+    assert_eq!(parser.depth(), 0);
+    assert_eq!(parser.next().unwrap().unwrap(), symbol!("b"));
+
+    // Long runs of reader characters don't use up the stack
+    let input = format!("{}x", "'".repeat(100000));
+    let mut parser = Parser::from_string(input, "<test>").with_char_handler(|_, obj| Ok(obj));
+    assert_eq!(parser.next().unwrap().unwrap(), symbol!("x"));
+}
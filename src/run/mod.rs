@@ -1,76 +1,335 @@
 //! The ease-of-use run system
 #[cfg(test)]
 mod test;
+pub mod testing;
 
+use std::cell::{Cell, RefCell};
 use std::convert::AsRef;
 use std::fmt;
-use std::io::{self, Read};
+use std::io::{self, Read, Write};
+use std::rc::Rc;
+use std::time::Duration;
 
-use super::core::{LispObj, AsLispObjRef, /* Environment, */ EnvironmentRef, EvalResult};
-use super::parser::{self, /* Lexer, */ Parser};
+use super::core::{LispObj, AsLispObjRef, /* Environment, */ EnvironmentRef, EvalResult, RuntimeError,
+                  NativeFuncSignature};
+use super::parser::{/* Lexer, */ Parser};
 use super::evaluator;
+use super::evaluator::limits::{self, Limits};
+use super::evaluator::flags::{self, Flags};
+use super::evaluator::input::{self, Reader, ReaderRef};
+use super::evaluator::output::{self, Stream};
+
+pub const COMMAND_LINE_ARGS_NAME: &'static str = "*command-line-args*";
+
+/// The evaluation steps a sandboxed evaluator allows each evaluation
+pub const SANDBOX_STEP_LIMIT: u64 = 10_000_000;
+
+/// The seconds a sandboxed evaluator allows each evaluation
+pub const SANDBOX_TIME_LIMIT_SECS: u64 = 10;
 
 pub struct Evaluator {
     top_level: EnvironmentRef,
+    limits: Limits,
+    stdout: Option<Stream>,
+    stderr: Option<Stream>,
+    stdin: Option<ReaderRef>,
 }
 
 impl Evaluator {
     pub fn new() -> Self {
         Evaluator {
-            top_level: evaluator::default_environment().to_env_ref()
+            top_level: evaluator::default_environment().to_env_ref(),
+            limits: Limits::default(),
+            stdout: None,
+            stderr: None,
+            stdin: None,
         }
     }
 
     pub fn from_existing(env: EnvironmentRef) -> Self {
         Evaluator {
-            top_level: env
+            top_level: env,
+            limits: Limits::default(),
+            stdout: None,
+            stderr: None,
+            stdin: None,
         }
     }
 
-    fn handle_char(&self, c: char, obj: LispObj) -> Result<LispObj, Option<LispObj>> {
-        let handler = match self.top_level.borrow().get_char_handler(c) {
-            Some(handler) => handler,
-            None => return Err(None),
-        };
+    /// Limits each evaluation to `steps` evaluation steps, after which it throws a
+    /// 'resource-error. Replaces any step limit already set.
+    ///
+    /// Each form typed at the REPL counts as one evaluation, as does each call to
+    /// `eval_all_from_parser` or `load_from_file`.
+    pub fn with_step_limit(mut self, steps: u64) -> Self {
+        self.limits.steps = Some(steps);
+        self
+    }
+
+    /// Sends what lisp code prints, and the REPL's results, to `out` instead of stdout.
+    pub fn with_stdout<W: Write + Send + 'static>(mut self, out: W) -> Self {
+        self.stdout = Some(output::stream(out));
+        self
+    }
+
+    /// Sends tracebacks, logs and parse errors to `out` instead of stderr.
+    pub fn with_stderr<W: Write + Send + 'static>(mut self, out: W) -> Self {
+        self.stderr = Some(output::stream(out));
+        self
+    }
+
+    /// Reads input for `read`, and the REPL's forms, from `input` instead of stdin.
+    pub fn with_stdin<R: Read + 'static>(mut self, input: R) -> Self {
+        self.stdin = Some(Rc::new(RefCell::new(Reader::new(input::read_chars(input), "<stdin>"))));
+        self
+    }
+
+    /// Runs `f` with this evaluator's streams, limits and flags.
+    fn run<T, F: FnOnce() -> T>(&self, f: F) -> T {
+        let limits = self.limits;
+        let flags = Flags::from_env(&self.top_level);
+        input::with_input(self.stdin.clone(), || {
+            output::with_output(self.stdout.clone(), self.stderr.clone(),
+                                || limits::with_limits(limits, || flags::with_flags(flags, f)))
+        })
+    }
+
+    /// An evaluator for untrusted code. It has no builtins which use the file system,
+    /// processes, the network or threads, and each evaluation is limited to
+    /// `SANDBOX_STEP_LIMIT` steps and `SANDBOX_TIME_LIMIT_SECS` seconds, after which it
+    /// throws a 'resource-error, as with `with_step_limit`.
+    pub fn new_sandboxed() -> Self {
+        Evaluator {
+            top_level: evaluator::sandboxed_environment().to_env_ref(),
+            limits: Limits {
+                steps: Some(SANDBOX_STEP_LIMIT),
+                time: Some(Duration::from_secs(SANDBOX_TIME_LIMIT_SECS)),
+            },
+            stdout: None,
+            stderr: None,
+            stdin: None,
+        }
+    }
+
+    /// Binds `*command-line-args*` to a lisp list of the given arguments, as strings.
+    pub fn set_command_line_args<I, S>(&mut self, args: I)
+            where I: IntoIterator<Item=S>, S: Into<String> {
+        let arglist: LispObj = args.into_iter().map(|arg| string!(arg)).collect();
+        let _ = self.top_level.borrow_mut()
+                    .let_new(String::from(COMMAND_LINE_ARGS_NAME), arglist.to_obj_ref());
+    }
 
-        evaluator::apply(handler, lisp_list!(obj), self.top_level.clone())
-                   .map(|obj| (*obj).clone())
-                   .map_err(|err| Some(err.into_lisp_obj()))
+    /// Installs a module of native functions, each given as its name, the function and
+    /// an optional docstring. They are bound as `prefix/name`, or just `name` if the
+    /// prefix is empty, replacing any existing bindings.
+    ///
+    /// # Example
+    /// ```
+    /// # #[macro_use] extern crate rustylisp;
+    /// use rustylisp::core::{AsLispObjRef, EnvironmentRef, EvalResult, LispObjRef};
+    /// use rustylisp::parser::Parser;
+    /// use rustylisp::run::Evaluator;
+    ///
+    /// fn greet(_: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+    ///     Ok(string!("hello").to_obj_ref())
+    /// }
+    ///
+    /// # fn main() {
+    /// let mut runner = Evaluator::new();
+    /// runner.register_module("demo", &[("greet", greet, Some("Says hello."))]);
+    /// runner.register_value("demo/version", int!(2));
+    /// let res = runner.eval_all_from_parser(Parser::from_string("(demo/greet)", "<example>")).unwrap();
+    /// assert_eq!(*res, string!("hello"));
+    /// # }
+    /// ```
+    pub fn register_module(&mut self, prefix: &str, funcs: &[(&str, NativeFuncSignature, Option<&str>)]) {
+        for &(name, func, doc) in funcs {
+            let name = if prefix.is_empty() {
+                String::from(name)
+            } else {
+                format!("{}/{}", prefix, name)
+            };
+            let native = LispObj::make_native(name.clone(), func, doc.map(String::from));
+            self.register_value(name, native);
+        }
     }
 
-    pub fn repl(&mut self) {
-        let instream = parser::Parser::new(io::stdin().chars(), "<stdin>");
+    /// Binds `name` to `value` in the top-level environment, replacing any existing
+    /// binding.
+    pub fn register_value<S: Into<String>, O: AsLispObjRef>(&mut self, name: S, value: O) {
+        let _ = self.top_level.borrow_mut().let_new(name.into(), value.to_obj_ref());
+    }
 
-        for obj in instream.with_char_handler(|c, obj| self.handle_char(c, obj)) {
+    /// Whether `*color-output*` is true and output goes to a terminal.
+    fn color_output(&self) -> bool {
+        let color = self.top_level.borrow().lookup(output::COLOR_OUTPUT_NAME);
+        output::stdout_is_terminal() && color.map_or(false, |color| !evaluator::is_false(&color))
+    }
+
+    /// Reads and evaluates forms from stdin until end of input.
+    ///
+    /// When stdin is a terminal, each line is prompted for, and the prompt shows
+    /// how many lists are still open. Results are coloured by type if `*color-output*`
+    /// is true and they are printed to a terminal, and procedures are shown with their
+    /// argument lists if `*print-arity*` is true.
+    ///
+    /// `read` reads from the same stream as the REPL, so a form which reads gets the
+    /// input typed after it. This evaluator keeps reading from that stream afterwards.
+    ///
+    /// Returns the requested status code if the session was ended by `(exit)`.
+    pub fn repl(&mut self) -> Option<i32> {
+        // Ctrl-C aborts the current evaluation and returns to the prompt
+        evaluator::interrupt::install_sigint_handler();
+
+        let reader = match self.stdin {
+            Some(ref reader) => reader.clone(),
+            None => {
+                let depth = Rc::new(Cell::new(0));
+                let chars = Prompter {
+                    chars: input::read_chars(io::stdin()),
+                    depth: depth.clone(),
+                    top_level: self.top_level.clone(),
+                    stdout: self.stdout.clone(),
+                    stderr: self.stderr.clone(),
+                    show: output::stdin_is_terminal(),
+                    line_start: true,
+                };
+                Rc::new(RefCell::new(Reader::new(chars, "<stdin>").with_depth_cell(depth)))
+            },
+        };
+        self.stdin = Some(reader.clone());
+
+        loop {
+            let obj = match reader.borrow_mut().read(&self.top_level) {
+                Some(obj) => obj,
+                None => break,
+            };
             match obj {
                 Ok(obj) => {
-                    match evaluator::eval(obj, self.top_level.clone()) {
-                        Ok(res)  => println!("{}", res),
-                        Err(err) => err.dump_traceback(),
+                    // Forget any Ctrl-C typed at the prompt
+                    evaluator::interrupt::clear();
+                    let top_level = self.top_level.clone();
+                    let exit_code = self.run(|| match evaluator::eval(obj, top_level) {
+                        Ok(res)  => {
+                            let res = output::show_value(&res, self.color_output(), &self.top_level);
+                            let _ = output::print(format_args!("{}\n", res));
+                            None
+                        },
+                        Err(err) => err.exit_code().or_else(|| {
+                            let _ = output::write_error(&err, &self.top_level);
+                            None
+                        }),
+                    });
+                    if exit_code.is_some() {
+                        return exit_code
                     }
                 },
                 Err(err) => {
-                    println!("Parse error: {:?}", err);
+                    let _ = self.run(|| output::eprint(format_args!("Parse error: {:?}\n", err)));
                 },
             }
         }
+
+        None
     }
 
-    // TODO mimic load-file and change directories
+    /// Evaluates every form in a file, throwing an 'io-error if it cannot be opened.
+    ///
+    /// As with `load-file`, the file's directory is the current directory while it is
+    /// evaluated, the names it defines are recorded for `reload`, and `*allow-redefine*`
+    /// is restored afterwards. Errors name the file by its absolute path.
     pub fn load_from_file<P: AsRef<::std::path::Path>>(&mut self, path: P) -> EvalResult {
-        let file_parser = Parser::from_file(path).unwrap();
-        self.eval_all_from_parser(file_parser)
+        let path = path.as_ref();
+        let file_parser = match Parser::from_file(path) {
+            Ok(file) => file,
+            Err(errmsg) => {
+                let abspath = try!(::std::env::current_dir()).join(path);
+                io_error!("cannot open file {}: {}", abspath.display(), errmsg)
+            },
+        };
+
+        let canon = try!(path.canonicalize());
+        let _ = try!(evaluator::push_directory(canon.parent().expect("all files should have parent dir"),
+                                               self.top_level.clone()));
+        let top_level = self.top_level.clone();
+        let res = evaluator::with_file_loading(&top_level, &canon, false, || self.eval_all_from_parser(file_parser));
+        let _ = try!(evaluator::pop_directory(self.top_level.clone()));
+        res
+    }
+
+    /// Calls the procedures registered with `at-exit`, most recent first, and forgets
+    /// them. Errors they throw are written to the error stream, and the rest are still
+    /// called. This is done when the evaluator is dropped, so a host which exits the
+    /// process without dropping it, e.g. after `(exit)`, should call this first.
+    pub fn run_exit_hooks(&mut self) {
+        let top_level = self.top_level.clone();
+        loop {
+            // Hooks may register more hooks, which are run in turn
+            let hooks = match top_level.borrow_mut().swap_values(evaluator::EXIT_HOOKS_NAME, nil!().to_obj_ref()) {
+                Some(ref hooks) if !hooks.is_nil() => hooks.clone(),
+                _ => return,
+            };
+
+            for hook in hooks.list_iter().filter_map(Result::ok) {
+                if let Err(err) = self.run(|| evaluator::apply(hook, nil!(), top_level.clone())) {
+                    if err.exit_code().is_none() {
+                        self.write_error(&err);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Writes `err` to this evaluator's error stream, rendered by `*error-formatter*` if
+    /// it is set, as the REPL shows errors.
+    pub fn write_error(&self, err: &RuntimeError) {
+        let _ = self.run(|| output::write_error(err, &self.top_level));
+    }
+
+    /// Completes the symbol at the end of `line`, for a line editor. Returns where the
+    /// symbol starts, as a byte offset, and the names it could complete to.
+    ///
+    /// See `evaluator::complete` for how lisp code can change the completions.
+    pub fn complete(&self, line: &str) -> EvalResult<(usize, Vec<String>)> {
+        let start = evaluator::complete::symbol_start(line);
+        let top_level = self.top_level.clone();
+        let names = try!(self.run(|| evaluator::complete::completions(&line[start..], &top_level)));
+        Ok((start, names))
+    }
+
+    /// Saves the state of the top-level environment to an image file.
+    ///
+    /// See `evaluator::image` for what is preserved.
+    pub fn save_state<P: AsRef<::std::path::Path>>(&self, path: P) -> EvalResult<()> {
+        evaluator::image::save_image(self.top_level.clone(), path)
+    }
+
+    /// Restores the top-level environment from an image file written by `save_state`.
+    pub fn load_state<P: AsRef<::std::path::Path>>(&mut self, path: P) -> EvalResult<()> {
+        evaluator::image::load_image(self.top_level.clone(), path)
     }
 
     pub fn eval_all_from_parser<I, E: fmt::Debug, _F>(&mut self, stream: Parser<I, E, _F>) -> EvalResult
             where I: Iterator<Item=Result<char, E>> {
+        let (limits, stdout, stderr, stdin) = (self.limits, self.stdout.clone(), self.stderr.clone(), self.stdin.clone());
+        let flags = Flags::from_env(&self.top_level);
+        input::with_input(stdin, || {
+            output::with_output(stdout, stderr, || limits::with_limits(limits, || {
+                flags::with_flags(flags, || self.eval_all_unlimited(stream))
+            }))
+        })
+    }
+
+    fn eval_all_unlimited<I, E: fmt::Debug, _F>(&mut self, stream: Parser<I, E, _F>) -> EvalResult
+            where I: Iterator<Item=Result<char, E>> {
         let mut out = nil!().to_obj_ref();
         let source_name = String::from(stream.source_name());
-        for item in stream.with_char_handler(|c, obj| self.handle_char(c, obj)) {
+        let top_level = self.top_level.clone();
+        for item in stream.with_char_handler(|c, obj| input::apply_char_handler(c, obj, &top_level)) {
             out = match item {
                 Ok(obj)     => try!(evaluator::eval(obj, self.top_level.clone())),
                 Err(err)    => {
-                    println!("error on input: {}", source_name);
+                    let _ = output::eprint(format_args!("error on input: {}\n", source_name));
                     read_error!("{:?}", err)
                 }
             };
@@ -79,8 +338,42 @@ impl Evaluator {
     }
 }
 
+/// Shows a prompt before reading each line of input, if `show` is set. Within an
+/// unfinished form, the prompt has the number of lists still open; otherwise it is
+/// given by `*prompt*`.
+struct Prompter<I> {
+    chars: I,
+    depth: Rc<Cell<usize>>,
+    top_level: EnvironmentRef,
+    stdout: Option<Stream>,
+    stderr: Option<Stream>,
+    show: bool,
+    line_start: bool,
+}
+
+impl<I: Iterator<Item=Result<char, E>>, E> Iterator for Prompter<I> {
+    type Item = Result<char, E>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.show && self.line_start {
+            let (depth, top_level) = (self.depth.get(), &self.top_level);
+            let _ = output::with_output(self.stdout.clone(), self.stderr.clone(), || {
+                output::print(format_args!("{}", output::prompt(depth, top_level)))
+            });
+        }
+
+        let next = self.chars.next();
+        self.line_start = match next {
+            Some(Ok('\n')) => true,
+            _ => false,
+        };
+        next
+    }
+}
+
 impl Drop for Evaluator {
     fn drop(&mut self) {
+        self.run_exit_hooks();
         self.top_level.borrow_mut().clear_bindings()
     }
 }
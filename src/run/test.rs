@@ -1,22 +1,22 @@
 use ::core::{LispObj, AsLispObjRef, RuntimeError, EvalResult};
+#[cfg(feature = "sync")]
+use ::core::{LispObjRef, EnvironmentRef};
 use ::evaluator::err_msgs;
+use ::evaluator::limits::{self, Limits};
 use ::parser::Parser;
+use super::testing::eval_in;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
 
 fn run_test(contents: &str, expected: EvalResult<LispObj>) {
-    let mut runner = super::Evaluator::new();
-    let parser = Parser::from_string(contents, "<test>");
-    let res = runner.eval_all_from_parser(parser);
-
-    match &res {
-        &Ok(ref result) => println!("{} => {}", contents, result),
-        &Err(ref err) => println!("{} => Err({:?})", contents, err)
-    };
-
-    match (expected, res) {
-        (Ok(exp), Ok(obj))  => assert_eq!(obj, exp.to_obj_ref()),
+    match expected {
+        Ok(exp) => super::testing::assert_eval_eq(contents, exp),
         // If error, just check they are the same error type
-        (Err(exp), Err(obj)) => assert_eq!(obj.errname, exp.errname),
-        (_, _) => assert!(false),
+        Err(exp) => super::testing::assert_eval_err(contents, &exp.errname),
     }
 }
 
@@ -26,6 +26,27 @@ macro_rules! tests {
     }
 }
 
+/// A path in the temporary directory for a test's file, unique to this process and call.
+fn temp_path(name: &str) -> PathBuf {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let n = COUNTER.fetch_add(1, Ordering::SeqCst);
+    ::std::env::temp_dir().join(format!("rustylisp-test-{}-{}-{}", process::id(), n, name))
+}
+
+/// Evaluates `setup`, saves the state in an image, and evaluates `check` in a new
+/// evaluator restored from it. The image is removed afterwards.
+fn image_round_trip(setup: &str, check: &str) -> EvalResult {
+    let path = temp_path("image.json");
+    let mut runner = super::Evaluator::new();
+    let res = eval_in(&mut runner, setup).and_then(|_| runner.save_state(&path)).and_then(|_| {
+        let mut runner = super::Evaluator::new();
+        try!(runner.load_state(&path));
+        eval_in(&mut runner, check)
+    });
+    let _ = ::std::fs::remove_file(&path);
+    res
+}
+
 #[test]
 fn test_simple_functions() {
     tests! {
@@ -82,3 +103,2271 @@ fn test_named_let() {
                        int!(3), int!(5)])
     }
 }
+
+#[test]
+#[cfg(feature = "sync")]
+fn test_sync_types_are_send() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<LispObj>();
+    assert_send_sync::<LispObjRef>();
+    assert_send_sync::<EnvironmentRef>();
+}
+
+#[test]
+fn test_channels() {
+    tests! {
+        "(define ch (channel)) (send ch 1) (send ch 2) (recv ch)"            => Ok(int!(1)),
+        "(define ch (channel)) (send ch 1) (send ch 2) (recv ch) (recv ch)"  => Ok(int!(2)),
+        "(port? (channel))"                                                  => Ok(lisp_true!()),
+        "(send 1 2)"                                                         => Err(RuntimeError::error(err_msgs::TYPE_ERROR)),
+        "(recv (make-string-builder))",
+        "(define ch (channel)) (socket-close ch) (send ch 1)"                => Err(RuntimeError::error(err_msgs::IO_ERROR))
+    }
+}
+
+#[test]
+#[cfg(feature = "sync")]
+fn test_lock_survives_panics() {
+    use ::core::shared::{Lock, Shared};
+    let lock = Shared::new(Lock::new(1));
+    let held = lock.clone();
+    let res = ::std::thread::spawn(move || {
+        *held.borrow_mut() = 2;
+        panic!("native function failed");
+    }).join();
+    assert!(res.is_err());
+    assert_eq!(*lock.borrow(), 2);
+    *lock.borrow_mut() = 3;
+    assert_eq!(*lock.borrow(), 3);
+}
+
+#[test]
+#[cfg(not(feature = "sync"))]
+fn test_spawn_needs_sync() {
+    tests! {
+        "(spawn (lambda () 1))" => Err(RuntimeError::error(err_msgs::ENVIRONMENT_ERROR))
+    }
+}
+
+#[test]
+#[cfg(feature = "sync")]
+fn test_threads() {
+    tests! {
+        "(join (spawn (lambda () (+ 1 2))))"                                 => Ok(int!(3)),
+        "(define ch (channel))
+         (define t (spawn (lambda () (send ch (recv ch)) 'done)))
+         (send ch 'ping)
+         (join t)"                                                            => Ok(symbol!("done")),
+        "(define x 10) (define y 0) (join (spawn (lambda () (set! y (+ x 1))))) y" => Ok(int!(11)),
+        "(join (spawn (lambda () (car 1))))"                                 => Err(RuntimeError::error(err_msgs::TYPE_ERROR)),
+        "(define t (spawn (lambda () 1))) (join t) (join t)",
+        "(join (channel))"                                                   => Err(RuntimeError::error(err_msgs::IO_ERROR))
+    }
+}
+
+#[test]
+fn test_json() {
+    let json_err = RuntimeError::error(err_msgs::JSON_ERROR);
+    tests! {
+        "(json->lisp \"[1, 2.5, \\\"three\\\", true, null]\")"
+            => Ok(LispObj::make_vector([int!(1), float!(2.5), string!("three"),
+                                        lisp_true!(), symbol!("null")].iter())),
+        "(json->lisp \"{\\\"a\\\": {}, \\\"b\\\": [\\\"\\\\u00e9\\\"]}\")"
+            => Ok(lisp_list![cons!(string!("a"), nil!()),
+                             cons!(string!("b"), LispObj::make_vector([string!("é")].iter()))]),
+        "(lisp->json '((name . \"rusty\") (tags . [1 2.0])))"
+            => Ok(string!("{\"name\":\"rusty\",\"tags\":[1,2.0]}")),
+        "(lisp->json '(1 \"a\\nb\" false))" => Ok(string!("[1,\"a\\nb\",false]")),
+        "(lisp->json (json->lisp \"{\\\"x\\\":[1,{\\\"y\\\":null}]}\"))"
+            => Ok(string!("{\"x\":[1,{\"y\":null}]}")),
+        "(json->lisp \"[1, 2\")", "(json->lisp \"{1: 2}\")", "(json->lisp \"[] []\")",
+        "(lisp->json car)"  => Err(json_err.clone())
+    }
+
+    // Deeply nested documents are read without overflowing the stack
+    let nested = format!("{}1{}", "[{\"a\":".repeat(100000), "}]".repeat(100000));
+    let mut value = LispObj::from_json(&nested).unwrap();
+    for _ in 0..100000 {
+        value = value.vec_ref().unwrap().iter().next().unwrap().cons_split().unwrap().0.cons_split().unwrap().1;
+    }
+    assert_eq!(value, int!(1).to_obj_ref());
+    assert_eq!(LispObj::from_json(&"[{\"a\":".repeat(100000)).unwrap_err().errname, err_msgs::JSON_ERROR);
+}
+
+#[test]
+fn test_image_round_trip() {
+    let setup = "(define x 1.0)
+                 (define counter
+                   (let ((n 10))
+                     (lambda () (set! n (+ n 1)) n)))
+                 (define-macro (twice e) `(begin ,e ,e))
+                 (define add +)";
+    let res = image_round_trip(setup, "(cons x (cons (twice (counter)) (add 1 2)))");
+    assert_eq!(res.unwrap(), cons!(float!(1.0), cons!(int!(12), int!(3))).to_obj_ref());
+
+    // Values nested too deeply to read back throw instead of overflowing the stack
+    let nest = |depth: usize| format!("(define (nest n acc) (if (equal? n 0) acc (nest (- n 1) (cons acc nil))))
+                                       (define deep (nest {} nil))", depth);
+    assert_eq!(image_round_trip(&nest(99), "(car deep)").unwrap().list_length(), Some(1));
+    assert_eq!(image_round_trip(&nest(100), "deep").unwrap_err().errname, err_msgs::RESOURCE_ERROR);
+    assert_eq!(image_round_trip(&nest(20000), "deep").unwrap_err().errname, err_msgs::RESOURCE_ERROR);
+    let deep = format!("[\"rustylisp-image\", 1, [[null, [[\"deep\", {}[\"n\"]{}]]]], [], [], []]",
+                       "[\"l\", [".repeat(20000), "], [\"n\"]]".repeat(20000));
+    let res = ::evaluator::image::image_from_string(super::Evaluator::new().top_level.clone(), &deep);
+    assert_eq!(res.unwrap_err().errname, err_msgs::READ_ERROR);
+}
+
+#[test]
+fn test_file_system() {
+    use std::fs;
+    use std::io::Write;
+
+    let dir = ::std::env::temp_dir().join("rustylisp-test-fs");
+    let _ = fs::remove_dir_all(&dir);
+    let dir = dir.to_str().unwrap().to_string();
+
+    run_test(&format!("(make-directory '(\"{}\" \"sub\"))", dir), Ok(lisp_true!()));
+    fs::File::create(format!("{}/a.txt", dir)).unwrap().write_all(b"hello").unwrap();
+
+    let io_err = RuntimeError::error(err_msgs::IO_ERROR);
+    tests! {
+        &format!("(list-directory \"{}\")", dir)           => Ok(lisp_list![string!("a.txt"), string!("sub")]),
+        &format!("(file-size '(\"{}\" \"a.txt\"))", dir)     => Ok(int!(5)),
+        &format!("(directory? '(\"{}\" \"sub\"))", dir)      => Ok(lisp_true!()),
+        &format!("(directory? '(\"{}\" \"a.txt\"))", dir)    => Ok(lisp_false!()),
+        &format!("(rename-file \"{0}/a.txt\" \"{0}/b.txt\")
+                  (list-directory \"{0}\")", dir)              => Ok(lisp_list![string!("b.txt"), string!("sub")]),
+        &format!("(delete-file \"{0}/b.txt\")
+                  (list-directory \"{0}\")", dir)              => Ok(lisp_list![string!("sub")]),
+        &format!("(delete-file \"{}/b.txt\")", dir),
+        &format!("(file-size \"{}/missing\")", dir),
+        &format!("(absolute-path \"{}/missing\")", dir)      => Err(io_err.clone())
+    }
+
+    // Names which aren't UTF-8 can't be listed as strings
+    #[cfg(unix)]
+    {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+        fs::File::create(::std::path::Path::new(&dir).join("sub").join(OsStr::from_bytes(b"bad\xff"))).unwrap();
+        run_test(&format!("(list-directory '(\"{}\" \"sub\"))", dir), Err(io_err.clone()));
+    }
+
+    let canon = fs::canonicalize(&dir).unwrap();
+    run_test(&format!("(absolute-path '(\"{}\" \"sub\" \"..\"))", dir),
+             Ok(string!(canon.to_str().unwrap())));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_eval_string() {
+    let read_err = RuntimeError::error(err_msgs::READ_ERROR);
+    tests! {
+        "(eval-string \"(define x 2) (+ x 3)\")"     => Ok(int!(5)),
+        "(eval-string \"\")"                         => Ok(nil!()),
+        "(eval-string \"(car '(a b))\")"             => Ok(symbol!("a")),
+        "(let ((y 4)) (eval-string \"(* y y)\"))"    => Ok(int!(16)),
+        "(eval-string \"(+ 1 2\")", "(eval-string \")\")" => Err(read_err.clone())
+    }
+}
+
+#[test]
+fn test_exit() {
+    let exit_req = RuntimeError::error(err_msgs::EXIT_REQUEST);
+    tests! {
+        "(exit)", "(exit 3)",
+        "(catch-error (exit 2))"            => Err(exit_req.clone()),
+        "(error? (catch-error (exit 'a)))"  => Ok(lisp_true!()),
+        "(exit 256)", "(exit 4294967297)",
+        "(exit -1)"                         => Err(RuntimeError::error(err_msgs::ARGUMENT_ERROR))
+    }
+
+    let mut runner = super::Evaluator::new();
+    let res = runner.eval_all_from_parser(Parser::from_string("(define-macro (m) (exit 4)) (m)", "<test>"));
+    assert_eq!(res.unwrap_err().exit_code(), Some(4));
+}
+
+#[test]
+fn test_profile() {
+    use ::evaluator::profile;
+
+    tests! {
+        "(profile-report)" => Err(RuntimeError::error(err_msgs::ENVIRONMENT_ERROR)),
+        "(time (+ 1 2))" => Ok(int!(3))
+    }
+
+    let mut runner = super::Evaluator::new();
+    profile::start();
+    runner.eval_all_from_parser(Parser::from_string("(define (f x) x) (f 1) (f 2) ((lambda () 3))", "<test>"))
+          .unwrap();
+    let results = profile::stop().unwrap();
+    assert_eq!(results.len(), 2);
+    let f = results.iter().find(|&&(ref name, _)| name == "f").unwrap();
+    assert_eq!(f.1.calls, 2);
+    assert!(!profile::is_running());
+}
+
+#[test]
+fn test_trace() {
+    tests! {
+        "(define (f l) (if l (f (cdr l)) 'done))
+         (trace f)"                             => Ok(lisp_list![symbol!("f")]),
+        "(define (f l) (if l (f (cdr l)) 'done))
+         (trace 'f)
+         (f '(1 2))"                            => Ok(symbol!("done")),
+        "(define (g l) (if l (cons 1 (g (cdr l))) ()))
+         (set! *trace-all* true)
+         (g '(a b))"                            => Ok(lisp_list![int!(1), int!(1)]),
+        "(untrace 'f 'h)"                       => Ok(lisp_list![symbol!("f")]),
+        "(untrace)"                             => Ok(nil!()),
+        "(trace 1)"                             => Err(RuntimeError::error(err_msgs::TYPE_ERROR)),
+        "(trace (lambda () 1))"                 => Err(RuntimeError::error(err_msgs::ARGUMENT_ERROR))
+    }
+
+    // Only traced calls are printed, and *trace-all* lasts between evaluations
+    let out = Capture::new();
+    let mut runner = super::Evaluator::new().with_stdout(out.clone());
+    eval_in(&mut runner, "(define (f) 1) (define (g) 2) (trace 'f) (f) (g) (untrace) (f)").unwrap();
+    assert_eq!(out.contents(), "(f)\nf returned 1\n");
+    eval_in(&mut runner, "(define! *trace-all* true)").unwrap();
+    eval_in(&mut runner, "(g)").unwrap();
+    assert_eq!(out.contents(), "(f)\nf returned 1\n(g)\ng returned 2\n");
+}
+
+#[test]
+fn test_time() {
+    let date = |fields: &[(&str, i64)]| {
+        LispObj::to_lisp_list(fields.iter().map(|&(name, val)| cons!(symbol!(name), int!(val))))
+    };
+
+    tests! {
+        "(date 0)" => Ok(date(&[("year", 1970), ("month", 1), ("day", 1), ("hour", 0),
+                                ("minute", 0), ("second", 0), ("weekday", 4)])),
+        "(date 951827696)" => Ok(date(&[("year", 2000), ("month", 2), ("day", 29), ("hour", 12),
+                                        ("minute", 34), ("second", 56), ("weekday", 2)])),
+        "(date -1)" => Ok(date(&[("year", 1969), ("month", 12), ("day", 31), ("hour", 23),
+                                 ("minute", 59), ("second", 59), ("weekday", 3)])),
+        "(sleep 1)" => Ok(nil!()),
+        "(sleep -1)" => Err(RuntimeError::error(err_msgs::ARGUMENT_ERROR)),
+        "(date 1 2)" => Err(RuntimeError::error(err_msgs::ARITY_ERROR))
+    }
+
+    let mut runner = super::Evaluator::new();
+    let res = runner.eval_all_from_parser(Parser::from_string("(- (- (* 1000 (current-seconds)) (current-milliseconds)))", "<test>"))
+                    .unwrap();
+    match *res {
+        LispObj::LInteger(n) => assert!(n >= 0 && n < 2000),
+        _ => panic!("expected integer, got {}", res),
+    }
+}
+
+#[test]
+#[cfg(unix)]
+fn test_os() {
+    let arg_err = RuntimeError::error(err_msgs::ARGUMENT_ERROR);
+    tests! {
+        "(getenv \"RUSTYLISP_TEST_UNSET_VAR\")"              => Ok(nil!()),
+        "(setenv \"RUSTYLISP_TEST_VAR\" \"value\")
+         (getenv \"RUSTYLISP_TEST_VAR\")"                    => Ok(string!("value")),
+        "(setenv \"RUSTYLISP_TEST_VAR\" nil)
+         (getenv \"RUSTYLISP_TEST_VAR\")"                    => Ok(nil!()),
+        "(getenv \"\")", "(setenv \"A=B\" \"c\")"            => Err(arg_err.clone()),
+        "(system \"exit 3\")"                               => Ok(int!(3)),
+        "(process-output \"echo\" \"hello\" 1 'a)"           => Ok(string!("hello 1 a\n")),
+        "(process-output \"/nonexistent/program\")"         => Err(RuntimeError::error(err_msgs::IO_ERROR))
+    }
+}
+
+#[test]
+fn test_sockets() {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    // An echo server which shouts back one line
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        reader.get_mut().write_all(line.to_uppercase().as_bytes()).unwrap();
+    });
+
+    let client = format!("(define sock (tcp-connect \"127.0.0.1\" {}))
+                          (socket-write sock \"hello \" 42 \"\\n\")
+                          (define reply (socket-read-line sock))
+                          (define eof (socket-read-line sock))
+                          (socket-close sock)
+                          (cons reply (cons eof (cons (string sock) ())))", port);
+    tests! {
+        &client => Ok(lisp_list![string!("HELLO 42"), nil!(),
+                                 string!(format!("#<closed-port:tcp-stream:127.0.0.1:{}>", port))])
+    }
+    server.join().unwrap();
+
+    let io_err = RuntimeError::error(err_msgs::IO_ERROR);
+    tests! {
+        "(define l (tcp-listen \"127.0.0.1\" 0))
+         (socket-close l)
+         (tcp-accept l)",
+        "(socket-read-line (tcp-listen \"127.0.0.1\" 0))"  => Err(io_err.clone()),
+        "(tcp-connect \"127.0.0.1\" 70000)"                => Err(RuntimeError::error(err_msgs::ARGUMENT_ERROR)),
+        "(socket-close 1)"                                 => Err(RuntimeError::error(err_msgs::TYPE_ERROR))
+    }
+}
+
+#[test]
+fn test_read_port() {
+    use std::io::Write;
+    use std::net::TcpListener;
+    use std::thread;
+
+    // Forms which run into each other, so each read looks ahead into the next
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        stream.write_all("foo(bar \"baz\")42 λ".as_bytes()).unwrap();
+    });
+
+    let client = format!("(define sock (tcp-connect \"127.0.0.1\" {}))
+                          (define a (read sock))
+                          (define b (read sock))
+                          (define c (read sock))
+                          (define d (read sock))
+                          (cons a (cons b (cons c (cons d ()))))", port);
+    tests! {
+        &client => Ok(lisp_list![symbol!("foo"), lisp_list![symbol!("bar"), string!("baz")],
+                                 int!(42), symbol!("λ")])
+    }
+    server.join().unwrap();
+
+    tests! {
+        "(read (make-string-builder))"  => Err(RuntimeError::error(err_msgs::IO_ERROR)),
+        "(read 1)"                      => Err(RuntimeError::error(err_msgs::TYPE_ERROR))
+    }
+}
+
+#[test]
+fn test_http() {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use std::thread;
+
+    // Answers two requests: the first with a fixed length body echoing the request
+    // line, the second with a chunked body echoing the request body. Sends back the
+    // Host header of each.
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let (hosts, host_headers) = mpsc::channel();
+    let server = thread::spawn(move || {
+        for chunked in vec![false, true] {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+
+            let mut length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line == "\r\n" {
+                    break
+                }
+                if line.to_lowercase().starts_with("content-length:") {
+                    length = line[15..].trim().parse().unwrap();
+                }
+                if line.starts_with("Host:") {
+                    hosts.send(String::from(line.trim())).unwrap();
+                }
+            }
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+
+            let response = if chunked {
+                format!("HTTP/1.1 201 Created\r\nTransfer-Encoding: chunked\r\n\r\n3\r\n{}\r\n{:x}\r\n{}\r\n0\r\n\r\n",
+                        "got", body.len(), String::from_utf8(body).unwrap())
+            } else {
+                let request_line = request_line.trim();
+                format!("HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: {}\r\n\r\n{}",
+                        request_line.len(), request_line)
+            };
+            reader.get_mut().write_all(response.as_bytes()).unwrap();
+        }
+    });
+
+    tests! {
+        &format!("(http-get \"http://127.0.0.1:{}/path?q=1\")", port) =>
+            Ok(lisp_list![cons!(symbol!("status"), int!(200)),
+                          cons!(symbol!("headers"), lisp_list![cons!(string!("content-type"), string!("text/plain")),
+                                                               cons!(string!("content-length"), string!("22"))]),
+                          cons!(symbol!("body"), string!("GET /path?q=1 HTTP/1.1"))]),
+        &format!("(cdr (car (cdr (cdr (http-request 'post \"127.0.0.1:{}\" '((\"X-Test\" . \"1\")) \"data\")))))", port) =>
+            Ok(string!("gotdata"))
+    }
+    server.join().unwrap();
+    let expected_host = format!("Host: 127.0.0.1:{}", port);
+    assert_eq!(host_headers.iter().collect::<Vec<_>>(), vec![expected_host.clone(), expected_host]);
+
+    // Line breaks in any part of the request could add headers or split it
+    let arg_err = RuntimeError::error(err_msgs::ARGUMENT_ERROR);
+    tests! {
+        "(http-get \"https://example.com/\")",
+        "(http-get \"http://:80/\")",
+        "(http-get \"http://localhost:http/\")",
+        "(set! *http-max-body* -1) (http-get \"http://127.0.0.1:1/\")",
+        "(http-get \"http://127.0.0.1:1/a\r\nX-Injected: 1\")",
+        "(http-request \"GET / HTTP/1.1\nX-Injected:\" \"http://127.0.0.1:1/\" () \"\")",
+        "(http-request 'get \"http://127.0.0.1:1/\" '((\"X-Test\" . \"1\r\nX-Injected: 1\")) \"\")",
+        "(http-request 'get \"http://127.0.0.1:1/\" '((\"X-Test\nX-Injected\" . \"1\")) \"\")" => Err(arg_err.clone())
+    }
+
+    // A server which never answers is given up on at the time limit
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let _ = stream.read_to_end(&mut vec![]);
+    });
+    let start = ::std::time::Instant::now();
+    let time = Limits { steps: None, time: Some(Duration::from_millis(50)) };
+    let mut runner = super::Evaluator::new();
+    let get = format!("(http-get \"http://127.0.0.1:{}/\")", port);
+    let res = limits::with_limits(time, || runner.eval_all_from_parser(Parser::from_string(&get, "<test>")));
+    assert_eq!(res.unwrap_err().errname, err_msgs::RESOURCE_ERROR);
+    assert!(start.elapsed() < Duration::from_secs(5));
+    server.join().unwrap();
+
+    // Answers with bodies longer than the limit: a huge Content-Length, and chunks or
+    // a body without a length which add up to more than *http-max-body*
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = thread::spawn(move || {
+        for response in vec!["HTTP/1.1 200 OK\r\nContent-Length: 1000000000000000\r\n\r\n",
+                             "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n4\r\nabcd\r\n4\r\nefgh\r\n0\r\n\r\n",
+                             "HTTP/1.1 200 OK\r\n\r\nabcdefgh",
+                             "HTTP/1.1 200 OK\r\n\r\nabcdefgh"] {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut line = String::new();
+            while line != "\r\n" {
+                line.clear();
+                reader.read_line(&mut line).unwrap();
+            }
+            reader.get_mut().write_all(response.as_bytes()).unwrap();
+        }
+    });
+
+    let get = format!("(http-get \"http://127.0.0.1:{}/\")", port);
+    let small = |body: &str| format!("(set! *http-max-body* 6) {}", body);
+    tests! {
+        &get,
+        &small(&get),
+        &small(&get) => Err(RuntimeError::error(err_msgs::IO_ERROR)),
+        &format!("(set! *http-max-body* nil) (cdr (car (cdr (cdr {}))))", get) => Ok(string!("abcdefgh"))
+    }
+    server.join().unwrap();
+}
+
+#[test]
+fn test_records() {
+    let point = "(define-record point (x y))
+                 (define p (make-point 1 2))";
+    let prog = |body: &str| format!("{} {}", point, body);
+    let type_err = RuntimeError::error(err_msgs::TYPE_ERROR);
+    let syntax_err = RuntimeError::error(err_msgs::SYNTAX_ERROR);
+
+    tests! {
+        &prog("(point-y p)")                        => Ok(int!(2)),
+        &prog("(point-x (point-set-x p 5))")        => Ok(int!(5)),
+        &prog("(point-set-x p 5) (point-x p)")      => Ok(int!(1)),
+        &prog("(string (point-set-y p 'b))")        => Ok(string!("#<point x:1 y:b>")),
+        &prog("(point? p)")                         => Ok(lisp_true!()),
+        &prog("(point? '(1 2))")                    => Ok(lisp_false!()),
+        &prog("(record-type p)")                    => Ok(symbol!("point")),
+        &prog("(record-fields p)")                  => Ok(lisp_list![symbol!("x"), symbol!("y")]),
+        &prog("(set! make-record 1) (point-x (make-point 3 4))") => Ok(int!(3)),
+        &prog("(define-record color (r g b))
+               (color-r p)"),
+        &prog("(point-x 1)")                        => Err(type_err.clone()),
+        &prog("(make-point 1)")                     => Err(RuntimeError::error(err_msgs::ARITY_ERROR)),
+        &prog("(record-get p 'z)")                  => Err(RuntimeError::error(err_msgs::ARGUMENT_ERROR)),
+        "(define-record p (x x))",
+        "(define-record (p) (x))",
+        "(define-record p (1))"                     => Err(syntax_err.clone())
+    }
+
+    let mut runner = super::Evaluator::new();
+    let p = runner.eval_all_from_parser(Parser::from_string(&prog("p"), "<test>")).unwrap();
+    let q = runner.eval_all_from_parser(Parser::from_string("(make-point 1 2)", "<test>")).unwrap();
+    assert_eq!(p, q);
+
+    let res = image_round_trip(&prog("p"), "(point-y (point-set-y p 7))");
+    assert_eq!(res.unwrap(), int!(7).to_obj_ref());
+}
+
+#[test]
+fn test_optional_and_keyword_args() {
+    let defs = "(define scale 10)
+                (define (f a (b 2) (c (* 2 scale))) (cons a (cons b (cons c ()))))
+                (define (g x #:key (y 5) z) (cons x (cons y z)))
+                (define (h #:key (k 1) . rest) (cons k rest))";
+    let prog = |body: &str| format!("{} {}", defs, body);
+    let arity_err = RuntimeError::error(err_msgs::ARITY_ERROR);
+
+    tests! {
+        &prog("(f 1)")                  => Ok(lisp_list![int!(1), int!(2), int!(20)]),
+        &prog("(f 1 3)")                => Ok(lisp_list![int!(1), int!(3), int!(20)]),
+        &prog("(f 1 3 4)")              => Ok(lisp_list![int!(1), int!(3), int!(4)]),
+        &prog("(g 1)")                  => Ok(cons!(int!(1), cons!(int!(5), nil!()))),
+        &prog("(g 1 #:z 3)")            => Ok(cons!(int!(1), cons!(int!(5), int!(3)))),
+        &prog("(g 1 #:z 3 #:y 4 #:z 0)") => Ok(cons!(int!(1), cons!(int!(4), int!(3)))),
+        &prog("(h #:k 2 #:other 3)")    => Ok(lisp_list![int!(2), symbol!("#:k"), int!(2), symbol!("#:other"), int!(3)]),
+        &prog("((lambda ((x 'a)) x))")  => Ok(symbol!("a")),
+        "#:size"                        => Ok(symbol!("#:size")),
+        ":size"                         => Ok(symbol!("#:size")),
+        "(cons (keyword? :a) (cons (keyword? '#:a) (cons (keyword? 'a) ())))" => Ok(lisp_list![lisp_true!(), lisp_true!(), lisp_false!()]),
+        "(equal? ':size #:size)"        => Ok(lisp_true!()),
+        &prog("(g 1 :z 3 :y 4)")        => Ok(cons!(int!(1), cons!(int!(4), int!(3)))),
+        &prog("(match :y (:x 1) (:y 2))") => Ok(int!(2)),
+        &prog("(f)"),
+        &prog("(f 1 2 3 4)"),
+        &prog("(g 1 2)"),
+        &prog("(g 1 #:y)"),
+        &prog("(g 1 #:w 2)")            => Err(arity_err.clone()),
+        "(lambda (a a) a)",
+        "(lambda ((a 1) b) a)",
+        "(lambda ((a 1 2)) a)"          => Err(RuntimeError::error(err_msgs::SYNTAX_ERROR)),
+        "#foo"                          => Err(RuntimeError::error(err_msgs::READ_ERROR))
+    }
+
+    let res = image_round_trip(defs, "(cons (f 1) (g 1 #:y 0))");
+    let expected = cons!(lisp_list![int!(1), int!(2), int!(20)], cons!(int!(1), cons!(int!(0), nil!())));
+    assert_eq!(res.unwrap(), expected.to_obj_ref());
+}
+
+#[test]
+fn test_define_docstring_and_meta() {
+    tests! {
+        "(define x \"The answer\" 42) x"                      => Ok(int!(42)),
+        "(define x \"The answer\" 42) (doc 'x)"               => Ok(string!("The answer")),
+        "(define f \"Adds one\" (lambda (n) (+ n 1))) (doc f)" => Ok(string!("Adds one")),
+        "(define (f) \"Does nothing\" nil) (doc 'f)"          => Ok(string!("Does nothing")),
+        "(define s \"just a string\") s"                      => Ok(string!("just a string")),
+        "(doc 'unbound-name)"                                 => Ok(lisp_false!()),
+        "(doc 1)"                                             => Ok(lisp_false!()),
+        "(doc car)"                                           => Ok(string!("(car ...)")),
+        "(define (f a (b 1) . more) a) (doc 'f)"              => Ok(string!("(f a (b 1) . more)")),
+        "(define f (case-lambda ((x) #:when x 1) (([x y] #:key z) 2))) (doc f)"
+            => Ok(string!("(f x) #:when x\n(f [x y] #:key (z ()))")),
+        "(set-meta! 'x 'author \"me\") (meta 'x 'author)"      => Ok(string!("me")),
+        "(set-meta! 'x 'a 1) (set-meta! 'x 'b 2) (set-meta! 'x 'a 3) (meta 'x)"
+            => Ok(lisp_list![cons!(symbol!("a"), int!(3)), cons!(symbol!("b"), int!(2))]),
+        "(define x \"doc\" 1) (meta 'x)"                       => Ok(lisp_list![cons!(symbol!("doc"), string!("doc"))]),
+        "(meta 'x)"                                           => Ok(nil!()),
+        "(meta 'x 'author)"                                   => Ok(nil!()),
+        "(define x 1 2)"                                      => Err(RuntimeError::error(err_msgs::SYNTAX_ERROR)),
+        "(meta \"x\")"                                        => Err(RuntimeError::error(err_msgs::TYPE_ERROR))
+    }
+
+    let res = image_round_trip("(define x \"The answer\" 42) (set-meta! 'y 'k '(1 2))", "(cons (doc 'x) (meta 'y 'k))");
+    assert_eq!(res.unwrap(), cons!(string!("The answer"), lisp_list![int!(1), int!(2)]).to_obj_ref());
+}
+
+#[test]
+fn test_assert_and_deftest() {
+    let assert_err = RuntimeError::error(err_msgs::ASSERTION_ERROR);
+
+    tests! {
+        "(assert (symbol=? 'a 'a))"                   => Ok(lisp_true!()),
+        "(assert-equal (cons 1 2) '(1 . 2))"          => Ok(lisp_true!()),
+        "(error-type (catch-error (assert false)))"   => Ok(symbol!("assertion-error")),
+        "(error-value (catch-error (assert nil \"broken\")))" => Ok(string!("broken")),
+        "(error-value (catch-error (assert-equal 1 2)))"    => Ok(string!("expected 2, got 1")),
+        "(deftest a (assert true)) (deftest b (assert-equal 1 1)) (run-tests)" => Ok(lisp_true!()),
+        "(deftest a (assert false)) (deftest b (assert true)) (run-tests)"     => Ok(lisp_false!()),
+        "(deftest a (assert false)) (deftest a (assert true)) (run-tests)"     => Ok(lisp_true!()),
+        "(deftest a 1) (deftest b 2) (deftest a 3) (car (car *tests*))"        => Ok(symbol!("a")),
+        "(run-tests)"                                 => Ok(lisp_true!()),
+        "(assert false)",
+        "(assert-equal 'a 'b \"symbols\")"             => Err(assert_err.clone()),
+        "(deftest a)",
+        "(deftest \"a\" 1)"                           => Err(RuntimeError::error(err_msgs::SYNTAX_ERROR)),
+        "(assert)"                                    => Err(RuntimeError::error(err_msgs::ARITY_ERROR))
+    }
+}
+
+#[test]
+fn test_booleans() {
+    let strict = |body: &str| format!("(set! *strict-booleans* true) {}", body);
+
+    tests! {
+        "(not false)"                       => Ok(lisp_true!()),
+        "(not nil)"                         => Ok(lisp_true!()),
+        "(not 0)"                           => Ok(lisp_true!()),
+        "(not 'a)"                          => Ok(lisp_false!()),
+        "(boolean? true)"                   => Ok(lisp_true!()),
+        "(boolean? false)"                  => Ok(lisp_true!()),
+        "(boolean? nil)"                    => Ok(lisp_false!()),
+        "(if \"\" 'yes 'no)"                => Ok(symbol!("no")),
+        &strict("(not 0)")                  => Ok(lisp_false!()),
+        &strict("(not nil)")                => Ok(lisp_true!()),
+        &strict("(if \"\" 'yes 'no)")       => Ok(symbol!("yes")),
+        &strict("(if false 'yes 'no)")      => Ok(symbol!("no")),
+        &strict("(and 1 0 2)")              => Ok(int!(2)),
+        &strict("(or 0 1)")                 => Ok(int!(0)),
+        &strict("(assert 0)")               => Ok(lisp_true!()),
+        "(define! *strict-booleans* true) (not 0)"  => Ok(lisp_false!()),
+        "(set! *strict-booleans* true) (set! *strict-booleans* false) (not 0)" => Ok(lisp_true!()),
+        "(not)"                             => Err(RuntimeError::error(err_msgs::ARITY_ERROR))
+    }
+}
+
+#[test]
+fn test_strict_booleans_per_evaluator() {
+    let mut strict = super::Evaluator::new();
+    let mut lax = super::Evaluator::new();
+    eval_in(&mut strict, "(set! *strict-booleans* true)").unwrap();
+    assert_eq!(*eval_in(&mut lax, "(not 0)").unwrap(), lisp_true!());
+    assert_eq!(*eval_in(&mut strict, "(not 0)").unwrap(), lisp_false!());
+}
+
+#[test]
+fn test_numeric_predicates() {
+    let type_err = RuntimeError::error(err_msgs::TYPE_ERROR);
+
+    tests! {
+        "(number? 1)"       => Ok(lisp_true!()),
+        "(number? 1.5)"     => Ok(lisp_true!()),
+        "(number? \"1\")"   => Ok(lisp_false!()),
+        "(integer? 2)"      => Ok(lisp_true!()),
+        "(integer? 2.0)"    => Ok(lisp_false!()),
+        "(float? 2.0)"      => Ok(lisp_true!()),
+        "(float? 'a)"       => Ok(lisp_false!()),
+        "(exact? 1)"        => Ok(lisp_true!()),
+        "(inexact? 1.0)"    => Ok(lisp_true!()),
+        "(zero? 0)"         => Ok(lisp_true!()),
+        "(zero? -0.0)"      => Ok(lisp_true!()),
+        "(zero? 1)"         => Ok(lisp_false!()),
+        "(positive? 3)"     => Ok(lisp_true!()),
+        "(positive? 0)"     => Ok(lisp_false!()),
+        "(negative? -1.5)"  => Ok(lisp_true!()),
+        "(negative? 0)"     => Ok(lisp_false!()),
+        "(even? 4)"         => Ok(lisp_true!()),
+        "(even? -3)"        => Ok(lisp_false!()),
+        "(odd? -3)"         => Ok(lisp_true!()),
+        "(odd? 0)"          => Ok(lisp_false!()),
+        "(zero? 'a)",
+        "(even? 2.0)",
+        "(exact? \"1\")"    => Err(type_err.clone()),
+        "(zero? (/ 0.0 0))" => Err(RuntimeError::error(err_msgs::ARGUMENT_ERROR)),
+        "(odd?)"            => Err(RuntimeError::error(err_msgs::ARITY_ERROR))
+    }
+}
+
+#[test]
+fn test_type_predicates() {
+    tests! {
+        "(char? '\\a)"                  => Ok(lisp_true!()),
+        "(char? 'a)"                    => Ok(lisp_false!()),
+        "(procedure? car)"              => Ok(lisp_true!()),
+        "(procedure? (lambda (x) x))"   => Ok(lisp_true!()),
+        "(procedure? 'car)"             => Ok(lisp_false!()),
+        "(native? car)"                 => Ok(lisp_true!()),
+        "(native? (lambda (x) x))"      => Ok(lisp_false!()),
+        "(lazy-cons? (lazy-cons 1 2))"  => Ok(lisp_true!()),
+        "(lazy-cons? (cons 1 2))"       => Ok(lisp_false!()),
+        "(port? (tcp-listen \"127.0.0.1\" 0))" => Ok(lisp_true!()),
+        "(port? 1)"                     => Ok(lisp_false!())
+    }
+}
+
+#[test]
+fn test_equality() {
+    tests! {
+        "(equal? '(1 (2 \"three\")) '(1 (2 \"three\")))"   => Ok(lisp_true!()),
+        "(equal? 1 1 1)"                                  => Ok(lisp_true!()),
+        "(equal? 1 1 2)"                                  => Ok(lisp_false!()),
+        "(equal? car car)"                                => Ok(lisp_true!()),
+        "(equal? car cdr)"                                => Ok(lisp_false!()),
+        "(equal? (lambda (x) x) (lambda (x) x))"          => Ok(lisp_false!()),
+        "(define id (lambda (x) x)) (equal? id id)"       => Ok(lisp_true!()),
+        "(define f (lambda (x) x)) (define g f) (equal? f g)" => Ok(lisp_true!()),
+        "(equal? (make-error 'a 1) (make-error 'a 1))"    => Ok(lisp_true!()),
+        "(equal? (make-error 'a 1) (make-error 'a 2))"    => Ok(lisp_false!()),
+        "(equal? 1)"                                      => Err(RuntimeError::error(err_msgs::ARITY_ERROR)),
+
+        // equal? compares numbers as objects, and = by value
+        "(equal? 1 1.0)"                                  => Ok(lisp_false!()),
+        "(equal? (/ 0.0 0.0) (/ 0.0 0.0))"                => Ok(lisp_true!()),
+        "(equal? (hash (/ 0.0 0.0)) (hash (- (/ 0.0 0.0))))" => Ok(lisp_true!()),
+        "(= 1 1.0 1)"                                     => Ok(lisp_true!()),
+        "(= 1 1.5)"                                       => Ok(lisp_false!()),
+        "(= 9007199254740993 9007199254740992.0)"         => Ok(lisp_false!()),
+        "(= (/ 0.0 0.0) (/ 0.0 0.0))"                     => Ok(lisp_false!()),
+        "(= 0.0 -0.0)"                                    => Ok(lisp_true!()),
+        "(= 1)"                                           => Err(RuntimeError::error(err_msgs::ARITY_ERROR)),
+        "(= 1 'a)"                                        => Err(RuntimeError::error(err_msgs::TYPE_ERROR))
+    }
+    assert_eq!(float!(::std::f64::NAN), float!(::std::f64::NAN));
+}
+
+#[test]
+fn test_hash() {
+    tests! {
+        "(equal? (hash '(1 \"two\" [3.0 \\c])) (hash '(1 \"two\" [3.0 \\c])))" => Ok(lisp_true!()),
+        "(equal? (hash 'a) (hash \"a\"))"                => Ok(lisp_false!()),
+        "(equal? (hash '(1 2)) (hash '(2 1)))"           => Ok(lisp_false!()),
+        "(equal? (hash 0.0) (hash -0.0))"                => Ok(lisp_true!()),
+        "(define-record point (x y))
+         (equal? (hash (make-point 1 2)) (hash (make-point 1 2)))" => Ok(lisp_true!()),
+        "(integer? (hash nil))"                          => Ok(lisp_true!()),
+        "(hash car)",
+        "(hash (cons 1 (lambda () 1)))"                  => Err(RuntimeError::error(err_msgs::TYPE_ERROR))
+    }
+}
+
+#[test]
+fn test_bytes() {
+    let arg_err = RuntimeError::error(err_msgs::ARGUMENT_ERROR);
+
+    tests! {
+        "(bytes->list (make-bytes 3 7))"               => Ok(lisp_list![int!(7), int!(7), int!(7)]),
+        "(bytes-length (make-bytes 0))"                => Ok(int!(0)),
+        "(bytes-ref (string->bytes \"A\") 0)"          => Ok(int!(65)),
+        "(bytes->string (string->bytes \"h\u{e9}llo\"))"  => Ok(string!("h\u{e9}llo")),
+        "(define b (make-bytes 2))
+         (cons (bytes->list (bytes-set b 1 9)) (bytes->list b))"
+            => Ok(cons!(lisp_list![int!(0), int!(9)], lisp_list![int!(0), int!(0)])),
+        "(equal? (list->bytes '(1 2)) (list->bytes '(1 2)))" => Ok(lisp_true!()),
+        "(string (list->bytes '(1 255)))"              => Ok(string!("#<bytes:1 255>")),
+        "(bytes? (make-bytes 1))"                      => Ok(lisp_true!()),
+        "(bytes? \"a\")"                               => Ok(lisp_false!()),
+        "(make-bytes 1 256)",
+        "(list->bytes '(-1))",
+        "(bytes->string (list->bytes '(255)))"         => Err(arg_err.clone()),
+        "(bytes-ref (make-bytes 2) 2)",
+        "(bytes-set (make-bytes 2) -3 0)"              => Err(RuntimeError::error(err_msgs::BOUNDS_ERROR)),
+        "(bytes-length \"abc\")"                       => Err(RuntimeError::error(err_msgs::TYPE_ERROR))
+    }
+
+    let path = temp_path("bytes.bin");
+    let prog = format!("(write-file-bytes {:?} (list->bytes '(0 1 254 255)))
+                        (bytes->list (read-file-bytes {:?}))",
+                       path.to_str().unwrap(), path.to_str().unwrap());
+    run_test(&prog, Ok(lisp_list![int!(0), int!(1), int!(254), int!(255)]));
+    let _ = ::std::fs::remove_file(&path);
+
+    let res = image_round_trip("(define b (list->bytes '(0 16 255)))", "(bytes->list b)");
+    assert_eq!(res.unwrap(), lisp_list![int!(0), int!(16), int!(255)].to_obj_ref());
+}
+
+#[test]
+fn test_f64vector() {
+    let floats = |items: Vec<f64>| LispObj::make_f64vector(items);
+    tests! {
+        "(make-f64vector 2 1)"                                   => Ok(floats(vec![1.0, 1.0])),
+        "(f64vector-length (make-f64vector 3))"                  => Ok(int!(3)),
+        "(f64vector-ref (list->f64vector [1 2.5]) 1)"            => Ok(float!(2.5)),
+        "(f64vector-ref (list->f64vector '(1 2.5)) -2)"          => Ok(float!(1.0)),
+        "(f64vector->list (list->f64vector '(1 2)))"             => Ok(lisp_list![float!(1.0), float!(2.0)]),
+        "(f64vector-add (list->f64vector '(1 2)) (list->f64vector '(10 20)))" => Ok(floats(vec![11.0, 22.0])),
+        "(f64vector-add (list->f64vector '(1 2)) 0.5)"           => Ok(floats(vec![1.5, 2.5])),
+        "(f64vector-mul (list->f64vector '(1 2)) (list->f64vector '(10 20)))" => Ok(floats(vec![10.0, 40.0])),
+        "(f64vector-mul (list->f64vector '(1 2)) 2)"             => Ok(floats(vec![2.0, 4.0])),
+        "(f64vector-sum (list->f64vector '(1 2 3.5)))"           => Ok(float!(6.5)),
+        "(f64vector-sum (make-f64vector 0))"                     => Ok(float!(0.0)),
+        "(equal? (make-f64vector 2 +nan) (make-f64vector 2 +nan))" => Ok(lisp_true!()),
+        "(equal? (hash (make-f64vector 1 0.0)) (hash (make-f64vector 1 -0.0)))" => Ok(lisp_true!()),
+        "(f64vector? (make-f64vector 1))"                        => Ok(lisp_true!()),
+        "(f64vector? [1.0])"                                     => Ok(lisp_false!()),
+        "(string (list->f64vector '(1 -inf)))"                   => Ok(string!("#<f64vector:1.0 -inf>")),
+        "(f64vector-ref (make-f64vector 2) 2)"                   => Err(RuntimeError::error(err_msgs::BOUNDS_ERROR)),
+        "(make-f64vector -1)",
+        "(f64vector-add (make-f64vector 2) (make-f64vector 3))"  => Err(RuntimeError::error(err_msgs::ARGUMENT_ERROR)),
+        "(list->f64vector '(1 a))", "(f64vector-sum [1.0])",
+        "(f64vector-mul (make-f64vector 2) 'a)"                  => Err(RuntimeError::error(err_msgs::TYPE_ERROR))
+    }
+
+    let res = image_round_trip("(define v (list->f64vector '(0.5 -0.0 +inf +nan)))", "v");
+    assert_eq!(*res.unwrap(), floats(vec![0.5, -0.0, ::std::f64::INFINITY, ::std::f64::NAN]));
+}
+
+#[test]
+fn test_string_builder() {
+    tests! {
+        "(define b (make-string-builder))
+         (builder-append! b \"x = \" 1 '\\space 'sym)
+         (builder-append! (builder-append! b \" [\") '(1 \"two\") \"]\")
+         (builder->string b)"                                    => Ok(string!("x = 1 sym [(1 \"two\")]")),
+        "(builder->string (make-string-builder))"               => Ok(string!("")),
+        "(define b (make-string-builder))
+         (builder-append! b \"a\")
+         (builder->string b)
+         (builder-append! b \"b\")
+         (builder->string b)"                                    => Ok(string!("ab")),
+        "(port? (make-string-builder))"                         => Ok(lisp_true!()),
+        "(define b (make-string-builder))
+         (socket-close b)
+         (builder-append! b \"a\")",
+        "(builder->string (tcp-listen \"127.0.0.1\" 0))"        => Err(RuntimeError::error(err_msgs::IO_ERROR)),
+        "(builder-append! \"a\" \"b\")"                         => Err(RuntimeError::error(err_msgs::TYPE_ERROR))
+    }
+}
+
+#[test]
+fn test_macro_expand_all() {
+    let defs = "(define-macro (my-unless c x) `(if ,c nil ,x))
+                (define-macro (my-when c x) `(my-unless (not ,c) ,x))";
+    let prog = |body: &str| format!("{} {}", defs, body);
+
+    tests! {
+        &prog("(macro-expand '(my-when a b))")
+            => Ok(lisp_list![symbol!("my-unless"), lisp_list![symbol!("not"), symbol!("a")], symbol!("b")]),
+        &prog("(macro-expand-all '(my-when a b))")
+            => Ok(lisp_list![symbol!("if"), lisp_list![symbol!("not"), symbol!("a")], symbol!("nil"), symbol!("b")]),
+        &prog("(macro-expand-all '(f (my-unless a b) . rest))")
+            => Ok(cons!(symbol!("f"), cons!(lisp_list![symbol!("if"), symbol!("a"), symbol!("nil"), symbol!("b")],
+                                            symbol!("rest")))),
+        &prog("(macro-expand-all '(f '(my-unless a b)))")
+            => Ok(lisp_list![symbol!("f"), lisp_list![symbol!("quote"),
+                             lisp_list![symbol!("my-unless"), symbol!("a"), symbol!("b")]]]),
+        &prog("(macro-expand-all 1)")         => Ok(int!(1)),
+        &prog("(set! *trace-macro-expansion* true) (my-when true 5)") => Ok(int!(5))
+    }
+}
+
+#[test]
+fn test_hygienic_macros() {
+    let defs = "(define-macro (swap-unhygienic! a b) `(let ((tmp ,a)) (set! ,a ,b) (set! ,b tmp)))
+                (define-hygienic-macro (swap! a b) `(let ((tmp ,a)) (set! ,a ,b) (set! ,b tmp)))";
+    let prog = |body: &str| format!("{} {}", defs, body);
+
+    tests! {
+        &prog("(let ((tmp 1) (y 2)) (swap! tmp y) (cons tmp y))")             => Ok(cons!(int!(2), int!(1))),
+        &prog("(let ((tmp 1) (y 2)) (swap-unhygienic! tmp y) (cons tmp y))")  => Ok(cons!(int!(1), int!(2))),
+        &prog("(let ((x 1) (y 2)) (swap! x y) (cons x y))")                   => Ok(cons!(int!(2), int!(1))),
+        // The macro's tmp is renamed even when there is a global tmp
+        &prog("(define tmp 1) (define y 2) (swap! tmp y) (cons tmp y)")       => Ok(cons!(int!(2), int!(1))),
+        &prog("(define x 10) (define-hygienic-macro (inc e) `((lambda (x) (+ x ,e)) 1)) (inc x)")
+            => Ok(int!(11)),
+        &prog("(symbol=? (car (cdr (car (car (cdr (macro-expand '(swap! tmp y))))))) 'tmp)")
+            => Ok(lisp_true!()),
+        &prog("(set! *allow-redefine* true)
+               (define-macro (swap! a b) `(let ((tmp ,a)) (set! ,a ,b) (set! ,b tmp)))
+               (let ((tmp 1) (y 2)) (swap! tmp y) (cons tmp y))")             => Ok(cons!(int!(1), int!(2))),
+        "(symbol=? (gensym) (gensym))"                                        => Ok(lisp_false!()),
+        "(symbol? (gensym \"x\"))"                                           => Ok(lisp_true!()),
+        "(gensym 1)"                    => Err(RuntimeError::error(err_msgs::TYPE_ERROR))
+    }
+}
+
+#[test]
+fn test_syntax_rules() {
+    let defs = "(define-syntax my-or
+                  (syntax-rules ()
+                    ((_) false)
+                    ((_ e) e)
+                    ((_ e rest ...) (let ((t e)) (if t t (my-or rest ...))))))
+                (define-syntax my-let*
+                  (syntax-rules ()
+                    ((_ () body ...) (let () body ...))
+                    ((_ ((x v) rest ...) body ...) (let ((x v)) (my-let* (rest ...) body ...)))))
+                (define-syntax with (syntax-rules (as) ((_ v as x body) (let ((x v)) body))))
+                (define-syntax columns (syntax-rules () ((_ (a b ...) ...) '((a ...) (b ... ...)))))
+                (define-syntax rest-of (syntax-rules () ((_ a . rest) 'rest)))
+                (define-syntax first-only (syntax-rules () ((_ a ...) a)))
+                (define-syntax swap! (syntax-rules () ((_ a b) (let ((tmp a)) (set! a b) (set! b tmp)))))";
+    let prog = |body: &str| format!("{} {}", defs, body);
+
+    tests! {
+        &prog("(my-or)")                            => Ok(lisp_false!()),
+        &prog("(my-or nil 0 3)")                    => Ok(int!(3)),
+        &prog("(let ((t 5)) (my-or nil t))")        => Ok(int!(5)),
+        &prog("(my-let* ((a 1) (b (+ a 1))) (+ a b))") => Ok(int!(3)),
+        &prog("(with 1 as y (+ y 1))")              => Ok(int!(2)),
+        &prog("(columns (1 2 3) (4 5))")
+            => Ok(lisp_list![lisp_list![int!(1), int!(4)], lisp_list![int!(2), int!(3), int!(5)]]),
+        &prog("(rest-of 1 2 3)")                    => Ok(lisp_list![int!(2), int!(3)]),
+        &prog("(let ((tmp 1) (y 2)) (swap! tmp y) (cons tmp y))")  => Ok(cons!(int!(2), int!(1))),
+        &prog("(define tmp 1) (define y 2) (swap! tmp y) (cons tmp y)")  => Ok(cons!(int!(2), int!(1))),
+        &prog("(with 1 of y y)"),
+        &prog("(first-only 1 2)")                   => Err(RuntimeError::error(err_msgs::MACRO_ERROR)),
+        "(define-syntax bad (syntax-rules () ((_ ... a) 1)))",
+        "(define-syntax bad (lambda (x) x))"        => Err(RuntimeError::error(err_msgs::SYNTAX_ERROR))
+    }
+}
+
+#[test]
+fn test_match() {
+    let defs = "(define (describe x)
+                  (match x
+                    (nil 'empty)
+                    ((a) 'singleton)
+                    ((a b . rest) #:when (equal? a b) 'starts-with-a-pair)
+                    ((_ . rest) 'list)
+                    ([x y] 'pair-vector)
+                    ('foo 'foo)
+                    (\"str\" 'string)
+                    (_ 'something-else)))
+                (define (len l) (match l (nil 0) ((_ . tl) (+ 1 (len tl)))))
+                (define (count-down n) (match n (0 'done) (n (count-down (- n 1)))))";
+    let prog = |body: &str| format!("{} {}", defs, body);
+
+    tests! {
+        &prog("(describe '())")                     => Ok(symbol!("empty")),
+        &prog("(describe '(1))")                    => Ok(symbol!("singleton")),
+        &prog("(describe '(1 1 2))")                => Ok(symbol!("starts-with-a-pair")),
+        &prog("(describe '(1 2 3))")                => Ok(symbol!("list")),
+        &prog("(describe [1 2])")                   => Ok(symbol!("pair-vector")),
+        &prog("(describe [1 2 3])")                 => Ok(symbol!("something-else")),
+        &prog("(describe 'foo)")                    => Ok(symbol!("foo")),
+        &prog("(describe \"str\")")                 => Ok(symbol!("string")),
+        &prog("(len '(1 2 3))")                     => Ok(int!(3)),
+        &prog("(count-down 100000)")                => Ok(symbol!("done")),
+        "(match '(1 2 3) ((a b . c) (cons a c)))"  => Ok(cons!(int!(1), lisp_list![int!(3)])),
+        "(match 1 (2 2))"                           => Err(RuntimeError::error(err_msgs::MATCH_ERROR)),
+        "(match '(1 1) ((a a) a))",
+        "(match 1 (x #:when))",
+        "(match 1 ())"                              => Err(RuntimeError::error(err_msgs::SYNTAX_ERROR))
+    }
+}
+
+#[test]
+fn test_destructuring() {
+    tests! {
+        "(let (((a b . rest) '(1 2 3 4))) (cons a rest))"   => Ok(lisp_list![int!(1), int!(3), int!(4)]),
+        "(let (([x (y z)] [1 '(2 3)]) (w (+ x y z))) w)"     => Ok(int!(6)),
+        "(define (f [a b] c) (+ a b c)) (f [1 2] 3)"         => Ok(int!(6)),
+        "(define (g [k (v . _)]) (cons k v)) (g ['x '(1 2)])" => Ok(cons!(symbol!("x"), int!(1))),
+        "(let (((a b) '(1))) a)",
+        "(define (f [a b]) a) (f '(1 2))"                    => Err(RuntimeError::error(err_msgs::MATCH_ERROR)),
+        "(let ((1 2)) 1)",
+        "(lambda ([a b] a) a)"                               => Err(RuntimeError::error(err_msgs::SYNTAX_ERROR))
+    }
+}
+
+#[test]
+fn test_case_lambda_guards() {
+    let defs = "(define classify
+                  (case-lambda
+                    ((x) #:when (integer? x) 'int)
+                    ((x) #:when (float? x) 'float)
+                    ((x) 'other)
+                    ((x y) 'two)))
+                (define only-int (case-lambda ((x) #:when (integer? x) x)))";
+    let prog = |body: &str| format!("{} {}", defs, body);
+
+    tests! {
+        &prog("(classify 1)")       => Ok(symbol!("int")),
+        &prog("(classify 1.5)")     => Ok(symbol!("float")),
+        &prog("(classify \"s\")")   => Ok(symbol!("other")),
+        &prog("(classify 1 2)")     => Ok(symbol!("two")),
+        &prog("(only-int 3)")       => Ok(int!(3)),
+        &prog("(only-int \"s\")")   => Err(RuntimeError::error(err_msgs::ARGUMENT_ERROR)),
+        "(case-lambda ((x) #:when))" => Err(RuntimeError::error(err_msgs::SYNTAX_ERROR))
+    }
+}
+
+#[test]
+fn test_rec() {
+    tests! {
+        "((rec (count-down n) (if (equal? n 0) 'done (count-down (- n 1)))) 100000)" => Ok(symbol!("done")),
+        "((rec fact (lambda (n) (if (equal? n 0) 1 (* n (fact (- n 1)))))) 5)"       => Ok(int!(120)),
+        "(car (cdr (cdr (rec ones (lazy-cons 1 ones)))))"                             => Ok(int!(1)),
+        "(rec fact (lambda (n) n)) fact"     => Err(RuntimeError::error(err_msgs::BOUND_ERROR)),
+        "(rec 1 2)",
+        "(rec (1) 2)"                        => Err(RuntimeError::error(err_msgs::SYNTAX_ERROR))
+    }
+}
+
+#[test]
+fn test_general_tail_calls() {
+    let defs = "(define (my-even? n) (if (equal? n 0) true (my-odd? (- n 1))))
+                (define (my-odd? n) (if (equal? n 0) false (my-even? (- n 1))))
+                (define (count-down n) (if (equal? n 0) 'done (alias (- n 1))))
+                (define alias count-down)
+                (define (call-with f x) (f x))
+                (define (bounce n) (if (equal? n 0) 'done (call-with bounce (- n 1))))
+                (define-macro (my-if c a b) `(if ,c ,a ,b))
+                (define (macro-loop n) (my-if (equal? n 0) 'done (macro-loop (- n 1))))
+                (define (shadowed n) (let ((shadowed (lambda (x) 'inner))) (shadowed n)))
+                (define (ping n) (if (equal? n 0) 'done (apply pong (cons (- n 1) nil))))
+                (define (pong n) (if (equal? n 0) 'done (apply apply (cons ping (cons (cons (- n 1) nil) nil)))))";
+    let prog = |body: &str| format!("{} {}", defs, body);
+
+    tests! {
+        &prog("(my-even? 20001)")   => Ok(lisp_false!()),
+        &prog("(count-down 20000)")  => Ok(symbol!("done")),
+        &prog("(bounce 20000)")     => Ok(symbol!("done")),
+        &prog("(macro-loop 20000)")  => Ok(symbol!("done")),
+        &prog("(shadowed 1)")       => Ok(symbol!("inner")),
+        &prog("(ping 20000)")       => Ok(symbol!("done")),
+        &prog("(define (f) (apply car 1 2)) (f)") => Err(RuntimeError::error(err_msgs::ARITY_ERROR))
+    }
+}
+
+#[test]
+fn test_escape_continuations() {
+    let defs = "(define (walk f lst) (if lst (begin (f (car lst)) (walk f (cdr lst))) nil))
+                (define (find-first pred lst)
+                  (call/ec (lambda (return)
+                    (walk (lambda (x) (if (pred x) (return x) nil)) lst)
+                    nil)))
+                (define saved nil)";
+    let prog = |body: &str| format!("{} {}", defs, body);
+
+    tests! {
+        "(call/ec (lambda (k) (+ 1 (k 42))))"                  => Ok(int!(42)),
+        "(call-with-escape-continuation (lambda (k) 1))"       => Ok(int!(1)),
+        "(call/ec (lambda (k) (k)))"                           => Ok(nil!()),
+        &prog("(find-first integer? '(a b 3 c 4))")            => Ok(int!(3)),
+        &prog("(find-first integer? '(a b))")                  => Ok(nil!()),
+        "(call/ec (lambda (outer) (+ 1 (call/ec (lambda (inner) (outer 5))))))"  => Ok(int!(5)),
+        "(call/ec (lambda (outer) (+ 1 (call/ec (lambda (inner) (inner 5))))))"  => Ok(int!(6)),
+        "(call/ec (lambda (k) (catch-error (k 'escaped)) 'caught))"               => Ok(symbol!("escaped")),
+        &prog("(call/ec (lambda (k) (set! saved k))) (saved 1)")
+            => Err(RuntimeError::error(err_msgs::ARGUMENT_ERROR))
+    }
+}
+
+#[test]
+#[cfg(not(feature = "sync"))]
+fn test_generators_need_sync() {
+    tests! {
+        "(make-generator (lambda (yield) (yield 1)))", "(next 1)"  => Err(RuntimeError::error(err_msgs::ENVIRONMENT_ERROR))
+    }
+}
+
+#[test]
+#[cfg(feature = "sync")]
+fn test_generators() {
+    let defs = "(define (count-from n yield) (yield n) (count-from (+ n 1) yield))
+                (define g (make-generator (lambda (yield) (yield 1) (yield 2))))
+                (define naturals (make-generator (lambda (yield) (count-from 0 yield))))
+                (define saved nil)
+                (define runs 0)
+                (define counted (make-generator (lambda (yield)
+                                  (set! runs (+ runs 1)) (yield 'a)
+                                  (set! runs (+ runs 1)) (yield 'b))))";
+    let prog = |body: &str| format!("{} {}", defs, body);
+
+    tests! {
+        &prog("(next g)")                              => Ok(int!(1)),
+        &prog("(next g) (next g)")                     => Ok(int!(2)),
+        &prog("(next g) (next g) (next g)")            => Ok(nil!()),
+        &prog("(next g) (next g) (next g 'done)")      => Ok(symbol!("done")),
+        &prog("(next naturals) (next naturals) (next naturals)")  => Ok(int!(2)),
+        "(record? (make-generator (lambda (yield) nil)) 'generator)"  => Ok(lisp_true!()),
+        "(call/ec (lambda (k) (next (make-generator (lambda (yield) (k 'out))))))"  => Ok(symbol!("out")),
+        "(next 1)"                                     => Err(RuntimeError::error(err_msgs::TYPE_ERROR)),
+        "(make-generator 1)"                           => Err(RuntimeError::error(err_msgs::TYPE_ERROR)),
+        &prog("(next (make-generator (lambda (yield) (set! saved yield) (yield 1)))) (saved 2)")
+            => Err(RuntimeError::error(err_msgs::ARGUMENT_ERROR)),
+        // The producer is suspended, not rerun, so its side effects happen once
+        &prog("(next counted) runs")                   => Ok(int!(1)),
+        &prog("(next counted) (next counted) runs")    => Ok(int!(2)),
+        &prog("(next counted) (next counted) (next counted) (next counted) runs")  => Ok(int!(2)),
+        &prog("(define (drain n) (if (equal? n 0) (next naturals) (begin (next naturals) (drain (- n 1))))) (drain 10000)")
+            => Ok(int!(10000)),
+        &prog("(define (pass-on yield) (yield (next naturals)) (pass-on yield))
+               (define evens (make-generator pass-on))
+               (next naturals) (next evens) (next naturals) (next evens)")  => Ok(int!(3)),
+        &prog("(call/ec (lambda (k) (next (make-generator (lambda (yield) (yield 1) (k 'out)))) (next g) 'kept))")
+            => Ok(symbol!("kept")),
+        &prog("(define k-gen nil)
+               (call/ec (lambda (k) (set! k-gen (make-generator (lambda (yield) (yield 1) (k 'out)))) (next k-gen)))
+               (call/ec (lambda (k) (next k-gen)))")  => Err(RuntimeError::error(err_msgs::ARGUMENT_ERROR)),
+        &prog("(define forever (make-generator (lambda (yield) (count-from 0 (lambda (n) n)))))
+               (with-limit 10000 (next forever))")  => Err(RuntimeError::error(err_msgs::RESOURCE_ERROR)),
+        &prog("(define (again) (next self)) (define self (make-generator (lambda (yield) (yield (again)))))
+               (next self)")  => Err(RuntimeError::error(err_msgs::IO_ERROR))
+    }
+
+    // A producer writes to the output of the next it runs for
+    let out = Capture::new();
+    let mut runner = super::Evaluator::new().with_stdout(out.clone());
+    let src = "(define g (make-generator (lambda (yield) (print 'a) (yield 1) (print 'b) (yield 2)))) (next g) (next g)";
+    runner.eval_all_from_parser(Parser::from_string(src, "<test>")).unwrap();
+    assert_eq!(out.contents(), "ab");
+
+    // Dropping a suspended generator stops its producer
+    tests! {
+        "(define g (make-generator (lambda (yield) (yield 1) (yield 2)))) (next g) (set! g nil) 'dropped"
+            => Ok(symbol!("dropped"))
+    }
+
+    // A running producer's state can't be saved in an image
+    assert!(image_round_trip(&prog("(next naturals)"), "naturals").is_err());
+}
+
+
+#[test]
+fn test_gc() {
+    // Each program first collects whatever earlier tests on this thread leaked
+    tests! {
+        "(gc) (let ((f (lambda () f))) 'leaked) (gc)"                   => Ok(int!(1)),
+        "(gc) ((rec (loop n) (if (equal? n 0) 'done (loop (- n 1)))) 3) (gc)"  => Ok(int!(1)),
+        "(gc) (let ((f (lambda () f))) 'leaked) (gc) (gc)"              => Ok(int!(0)),
+        "(gc) (let ((f (lambda () f))) 'leaked) (cdr (car (cdr (gc-stats))))"  => Ok(int!(1)),
+        "(gc) (define kept (let ((f (lambda () f))) f)) (gc) (procedure? (kept))"  => Ok(lisp_true!()),
+        "(gc) (define lst (let ((f (lambda () f))) (cons f nil))) (gc) (procedure? ((car lst)))"  => Ok(lisp_true!()),
+        "(gc) (define (mk) (let ((g (lambda (x) (if x (g nil) 'ok)))) g)) (define h (mk)) (gc) (h 1)"
+            => Ok(symbol!("ok"))
+    }
+}
+
+#[test]
+fn test_first_class_environments() {
+    tests! {
+        "(define env (make-environment)) (eval '(define y 1) env) (eval 'y env)"  => Ok(int!(1)),
+        "(define env (make-environment)) (environment-define env 'double (lambda (x) (* 2 x)))
+         (eval '(double 4) env)"                                                 => Ok(int!(8)),
+        "(let ((x 1)) (eval 'x (current-environment)))"                          => Ok(int!(1)),
+        "(define x 2) (eval '(+ x 1) (make-environment (current-environment)))" => Ok(int!(3)),
+        "(define child (make-environment (current-environment)))
+         (environment-define child 'local 1)
+         (bound? 'local)"                                                        => Ok(lisp_false!()),
+        "(environment? (make-environment))"                                      => Ok(lisp_true!()),
+        "(define env (make-environment)) (equal? env env)"                       => Ok(lisp_true!()),
+        "(define env (make-environment)) (eval '(define y 1) env) y",
+        "(define x 2) (eval 'x (make-environment))"                              => Err(RuntimeError::error(err_msgs::BOUND_ERROR)),
+        "(eval 1 2)", "(environment-define 1 'x 2)"                              => Err(RuntimeError::error(err_msgs::TYPE_ERROR))
+    }
+
+    // Environments keep their bindings through an image
+    let res = image_round_trip("(define env (make-environment (current-environment)))
+                                (environment-define env 'x 3)", "(eval 'x env)");
+    assert_eq!(res.unwrap(), int!(3).to_obj_ref());
+}
+
+#[test]
+fn test_sandbox() {
+    let run_sandboxed = |prog: &str| {
+        let mut runner = super::Evaluator::new_sandboxed();
+        runner.eval_all_from_parser(Parser::from_string(prog, "<test>"))
+    };
+    assert_eq!(run_sandboxed("(+ 1 2)").unwrap(), int!(3).to_obj_ref());
+    assert_eq!(run_sandboxed("(bound? 'system)").unwrap(), lisp_false!().to_obj_ref());
+    assert_eq!(run_sandboxed("(eval '(bound? 'delete-file) (make-environment))").unwrap(),
+               lisp_false!().to_obj_ref());
+    assert_eq!(run_sandboxed("(load-file \"script.lisp\")").unwrap_err().errname, err_msgs::BOUND_ERROR);
+    // Reading stdin would block past the time limit
+    assert_eq!(run_sandboxed("(bound? 'read)").unwrap(), lisp_false!().to_obj_ref());
+    assert_eq!(run_sandboxed("(read)").unwrap_err().errname, err_msgs::BOUND_ERROR);
+    // Sandboxed code can't end the host process, or start threads
+    assert_eq!(run_sandboxed("(exit 1)").unwrap_err().errname, err_msgs::BOUND_ERROR);
+    assert_eq!(run_sandboxed("(bound? 'make-generator)").unwrap(), lisp_false!().to_obj_ref());
+    assert_eq!(run_sandboxed("(bound? 'next)").unwrap(), lisp_false!().to_obj_ref());
+
+    // Limits
+    let mut runner = super::Evaluator::new().with_step_limit(10000);
+    let res = runner.eval_all_from_parser(Parser::from_string("(define (loop) (loop)) (loop)", "<test>"));
+    assert_eq!(res.unwrap_err().errname, err_msgs::RESOURCE_ERROR);
+    let res = runner.eval_all_from_parser(Parser::from_string("(+ 1 2)", "<test>"));
+    assert_eq!(res.unwrap(), int!(3).to_obj_ref());
+
+    let run_limited = |prog: &str, limits: Limits| {
+        let mut runner = super::Evaluator::new();
+        limits::with_limits(limits, || runner.eval_all_from_parser(Parser::from_string(prog, "<test>")))
+    };
+    let steps = Limits { steps: Some(10000), time: None };
+    let time = Limits { steps: None, time: Some(Duration::from_millis(50)) };
+    let looping = "(define (loop) (loop)) (loop)";
+    assert_eq!(run_limited("(+ 1 2)", steps).unwrap(), int!(3).to_obj_ref());
+    assert_eq!(run_limited(looping, steps).unwrap_err().errname, err_msgs::RESOURCE_ERROR);
+    assert_eq!(run_limited(looping, time).unwrap_err().errname, err_msgs::RESOURCE_ERROR);
+    // Waiting on a channel counts against the time limit
+    let start = ::std::time::Instant::now();
+    let mut runner = super::Evaluator::new_sandboxed();
+    let res = limits::with_limits(time, || runner.eval_all_from_parser(Parser::from_string("(recv (channel))", "<test>")));
+    assert_eq!(res.unwrap_err().errname, err_msgs::RESOURCE_ERROR);
+    assert!(start.elapsed() < Duration::from_secs(5));
+    let caught = "(define (loop) (loop)) (catch-error (loop)) 'survived";
+    assert_eq!(run_limited(caught, steps).unwrap_err().errname, err_msgs::RESOURCE_ERROR);
+}
+
+#[test]
+fn test_check_type_evaluates_once() {
+    let calls = ::std::cell::Cell::new(0);
+    let value = || {
+        calls.set(calls.get() + 1);
+        symbol!("a").to_obj_ref()
+    };
+    let check = || -> EvalResult<i64> { Ok(check_type!(value(), LInteger)) };
+    assert_eq!(check().unwrap_err().errname, err_msgs::TYPE_ERROR);
+    assert_eq!(calls.get(), 1);
+}
+
+#[test]
+fn test_with_limit() {
+    let defs = "(define (loop) (loop))
+                (define (count-down n) (if (equal? n 0) 'done (count-down (- n 1))))";
+    let prog = |body: &str| format!("{} {}", defs, body);
+
+    tests! {
+        "(with-limit 1000 (+ 1 2))"                                 => Ok(int!(3)),
+        &prog("(with-limit 1000 (count-down 10))")                  => Ok(symbol!("done")),
+        &prog("(error-type (catch-error (with-limit 1000 (loop))))")  => Ok(symbol!("resource-error")),
+        &prog("(catch-error (with-limit 1000 (loop))) (count-down 1000)")  => Ok(symbol!("done")),
+        &prog("(with-limit 1000 (count-down 10000))"),
+        &prog("(with-limit 100000 (with-limit 1000 (count-down 10000)))"),
+        &prog("(with-limit 1000 (with-limit 100000 (count-down 10000)))")  => Err(RuntimeError::error(err_msgs::RESOURCE_ERROR)),
+        "(with-limit -1 1)"                                         => Err(RuntimeError::error(err_msgs::ARGUMENT_ERROR)),
+        "(with-limit 'a 1)"                                         => Err(RuntimeError::error(err_msgs::TYPE_ERROR)),
+        // The limit is evaluated once, even when it has the wrong type
+        "(define n 0) (with-limit (begin (set! n (+ n 1)) 1000) n)"  => Ok(int!(1)),
+        "(define n 0) (catch-error (with-limit (begin (set! n (+ n 1)) 'a) 1)) n"  => Ok(int!(1)),
+        "(with-limit 10)"                                           => Err(RuntimeError::error(err_msgs::SYNTAX_ERROR))
+    }
+}
+
+#[test]
+fn test_number_printing_round_trips() {
+    // The reader reads back what the printer writes, as the same type
+    for &num in [1.0, -0.5, 100.0, 1e300, 2.5e-10, 1.0 / 0.0, -1.0 / 0.0].iter() {
+        run_test(&format!("{}", float!(num)), Ok(float!(num)));
+    }
+    run_test(&format!("{}", int!(i64::min_value())), Ok(int!(i64::min_value())));
+
+    let arg_err = RuntimeError::error(err_msgs::ARGUMENT_ERROR);
+    tests! {
+        "(float? +nan)"                     => Ok(lisp_true!()),
+        "'(+ - -a +b +infinite)"            => Ok(lisp_list![symbol!("+"), symbol!("-"), symbol!("-a"),
+                                                             symbol!("+b"), symbol!("+infinite")]),
+        "(number->string 1.0)"              => Ok(string!("1.0")),
+        "(number->string 42)"               => Ok(string!("42")),
+        "(number->string 255 16)"           => Ok(string!("ff")),
+        "(number->string -5 2)"             => Ok(string!("-101")),
+        "(number->string 3.14159 10 2)"     => Ok(string!("3.14")),
+        "(number->string 2 10 1)"           => Ok(string!("2.0")),
+        "(number->string 1.5 2)", "(number->string 1 37)", "(number->string 1 10 -1)" => Err(arg_err.clone()),
+        "(number->string 'a)"               => Err(RuntimeError::error(err_msgs::TYPE_ERROR))
+    }
+}
+
+#[test]
+fn test_printing_deep_nesting() {
+    // Lists and vectors are printed, compared and freed without recursion, so nesting
+    // them deeply doesn't overflow the stack
+    let mut obj = int!(1);
+    for _ in 0..100000 {
+        obj = lisp_list![obj, LispObj::make_vector([int!(2)].iter().cloned())];
+    }
+    let printed = format!("{}", obj);
+    assert!(printed.starts_with(&"(".repeat(100000)));
+    assert!(printed.contains("((1 [2]) [2])"));
+    assert!(printed.ends_with("[2]) [2])"));
+    assert!(obj == obj.clone());
+
+    let mut long = nil!().to_obj_ref();
+    for i in 0..100000 {
+        long = cons!(int!(i), long).to_obj_ref();
+    }
+    assert!(long == long.clone());
+    drop(long);
+
+    // Parsing and quasiquoting deeply nested forms
+    let nested = format!("`{}x{}", "(".repeat(100000), ")".repeat(100000));
+    let mut runner = super::Evaluator::new();
+    let res = runner.eval_all_from_parser(Parser::from_string(&nested, "<test>")).unwrap();
+    assert_eq!(format!("{}", res), format!("{}x{}", "(".repeat(100000), ")".repeat(100000)));
+}
+
+#[test]
+fn test_deep_nesting_in_heads_and_vectors() {
+    // Lists nested in their heads and vectors nested in vectors are compared and freed
+    // without recursion too. A small stack makes any recursion fail quickly.
+    let run = ::std::thread::Builder::new().stack_size(256 * 1024).spawn(|| {
+        let head_nested = || {
+            let mut obj = int!(1).to_obj_ref();
+            for _ in 0..100000 {
+                obj = cons!(obj, nil!()).to_obj_ref();
+            }
+            obj
+        };
+        let vec_nested = |last: i64| {
+            let mut obj = int!(last).to_obj_ref();
+            for _ in 0..100000 {
+                obj = LispObj::make_vector(::std::iter::once(obj)).to_obj_ref();
+            }
+            obj
+        };
+        assert!(head_nested() == head_nested());
+        assert!(vec_nested(1) == vec_nested(1));
+        assert!(vec_nested(1) != vec_nested(2));
+        drop(vec_nested(1));
+    }).unwrap();
+    run.join().unwrap();
+}
+
+#[test]
+fn test_char_printing_round_trips() {
+    for &ch in ['a', '5', '\\', '(', ';', '"', ' ', '\0', '\r', '\x01', '\x7f', '\u{e9}'].iter() {
+        run_test(&format!("{}", LispObj::LChar(ch)), Ok(LispObj::LChar(ch)));
+    }
+    assert_eq!(format!("{}", LispObj::LChar('\x01')), "\\x1");
+    assert_eq!(format!("{}", LispObj::LChar('\r')), "\\return");
+
+    tests! {
+        "(symbol->char 'nul)"   => Ok(LispObj::LChar('\0')),
+        "(symbol->char 'x41)"   => Ok(LispObj::LChar('A')),
+        "(symbol->char 'x)"     => Ok(LispObj::LChar('x')),
+        "(symbol->char 'xyz)", "(symbol->char 'ab)" => Err(RuntimeError::error(err_msgs::ARGUMENT_ERROR))
+    }
+}
+
+#[test]
+fn test_procedure_arguments_type_checked() {
+    tests! {
+        "(generate-vector 2 (lambda (i) (* i i)))"  => Ok(LispObj::make_vector([int!(0), int!(1)].iter().cloned())),
+        "(call/ec car)"                             => Err(RuntimeError::error(err_msgs::TYPE_ERROR)),
+        "(generate-vector 2 'a)", "(call/ec 5)"
+            => Err(RuntimeError::error(err_msgs::TYPE_ERROR))
+    }
+}
+
+#[test]
+fn test_builtin_optional_and_rest_arguments() {
+    let arity_err = RuntimeError::error(err_msgs::ARITY_ERROR);
+    tests! {
+        "(bytes->list (make-bytes 2))"          => Ok(lisp_list![int!(0), int!(0)]),
+        "(bytes->list (make-bytes 2 7))"        => Ok(lisp_list![int!(7), int!(7)]),
+        "(number->string 10 2)"                 => Ok(string!("1010")),
+        "(eval '(+ 1 2) (make-environment))"    => Ok(int!(3)),
+        "(record-get (make-record 'p '(x y) 1 2) 'y)" => Ok(int!(2)),
+        "(error-value (make-error 'oops 5))"    => Ok(int!(5)),
+        "(make-bytes)", "(make-bytes 1 2 3)", "(eval)", "(eval 1 (make-environment) 3)",
+        "(make-record 'p)", "(exit 1 2)", "(meta)", "(builder-append!)"
+            => Err(arity_err.clone())
+    }
+}
+
+#[test]
+fn test_error_details() {
+    tests! {
+        "(error-detail (catch-error (car 1)) 'expected)"            => Ok(symbol!("cons")),
+        "(error-detail (catch-error (car 1)) 'args)"                => Ok(lisp_list![int!(1)]),
+        "(error-detail (catch-error (car 1)) 'message)"             => Ok(string!("car: expected cons, got 1")),
+        "(error-detail (catch-error (vector-ref [1] 'a)) 'expected)" => Ok(symbol!("int")),
+        "(error-detail (catch-error (+ 1 2.0 \"3\")) 'args)"        => Ok(lisp_list![string!("3")]),
+        "(error-detail (catch-error (gensym 'a 'b)) 'args)"         => Ok(lisp_list![symbol!("a"), symbol!("b")]),
+        "(error-detail (catch-error (gensym 'a 'b)) 'expected)"     => Ok(nil!()),
+        "(error-detail (make-error 'oops 5) 'args)"                 => Ok(nil!()),
+        "(error-value (make-error 'oops \"message\"))"              => Ok(string!("message")),
+        "(error-detail 1 'args)"                                    => Err(RuntimeError::error(err_msgs::TYPE_ERROR))
+    }
+
+    let err = RuntimeError::error(err_msgs::TYPE_ERROR).with_value(string!("bad"))
+                  .with_details(vec![int!(1).to_obj_ref()], Some("string"));
+    assert_eq!(err.detail("message"), Some(string!("bad").to_obj_ref()));
+    assert_eq!(err.detail("expected"), Some(symbol!("string").to_obj_ref()));
+    assert_eq!(err.detail("cause"), None);
+}
+
+#[test]
+fn test_logging() {
+    use std::fs;
+    use std::io::Read;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use ::evaluator::log::{self, Level};
+
+    let to_builder = |body: &str| format!("(define b (make-string-builder)) (set! *log-port* b) {} (builder->string b)", body);
+    tests! {
+        &to_builder("(log-info \"loaded \" 3 \" files\")")         => Ok(string!("[info] loaded 3 files\n")),
+        &to_builder("(log-debug \"hidden\") (log-error 'failed)")  => Ok(string!("[error] failed\n")),
+        &to_builder("(set! *log-level* 'debug) (log-debug 1)")     => Ok(string!("[debug] 1\n")),
+        &to_builder("(set! *log-level* 'error) (log-warn 1)")      => Ok(string!("")),
+        "(log-debug \"hidden\")"                                   => Ok(lisp_false!()),
+        "(set! *log-level* 'loud) (log-error 1)"                    => Err(RuntimeError::error(err_msgs::ARGUMENT_ERROR))
+    }
+
+    let path = ::std::env::temp_dir().join("rustylisp-test.log");
+    let _ = fs::remove_file(&path);
+    run_test(&format!("(set! *log-port* (open-log-file \"{0}\")) (log-warn 'one)
+                       (set! *log-port* (open-log-file \"{0}\")) (log-warn 'two)", path.display()),
+             Ok(lisp_true!()));
+    let mut logged = String::new();
+    fs::File::open(&path).unwrap().read_to_string(&mut logged).unwrap();
+    assert_eq!(logged, "[warn] one\n[warn] two\n");
+    fs::remove_file(&path).unwrap();
+
+    // Without a port, messages go to the host's handler
+    static HANDLED: AtomicUsize = AtomicUsize::new(0);
+    fn handler(level: Level, msg: &str) {
+        if level == Level::Warn && msg == "to the host" {
+            HANDLED.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+    log::set_handler(Some(handler));
+    run_test("(log-warn \"to the host\")", Ok(lisp_true!()));
+    log::set_handler(None);
+    assert_eq!(HANDLED.load(Ordering::SeqCst), 1);
+}
+
+/// A stream which output can be sent to, and read back from
+#[derive(Clone)]
+struct Capture(Arc<Mutex<Vec<u8>>>);
+
+impl Capture {
+    fn new() -> Self {
+        Capture(Arc::new(Mutex::new(vec![])))
+    }
+
+    fn contents(&self) -> String {
+        String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+    }
+}
+
+impl Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_output_streams() {
+    let (out, err) = (Capture::new(), Capture::new());
+    let mut runner = super::Evaluator::new().with_stdout(out.clone()).with_stderr(err.clone());
+    let src = "(print \"a\" 1) (println 'b) (format true \"~d items~%\" 3) (time 1)
+               (dump-traceback (catch-error (car 1)))
+               (log-warn \"careful\")";
+    runner.eval_all_from_parser(Parser::from_string(src, "<test>")).unwrap();
+    let printed = out.contents();
+    assert!(printed.starts_with("a1b\n3 items\ntime: "), "{}", printed);
+    let errors = err.contents();
+    assert!(errors.starts_with("type-error: \"car: expected cons, got 1\"\n"), "{}", errors);
+    assert!(errors.ends_with("[warn] careful\n"), "{}", errors);
+
+    assert!(runner.eval_all_from_parser(Parser::from_string("(+ 1", "<test>")).is_err());
+    assert!(err.contents().ends_with("error on input: <test>\n"));
+
+    let car_err = runner.eval_all_from_parser(Parser::from_string("(car 1)", "<test>")).unwrap_err();
+    assert!(car_err.traceback_string().starts_with("type-error: \"car: expected cons, got 1\"\n\tfrom "),
+            "{}", car_err.traceback_string());
+    let not_err = runner.eval_all_from_parser(Parser::from_string("(dump-traceback 1)", "<test>")).unwrap_err();
+    assert_eq!(not_err.errname, err_msgs::TYPE_ERROR);
+
+    // *error-formatter* renders errors for dump-traceback and the host
+    let src = "(set! *error-formatter* (lambda (e) (format \"oops: ~a\" (error-type e))))
+               (dump-traceback (catch-error (car 1)))";
+    runner.eval_all_from_parser(Parser::from_string(src, "<test>")).unwrap();
+    assert!(err.contents().ends_with("oops: type-error\n"), "{}", err.contents());
+    runner.write_error(&RuntimeError::error("io-error"));
+    assert!(err.contents().ends_with("oops: io-error\n"), "{}", err.contents());
+
+    // A failing formatter falls back to the traceback
+    runner.eval_all_from_parser(Parser::from_string("(set! *error-formatter* car)", "<test>")).unwrap();
+    runner.write_error(&RuntimeError::error("io-error"));
+    assert!(err.contents().contains("io-error: \nerror in *error-formatter*:\ntype-error: "), "{}", err.contents());
+}
+
+#[test]
+fn test_load_from_file() {
+    use std::fs::{self, File};
+    use std::io::Write;
+
+    let mut runner = super::Evaluator::new();
+    let missing = runner.load_from_file("/nonexistent/rustylisp-test.lisp").unwrap_err();
+    assert_eq!(missing.errname, err_msgs::IO_ERROR);
+
+    // The file is evaluated from its own directory, which is left afterwards
+    let dir = ::std::env::temp_dir().join("rustylisp-test-load");
+    let _ = fs::create_dir(&dir);
+    let path = dir.join("script.lisp");
+    File::create(&path).unwrap().write_all(b"(define x 2) (current-directory)").unwrap();
+    let before = ::std::env::current_dir().unwrap();
+    let res = runner.load_from_file(&path).unwrap();
+    assert_eq!(res, string!(dir.canonicalize().unwrap().to_str().unwrap()).to_obj_ref());
+    assert_eq!(::std::env::current_dir().unwrap(), before);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_try_unwrap() {
+    assert_eq!(symbol!("a").try_unwrap_symbol().unwrap(), "a");
+    let not_symbol = string!("a").try_unwrap_symbol().unwrap_err();
+    assert_eq!(not_symbol.errname, err_msgs::TYPE_ERROR);
+    assert_eq!(not_symbol.detail("expected"), Some(symbol!("symbol").to_obj_ref()));
+
+    assert_eq!(LispObj::LInteger(1).try_unwrap_proc().unwrap_err().errname, err_msgs::TYPE_ERROR);
+    assert_eq!(nil!().try_unwrap_native().unwrap_err().errname, err_msgs::TYPE_ERROR);
+    tests! {
+        "(apply 1 '(2))", "((quote car) '(1))" => Err(RuntimeError::error(err_msgs::TYPE_ERROR))
+    }
+}
+
+#[test]
+fn test_native_and_invalid_macros() {
+    use ::core::{Environment, EnvironmentRef, LispObjRef};
+
+    // Expands (first-of a b ...) to a
+    fn first_of(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+        Ok(args[0].clone())
+    }
+
+    let env = ::evaluator::default_environment().to_env_ref();
+    let native = LispObj::make_native("first-of", first_of, None);
+    assert!(env.borrow_mut().let_macro(String::from("first-of"), native.to_obj_ref()).unwrap().is_none());
+    let err = env.borrow_mut().let_macro(String::from("three"), int!(3).to_obj_ref()).unwrap_err();
+    assert_eq!(err.errname, err_msgs::MACRO_ERROR);
+
+    let mut runner = super::Evaluator::from_existing(env);
+    let res = runner.eval_all_from_parser(Parser::from_string("(first-of (+ 1 2) (car 1))", "<test>"));
+    assert_eq!(res.unwrap(), int!(3).to_obj_ref());
+
+    // Macros bound without let_macro's checks still fail cleanly
+    let env = Environment::new().with_macros(vec![(String::from("three"), int!(3).to_obj_ref())].into_iter());
+    let mut runner = super::Evaluator::from_existing(env.to_env_ref());
+    let err = runner.eval_all_from_parser(Parser::from_string("(three)", "<test>")).unwrap_err();
+    assert_eq!(err.errname, err_msgs::MACRO_ERROR);
+}
+
+#[test]
+fn test_internal_defines() {
+    tests! {
+        "(define (f x) (define y (* x 2)) (+ x y)) (f 3)"                        => Ok(int!(9)),
+        "(define (f) (define y 1) y) (f) (bound? 'y)"                            => Ok(lisp_false!()),
+        "(define y 'outer) (define (f) (define y 'inner) y) (f) y"               => Ok(symbol!("outer")),
+        "(define (parity n)
+           (define (even? n) (if (equal? n 0) true (odd? (- n 1))))
+           (define (odd? n) (if (equal? n 0) false (even? (- n 1))))
+           (even? n))
+         (parity 10)"                                                            => Ok(lisp_true!()),
+        "(define (f) (define (g) h) (define h 'later) (g)) (f)"                  => Ok(symbol!("later")),
+        "(define (f x) (define x (+ x 1)) x) (f 1)"                              => Ok(int!(2)),
+        "(let ((a 1)) (define b (+ a 1)) b)"                                     => Ok(int!(2)),
+        "(let ((a 1)) (define b 2) b) (bound? 'b)"                               => Ok(lisp_false!()),
+        "(define (f) (define g \"docs\" 1) (doc 'g)) (f)"                        => Ok(string!("docs")),
+        "(begin (define top 1)) top"                                             => Ok(int!(1)),
+        "(define x 1) (define x 2)"                                              => Err(RuntimeError::error(err_msgs::REDEFINE_ERROR))
+    }
+}
+
+#[test]
+fn test_set_places() {
+    tests! {
+        "(define v [1 2 3]) (set! (vector-ref v 0) 'a) v"                      => Ok(LispObj::LVector(vec![symbol!("a").to_obj_ref(), int!(2).to_obj_ref(), int!(3).to_obj_ref()].into_iter().collect())),
+        "(define v [1 2 3]) (set! (vector-ref v 1) 'b)"                        => Ok(int!(2)),
+        "(define v [1 2 3]) (define w v) (set! (vector-ref v 0) 'a) (vector-ref w 0)" => Ok(int!(1)),
+        "(define m [[1 2] [3 4]]) (set! (vector-ref (vector-ref m 1) 0) 'x) (vector-ref (vector-ref m 1) 0)" => Ok(symbol!("x")),
+        "(define-record point (x y)) (define p (make-point 1 2)) (set! (record-get p 'y) 5) (record-get p 'y)" => Ok(int!(5)),
+        "(define-record point (x y)) (define p [(make-point 1 2)])
+         (set! (record-get (vector-ref p 0) 'x 'point) 7) (point-x (vector-ref p 0))" => Ok(int!(7)),
+        "(let ((v [0])) (set! (vector-ref v 0) 1) v)"                          => Ok(LispObj::LVector(vec![int!(1).to_obj_ref()].into_iter().collect())),
+        "(set! (vector-ref undefined 0) 1)"                                    => Err(RuntimeError::error(err_msgs::BOUND_ERROR)),
+        "(set! (car x) 1)", "(set! (vector-ref) 1)", "(set! 1 2)"              => Err(RuntimeError::error(err_msgs::SYNTAX_ERROR)),
+        "(define v [[1]]) (set! (vector-ref (vector-ref v 0) 0) (car 1))"      => Err(RuntimeError::error(err_msgs::TYPE_ERROR)),
+        "(define p 1) (set! (record-get p 'x) 1)"                              => Err(RuntimeError::error(err_msgs::TYPE_ERROR))
+    }
+
+    // The traceback shows each enclosing place, ending with the one passed to set!
+    let mut runner = super::Evaluator::new();
+    let src = "(define-record point (x y)) (define v [(make-point 1 2)]) (set! (record-get (vector-ref v 0) 'z) 1)";
+    let err = runner.eval_all_from_parser(Parser::from_string(src, "<test>")).unwrap_err();
+    assert_eq!(err.errname, err_msgs::ARGUMENT_ERROR);
+    let sources: Vec<String> = err.into_traceback().into_iter().map(|e| format!("{}", e.source.unwrap())).collect();
+    assert_eq!(sources, vec!["(vector-ref v 0)", "(record-get (vector-ref v 0) (quote z))"]);
+}
+
+#[test]
+fn test_define_bang() {
+    tests! {
+        "(define x 1) (define! x 2) x"                            => Ok(int!(2)),
+        "(define! y 1) y"                                         => Ok(int!(1)),
+        "(define! (f) 'new) (define! (f) 'newer) (f)"             => Ok(symbol!("newer")),
+        "(define x 1) (define! x \"docs\" 2) (doc 'x)"            => Ok(string!("docs")),
+        "(define x 1) (define x 2)"                               => Err(RuntimeError::error(err_msgs::REDEFINE_ERROR)),
+        "(define)", "(define!)", "(define! 1 2)"                  => Err(RuntimeError::error(err_msgs::SYNTAX_ERROR))
+    }
+}
+
+#[test]
+fn test_allow_redefine_per_file() {
+    use std::fs::{self, File};
+    use std::io::Write;
+
+    let dir = ::std::env::temp_dir().join("rustylisp-test-redefine");
+    let _ = fs::create_dir(&dir);
+    let path = dir.join("defs.lisp");
+    File::create(&path).unwrap().write_all(b"(set! *allow-redefine* true) (define x 1) (define x 2)").unwrap();
+    let path = path.display();
+
+    tests! {
+        &format!("(load-file \"{0}\") (load-file \"{0}\") x", path)                  => Ok(int!(2)),
+        &format!("(load-file \"{}\") *allow-redefine*", path)                          => Ok(lisp_false!()),
+        &format!("(load-file \"{}\") (define x 3)", path)                              => Err(RuntimeError::error(err_msgs::REDEFINE_ERROR)),
+        &format!("(set! *allow-redefine* true) (load-file \"{}\") *allow-redefine*", path) => Ok(lisp_true!())
+    }
+
+    let mut runner = super::Evaluator::new();
+    runner.load_from_file(dir.join("defs.lisp")).unwrap();
+    let res = runner.eval_all_from_parser(Parser::from_string("*allow-redefine*", "<test>"));
+    assert_eq!(res.unwrap(), lisp_false!().to_obj_ref());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_reload() {
+    use std::fs::{self, File};
+    use std::io::Write;
+
+    let dir = ::std::env::temp_dir().join("rustylisp-test-reload");
+    let _ = fs::create_dir(&dir);
+    let path = dir.join("lib.lisp");
+    let write_lib = |src: &str| File::create(&path).unwrap().write_all(src.as_bytes()).unwrap();
+    write_lib("(define x 1) (define (f) 'old) (define-macro (m) ''old-macro) (define-record point (x y))");
+
+    let mut runner = super::Evaluator::new();
+    let mut run = |src: &str| runner.eval_all_from_parser(Parser::from_string(src, "<test>"));
+    let load = format!("(load-file \"{}\")", path.display());
+    let reload = format!("(reload \"{}\")", path.display());
+    run(&load).unwrap();
+    assert_eq!(run(&load).unwrap_err().errname, err_msgs::REDEFINE_ERROR);
+
+    write_lib("(define x 2) (define (f) 'new) (define-macro (m) ''new-macro) (define-record point (x y))
+               (define y 3)");
+    run(&reload).unwrap();
+    assert_eq!(run("(cons x (cons (f) (cons (m) nil)))").unwrap(), lisp_list![int!(2), symbol!("new"), symbol!("new-macro")].to_obj_ref());
+
+    // Names the file didn't define before still can't be redefined
+    run("(define z 0)").unwrap();
+    write_lib("(define z 1)");
+    assert_eq!(run(&reload).unwrap_err().errname, err_msgs::REDEFINE_ERROR);
+
+    let files = run("*loaded-files*").unwrap();
+    let (_, names) = files.cons_split().and_then(|(entry, _)| entry.cons_split()).unwrap();
+    let names: Vec<_> = names.list_iter().map(|name| format!("{}", name.unwrap())).collect();
+    for name in ["x", "f", "m", "make-point", "y"].iter() {
+        assert!(names.iter().any(|n| n == name), "{} not in {:?}", name, names);
+    }
+    assert!(!names.iter().any(|n| n == "z"));
+    assert!(run("(reload \"/nonexistent/rustylisp-test.lisp\")").is_err());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_source_file() {
+    use std::fs::{self, File};
+    use std::io::Write;
+
+    let dir = ::std::env::temp_dir().join("rustylisp-test-source-file");
+    let _ = fs::create_dir(&dir);
+    let (main, lib) = (dir.join("main.lisp"), dir.join("lib.lisp"));
+    File::create(&lib).unwrap().write_all(b"(define lib-file (source-file)) (define (fail) (car 1))").unwrap();
+    let main_src = format!("(define before (source-file)) (load-file \"{}\") (define after (source-file))",
+                           dir.join("lib").display());
+    File::create(&main).unwrap().write_all(main_src.as_bytes()).unwrap();
+
+    let mut runner = super::Evaluator::new();
+    runner.load_from_file(&main).unwrap();
+    let canon = |path: &::std::path::Path| string!(path.canonicalize().unwrap().to_str().unwrap());
+    assert_eq!(*eval_in(&mut runner, "before").unwrap(), canon(&main));
+    assert_eq!(*eval_in(&mut runner, "lib-file").unwrap(), canon(&lib));
+    assert_eq!(*eval_in(&mut runner, "after").unwrap(), canon(&main));
+    assert_eq!(*eval_in(&mut runner, "(source-file)").unwrap(), nil!());
+
+    // Errors name the file by its absolute path
+    File::create(&lib).unwrap().write_all(b"(car 1)").unwrap();
+    let err = eval_in(&mut runner, &format!("(reload \"{}\")", lib.display())).unwrap_err();
+    assert_eq!(err.errname, err_msgs::TYPE_ERROR);
+    let traceback = err.traceback_string();
+    assert!(traceback.contains(&format!("\tfrom (reload {})", canon(&lib))), "{}", traceback);
+    let missing = runner.load_from_file("rustylisp-test-missing.lisp").unwrap_err();
+    let msg = format!("{}", missing.value.unwrap());
+    assert!(msg.contains("cannot open file /") && msg.contains("/rustylisp-test-missing.lisp"), "{}", msg);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_circular_load() {
+    use std::fs::{self, File};
+    use std::io::Write;
+
+    let dir = ::std::env::temp_dir().join("rustylisp-test-circular-load");
+    let _ = fs::create_dir(&dir);
+    let (a, b) = (dir.join("a.lisp"), dir.join("b.lisp"));
+    File::create(&a).unwrap().write_all(format!("(load-file \"{}\")", b.display()).as_bytes()).unwrap();
+    File::create(&b).unwrap().write_all(format!("(load-file \"{}\")", a.display()).as_bytes()).unwrap();
+
+    let mut runner = super::Evaluator::new();
+    let err = runner.load_from_file(&a).unwrap_err();
+    assert_eq!(err.errname, err_msgs::IO_ERROR);
+    let (a, b) = (a.canonicalize().unwrap(), b.canonicalize().unwrap());
+    let cycle = format!("circular load: {0} -> {1} -> {0}", a.display(), b.display());
+    assert!(err.traceback_string().contains(&cycle), "{}", err.traceback_string());
+
+    // The loads which failed are unwound
+    assert_eq!(*eval_in(&mut runner, "*directory-stack*").unwrap(), nil!());
+    File::create(&b).unwrap().write_all(b"(define b 1)").unwrap();
+    assert_eq!(*runner.load_from_file(&a).unwrap(), symbol!("b"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_completion() {
+    let mut runner = super::Evaluator::new();
+    let (start, names) = runner.complete("(car (string->l").unwrap();
+    assert_eq!((start, names), (6, vec![String::from("string->list")]));
+
+    // Special forms, macros and new definitions are completed too
+    runner.eval_all_from_parser(Parser::from_string("(define-macro (defx) 1) (define defy 2)", "<test>")).unwrap();
+    let (_, names) = runner.complete("(def").unwrap();
+    for name in ["define", "define-macro", "defx", "defy"].iter() {
+        assert!(names.iter().any(|n| n == name), "{} not in {:?}", name, names);
+    }
+    assert_eq!(runner.complete("").unwrap().1.len(), runner.complete("(").unwrap().1.len());
+
+    runner.eval_all_from_parser(Parser::from_string(
+        "(set! *completion-function* (lambda (prefix) (cons \"custom\" (completions prefix))))", "<test>")).unwrap();
+    assert_eq!(runner.complete("defy").unwrap().1, vec![String::from("custom"), String::from("defy")]);
+
+    runner.eval_all_from_parser(Parser::from_string("(set! *completion-function* (lambda (p) '(1)))", "<test>")).unwrap();
+    assert_eq!(runner.complete("x").unwrap_err().errname, err_msgs::TYPE_ERROR);
+    tests! {
+        "(completions \"string->l\")"     => Ok(lisp_list![string!("string->list")]),
+        "(completions 'car)"              => Err(RuntimeError::error(err_msgs::TYPE_ERROR))
+    }
+}
+
+#[test]
+fn test_colorize() {
+    use evaluator::output::colorize;
+    assert_eq!(colorize(&int!(1), String::from("1")), "\x1b[36m1\x1b[0m");
+    assert_eq!(colorize(&symbol!("a"), String::from("a")), "\x1b[33ma\x1b[0m");
+    assert_eq!(colorize(&lisp_list![int!(1)], String::from("(1)")), "(1)");
+    tests! {
+        "*color-output*"                  => Ok(symbol!("true"))
+    }
+}
+
+#[test]
+fn test_vector_literals() {
+    let vector = |items: Vec<LispObj>| LispObj::make_vector(items.into_iter());
+    tests! {
+        "#(1 2 3)"                        => Ok(vector(vec![int!(1), int!(2), int!(3)])),
+        "#()"                             => Ok(vector(vec![])),
+        "#(1 (+ 1 2))"                    => Ok(vector(vec![int!(1), int!(3)])),
+        "'#(a (b))"                       => Ok(vector(vec![symbol!("a"), lisp_list![symbol!("b")]])),
+        "(quote [a (+ 1 2)])"             => Ok(vector(vec![symbol!("a"), lisp_list![symbol!("+"), int!(1), int!(2)]])),
+        "`[a ,(+ 1 2)]"                   => Ok(vector(vec![symbol!("a"), int!(3)])),
+        "`#(a (b ,(+ 1 2)) [,(+ 2 2)])"   => Ok(vector(vec![symbol!("a"), lisp_list![symbol!("b"), int!(3)],
+                                                            vector(vec![int!(4)])])),
+        "#(1 . 2)"                        => Err(RuntimeError::error(err_msgs::READ_ERROR))
+    }
+}
+
+#[test]
+fn test_vector_ref() {
+    tests! {
+        "(vector-ref [1 false 3] 1)"                 => Ok(lisp_false!()),
+        "(vector-ref-safe [1 2 3] 2)"                => Ok(int!(3)),
+        "(vector-ref-safe [1 2 3] 3)"                => Ok(lisp_false!()),
+        "(vector-ref-safe [1 2 3] 3 'none)"          => Ok(symbol!("none")),
+        "(error-type (catch-error (vector-ref [1 2 3] 3)))" => Ok(symbol!("bounds-error")),
+        "(vector-ref [1 2 3] -1)"                    => Ok(int!(3)),
+        "(vector-ref-safe [1 2 3] -4 'none)"         => Ok(symbol!("none")),
+        "(vector-assoc [1 2] -2 'x)"                 => Ok(LispObj::make_vector(vec![symbol!("x"), int!(2)].into_iter())),
+        "(define v [1 2]) (set! (vector-ref v -1) 'x) (vector-ref v 1)" => Ok(symbol!("x")),
+        "(string-ref \"h\u{e9}llo\" 1)"              => Ok(char!('\u{e9}')),
+        "(string-ref \"h\u{e9}llo\" -1)"             => Ok(char!('o')),
+        "(bytes-ref (string->bytes \"AB\") -1)"      => Ok(int!(66)),
+        "(vector-ref [1 2 3] 3)", "(vector-ref [] 0)", "(vector-ref [1] -2)",
+        "(vector-ref [1] 9223372036854775807)", "(vector-ref [1] -9223372036854775807)",
+        "(vector-assoc [1] 5 'x)", "(define v [1]) (set! (vector-ref v 1) 2)",
+        "(string-ref \"h\u{e9}llo\" 5)", "(string-ref \"\" -1)"
+                                                     => Err(RuntimeError::error(err_msgs::BOUNDS_ERROR))
+    }
+}
+
+#[test]
+fn test_list_accessors() {
+    let bounds_err = RuntimeError::error(err_msgs::BOUNDS_ERROR);
+    tests! {
+        "(nth '(a b c) 0)"                           => Ok(symbol!("a")),
+        "(nth '(a b c) -1)"                          => Ok(symbol!("c")),
+        "(last '(a b c))"                            => Ok(symbol!("c")),
+        "(take '(a b c) 2)"                          => Ok(lisp_list![symbol!("a"), symbol!("b")]),
+        "(take '(a b c) 0)"                          => Ok(nil!()),
+        "(take '(a b c) -1)"                         => Ok(lisp_list![symbol!("a"), symbol!("b")]),
+        "(drop '(a b c) 1)"                          => Ok(lisp_list![symbol!("b"), symbol!("c")]),
+        "(drop '(a b c) 3)"                          => Ok(nil!()),
+        "(list-tail '(a b c) -1)"                    => Ok(lisp_list![symbol!("c")]),
+        "(nth '(a b c) 3)", "(nth '(a) -2)", "(nth () 0)", "(last ())",
+        "(take '(a b) 3)", "(drop '(a b) -3)"        => Err(bounds_err.clone()),
+        "(nth '(a . b) 1)", "(last [1 2])", "(take 'a 0)",
+        "(drop '(a b) 'x)"                           => Err(RuntimeError::error(err_msgs::TYPE_ERROR))
+    }
+}
+
+#[test]
+fn test_alists() {
+    tests! {
+        "(assoc \"b\" '((\"a\" . 1) (\"b\" . 2)))"    => Ok(cons!(string!("b"), int!(2))),
+        "(assoc '(1) '(((1) . one)))"                => Ok(cons!(lisp_list![int!(1)], symbol!("one"))),
+        "(assoc 'c '((a . 1)))"                      => Ok(lisp_false!()),
+        "(assq 'b '((a . 1) (b . 2) (b . 3)))"       => Ok(cons!(symbol!("b"), int!(2))),
+        "(assq 2 '((1 . a) (2 . b)))"                => Ok(cons!(int!(2), symbol!("b"))),
+        "(assq \"b\" '((\"b\" . 2)))"                => Ok(lisp_false!()),
+        "(define k \"b\") (assq k (cons (cons k 2) ()))" => Ok(cons!(string!("b"), int!(2))),
+        "(define al '((a . 1))) (equal? (assq 'a al) (car al))" => Ok(lisp_true!()),
+        "(assq 'a ())"                               => Ok(lisp_false!()),
+        "(alist-update '((a . 1) (b . 2) (a . 3)) 'a 10)"
+            => Ok(lisp_list![cons!(symbol!("a"), int!(10)), cons!(symbol!("b"), int!(2)), cons!(symbol!("a"), int!(3))]),
+        "(alist-update '((a . 1)) 'c 3)"             => Ok(lisp_list![cons!(symbol!("c"), int!(3)), cons!(symbol!("a"), int!(1))]),
+        "(define al '((a . 1))) (alist-update al 'a 2) al" => Ok(lisp_list![cons!(symbol!("a"), int!(1))]),
+        "(assq 'a 'b)", "(assoc 'a '(a))", "(alist-update '((a . 1) . b) 'a 2)"
+                                                     => Err(RuntimeError::error(err_msgs::TYPE_ERROR))
+    }
+}
+
+#[test]
+fn test_unquote_splicing() {
+    let vector = |items: Vec<LispObj>| LispObj::make_vector(items.into_iter());
+    let defs = "(define xs (cons 1 (cons 2 ()))) (define v [3 4])";
+    let prog = |body: &str| format!("{} {}", defs, body);
+    tests! {
+        &prog("`(a ,@xs b)")              => Ok(lisp_list![symbol!("a"), int!(1), int!(2), symbol!("b")]),
+        &prog("`(,@xs ,@v)")              => Ok(lisp_list![int!(1), int!(2), int!(3), int!(4)]),
+        &prog("`(a ,@() b)")              => Ok(lisp_list![symbol!("a"), symbol!("b")]),
+        &prog("`[0 ,@xs ,@v 5]")          => Ok(vector(vec![int!(0), int!(1), int!(2), int!(3), int!(4), int!(5)])),
+        &prog("`(a [,@xs])")              => Ok(lisp_list![symbol!("a"), vector(vec![int!(1), int!(2)])]),
+        &prog("`(a ,@1)")                 => Err(RuntimeError::error(err_msgs::TYPE_ERROR)),
+        &prog("`,@xs")                    => Err(RuntimeError::error(err_msgs::SYNTAX_ERROR))
+    }
+}
+
+#[test]
+fn test_eval_shares_values() {
+    use ::core::shared::Shared;
+    use ::evaluator::{default_environment, eval};
+
+    // Values and variables evaluate to the object itself, rather than a copy
+    let env = default_environment().to_env_ref();
+    let text = string!("some text").to_obj_ref();
+    assert!(Shared::ptr_eq(&eval(text.clone(), env.clone()).unwrap(), &text));
+
+    env.borrow_mut().let_new(String::from("text"), text.clone());
+    env.borrow_mut().let_new(String::from("texts"), LispObj::make_vector(vec![text.clone()].into_iter()).to_obj_ref());
+    assert!(Shared::ptr_eq(&eval(symbol!("text"), env.clone()).unwrap(), &text));
+    let elem = eval(lisp_list![symbol!("vector-ref"), symbol!("texts"), int!(0)], env).unwrap();
+    assert!(Shared::ptr_eq(&elem, &text));
+}
+
+#[test]
+fn test_native_calls() {
+    // Natives are called with their arguments in a slice, from eval, from the tail of
+    // a procedure, and through apply
+    tests! {
+        "(+ 1 2 (* 3 4))"                           => Ok(int!(15)),
+        "((lambda (x) (+ x 1)) 1)"                  => Ok(int!(2)),
+        "((lambda (f) (apply f (cons 1 (cons 2 nil)))) +)" => Ok(int!(3)),
+        "(+ 1 . 2)", "((lambda () (+ 1 . 2)))"      => Err(RuntimeError::error(err_msgs::SYNTAX_ERROR)),
+        "((lambda () (+ 1 'a)))"                    => Err(RuntimeError::error(err_msgs::TYPE_ERROR))
+    }
+}
+
+#[test]
+fn test_list_construction() {
+    let expected = lisp_list![int!(1), int!(2), int!(3)];
+    assert_eq!(LispObj::from_vec(vec![int!(1).to_obj_ref(), int!(2).to_obj_ref(), int!(3).to_obj_ref()]), expected);
+    assert_eq!(LispObj::to_lisp_list((1..4).map(|n| int!(n))), expected);
+    assert_eq!((1..4).map(|n| int!(n)).filter(|_| true).collect::<LispObj>(), expected);
+    assert_eq!(LispObj::from_vec(vec![]), nil!());
+
+    tests! {
+        "(vector->list [1 2 3])"    => Ok(expected.clone()),
+        "(vector->list [])"         => Ok(nil!())
+    }
+}
+
+#[test]
+fn test_cached_values() {
+    use ::core::shared::Shared;
+
+    // Common values share one object, rather than being allocated each time
+    for obj in vec![nil!(), lisp_true!(), lisp_false!(), int!(-128), int!(0), int!(256)] {
+        assert!(Shared::ptr_eq(&obj.clone().to_obj_ref(), &obj.to_obj_ref()));
+    }
+    for obj in vec![int!(-129), int!(257), symbol!("truth"), float!(0.0)] {
+        assert!(!Shared::ptr_eq(&obj.clone().to_obj_ref(), &obj.to_obj_ref()));
+    }
+    assert!(Shared::ptr_eq(&lisp_true_ref!(), &lisp_true!().to_obj_ref()));
+    assert!(Shared::ptr_eq(&lisp_false_ref!(), &lisp_bool_ref!(false)));
+    tests! {
+        "(+ 255 1)"     => Ok(int!(256)),
+        "(+ 256 1)"     => Ok(int!(257))
+    }
+}
+
+#[test]
+fn test_integer_overflow() {
+    let overflow = RuntimeError::error(err_msgs::ARITHMETIC_ERROR);
+    tests! {
+        "(+ 9223372036854775807 1)",
+        "(- -9223372036854775807 2)",
+        "(- (- -9223372036854775807 1))",
+        "(* 4611686018427387904 2)"             => Err(overflow.clone()),
+        "(+ 9223372036854775807 1.0)"           => Ok(float!(9223372036854775808.0)),
+        "(+wrap 9223372036854775807 1)"         => Ok(int!(::std::i64::MIN)),
+        "(*wrap 4611686018427387904 2)"         => Ok(int!(::std::i64::MIN)),
+        "(+wrap)"                               => Ok(int!(0)),
+        "(*wrap 2 3 4)"                         => Ok(int!(24)),
+        "(+wrap 1 1.0)"                         => Err(RuntimeError::error(err_msgs::TYPE_ERROR))
+    }
+}
+
+#[test]
+fn test_expt_gcd_lcm() {
+    let overflow = RuntimeError::error(err_msgs::ARITHMETIC_ERROR);
+    let type_err = RuntimeError::error(err_msgs::TYPE_ERROR);
+    tests! {
+        "(expt 2 10)"                   => Ok(int!(1024)),
+        "(expt -3 3)"                   => Ok(int!(-27)),
+        "(expt 0 0)"                    => Ok(int!(1)),
+        "(expt -1 10000000001)"         => Ok(int!(-1)),
+        "(expt 2 -1)"                   => Ok(float!(0.5)),
+        "(expt 9 0.5)"                  => Ok(float!(3.0)),
+        "(expt 2.0 3)"                  => Ok(float!(8.0)),
+        "(expt 2 63)"                   => Err(overflow.clone()),
+        "(expt 'a 2)"                   => Err(type_err.clone()),
+
+        "(gcd 12 18)"                   => Ok(int!(6)),
+        "(gcd -4 6 10)"                 => Ok(int!(2)),
+        "(gcd)"                         => Ok(int!(0)),
+        "(gcd 0 5)"                     => Ok(int!(5)),
+        "(gcd (- -9223372036854775807 1))" => Err(overflow.clone()),
+        "(lcm 4 6)"                     => Ok(int!(12)),
+        "(lcm -3 5)"                    => Ok(int!(15)),
+        "(lcm)"                         => Ok(int!(1)),
+        "(lcm 0 5)"                     => Ok(int!(0)),
+        "(lcm 9223372036854775807 2)"   => Err(overflow),
+        "(gcd 1.0 2)", "(lcm 1 'a)"     => Err(type_err.clone())
+    }
+}
+
+#[test]
+fn test_min_max_sum() {
+    let type_err = RuntimeError::error(err_msgs::TYPE_ERROR);
+    tests! {
+        "(min 3 1 2)"                   => Ok(int!(1)),
+        "(max 3 1 2)"                   => Ok(int!(3)),
+        "(min 1.5 2)"                   => Ok(float!(1.5)),
+        "(max 2 2.0)"                   => Ok(int!(2)),
+        "(max 7)"                       => Ok(int!(7)),
+        "(min)"                         => Err(RuntimeError::error(err_msgs::ARITY_ERROR)),
+        "(max 1 (/ 0.0 0.0))"           => Err(RuntimeError::error(err_msgs::ARGUMENT_ERROR)),
+        "(min 1 'a)"                    => Err(type_err.clone()),
+
+        "(sum '(1 2 3))"                => Ok(int!(6)),
+        "(sum [1 2.5])"                 => Ok(float!(3.5)),
+        "(sum nil)"                     => Ok(int!(0)),
+        "(product-of '(1 2 3 4))"       => Ok(int!(24)),
+        "(product-of [])"               => Ok(int!(1)),
+        "(sum '(1 a))", "(sum 5)", "(product-of (cons 1 2))" => Err(type_err.clone())
+    }
+}
+
+#[test]
+fn test_format() {
+    let arg_err = RuntimeError::error(err_msgs::ARGUMENT_ERROR);
+    tests! {
+        "(format \"x=~a y=~s~%\" \"one\" \"two\")"  => Ok(string!("x=one y=\"two\"\n")),
+        "(format \"~d/~D ~~\" 1 2)"                 => Ok(string!("1/2 ~")),
+        "(format \"~2f ~f ~f\" 12.345 1 0.5)"       => Ok(string!("12.35 1.0 0.5")),
+        "(format \"~a ~a\" \\c '(1 \"a\"))"        => Ok(string!("c (1 \"a\")")),
+        "(format \"~a\")", "(format \"~a\" 1 2)",
+        "(format \"~q\" 1)", "(format \"~2d\" 1)",
+        "(format \"~99999999999999999999999f\" 1.5)",
+        "(format \"oops~\")"                        => Err(arg_err.clone()),
+        "(format \"~d\" 1.5)", "(format \"~f\" 'a)",
+        "(format 'a)"                               => Err(RuntimeError::error(err_msgs::TYPE_ERROR))
+    }
+
+    // Precisions are capped, rather than padding with as many zeros as asked for
+    let mut runner = super::Evaluator::new();
+    let res = eval_in(&mut runner, "(format \"~1000000f\" 1.5)").unwrap();
+    assert_eq!(res.string_ref().map(|s| s.len()), Some(102));
+}
+
+#[test]
+fn test_native_panics() {
+    use ::core::{EnvironmentRef, LispObjRef};
+
+    fn explode(_: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+        panic!("boom")
+    }
+
+    let env = ::evaluator::default_environment().to_env_ref();
+    env.borrow_mut().let_new(String::from("explode"), LispObj::make_native("explode", explode, None).to_obj_ref());
+    env.borrow_mut().let_macro(String::from("explode-macro"),
+                               LispObj::make_native("explode-macro", explode, None).to_obj_ref()).unwrap();
+
+    // Panics become errors, which can be caught, and the evaluator carries on
+    let mut runner = super::Evaluator::from_existing(env);
+    let err = runner.eval_all_from_parser(Parser::from_string("(explode)", "<test>")).unwrap_err();
+    assert_eq!(err.errname, err_msgs::INTERNAL_ERROR);
+    assert_eq!(err.value, Some(string!("native function panicked: boom").to_obj_ref()));
+    let res = runner.eval_all_from_parser(Parser::from_string("(error-type (catch-error (apply explode '(1))))", "<test>"));
+    assert_eq!(res.unwrap(), symbol!("internal-error").to_obj_ref());
+    let err = runner.eval_all_from_parser(Parser::from_string("(explode-macro)", "<test>")).unwrap_err();
+    assert!(err.find_in_chain(err_msgs::INTERNAL_ERROR).is_some(), "{:?}", err);
+    let res = runner.eval_all_from_parser(Parser::from_string("(+ 1 2)", "<test>")).unwrap();
+    assert_eq!(res, int!(3).to_obj_ref());
+}
+
+#[test]
+fn test_register_module() {
+    use ::core::{EnvironmentRef, LispObjRef};
+
+    fn count(args: &[LispObjRef], _: EnvironmentRef) -> EvalResult {
+        Ok(int!(args.len()).to_obj_ref())
+    }
+
+    let mut runner = super::Evaluator::new();
+    runner.register_module("m", &[("count", count, Some("Counts its arguments."))]);
+    runner.register_module("", &[("car", count, None)]);
+    runner.register_value("m/answer", int!(42));
+    let src = "(cons (m/count 1 2) (cons (car 1) (cons m/answer (doc 'm/count))))";
+    let res = runner.eval_all_from_parser(Parser::from_string(src, "<test>")).unwrap();
+    assert_eq!(*res, cons!(int!(2), cons!(int!(1), cons!(int!(42), string!("Counts its arguments.")))));
+}
+
+#[test]
+#[cfg(not(all(unix, feature = "native-extensions")))]
+fn test_load_native_needs_feature() {
+    tests! {
+        "(load-native \"libfoo\")" => Err(RuntimeError::error(err_msgs::ENVIRONMENT_ERROR))
+    }
+}
+
+#[test]
+#[cfg(all(unix, feature = "native-extensions"))]
+fn test_load_native() {
+    tests! {
+        "(load-native \"/nonexistent/libfoo\")"  => Err(RuntimeError::error(err_msgs::IO_ERROR)),
+        "(load-native 1)"                         => Err(RuntimeError::error(err_msgs::TYPE_ERROR))
+    }
+}
+
+#[test]
+fn test_convert() {
+    use std::collections::HashMap;
+    use ::core::convert::{FromLisp, ToLisp};
+
+    fn round_trip<T: ToLisp + FromLisp + Clone + PartialEq + ::std::fmt::Debug>(val: T) {
+        assert_eq!(T::from_lisp(&val.clone().to_lisp().unwrap()).unwrap(), val);
+    }
+    round_trip(-3i8);
+    round_trip(7usize);
+    round_trip(1.5f64);
+    round_trip(true);
+    round_trip('x');
+    round_trip(String::from("abc"));
+    round_trip(vec![Some(1i64), None]);
+    round_trip(vec![(1u8, 'a')].into_iter().collect::<HashMap<_, _>>());
+
+    assert_eq!(*vec![1, 2].to_lisp().unwrap(), lisp_list![int!(1), int!(2)]);
+    assert_eq!(f64::from_lisp(&int!(2).to_obj_ref()).unwrap(), 2.0);
+    assert_eq!(Vec::<i64>::from_lisp(&LispObj::LVector(vec![int!(1).to_obj_ref()].into_iter().collect()).to_obj_ref()).unwrap(),
+               vec![1]);
+    let err = RuntimeError::error("io-error");
+    assert_eq!(Result::<i64, RuntimeError>::from_lisp(&err.clone().into_lisp_obj().to_obj_ref()).unwrap(), Err(err.clone()));
+    assert_eq!(Err::<i64, _>(err.clone()).to_lisp(), Err(err));
+
+    assert_eq!(u8::from_lisp(&int!(256).to_obj_ref()).unwrap_err().errname, err_msgs::ARGUMENT_ERROR);
+    assert_eq!(u64::max_value().to_lisp().unwrap_err().errname, err_msgs::ARGUMENT_ERROR);
+    assert_eq!(bool::from_lisp(&int!(1).to_obj_ref()).unwrap_err().errname, err_msgs::TYPE_ERROR);
+    assert_eq!(Vec::<i64>::from_lisp(&cons!(int!(1), int!(2)).to_obj_ref()).unwrap_err().errname, err_msgs::TYPE_ERROR);
+
+    fn scale(xs: Vec<f64>, by: f64) -> EvalResult<Vec<f64>> {
+        Ok(xs.into_iter().map(|x| x * by).collect())
+    }
+    fn greeting(name: Option<String>) -> String {
+        format!("hello, {}", name.unwrap_or(String::from("world")))
+    }
+
+    let mut runner = super::Evaluator::new();
+    runner.register_module("", &[("scale", native_fn!(scale(Vec<f64>, f64)), None),
+                                 ("greeting", native_fn!(greeting(Option<String>)), None)]);
+    let mut eval = |src: &str| runner.eval_all_from_parser(Parser::from_string(src, "<test>"));
+    assert_eq!(*eval("(scale [1 2.5] 2)").unwrap(), lisp_list![float!(2), float!(5)]);
+    assert_eq!(*eval("(greeting nil)").unwrap(), string!("hello, world"));
+    assert_eq!(eval("(scale 1 2)").unwrap_err().errname, err_msgs::TYPE_ERROR);
+    assert_eq!(eval("(scale '(1))").unwrap_err().errname, err_msgs::ARITY_ERROR);
+}
+
+#[test]
+fn test_eval_str_in() {
+    use ::evaluator::eval_str_in;
+
+    let env = ::evaluator::default_environment().to_env_ref();
+    assert_eq!(*eval_str_in("(define x 2) `(,x y)", env.clone()).unwrap(), lisp_list![int!(2), symbol!("y")]);
+    assert_eq!(*eval_str_in("", env.clone()).unwrap(), nil!());
+    assert_eq!(eval_str_in("(car", env.clone()).unwrap_err().errname, err_msgs::READ_ERROR);
+    assert_eq!(eval_str_in("\"abc\\", env).unwrap_err().errname, err_msgs::READ_ERROR);
+}
+
+#[test]
+#[should_panic(expected = "(+ 1 2) evaluated to 3, expected 4")]
+fn test_testing_reports_mismatches() {
+    use super::testing::{assert_eval_prints, is_error_type};
+
+    assert_eval_prints("'(a \"b\")", "(a \"b\")");
+    assert!(is_error_type(&super::Evaluator::new().eval_all_from_parser(Parser::from_string("(car 1)", "<test>")),
+                          err_msgs::TYPE_ERROR));
+    super::testing::assert_eval_eq("(+ 1 2)", int!(4));
+}
+
+#[test]
+fn test_shared_stdin() {
+    // read takes the input after the form which calls it, from the REPL's own reader
+    let out = Capture::new();
+    let input = "(define x (read)) (+ 1\n 2) (cons x (read)) 3 (read)";
+    let mut runner = super::Evaluator::new().with_stdin(input.as_bytes()).with_stdout(out.clone())
+                                            .with_stderr(Capture::new());
+    assert_eq!(runner.repl(), None);
+    assert!(out.contents().ends_with("((+ 1 2) . 3)\n"), "{}", out.contents());
+
+    // The evaluator keeps reading from it afterwards
+    let mut runner = super::Evaluator::new().with_stdin("a (b) c".as_bytes());
+    assert_eq!(*eval_in(&mut runner, "(read)").unwrap(), symbol!("a"));
+    assert_eq!(*eval_in(&mut runner, "(read)").unwrap(), lisp_list![symbol!("b")]);
+    assert_eq!(*eval_in(&mut runner, "(read) (read)").unwrap_err().value.unwrap(), symbol!("eof"));
+}
+
+#[test]
+fn test_prompt() {
+    use ::evaluator::output::prompt;
+
+    let err = Capture::new();
+    let mut runner = super::Evaluator::new().with_stderr(err.clone());
+    assert_eq!(prompt(0, &runner.top_level), "> ");
+    assert_eq!(prompt(2, &runner.top_level), "..2> ");
+
+    eval_in(&mut runner, "(set! *prompt* \"lisp> \")").unwrap();
+    assert_eq!(prompt(0, &runner.top_level), "lisp> ");
+    assert_eq!(prompt(1, &runner.top_level), "..1> ");
+
+    eval_in(&mut runner, "(define n 0) (set! *prompt* (lambda () (set! n (+ n 1)) (format \"~d> \" n)))").unwrap();
+    assert_eq!(prompt(0, &runner.top_level), "1> ");
+    assert_eq!(prompt(0, &runner.top_level), "2> ");
+
+    // A failing prompt procedure falls back to the default
+    eval_in(&mut runner, "(set! *prompt* (lambda () (car 1)))").unwrap();
+    let shown = runner.run(|| prompt(0, &runner.top_level));
+    assert_eq!(shown, "> ");
+    assert!(err.contents().starts_with("error in *prompt*:\ntype-error: "), "{}", err.contents());
+    eval_in(&mut runner, "(set! *prompt* 'sym)").unwrap();
+    assert_eq!(runner.run(|| prompt(0, &runner.top_level)), "> ");
+}
+
+#[test]
+fn test_at_exit() {
+    let (out, err) = (Capture::new(), Capture::new());
+    let mut runner = super::Evaluator::new().with_stdout(out.clone()).with_stderr(err.clone());
+    let src = "(at-exit (lambda () (println \"first\")))
+               (at-exit (lambda () (car 1)))
+               (at-exit (lambda () (println \"second\") (at-exit (lambda () (println \"third\")))))
+               (at-exit (lambda () (exit 3)))";
+    assert_eq!(*eval_in(&mut runner, src).unwrap(), lisp_true!());
+    assert_eq!(out.contents(), "");
+
+    // Hooks run once, most recent first, carrying on past errors and exits
+    drop(runner);
+    assert_eq!(out.contents(), "second\nfirst\nthird\n");
+    assert!(err.contents().starts_with("type-error: "), "{}", err.contents());
+
+    let out = Capture::new();
+    let mut runner = super::Evaluator::new().with_stdout(out.clone());
+    eval_in(&mut runner, "(at-exit (lambda () (print 'bye)))").unwrap();
+    runner.run_exit_hooks();
+    runner.run_exit_hooks();
+    drop(runner);
+    assert_eq!(out.contents(), "bye");
+
+    tests! {
+        "(at-exit 1)"                                => Err(RuntimeError::error(err_msgs::TYPE_ERROR)),
+        "(at-exit (lambda () 1)) (cdr *exit-hooks*)" => Ok(nil!())
+    }
+}
+
+#[test]
+fn test_procedure_to_list() {
+    tests! {
+        "(procedure->list (lambda (a b) \"doc\" (+ a b)))"
+            => Ok(lisp_list![lisp_list![symbol!("a"), symbol!("b")], string!("doc"),
+                             lisp_list![symbol!("+"), symbol!("a"), symbol!("b")]]),
+        "(procedure->list (lambda ([a b] (c 1) #:key d . rest) c))"
+            => Ok(cons!(cons!(LispObj::make_vector(vec![symbol!("a"), symbol!("b")].into_iter()),
+                              cons!(lisp_list![symbol!("c"), int!(1)],
+                                    cons!(symbol!("#:key"),
+                                          cons!(lisp_list![symbol!("d"), nil!()], symbol!("rest"))))),
+                        lisp_list![symbol!("c")])),
+        "(procedure->list (case-lambda ((x) #:when x 1) (() 2)))"
+            => Ok(lisp_list![lisp_list![lisp_list![symbol!("x")], symbol!("#:when"), symbol!("x"), int!(1)],
+                             lisp_list![nil!(), int!(2)]]),
+        "(procedure->list car)" => Err(RuntimeError::error(err_msgs::TYPE_ERROR))
+    }
+
+    let mut runner = super::Evaluator::new();
+    let procd = eval_in(&mut runner, "(define (add a (b 1) . more) a) add").unwrap();
+    assert_eq!(format!("{}", procd), "#<named-procedure:add>");
+    assert_eq!(format!("{:#}", procd), "#<named-procedure:add (a (b 1) . more)>");
+
+    use evaluator::output::show_value;
+    let top_level = runner.top_level.clone();
+    assert_eq!(show_value(&procd, false, &top_level), "#<named-procedure:add>");
+    assert_eq!(show_value(&int!(1), true, &top_level), "\x1b[36m1\x1b[0m");
+    eval_in(&mut runner, "(set! *print-arity* true)").unwrap();
+    let procs = eval_in(&mut runner, "(cons add (case-lambda ((x) x) ((x y) y)))").unwrap();
+    let shown = show_value(&procs, false, &top_level);
+    assert!(shown.starts_with("(#<named-procedure:add (a (b 1) . more)> . #<anonymous-procedure:"), "{}", shown);
+    assert!(shown.ends_with(" (x) (x y)>)"), "{}", shown);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_on_signal() {
+    use std::os::raw::c_int;
+
+    extern "C" {
+        fn raise(signum: c_int) -> c_int;
+    }
+
+    let mut runner = super::Evaluator::new();
+    let set = "(define got nil) (define (handler sig) (set! got sig)) (on-signal 'sighup handler)";
+    assert_eq!(*eval_in(&mut runner, set).unwrap(), nil!());
+
+    // The handler runs at the next evaluation step, before got is looked up
+    assert_eq!(unsafe { raise(1) }, 0);
+    assert_eq!(*eval_in(&mut runner, "got").unwrap(), symbol!("sighup"));
+    assert_eq!(*eval_in(&mut runner, "(set! got nil) got").unwrap(), nil!());
+
+    // Errors from the handler are thrown from what was being evaluated
+    eval_in(&mut runner, "(on-signal 'sighup (lambda (sig) (car sig)))").unwrap();
+    assert_eq!(unsafe { raise(1) }, 0);
+    assert_eq!(eval_in(&mut runner, "(+ 1 2)").unwrap_err().errname, err_msgs::TYPE_ERROR);
+
+    let removed = eval_in(&mut runner, "(on-signal 'sighup nil) (on-signal 'sighup nil)").unwrap();
+    assert_eq!(*removed, nil!());
+    assert_eq!(*eval_in(&mut runner, "*signal-handlers*").unwrap(), nil!());
+    assert_eq!(eval_in(&mut runner, "(on-signal 'sigfoo handler)").unwrap_err().errname, err_msgs::ARGUMENT_ERROR);
+    assert_eq!(eval_in(&mut runner, "(on-signal \"sighup\" handler)").unwrap_err().errname, err_msgs::TYPE_ERROR);
+    assert_eq!(eval_in(&mut runner, "(on-signal 'sighup 5)").unwrap_err().errname, err_msgs::TYPE_ERROR);
+}
+
+#[test]
+fn test_interrupt_uncatchable() {
+    let err = RuntimeError::new_from(RuntimeError::interrupt(), lisp_list![symbol!("loop")].to_obj_ref());
+    assert!(err.is_interrupt());
+    assert!(!err.is_catchable());
+    assert!(RuntimeError::error(err_msgs::TYPE_ERROR).is_catchable());
+}
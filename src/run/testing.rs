@@ -0,0 +1,88 @@
+//! Helpers for testing lisp code and native functions.
+//!
+//! Each helper evaluates a string of source, and panics with the source and what it
+//! gave if the result isn't as expected. The plain versions use a fresh `Evaluator`;
+//! the `_in` versions take one, e.g. with a builtin pack installed by
+//! `Evaluator::register_module`:
+//!
+//! ```
+//! # #[macro_use] extern crate rustylisp;
+//! use rustylisp::evaluator::err_msgs;
+//! use rustylisp::run::Evaluator;
+//! use rustylisp::run::testing::{assert_eval_eq, assert_eval_err, assert_eval_prints_in};
+//!
+//! # fn main() {
+//! assert_eval_eq("(+ 1 2)", int!(3));
+//! assert_eval_err("(car 1)", err_msgs::TYPE_ERROR);
+//!
+//! let mut runner = Evaluator::new();
+//! runner.register_value("answer", int!(42));
+//! assert_eval_prints_in(&mut runner, "(cons answer '(b))", "(42 b)");
+//! # }
+//! ```
+use ::core::{AsLispObjRef, EvalResult};
+use ::parser::Parser;
+use super::Evaluator;
+
+/// Evaluates every form in `source` with `runner`, returning the last value.
+pub fn eval_in(runner: &mut Evaluator, source: &str) -> EvalResult {
+    runner.eval_all_from_parser(Parser::from_string(source, "<test>"))
+}
+
+/// Asserts that `source` evaluates to a value equal to `expected`.
+pub fn assert_eval_eq<O: AsLispObjRef>(source: &str, expected: O) {
+    assert_eval_eq_in(&mut Evaluator::new(), source, expected)
+}
+
+/// Like `assert_eval_eq`, evaluating with `runner`.
+pub fn assert_eval_eq_in<O: AsLispObjRef>(runner: &mut Evaluator, source: &str, expected: O) {
+    let expected = expected.to_obj_ref();
+    match eval_in(runner, source) {
+        Ok(ref val) if *val == expected => {},
+        Ok(val) => panic!("{} evaluated to {}, expected {}", source, val, expected),
+        Err(err) => panic!("{} threw {}, expected {}", source, err, expected),
+    }
+}
+
+/// Asserts that `source` evaluates to a value which prints as `expected`.
+pub fn assert_eval_prints(source: &str, expected: &str) {
+    assert_eval_prints_in(&mut Evaluator::new(), source, expected)
+}
+
+/// Like `assert_eval_prints`, evaluating with `runner`.
+pub fn assert_eval_prints_in(runner: &mut Evaluator, source: &str, expected: &str) {
+    match eval_in(runner, source) {
+        Ok(val) => {
+            let printed = format!("{}", val);
+            if printed != expected {
+                panic!("{} evaluated to {}, expected {}", source, printed, expected)
+            }
+        },
+        Err(err) => panic!("{} threw {}, expected {}", source, err, expected),
+    }
+}
+
+/// Asserts that `source` throws an error named `errname`, such as
+/// `err_msgs::TYPE_ERROR`.
+pub fn assert_eval_err(source: &str, errname: &str) {
+    assert_eval_err_in(&mut Evaluator::new(), source, errname)
+}
+
+/// Like `assert_eval_err`, evaluating with `runner`.
+pub fn assert_eval_err_in(runner: &mut Evaluator, source: &str, errname: &str) {
+    let res = eval_in(runner, source);
+    if !is_error_type(&res, errname) {
+        match res {
+            Ok(val) => panic!("{} evaluated to {}, expected a {}", source, val, errname),
+            Err(err) => panic!("{} threw {}, expected a {}", source, err, errname),
+        }
+    }
+}
+
+/// Whether `res` is an error named `errname`.
+pub fn is_error_type<T>(res: &EvalResult<T>, errname: &str) -> bool {
+    match *res {
+        Err(ref err) => err.errname == errname,
+        Ok(_) => false,
+    }
+}